@@ -1,4 +1,6 @@
 pub mod insert_astroplant_definitions;
 pub mod migrate;
+pub mod partitioning;
+pub mod retention;
 
 pub use insert_astroplant_definitions::insert_astroplant_definitions;
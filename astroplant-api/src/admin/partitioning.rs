@@ -0,0 +1,293 @@
+//! Maintenance of the monthly partitions of the measurement tables.
+//!
+//! The measurement tables are partitioned by month. Partitions are created ahead of time by
+//! [create_partitions], which should be run periodically (e.g. monthly). Measurements stored before
+//! partitioning are kept in the unpartitioned tables by the migration; [backfill] copies them into
+//! the partitioned tables in batches, newest first, and drops the unpartitioned tables.
+
+use std::time::{Duration, Instant};
+
+use chrono::TimeZone;
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Integer, Timestamptz, Uuid};
+use diesel::PgConnection;
+
+/// Create the partitions of the measurement tables for the month containing `now` and the
+/// `months_ahead` months after it, if they do not exist yet.
+pub fn create_partitions(
+    conn: &mut PgConnection,
+    now: chrono::DateTime<chrono::Utc>,
+    months_ahead: i32,
+) -> anyhow::Result<()> {
+    diesel::sql_query(
+        "
+        SELECT create_measurement_partitions(month)
+        FROM generate_series($1, $1 + make_interval(months => $2), interval '1 month') AS month
+        ",
+    )
+    .bind::<Timestamptz, _>(now)
+    .bind::<Integer, _>(months_ahead)
+    .execute(conn)?;
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Table {
+    name: &'static str,
+    partition_key: &'static str,
+    columns: &'static str,
+    /// The columns to select from the unpartitioned table.
+    unpartitioned_columns: &'static str,
+}
+
+const RAW_MEASUREMENTS: Table = Table {
+    name: "raw_measurements",
+    partition_key: "datetime",
+    columns: "id, peripheral_id, kit_id, kit_configuration_id, quantity_type_id, value, datetime",
+    unpartitioned_columns:
+        "id, peripheral_id, kit_id, kit_configuration_id, quantity_type_id, value, datetime",
+};
+
+const AGGREGATE_MEASUREMENTS: Table = Table {
+    name: "aggregate_measurements",
+    partition_key: "datetime_start",
+    columns: "id, peripheral_id, kit_id, kit_configuration_id, quantity_type_id, datetime_start, datetime_end, values, server_computed",
+    unpartitioned_columns: "id, peripheral_id, kit_id, kit_configuration_id, quantity_type_id, datetime_start, datetime_end, values::jsonb, server_computed",
+};
+
+#[derive(Debug, QueryableByName)]
+struct BatchEnd {
+    #[diesel(sql_type = Timestamptz)]
+    key: chrono::DateTime<chrono::Utc>,
+    #[diesel(sql_type = Uuid)]
+    id: uuid::Uuid,
+    #[diesel(sql_type = BigInt)]
+    rows: i64,
+}
+
+#[derive(Debug, QueryableByName)]
+struct Exists {
+    #[diesel(sql_type = diesel::sql_types::Bool)]
+    exists: bool,
+}
+
+fn unpartitioned_exists(conn: &mut PgConnection, table: Table) -> anyhow::Result<bool> {
+    let exists: Exists = diesel::sql_query(format!(
+        "SELECT to_regclass('{}_unpartitioned') IS NOT NULL AS exists",
+        table.name
+    ))
+    .get_result(conn)?;
+    Ok(exists.exists)
+}
+
+/// Copy the rows of the unpartitioned table into the partitioned table in batches, newest first by
+/// the partition key, so recent measurements are served first. Rows that were copied before are
+/// skipped, so this can be resumed. Returns the number of rows copied.
+fn backfill_table(conn: &mut PgConnection, table: Table, batch_size: i64) -> anyhow::Result<i64> {
+    let Table {
+        name,
+        partition_key: key,
+        columns,
+        unpartitioned_columns,
+    } = table;
+
+    // Create the partitions of all months in the unpartitioned table.
+    diesel::sql_query(format!(
+        "
+        SELECT create_measurement_partitions(month)
+        FROM generate_series(
+            date_trunc('month', (SELECT min({key}) FROM {name}_unpartitioned) AT TIME ZONE 'UTC') AT TIME ZONE 'UTC',
+            (SELECT max({key}) FROM {name}_unpartitioned),
+            interval '1 month'
+        ) AS month
+        ",
+    ))
+    .execute(conn)?;
+
+    let batch = format!(
+        "
+        WITH batch AS (
+            SELECT {unpartitioned_columns}
+            FROM {name}_unpartitioned
+            WHERE {key} <= $1 AND ({key} < $1 OR id < $2)
+            ORDER BY {key} DESC, id DESC
+            LIMIT $3
+        ),
+        inserted AS (
+            INSERT INTO {name} ({columns})
+            SELECT * FROM batch
+            ON CONFLICT DO NOTHING
+        )
+        SELECT {key} AS key, id, count(*) OVER () AS rows
+        FROM batch
+        ORDER BY {key}, id
+        LIMIT 1
+        ",
+    );
+
+    let mut copied = 0;
+    // After any measurement.
+    let mut before = (
+        chrono::Utc.with_ymd_and_hms(9999, 1, 1, 0, 0, 0).unwrap(),
+        uuid::Uuid::from_u128(u128::MAX),
+    );
+    loop {
+        let end: Option<BatchEnd> = diesel::sql_query(&batch)
+            .bind::<Timestamptz, _>(before.0)
+            .bind::<Uuid, _>(before.1)
+            .bind::<BigInt, _>(batch_size)
+            .get_result(conn)
+            .optional()?;
+
+        match end {
+            Some(end) => {
+                copied += end.rows;
+                before = (end.key, end.id);
+                tracing::info!("Backfilled {} rows of {} back to {}", copied, name, end.key);
+            }
+            None => break,
+        }
+    }
+
+    // The copied rows are not yet in the visibility maps and statistics of the partitions, which
+    // would otherwise be left to autovacuum.
+    diesel::sql_query(format!("VACUUM (ANALYZE) {name}")).execute(conn)?;
+
+    Ok(copied)
+}
+
+/// Copy the measurements stored before partitioning into the partitioned tables. This should be run
+/// right after the partitioning migration, as the API serves only the measurements copied so far.
+/// If `drop_unpartitioned` is set, the unpartitioned tables are dropped afterwards.
+pub fn backfill(
+    conn: &mut PgConnection,
+    batch_size: i64,
+    drop_unpartitioned: bool,
+) -> anyhow::Result<()> {
+    for table in [RAW_MEASUREMENTS, AGGREGATE_MEASUREMENTS] {
+        if !unpartitioned_exists(conn, table)? {
+            tracing::info!("{} was backfilled before", table.name);
+            continue;
+        }
+
+        let copied = backfill_table(conn, table, batch_size)?;
+        tracing::info!("Backfilled {} rows of {}", copied, table.name);
+
+        if drop_unpartitioned {
+            diesel::sql_query(format!("DROP TABLE {}_unpartitioned", table.name)).execute(conn)?;
+            tracing::info!("Dropped {}_unpartitioned", table.name);
+        }
+    }
+
+    Ok(())
+}
+
+/// The timings of a query on the unpartitioned and partitioned aggregate measurement tables.
+#[derive(Debug)]
+pub struct Benchmark {
+    pub query: &'static str,
+    pub unpartitioned: Duration,
+    pub partitioned: Duration,
+}
+
+#[derive(Debug, QueryableByName)]
+struct Size {
+    #[diesel(sql_type = BigInt)]
+    #[allow(dead_code)]
+    bytes: i64,
+}
+
+/// The queries to benchmark, with `{table}` and `{object_keys}` to be substituted by the table
+/// and its JSON object keys function. These mirror [AggregateMeasurement::page](crate::models::AggregateMeasurement::page)
+/// and the archive. The queries are wrapped in a sum of the sizes of the rows, so every selected
+/// column is read, but only the query execution is measured rather than the transfer of the
+/// results.
+const BENCHMARK_QUERIES: &[(&str, &str)] = &[
+    (
+        "aggregate measurements page (first)",
+        "SELECT * FROM {table} WHERE kit_id = $1 ORDER BY datetime_start DESC, id DESC LIMIT 50",
+    ),
+    (
+        "aggregate measurements page (90 days ago)",
+        "SELECT * FROM {table}
+         WHERE kit_id = $1
+           AND (datetime_start < now() - interval '90 days'
+             OR (datetime_start = now() - interval '90 days' AND id < 'ffffffff-ffff-ffff-ffff-ffffffffffff'))
+         ORDER BY datetime_start DESC, id DESC
+         LIMIT 50",
+    ),
+    (
+        "archive aggregate keys (30 days)",
+        "SELECT DISTINCT {object_keys}(values) AS key FROM {table}
+         WHERE kit_id = $1 AND datetime_start >= now() - interval '30 days'",
+    ),
+    (
+        "archive aggregates (30 days)",
+        "SELECT datetime_start, datetime_end, peripheral_id, kit_configuration_id, quantity_type_id, values
+         FROM {table}
+         WHERE kit_id = $1 AND datetime_start >= now() - interval '30 days'",
+    ),
+    (
+        "archive aggregates (all)",
+        "SELECT datetime_start, datetime_end, peripheral_id, kit_configuration_id, quantity_type_id, values
+         FROM {table}
+         WHERE kit_id = $1",
+    ),
+];
+
+fn median_duration(
+    conn: &mut PgConnection,
+    query: &str,
+    kit_id: i32,
+    iterations: usize,
+) -> anyhow::Result<Duration> {
+    let query = format!(
+        "SELECT coalesce(sum(pg_column_size(q.*)), 0) AS bytes FROM ({}) q",
+        query
+    );
+
+    let mut durations = Vec::with_capacity(iterations);
+    for _ in 0..iterations {
+        let start = Instant::now();
+        let _: Size = diesel::sql_query(&query)
+            .bind::<Integer, _>(kit_id)
+            .get_result(conn)?;
+        durations.push(start.elapsed());
+    }
+    durations.sort();
+
+    Ok(durations[durations.len() / 2])
+}
+
+/// Benchmark the aggregate measurement queries of the kit on the unpartitioned and the partitioned
+/// tables. This should be run before dropping the unpartitioned tables, while both tables hold the
+/// same measurements.
+pub fn benchmark(
+    conn: &mut PgConnection,
+    kit_id: i32,
+    iterations: usize,
+) -> anyhow::Result<Vec<Benchmark>> {
+    if !unpartitioned_exists(conn, AGGREGATE_MEASUREMENTS)? {
+        anyhow::bail!("the unpartitioned aggregate measurements table was dropped");
+    }
+
+    let iterations = iterations.max(1);
+    BENCHMARK_QUERIES
+        .iter()
+        .map(|(name, query)| {
+            let unpartitioned = query
+                .replace("{table}", "aggregate_measurements_unpartitioned")
+                .replace("{object_keys}", "json_object_keys");
+            let partitioned = query
+                .replace("{table}", "aggregate_measurements")
+                .replace("{object_keys}", "jsonb_object_keys");
+
+            Ok(Benchmark {
+                query: name,
+                unpartitioned: median_duration(conn, &unpartitioned, kit_id, iterations)?,
+                partitioned: median_duration(conn, &partitioned, kit_id, iterations)?,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,268 @@
+//! Retention of raw measurements.
+//!
+//! Raw measurements older than the retention period that are covered by aggregate measurements
+//! (published by the kit or computed by the server) are deleted or downsampled. Raw measurements
+//! not covered by aggregates are always retained, so no data is lost entirely. The instance-wide
+//! policy applies to kits without a [KitRetentionPolicy](crate::models::KitRetentionPolicy).
+//!
+//! This is meant to be run periodically, e.g. as a cron job.
+
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use diesel::sql_types::{Array, BigInt, Integer, Nullable, Text, Timestamptz};
+use diesel::PgConnection;
+
+use crate::models::KitRetentionPolicy;
+use crate::schema::{kit_retention_policies, kits};
+
+/// The instance-wide retention policy of raw measurements.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RetentionPolicy {
+    /// The number of days raw measurements are retained. If `None`, raw measurements are retained
+    /// indefinitely.
+    pub raw_measurement_days: Option<i32>,
+    /// If set, raw measurements older than the retention period are downsampled to one per
+    /// peripheral, quantity type and window of this many minutes, rather than deleted.
+    pub downsample_minutes: Option<i32>,
+}
+
+impl RetentionPolicy {
+    /// The policy applying to a kit: the kit's own policy if it has one, otherwise this
+    /// instance-wide policy.
+    fn for_kit(self, kit_policy: Option<&KitRetentionPolicy>) -> Self {
+        match kit_policy {
+            Some(kit_policy) => Self {
+                raw_measurement_days: kit_policy.raw_measurement_days,
+                downsample_minutes: kit_policy.downsample_minutes,
+            },
+            None => self,
+        }
+    }
+
+    /// The time before which raw measurements are past their retention period at `now`, if they
+    /// are not retained indefinitely.
+    fn cutoff(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.raw_measurement_days
+            .map(|days| now - chrono::Duration::days(days.into()))
+    }
+}
+
+/// A kit whose raw measurements before `cutoff` are past their retention period.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Expiry {
+    kit_id: i32,
+    cutoff: DateTime<Utc>,
+    downsample_minutes: Option<i32>,
+}
+
+/// Resolve the policies of the kits, given with their own policies, at `now`. Kits whose raw
+/// measurements are retained indefinitely are omitted.
+fn expiries(
+    instance_policy: RetentionPolicy,
+    kits: &[(i32, Option<KitRetentionPolicy>)],
+    now: DateTime<Utc>,
+) -> Vec<Expiry> {
+    kits.iter()
+        .filter_map(|(kit_id, kit_policy)| {
+            let policy = instance_policy.for_kit(kit_policy.as_ref());
+            Some(Expiry {
+                kit_id: *kit_id,
+                cutoff: policy.cutoff(now)?,
+                downsample_minutes: policy.downsample_minutes,
+            })
+        })
+        .collect()
+}
+
+/// The raw measurements of a kit reclaimed (or reclaimable, in a dry run) by applying the
+/// retention policies.
+#[derive(Debug, QueryableByName)]
+pub struct Reclaimed {
+    #[diesel(sql_type = Text)]
+    pub kit_serial: String,
+    #[diesel(sql_type = BigInt)]
+    pub rows: i64,
+    /// The size of the rows' data. This excludes indices and storage overhead. Deleted rows'
+    /// storage is reclaimed by the database's vacuuming.
+    #[diesel(sql_type = BigInt)]
+    pub bytes: i64,
+}
+
+/// Selects the raw measurements to delete as `reclaimable (id, kit_id, size)`, given the kits'
+/// [expiries](Expiry) as arrays of kit IDs, cutoffs and downsampling windows.
+///
+/// A raw measurement is covered by aggregates if the latest aggregate of its peripheral and
+/// quantity type starting at or before it also ends after it. To bound the per-measurement index
+/// lookup to at most two monthly partitions, only aggregates starting within a day before the raw
+/// measurement are considered.
+///
+/// When downsampling, the earliest raw measurement of each window is retained. As retained raw
+/// measurements remain the earliest of their window, applying the policy again is a no-op.
+const RECLAIMABLE: &str = "
+    WITH policies AS (
+        SELECT *
+        FROM unnest($1::int4[], $2::timestamptz[], $3::int4[])
+            AS policy (kit_id, cutoff, downsample_minutes)
+    ),
+    expired AS (
+        SELECT
+            raw.id,
+            raw.datetime,
+            raw.kit_id,
+            pg_column_size(raw.*) AS size,
+            policies.downsample_minutes,
+            row_number() OVER (
+                PARTITION BY
+                    raw.peripheral_id,
+                    raw.quantity_type_id,
+                    floor(extract(epoch FROM raw.datetime) / (coalesce(policies.downsample_minutes, 1) * 60))
+                ORDER BY raw.datetime, raw.id
+            ) AS rank
+        FROM raw_measurements raw
+        JOIN policies ON (policies.kit_id = raw.kit_id)
+        WHERE raw.datetime < policies.cutoff
+          AND EXISTS (
+              SELECT 1
+              FROM (
+                  SELECT aggregate.datetime_end
+                  FROM aggregate_measurements aggregate
+                  WHERE aggregate.peripheral_id = raw.peripheral_id
+                    AND aggregate.quantity_type_id = raw.quantity_type_id
+                    AND aggregate.datetime_start <= raw.datetime
+                    AND aggregate.datetime_start > raw.datetime - interval '1 day'
+                  ORDER BY aggregate.datetime_start DESC
+                  LIMIT 1
+              ) latest
+              WHERE latest.datetime_end > raw.datetime
+          )
+    ),
+    reclaimable AS (
+        SELECT id, datetime, kit_id, size
+        FROM expired
+        WHERE downsample_minutes IS NULL OR rank > 1
+    )
+";
+
+const REPORT: &str = "
+    SELECT kits.serial AS kit_serial, count(*) AS rows, coalesce(sum(size), 0)::int8 AS bytes
+    FROM reclaimed
+    JOIN kits ON (kits.id = reclaimed.kit_id)
+    GROUP BY kits.serial
+    ORDER BY kits.serial
+";
+
+/// Apply the retention policies to raw measurements older than their retention period at `now`.
+/// In a dry run, nothing is deleted. Returns the (reclaimable) raw measurements per kit.
+pub fn apply(
+    conn: &mut PgConnection,
+    instance_policy: RetentionPolicy,
+    now: chrono::DateTime<chrono::Utc>,
+    dry_run: bool,
+) -> anyhow::Result<Vec<Reclaimed>> {
+    let kits: Vec<(i32, Option<KitRetentionPolicy>)> = kits::table
+        .left_join(kit_retention_policies::table)
+        .select((kits::id, kit_retention_policies::all_columns.nullable()))
+        .load(conn)?;
+    let expiries = expiries(instance_policy, &kits, now);
+
+    let query = if dry_run {
+        format!(
+            "{}, reclaimed AS (SELECT * FROM reclaimable) {}",
+            RECLAIMABLE, REPORT
+        )
+    } else {
+        format!(
+            "{}, reclaimed AS (
+                DELETE FROM raw_measurements
+                USING reclaimable
+                WHERE raw_measurements.id = reclaimable.id
+                  AND raw_measurements.datetime = reclaimable.datetime
+                RETURNING reclaimable.kit_id, reclaimable.size
+            ) {}",
+            RECLAIMABLE, REPORT
+        )
+    };
+
+    let reclaimed = diesel::sql_query(query)
+        .bind::<Array<Integer>, _>(
+            expiries
+                .iter()
+                .map(|expiry| expiry.kit_id)
+                .collect::<Vec<_>>(),
+        )
+        .bind::<Array<Timestamptz>, _>(
+            expiries
+                .iter()
+                .map(|expiry| expiry.cutoff)
+                .collect::<Vec<_>>(),
+        )
+        .bind::<Array<Nullable<Integer>>, _>(
+            expiries
+                .iter()
+                .map(|expiry| expiry.downsample_minutes)
+                .collect::<Vec<_>>(),
+        )
+        .load(conn)?;
+
+    Ok(reclaimed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn kit_policies_override_the_instance_policy() {
+        let now = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap();
+        let instance_policy = RetentionPolicy {
+            raw_measurement_days: Some(30),
+            downsample_minutes: Some(10),
+        };
+        let kits = vec![
+            // Without a policy of its own.
+            (1, None),
+            // Retained indefinitely.
+            (
+                2,
+                Some(KitRetentionPolicy {
+                    kit_id: 2,
+                    raw_measurement_days: None,
+                    downsample_minutes: Some(60),
+                }),
+            ),
+            // Deleted rather than downsampled.
+            (
+                3,
+                Some(KitRetentionPolicy {
+                    kit_id: 3,
+                    raw_measurement_days: Some(7),
+                    downsample_minutes: None,
+                }),
+            ),
+        ];
+
+        assert_eq!(
+            expiries(instance_policy, &kits, now),
+            vec![
+                Expiry {
+                    kit_id: 1,
+                    cutoff: Utc.with_ymd_and_hms(2026, 1, 30, 12, 0, 0).unwrap(),
+                    downsample_minutes: Some(10),
+                },
+                Expiry {
+                    kit_id: 3,
+                    cutoff: Utc.with_ymd_and_hms(2026, 2, 22, 12, 0, 0).unwrap(),
+                    downsample_minutes: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn retained_indefinitely_by_default() {
+        let now = Utc::now();
+        assert_eq!(RetentionPolicy::default().cutoff(now), None);
+        assert!(expiries(RetentionPolicy::default(), &[(1, None)], now).is_empty());
+    }
+}
@@ -0,0 +1,540 @@
+//! Evaluation of alert rules on the raw measurement stream.
+//!
+//! Rules are kept in memory and reloaded from the database periodically, so changes to rules take
+//! effect within [RELOAD_INTERVAL]. Transitions of rules into (firing) and out of (resolved) the
+//! alerting state are stored as [KitAlerts](crate::models::KitAlert).
+
+use crate::database::PgPool;
+use crate::{models, problem};
+
+use chrono::{DateTime, Duration, Utc};
+use futures::channel::mpsc;
+use futures::stream::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+
+/// The interval at which rules are reloaded from the database.
+pub const RELOAD_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// The interval at which absence rules are checked.
+const ABSENCE_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Comparison {
+    Above,
+    Below,
+}
+
+impl Comparison {
+    fn holds(self, value: f64, threshold: f64) -> bool {
+        match self {
+            Comparison::Above => value > threshold,
+            Comparison::Below => value < threshold,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Comparison::Above => "above",
+            Comparison::Below => "below",
+        }
+    }
+}
+
+/// The condition of an alert rule. The rule fires when the condition holds for at least the
+/// rule's duration.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum Condition {
+    /// The measured value compares to the threshold.
+    Threshold {
+        comparison: Comparison,
+        threshold: f64,
+    },
+    /// The change per minute between consecutive measurements compares to the threshold.
+    RateOfChange {
+        comparison: Comparison,
+        threshold: f64,
+    },
+    /// No measurement was received. This requires a positive duration.
+    Absence,
+}
+
+impl Condition {
+    /// The condition as stored in the database: its kind, comparison and threshold.
+    pub fn to_columns(self) -> (String, Option<String>, Option<f64>) {
+        match self {
+            Condition::Threshold {
+                comparison,
+                threshold,
+            } => (
+                "threshold".to_owned(),
+                Some(comparison.as_str().to_owned()),
+                Some(threshold),
+            ),
+            Condition::RateOfChange {
+                comparison,
+                threshold,
+            } => (
+                "rate_of_change".to_owned(),
+                Some(comparison.as_str().to_owned()),
+                Some(threshold),
+            ),
+            Condition::Absence => ("absence".to_owned(), None, None),
+        }
+    }
+}
+
+impl TryFrom<&models::KitAlertRule> for Condition {
+    type Error = problem::Problem;
+
+    fn try_from(rule: &models::KitAlertRule) -> Result<Self, Self::Error> {
+        let comparison = match rule.comparison.as_deref() {
+            Some("above") => Some(Comparison::Above),
+            Some("below") => Some(Comparison::Below),
+            None => None,
+            Some(_) => return Err(problem::INTERNAL_SERVER_ERROR),
+        };
+
+        match (rule.kind.as_str(), comparison, rule.threshold) {
+            ("threshold", Some(comparison), Some(threshold)) => Ok(Condition::Threshold {
+                comparison,
+                threshold,
+            }),
+            ("rate_of_change", Some(comparison), Some(threshold)) => Ok(Condition::RateOfChange {
+                comparison,
+                threshold,
+            }),
+            ("absence", None, None) => Ok(Condition::Absence),
+            _ => Err(problem::INTERNAL_SERVER_ERROR),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule {
+    pub id: i32,
+    pub kit_id: i32,
+    pub kit_serial: String,
+    pub peripheral: i32,
+    pub quantity_type: i32,
+    pub condition: Condition,
+    pub duration: Duration,
+}
+
+impl Rule {
+    pub fn from_model(
+        rule: &models::KitAlertRule,
+        kit_serial: String,
+    ) -> Result<Self, problem::Problem> {
+        Ok(Self {
+            id: rule.id,
+            kit_id: rule.kit_id,
+            kit_serial,
+            peripheral: rule.peripheral_id,
+            quantity_type: rule.quantity_type_id,
+            condition: Condition::try_from(rule)?,
+            duration: Duration::seconds(rule.duration_seconds.into()),
+        })
+    }
+}
+
+/// A transition of a rule into or out of the alerting state.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Transition {
+    pub rule_id: i32,
+    pub kit_id: i32,
+    pub datetime: DateTime<Utc>,
+    pub firing: bool,
+    pub value: Option<f64>,
+}
+
+#[derive(Debug)]
+struct RuleState {
+    rule: Rule,
+    firing: bool,
+    /// Since when the condition holds, for threshold and rate of change rules.
+    holds_since: Option<DateTime<Utc>>,
+    /// The previous measurement, for rate of change rules.
+    previous: Option<(DateTime<Utc>, f64)>,
+    /// When the last measurement was received, for absence rules.
+    last_received: DateTime<Utc>,
+}
+
+impl RuleState {
+    fn transition(
+        &mut self,
+        datetime: DateTime<Utc>,
+        firing: bool,
+        value: Option<f64>,
+    ) -> Transition {
+        self.firing = firing;
+        Transition {
+            rule_id: self.rule.id,
+            kit_id: self.rule.kit_id,
+            datetime,
+            firing,
+            value,
+        }
+    }
+
+    fn measurement(
+        &mut self,
+        measurement: &astroplant_mqtt::RawMeasurement,
+        received: DateTime<Utc>,
+    ) -> Option<Transition> {
+        self.last_received = received;
+
+        let (comparison, threshold, value) = match self.rule.condition {
+            Condition::Absence => {
+                return if self.firing {
+                    Some(self.transition(received, false, Some(measurement.value)))
+                } else {
+                    None
+                };
+            }
+            Condition::Threshold {
+                comparison,
+                threshold,
+            } => (comparison, threshold, measurement.value),
+            Condition::RateOfChange {
+                comparison,
+                threshold,
+            } => {
+                let previous = self
+                    .previous
+                    .replace((measurement.datetime, measurement.value));
+                let (previous_datetime, previous_value) = previous?;
+                let minutes =
+                    (measurement.datetime - previous_datetime).num_milliseconds() as f64 / 60_000.0;
+                if minutes <= 0.0 {
+                    return None;
+                }
+                (
+                    comparison,
+                    threshold,
+                    (measurement.value - previous_value) / minutes,
+                )
+            }
+        };
+
+        if comparison.holds(value, threshold) {
+            let holds_since = *self.holds_since.get_or_insert(measurement.datetime);
+            if !self.firing && measurement.datetime - holds_since >= self.rule.duration {
+                return Some(self.transition(measurement.datetime, true, Some(value)));
+            }
+        } else {
+            self.holds_since = None;
+            if self.firing {
+                return Some(self.transition(measurement.datetime, false, Some(value)));
+            }
+        }
+
+        None
+    }
+
+    fn tick(&mut self, now: DateTime<Utc>) -> Option<Transition> {
+        if self.rule.condition == Condition::Absence
+            && !self.firing
+            && now - self.last_received >= self.rule.duration
+        {
+            Some(self.transition(now, true, None))
+        } else {
+            None
+        }
+    }
+}
+
+/// Evaluates alert rules on raw measurements. This does not perform IO: the caller provides the
+/// rules and records the transitions.
+#[derive(Debug, Default)]
+pub struct Evaluator {
+    states: HashMap<i32, RuleState>,
+    /// The ids of the rules on each kit, peripheral and quantity type.
+    index: HashMap<(String, i32, i32), Vec<i32>>,
+}
+
+impl Evaluator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the rules. The state of rules that did not change is retained. New and changed
+    /// rules are firing if their id is in `firing`, and absence rules consider a measurement to
+    /// have been received at `now`.
+    pub fn set_rules(&mut self, rules: Vec<Rule>, firing: &HashSet<i32>, now: DateTime<Utc>) {
+        let mut states = std::mem::take(&mut self.states);
+        self.index.clear();
+
+        for rule in rules {
+            self.index
+                .entry((rule.kit_serial.clone(), rule.peripheral, rule.quantity_type))
+                .or_default()
+                .push(rule.id);
+
+            let state = match states.remove(&rule.id) {
+                Some(state) if state.rule == rule => state,
+                _ => RuleState {
+                    firing: firing.contains(&rule.id),
+                    holds_since: None,
+                    previous: None,
+                    last_received: now,
+                    rule,
+                },
+            };
+            self.states.insert(state.rule.id, state);
+        }
+    }
+
+    /// Evaluate the rules on the kit's peripheral and quantity type on a measurement received at
+    /// `received`.
+    pub fn measurement(
+        &mut self,
+        measurement: &astroplant_mqtt::RawMeasurement,
+        received: DateTime<Utc>,
+    ) -> Vec<Transition> {
+        let Self { states, index } = self;
+        let rule_ids = match index.get(&(
+            measurement.kit_serial.clone(),
+            measurement.peripheral,
+            measurement.quantity_type,
+        )) {
+            Some(rule_ids) => rule_ids,
+            None => return vec![],
+        };
+
+        rule_ids
+            .iter()
+            .filter_map(|rule_id| {
+                states
+                    .get_mut(rule_id)
+                    .and_then(|state| state.measurement(measurement, received))
+            })
+            .collect()
+    }
+
+    /// Evaluate the absence rules at `now`.
+    pub fn tick(&mut self, now: DateTime<Utc>) -> Vec<Transition> {
+        self.states
+            .values_mut()
+            .filter_map(|state| state.tick(now))
+            .collect()
+    }
+}
+
+async fn load_rules(pg_pool: PgPool) -> Result<(Vec<Rule>, HashSet<i32>), problem::Problem> {
+    let conn = pg_pool.get().await?;
+    conn.interact_flatten_err(move |conn| {
+        let rules = models::KitAlertRule::all_with_kit_serials(conn)?
+            .into_iter()
+            .filter_map(
+                |(rule, kit_serial)| match Rule::from_model(&rule, kit_serial) {
+                    Ok(rule) => Some(rule),
+                    Err(_) => {
+                        tracing::warn!("alert rule with id {} is invalid", rule.id);
+                        None
+                    }
+                },
+            )
+            .collect();
+        let firing = models::KitAlert::firing_rule_ids(conn)?
+            .into_iter()
+            .collect();
+        Ok::<_, problem::Problem>((rules, firing))
+    })
+    .await
+}
+
+async fn record_transitions(pg_pool: PgPool, transitions: Vec<Transition>) {
+    let implementation = move || async move {
+        let conn = pg_pool.get().await?;
+        conn.interact_flatten_err(move |conn| {
+            for transition in transitions {
+                tracing::debug!(
+                    "Alert rule {} of kit {} is {}",
+                    transition.rule_id,
+                    transition.kit_id,
+                    if transition.firing {
+                        "firing"
+                    } else {
+                        "resolved"
+                    },
+                );
+
+                models::NewKitAlert::new(
+                    models::KitId(transition.kit_id),
+                    models::KitAlertRuleId(transition.rule_id),
+                    transition.datetime,
+                    transition.firing,
+                    transition.value,
+                )
+                .create(conn)?;
+            }
+
+            Ok::<_, problem::Problem>(())
+        })
+        .await
+    };
+
+    if implementation().await.is_err() {
+        tracing::warn!("encountered a problem when recording alert transitions");
+    }
+}
+
+/// Evaluate the alert rules on the raw measurements sent to the returned sender.
+///
+/// Must be called from within a Tokio runtime.
+pub fn run(pg_pool: PgPool) -> mpsc::Sender<astroplant_mqtt::RawMeasurement> {
+    let (raw_measurement_sender, mut raw_measurement_receiver) = mpsc::channel(32);
+
+    tokio::spawn(async move {
+        let mut evaluator = Evaluator::new();
+
+        let mut reload_interval = tokio::time::interval(RELOAD_INTERVAL);
+        reload_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let mut absence_check_interval = tokio::time::interval(ABSENCE_CHECK_INTERVAL);
+        absence_check_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            let transitions = tokio::select! {
+                measurement = raw_measurement_receiver.next() => {
+                    let measurement = match measurement {
+                        Some(measurement) => measurement,
+                        None => break,
+                    };
+                    evaluator.measurement(&measurement, Utc::now())
+                }
+                _ = reload_interval.tick() => {
+                    match load_rules(pg_pool.clone()).await {
+                        Ok((rules, firing)) => evaluator.set_rules(rules, &firing, Utc::now()),
+                        Err(_) => tracing::warn!("encountered a problem when loading alert rules"),
+                    }
+                    vec![]
+                }
+                _ = absence_check_interval.tick() => evaluator.tick(Utc::now()),
+            };
+
+            if !transitions.is_empty() {
+                tokio::spawn(record_transitions(pg_pool.clone(), transitions));
+            }
+        }
+    });
+
+    raw_measurement_sender
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn rule(id: i32, condition: Condition, duration_minutes: i64) -> Rule {
+        Rule {
+            id,
+            kit_id: 1,
+            kit_serial: "k-1".to_owned(),
+            peripheral: 2,
+            quantity_type: 3,
+            condition,
+            duration: Duration::minutes(duration_minutes),
+        }
+    }
+
+    fn measurement(minute: i64, value: f64) -> astroplant_mqtt::RawMeasurement {
+        astroplant_mqtt::RawMeasurement {
+            id: uuid::Uuid::nil(),
+            kit_serial: "k-1".to_owned(),
+            datetime: at(minute),
+            peripheral: 2,
+            quantity_type: 3,
+            value,
+        }
+    }
+
+    fn at(minute: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap() + Duration::minutes(minute)
+    }
+
+    /// Whether the single rule under test started firing (`Some(true)`) or resolved
+    /// (`Some(false)`).
+    fn firing(transitions: Vec<Transition>) -> Option<bool> {
+        assert!(transitions.len() <= 1);
+        transitions.first().map(|transition| transition.firing)
+    }
+
+    #[test]
+    fn threshold_fires_after_duration_and_resolves() {
+        let mut evaluator = Evaluator::new();
+        let condition = Condition::Threshold {
+            comparison: Comparison::Above,
+            threshold: 35.0,
+        };
+        evaluator.set_rules(vec![rule(1, condition, 10)], &HashSet::new(), at(0));
+
+        let mut observe =
+            |minute, value| firing(evaluator.measurement(&measurement(minute, value), at(minute)));
+        assert_eq!(observe(0, 36.0), None);
+        assert_eq!(observe(5, 37.0), None);
+        assert_eq!(observe(10, 36.0), Some(true));
+        assert_eq!(observe(11, 36.0), None);
+        assert_eq!(observe(12, 30.0), Some(false));
+        assert_eq!(observe(13, 36.0), None);
+    }
+
+    #[test]
+    fn rate_of_change_is_per_minute() {
+        let mut evaluator = Evaluator::new();
+        let condition = Condition::RateOfChange {
+            comparison: Comparison::Below,
+            threshold: -1.0,
+        };
+        evaluator.set_rules(vec![rule(1, condition, 0)], &HashSet::new(), at(0));
+
+        let mut observe =
+            |minute, value| firing(evaluator.measurement(&measurement(minute, value), at(minute)));
+        assert_eq!(observe(0, 20.0), None);
+        assert_eq!(observe(2, 19.0), None);
+        assert_eq!(observe(3, 17.0), Some(true));
+        assert_eq!(observe(4, 17.0), Some(false));
+    }
+
+    #[test]
+    fn absence_fires_on_tick_and_resolves_on_measurement() {
+        let mut evaluator = Evaluator::new();
+        evaluator.set_rules(
+            vec![rule(1, Condition::Absence, 10)],
+            &HashSet::new(),
+            at(0),
+        );
+
+        assert_eq!(firing(evaluator.tick(at(9))), None);
+        assert_eq!(firing(evaluator.tick(at(10))), Some(true));
+        assert_eq!(firing(evaluator.tick(at(11))), None);
+        assert_eq!(
+            firing(evaluator.measurement(&measurement(12, 1.0), at(12))),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn reloading_retains_state_of_unchanged_rules() {
+        let mut evaluator = Evaluator::new();
+        let condition = Condition::Threshold {
+            comparison: Comparison::Above,
+            threshold: 35.0,
+        };
+        evaluator.set_rules(vec![rule(1, condition, 0)], &HashSet::new(), at(0));
+        assert_eq!(
+            firing(evaluator.measurement(&measurement(0, 36.0), at(0))),
+            Some(true)
+        );
+
+        evaluator.set_rules(vec![rule(1, condition, 0)], &HashSet::new(), at(1));
+        assert_eq!(
+            firing(evaluator.measurement(&measurement(1, 36.0), at(1))),
+            None
+        );
+    }
+}
@@ -39,17 +39,21 @@ pub trait Permission {
 pub enum KitAction {
     View,
     SubscribeRealTimeMeasurements,
+    ViewDroppedMessages,
     Delete,
     DeleteMedia,
     ResetPassword,
     EditDetails,
     EditConfiguration,
+    EditAlertRules,
+    EditWebhooks,
     EditMembers,
     EditSuperMembers,
     RpcVersion,
     RpcUptime,
     RpcPeripheralCommand,
     RpcPeripheralCommandLock,
+    ViewPreview,
 }
 
 pub enum KitUser {
@@ -71,11 +75,13 @@ impl Permission for KitAction {
                 _ => false,
             },
             UserWithMembership(_user, membership) => match self {
-                View | SubscribeRealTimeMeasurements => true,
-                EditDetails | EditConfiguration | DeleteMedia => {
+                View | SubscribeRealTimeMeasurements | ViewDroppedMessages => true,
+                EditDetails | EditConfiguration | EditAlertRules | DeleteMedia | ViewPreview => {
                     membership.access_configure || membership.access_super
                 }
-                Delete | ResetPassword | EditMembers | EditSuperMembers => membership.access_super,
+                Delete | ResetPassword | EditWebhooks | EditMembers | EditSuperMembers => {
+                    membership.access_super
+                }
                 RpcVersion | RpcUptime | RpcPeripheralCommand | RpcPeripheralCommandLock => {
                     membership.access_super
                 }
@@ -2,6 +2,7 @@ use clap::Parser;
 
 use astroplant_api::admin::{
     self, insert_astroplant_definitions::ExistingPeripheralDefinitionStrategy, migrate,
+    partitioning, retention,
 };
 
 /// AstroPlant backend administration tools.
@@ -13,6 +14,25 @@ enum Command {
     /// Insert the AstroPlant-specific data definitions into the database. This includes quantity
     /// types and peripheral definitions.
     InsertAstroplantDefinitions(InsertAstroplantDefinitionsOpts),
+    /// Hash a password for use in the MQTT server accounts (`MQTT_SERVER_ACCOUNTS`). The password
+    /// is read from standard input
+    HashMqttPassword,
+    /// Apply the retention policies of raw measurements. Raw measurements older than the retention
+    /// period that are covered by aggregate measurements are deleted or downsampled. This is meant
+    /// to be run periodically.
+    ApplyRetention(ApplyRetentionOpts),
+    /// Set a kit's retention policy of raw measurements, overriding the instance-wide policy.
+    SetKitRetention(SetKitRetentionOpts),
+    /// Create the monthly partitions of the measurement tables for the current month and the months
+    /// ahead. This is meant to be run periodically.
+    CreateMeasurementPartitions(CreateMeasurementPartitionsOpts),
+    /// Copy the measurements stored before the measurement tables were partitioned into the
+    /// partitioned tables, newest first. Run this right after migrating; it can be resumed if
+    /// interrupted.
+    BackfillMeasurements(BackfillMeasurementsOpts),
+    /// Compare the durations of a kit's aggregate measurement queries on the unpartitioned and the
+    /// partitioned tables. Run this before dropping the unpartitioned tables.
+    BenchmarkMeasurementQueries(BenchmarkMeasurementQueriesOpts),
 }
 
 #[derive(Parser, Debug)]
@@ -25,10 +45,78 @@ struct InsertAstroplantDefinitionsOpts {
     update_existing_peripheral_definitions: bool,
 }
 
+#[derive(Parser, Debug)]
+struct ApplyRetentionOpts {
+    /// The number of days raw measurements are retained, for kits without a retention policy of
+    /// their own. If not set, raw measurements of those kits are retained indefinitely.
+    #[clap(long, value_parser = clap::value_parser!(i32).range(1..))]
+    raw_measurement_days: Option<i32>,
+    /// Downsample raw measurements older than the retention period to one per window of this many
+    /// minutes, rather than deleting them, for kits without a retention policy of their own.
+    #[clap(long, value_parser = clap::value_parser!(i32).range(1..))]
+    downsample_minutes: Option<i32>,
+    /// Only report how many raw measurements would be reclaimed, without deleting anything.
+    #[clap(long)]
+    dry_run: bool,
+}
+
+#[derive(Parser, Debug)]
+struct SetKitRetentionOpts {
+    /// The serial of the kit.
+    kit_serial: String,
+    /// The number of days the kit's raw measurements are retained. If not set, the kit's raw
+    /// measurements are retained indefinitely.
+    #[clap(long, value_parser = clap::value_parser!(i32).range(1..))]
+    raw_measurement_days: Option<i32>,
+    /// Downsample the kit's raw measurements older than the retention period to one per window of
+    /// this many minutes, rather than deleting them.
+    #[clap(long, value_parser = clap::value_parser!(i32).range(1..))]
+    downsample_minutes: Option<i32>,
+    /// Remove the kit's retention policy, such that the instance-wide policy applies.
+    #[clap(long, conflicts_with_all = ["raw_measurement_days", "downsample_minutes"])]
+    reset: bool,
+}
+
+#[derive(Parser, Debug)]
+struct CreateMeasurementPartitionsOpts {
+    /// The number of months after the current month to create partitions for.
+    #[clap(long, default_value_t = 2, value_parser = clap::value_parser!(i32).range(0..))]
+    months_ahead: i32,
+}
+
+#[derive(Parser, Debug)]
+struct BackfillMeasurementsOpts {
+    /// The number of rows to copy per batch.
+    #[clap(long, default_value_t = 10_000, value_parser = clap::value_parser!(i64).range(1..))]
+    batch_size: i64,
+    /// Drop the unpartitioned tables after backfilling.
+    #[clap(long)]
+    drop_unpartitioned: bool,
+}
+
+#[derive(Parser, Debug)]
+struct BenchmarkMeasurementQueriesOpts {
+    /// The serial of the kit whose measurements to query.
+    kit_serial: String,
+    /// The number of times to run each query. The median duration is reported.
+    #[clap(long, default_value_t = 11)]
+    iterations: usize,
+}
+
 fn main() -> anyhow::Result<()> {
     astroplant_api::utils::tracing::init();
     let command = Command::parse();
 
+    if let Command::HashMqttPassword = command {
+        let mut password = String::new();
+        std::io::stdin().read_line(&mut password)?;
+        println!(
+            "{}",
+            astroplant_auth::hash::hash_kit_password(password.trim_end_matches(&['\r', '\n'][..]))
+        );
+        return Ok(());
+    }
+
     tracing::debug!("Connecting to database");
     let mut conn = astroplant_api::database::oneoff_connection()?;
     tracing::debug!("Connected to database");
@@ -51,6 +139,68 @@ fn main() -> anyhow::Result<()> {
                 existing_peripheral_definition_strategy,
             )?;
         }
+        Command::ApplyRetention(opts) => {
+            let instance_policy = retention::RetentionPolicy {
+                raw_measurement_days: opts.raw_measurement_days,
+                downsample_minutes: opts.downsample_minutes,
+            };
+            let reclaimed =
+                retention::apply(&mut conn, instance_policy, chrono::Utc::now(), opts.dry_run)?;
+
+            let verb = if opts.dry_run {
+                "Would reclaim"
+            } else {
+                "Reclaimed"
+            };
+            for kit in &reclaimed {
+                println!(
+                    "{}: {} raw measurements ({} bytes)",
+                    kit.kit_serial, kit.rows, kit.bytes
+                );
+            }
+            println!(
+                "{} {} raw measurements ({} bytes) of {} kits",
+                verb,
+                reclaimed.iter().map(|kit| kit.rows).sum::<i64>(),
+                reclaimed.iter().map(|kit| kit.bytes).sum::<i64>(),
+                reclaimed.len(),
+            );
+        }
+        Command::SetKitRetention(opts) => {
+            use astroplant_api::models::{Kit, KitRetentionPolicy};
+            use diesel::prelude::*;
+
+            let kit: Kit = Kit::by_serial(&opts.kit_serial).first(&mut conn)?;
+            if opts.reset {
+                KitRetentionPolicy::delete_of_kit(&mut conn, kit.get_id())?;
+            } else {
+                KitRetentionPolicy {
+                    kit_id: kit.id,
+                    raw_measurement_days: opts.raw_measurement_days,
+                    downsample_minutes: opts.downsample_minutes,
+                }
+                .upsert(&mut conn)?;
+            }
+        }
+        Command::CreateMeasurementPartitions(opts) => {
+            partitioning::create_partitions(&mut conn, chrono::Utc::now(), opts.months_ahead)?;
+        }
+        Command::BackfillMeasurements(opts) => {
+            partitioning::backfill(&mut conn, opts.batch_size, opts.drop_unpartitioned)?;
+        }
+        Command::BenchmarkMeasurementQueries(opts) => {
+            use astroplant_api::models::Kit;
+            use diesel::prelude::*;
+
+            let kit: Kit = Kit::by_serial(&opts.kit_serial).first(&mut conn)?;
+            for benchmark in partitioning::benchmark(&mut conn, kit.id, opts.iterations)? {
+                println!(
+                    "{}: {:?} unpartitioned, {:?} partitioned",
+                    benchmark.query, benchmark.unpartitioned, benchmark.partitioned
+                );
+            }
+        }
+        Command::HashMqttPassword => unreachable!(),
     }
 
     Ok(())
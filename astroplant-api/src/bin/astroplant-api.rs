@@ -1,6 +1,7 @@
 use axum::{
-    extract::ws::WebSocketUpgrade,
+    extract::{ws::WebSocketUpgrade, TypedHeader},
     handler::Handler,
+    headers::{authorization::Bearer, Authorization},
     http::Method,
     http::{header, Uri},
     response::IntoResponse,
@@ -11,14 +12,14 @@ use futures::StreamExt;
 use tower_http::cors::CorsLayer;
 
 use astroplant_api::{
-    authorization,
+    alerts, bridge,
     controllers::{
-        kit, kit_configuration, kit_rpc, me, measurement, media, peripheral_definition, permission,
-        quantity_type, user,
+        actuator_state, kit, kit_alert, kit_configuration, kit_event, kit_rpc, kit_webhook, me,
+        measurement, media, mqtt_auth, peripheral_definition, permission, quantity_type, user,
     },
-    database, helpers, init_token_signer, models, mqtt,
+    database, init_token_signer, mqtt,
     problem::{GenericProblem, Problem},
-    response, DEFAULT_S3_ENDPOINT, DEFAULT_S3_REGION,
+    response, webhooks, websocket, DEFAULT_S3_ENDPOINT, DEFAULT_S3_REGION,
 };
 
 #[tokio::main]
@@ -37,14 +38,55 @@ async fn main() -> anyhow::Result<()> {
         std::env::var("AWS_S3_ENDPOINT").unwrap_or(DEFAULT_S3_ENDPOINT.to_owned()),
     );
 
-    // Start MQTT.
-    let (mut raw_measurement_receiver, kits_rpc) = mqtt::run(pg.clone(), object_store.clone());
+    let webhook_destinations = webhooks::Destinations::from_env()?;
+    let webhooks = webhooks::Webhooks::new(pg.clone(), webhook_destinations);
 
     // Start WebSockets.
-    let (ws_publisher, ws_handler) = astroplant_websocket::create();
+    let (ws_publisher, ws_handler) = websocket::create(sqlx_pg.clone())?;
+
+    // Start MQTT.
+    let (mut raw_measurement_receiver, kits_rpc, mut mqtt_connection_state) = mqtt::run(
+        pg.clone(),
+        object_store.clone(),
+        webhooks.clone(),
+        ws_publisher.clone(),
+    )?;
+    let ws_handler = ws_handler.with_kits_rpc(kits_rpc.clone());
+
+    tokio::spawn(async move {
+        while mqtt_connection_state.changed().await.is_ok() {
+            let state = mqtt_connection_state.borrow().clone();
+            tracing::info!("MQTT connection state changed: {:?}", state);
+        }
+    });
+
+    // Start alert rule evaluation.
+    let mut alerts_sender = alerts::run(pg.clone());
+
+    // Start the MQTT bridge, if configured.
+    let mut bridge_sender =
+        bridge::Config::from_env().map(|config| bridge::run(pg.clone(), config));
 
+    // Alert rule evaluation and the bridge must not hold up publishing measurements to
+    // WebSockets, so measurements are dropped for them while they are behind.
     tokio::spawn(async move {
+        let mut alerts_dropped = 0;
+        let mut bridge_dropped = 0;
         while let Some(raw_measurement) = raw_measurement_receiver.next().await {
+            forward(
+                &mut alerts_sender,
+                &raw_measurement,
+                "alert rule evaluation",
+                &mut alerts_dropped,
+            );
+            if let Some(bridge_sender) = &mut bridge_sender {
+                forward(
+                    bridge_sender,
+                    &raw_measurement,
+                    "MQTT bridge",
+                    &mut bridge_dropped,
+                );
+            }
             ws_publisher.publish_raw_measurement(raw_measurement).await;
         }
     });
@@ -53,7 +95,10 @@ async fn main() -> anyhow::Result<()> {
     // let _rate_limit = rate_limit::leaky_bucket();
 
     let app = Router::new()
-        .route("/ws", get(websocket_handler).layer(Extension(ws_handler)))
+        .route(
+            "/ws",
+            get(websocket_handler).layer(Extension(ws_handler.clone())),
+        )
         .route(
             "/media/:media_id/content",
             get(media::download_media).layer(Extension(object_store)),
@@ -71,6 +116,22 @@ async fn main() -> anyhow::Result<()> {
             get(kit::get_member_suggestions),
         )
         .route("/kits/:kit_serial/password", post(kit::reset_password))
+        .route(
+            "/kits/:kit_serial/password/rotate",
+            post(kit::rotate_password).layer(Extension(kits_rpc.clone())),
+        )
+        .route(
+            "/kits/:kit_serial/dropped-messages",
+            get(kit::dropped_messages),
+        )
+        .route(
+            "/kits/:kit_serial/live",
+            get(kit::live).layer(Extension(ws_handler.clone())),
+        )
+        .route(
+            "/kits/:kit_serial/preview",
+            get(kit::preview).layer(Extension(ws_handler)),
+        )
         .route(
             "/kits/:kit_serial/configurations",
             get(kit_configuration::configurations_by_kit_serial),
@@ -83,9 +144,49 @@ async fn main() -> anyhow::Result<()> {
             "/kits/:kit_serial/aggregate-measurements",
             get(measurement::kit_aggregate_measurements),
         )
+        .route(
+            "/kits/:kit_serial/latest-measurements",
+            get(measurement::kit_latest_measurements),
+        )
+        .route(
+            "/kits/:kit_serial/actuator-states",
+            get(actuator_state::kit_actuator_states),
+        )
         .route("/kits/:kit_serial/media", get(media::kit_media))
+        .route("/kits/:kit_serial/events", get(kit_event::kit_events))
+        .route(
+            "/kits/:kit_serial/alert-rules",
+            get(kit_alert::kit_alert_rules),
+        )
+        .route(
+            "/kits/:kit_serial/alert-rules",
+            post(kit_alert::create_alert_rule),
+        )
+        .route("/kits/:kit_serial/alerts", get(kit_alert::kit_alerts))
+        .route(
+            "/alert-rules/:alert_rule_id",
+            delete(kit_alert::delete_alert_rule),
+        )
+        .route("/kits/:kit_serial/webhooks", get(kit_webhook::kit_webhooks))
+        .route(
+            "/kits/:kit_serial/webhooks",
+            post(kit_webhook::create_webhook).layer(Extension(webhook_destinations)),
+        )
+        .route("/webhooks/:webhook_id", delete(kit_webhook::delete_webhook))
+        .route(
+            "/webhooks/:webhook_id/deliveries",
+            get(kit_webhook::webhook_deliveries),
+        )
         .route("/kits/:kit_serial/archive", get(kit::archive))
         .route("/kits/:kit_serial/archive", post(kit::archive_authorize))
+        .route(
+            "/unclaimed-kits",
+            post(kit::register_unclaimed_kit).layer(Extension(kit::ProvisioningKey::from_env())),
+        )
+        .route(
+            "/kit-claims",
+            post(kit::claim_kit).layer(Extension(kit::ClaimCodeMaxAge::from_env()?)),
+        )
         .route(
             "/kit-memberships/:kit_membership_id",
             patch(kit::patch_kit_membership),
@@ -96,7 +197,7 @@ async fn main() -> anyhow::Result<()> {
         )
         .route(
             "/kit-configurations/:kit_configuration_id",
-            patch(kit_configuration::patch_configuration),
+            patch(kit_configuration::patch_configuration).layer(Extension(webhooks)),
         )
         .route(
             "/kit-configurations/:kit_configuration_id/peripherals",
@@ -117,6 +218,17 @@ async fn main() -> anyhow::Result<()> {
                 )
                 .layer(Extension(kits_rpc)),
         )
+        .nest(
+            "/mqtt-auth",
+            Router::new()
+                .route("/mosquitto/user", post(mqtt_auth::mosquitto_user))
+                .route("/mosquitto/superuser", post(mqtt_auth::mosquitto_superuser))
+                .route("/mosquitto/acl", post(mqtt_auth::mosquitto_acl))
+                .route("/emqx/authenticate", post(mqtt_auth::emqx_authenticate))
+                .route("/emqx/authorize", post(mqtt_auth::emqx_authorize))
+                .layer(Extension(mqtt_auth::ServerAccounts::from_env()))
+                .layer(Extension(mqtt_auth::MqttAuthKey::from_env())),
+        )
         .route(
             "/peripherals/:peripheral_id",
             patch(kit_configuration::patch_peripheral),
@@ -177,6 +289,31 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Send a raw measurement to a consumer without waiting. If the consumer's queue is full, the
+/// measurement is dropped and counted in `dropped`.
+fn forward(
+    sender: &mut futures::channel::mpsc::Sender<astroplant_mqtt::RawMeasurement>,
+    raw_measurement: &astroplant_mqtt::RawMeasurement,
+    consumer: &str,
+    dropped: &mut u64,
+) {
+    if let Err(err) = sender.try_send(raw_measurement.clone()) {
+        if err.is_disconnected() {
+            tracing::warn!("{} stopped", consumer);
+        } else {
+            *dropped += 1;
+            // Warn on the first drop and then at exponentially increasing intervals.
+            if dropped.is_power_of_two() {
+                tracing::warn!(
+                    "{} is behind, dropped {} raw measurements so far",
+                    consumer,
+                    dropped
+                );
+            }
+        }
+    }
+}
+
 /// 404 handler
 async fn fallback(_uri_: Uri) -> impl IntoResponse {
     Problem::Generic(GenericProblem::NotFound).into_response()
@@ -186,24 +323,19 @@ async fn websocket_handler(
     Extension(pg): Extension<database::PgPool>,
     Extension(ws_handle): Extension<astroplant_websocket::SocketHandler>,
     ws: WebSocketUpgrade,
-    user_id: Option<models::UserId>,
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
 ) -> impl IntoResponse {
-    ws.on_upgrade(move |ws| async move {
-        ws_handle
-            .handle(ws, move |kit_serial| {
-                let pg = pg.clone();
-                let user_id = user_id;
-                async move {
-                    helpers::fut_kit_permission_or_forbidden(
-                        pg,
-                        user_id,
-                        kit_serial,
-                        authorization::KitAction::SubscribeRealTimeMeasurements,
-                    )
-                    .await
-                    .is_ok()
-                }
-            })
-            .await;
-    })
+    use astroplant_websocket::auth::Auth;
+
+    // Connections may authenticate when upgrading, or later through the `authenticate` method.
+    let auth = websocket::Auth::new(pg);
+    let session = match bearer {
+        Some(TypedHeader(Authorization(bearer))) => auth.authenticate(bearer.token()).await.ok(),
+        None => None,
+    };
+
+    ws.protocols(astroplant_websocket::PROTOCOLS)
+        .on_upgrade(move |ws| async move {
+            ws_handle.handle(ws, auth, session).await;
+        })
 }
@@ -0,0 +1,476 @@
+//! Bridge of kits' raw measurements to plain JSON MQTT topics, with Home Assistant MQTT discovery.
+//!
+//! Only kits that opted in through their `privacy_bridge_measurements` flag are bridged. Bridged
+//! kits, their peripherals and the quantity types are reloaded from the database periodically, so
+//! changes take effect within [RELOAD_INTERVAL].
+//!
+//! Each measured quantity of a peripheral is a Home Assistant sensor. Its discovery configuration
+//! is published (retained) when its first measurement is bridged, and removed when the kit opts
+//! out or the peripheral is deleted.
+
+use crate::database::PgPool;
+use crate::{models, problem, views};
+
+use futures::channel::mpsc;
+use futures::stream::StreamExt;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// The interval at which bridged kits are reloaded from the database.
+pub const RELOAD_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+const DEFAULT_PORT: u16 = 1883;
+const DEFAULT_TOPIC_PREFIX: &str = "astroplant";
+const DEFAULT_DISCOVERY_PREFIX: &str = "homeassistant";
+
+/// The bridge configuration.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub host: String,
+    pub port: u16,
+    pub credentials: Option<(String, String)>,
+    /// Measurements are published on `{topic_prefix}/{kitSerial}/{peripheralId}/{quantityTypeId}`.
+    pub topic_prefix: String,
+    /// The Home Assistant discovery prefix.
+    pub discovery_prefix: String,
+}
+
+impl Config {
+    /// Read the bridge configuration from the environment. The bridge is enabled by setting
+    /// `MQTT_BRIDGE_HOST`.
+    pub fn from_env() -> Option<Self> {
+        let host = std::env::var("MQTT_BRIDGE_HOST").ok()?;
+
+        Some(Self {
+            host,
+            port: std::env::var("MQTT_BRIDGE_PORT")
+                .ok()
+                .and_then(|port| port.parse().ok())
+                .unwrap_or(DEFAULT_PORT),
+            credentials: std::env::var("MQTT_BRIDGE_USERNAME").ok().map(|username| {
+                (
+                    username,
+                    std::env::var("MQTT_BRIDGE_PASSWORD").unwrap_or_default(),
+                )
+            }),
+            topic_prefix: std::env::var("MQTT_BRIDGE_TOPIC_PREFIX")
+                .unwrap_or_else(|_| DEFAULT_TOPIC_PREFIX.to_owned()),
+            discovery_prefix: std::env::var("MQTT_BRIDGE_DISCOVERY_PREFIX")
+                .unwrap_or_else(|_| DEFAULT_DISCOVERY_PREFIX.to_owned()),
+        })
+    }
+}
+
+/// A measured quantity of a peripheral of a kit, which is a sensor in Home Assistant.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Entity {
+    kit_serial: String,
+    peripheral: i32,
+    quantity_type: i32,
+}
+
+/// A message to publish.
+#[derive(Debug)]
+struct Publication {
+    topic: String,
+    payload: Vec<u8>,
+    retain: bool,
+    /// Set if this publishes the discovery configuration of an entity.
+    announces: Option<Entity>,
+}
+
+struct BridgedKit {
+    name: String,
+    peripherals: HashMap<i32, views::Peripheral>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct State<'a> {
+    kit_serial: &'a str,
+    peripheral: &'a str,
+    physical_quantity: &'a str,
+    physical_unit: &'a str,
+    datetime: chrono::DateTime<chrono::Utc>,
+    value: f64,
+}
+
+#[derive(Serialize)]
+struct Device<'a> {
+    identifiers: [String; 1],
+    name: &'a str,
+    manufacturer: &'static str,
+    model: &'static str,
+}
+
+/// A Home Assistant MQTT sensor discovery configuration.
+#[derive(Serialize)]
+struct Discovery<'a> {
+    name: String,
+    unique_id: String,
+    state_topic: &'a str,
+    value_template: &'static str,
+    unit_of_measurement: &'a str,
+    state_class: &'static str,
+    device: Device<'a>,
+}
+
+struct Bridge {
+    topic_prefix: String,
+    discovery_prefix: String,
+    kits: HashMap<String, BridgedKit>,
+    quantity_types: HashMap<i32, views::QuantityType>,
+    /// The discovery configurations that have been published.
+    announced: HashMap<Entity, Vec<u8>>,
+}
+
+impl Bridge {
+    fn new(topic_prefix: String, discovery_prefix: String) -> Self {
+        Self {
+            topic_prefix,
+            discovery_prefix,
+            kits: HashMap::new(),
+            quantity_types: HashMap::new(),
+            announced: HashMap::new(),
+        }
+    }
+
+    fn state_topic(&self, entity: &Entity) -> String {
+        format!(
+            "{}/{}/{}/{}",
+            self.topic_prefix, entity.kit_serial, entity.peripheral, entity.quantity_type
+        )
+    }
+
+    fn discovery_topic(&self, entity: &Entity) -> String {
+        format!(
+            "{}/sensor/{}/{}_{}/config",
+            self.discovery_prefix, entity.kit_serial, entity.peripheral, entity.quantity_type
+        )
+    }
+
+    /// Replace the bridged kits and the quantity types. Returns the removals of the discovery
+    /// configurations of entities that are no longer bridged.
+    fn set_kits(
+        &mut self,
+        kits: HashMap<String, BridgedKit>,
+        quantity_types: HashMap<i32, views::QuantityType>,
+    ) -> Vec<Publication> {
+        self.kits = kits;
+        self.quantity_types = quantity_types;
+
+        let removed: Vec<Entity> = self
+            .announced
+            .keys()
+            .filter(|entity| {
+                let peripheral = self
+                    .kits
+                    .get(&entity.kit_serial)
+                    .and_then(|kit| kit.peripherals.get(&entity.peripheral));
+                peripheral.is_none() || !self.quantity_types.contains_key(&entity.quantity_type)
+            })
+            .cloned()
+            .collect();
+
+        removed
+            .into_iter()
+            .map(|entity| {
+                self.announced.remove(&entity);
+                Publication {
+                    topic: self.discovery_topic(&entity),
+                    payload: vec![],
+                    retain: true,
+                    announces: None,
+                }
+            })
+            .collect()
+    }
+
+    /// Bridge a measurement. If the measurement's entity has not been announced, or its discovery
+    /// configuration changed (e.g., the peripheral was renamed), its discovery configuration is
+    /// published first.
+    fn measurement(&self, measurement: &astroplant_mqtt::RawMeasurement) -> Vec<Publication> {
+        let kit = match self.kits.get(&measurement.kit_serial) {
+            Some(kit) => kit,
+            None => return vec![],
+        };
+        let (peripheral, quantity_type) = match (
+            kit.peripherals.get(&measurement.peripheral),
+            self.quantity_types.get(&measurement.quantity_type),
+        ) {
+            (Some(peripheral), Some(quantity_type)) => (peripheral, quantity_type),
+            _ => return vec![],
+        };
+
+        let entity = Entity {
+            kit_serial: measurement.kit_serial.clone(),
+            peripheral: measurement.peripheral,
+            quantity_type: measurement.quantity_type,
+        };
+        let state_topic = self.state_topic(&entity);
+        let mut publications = vec![];
+
+        let discovery = serde_json::to_vec(&Discovery {
+            name: format!("{} {}", peripheral.name, quantity_type.physical_quantity),
+            unique_id: format!(
+                "astroplant_{}_{}_{}",
+                entity.kit_serial, entity.peripheral, entity.quantity_type
+            ),
+            state_topic: &state_topic,
+            value_template: "{{ value_json.value }}",
+            unit_of_measurement: quantity_type
+                .physical_unit_symbol
+                .as_deref()
+                .unwrap_or(&quantity_type.physical_unit),
+            state_class: "measurement",
+            device: Device {
+                identifiers: [format!("astroplant_{}", entity.kit_serial)],
+                name: &kit.name,
+                manufacturer: "AstroPlant",
+                model: "AstroPlant kit",
+            },
+        })
+        .expect("discovery configurations serialize");
+        if self.announced.get(&entity) != Some(&discovery) {
+            publications.push(Publication {
+                topic: self.discovery_topic(&entity),
+                payload: discovery,
+                retain: true,
+                announces: Some(entity),
+            });
+        }
+
+        let state = serde_json::to_vec(&State {
+            kit_serial: &measurement.kit_serial,
+            peripheral: &peripheral.name,
+            physical_quantity: &quantity_type.physical_quantity,
+            physical_unit: &quantity_type.physical_unit,
+            datetime: measurement.datetime,
+            value: measurement.value,
+        })
+        .expect("states serialize");
+        publications.push(Publication {
+            topic: state_topic,
+            payload: state,
+            retain: false,
+            announces: None,
+        });
+
+        publications
+    }
+}
+
+async fn load_kits(
+    pg_pool: PgPool,
+) -> Result<
+    (
+        HashMap<String, BridgedKit>,
+        HashMap<i32, views::QuantityType>,
+    ),
+    problem::Problem,
+> {
+    let conn = pg_pool.get().await?;
+    conn.interact_flatten_err(move |conn| {
+        use diesel::prelude::*;
+
+        let mut kits = HashMap::new();
+        for kit in models::Kit::all()
+            .filter(models::Kit::bridge_measurements())
+            .load::<models::Kit>(conn)?
+        {
+            let peripherals = models::Peripheral::peripherals_of_kit_id(conn, kit.get_id())?
+                .into_iter()
+                .map(|peripheral| (peripheral.id, views::Peripheral::from(peripheral)))
+                .collect();
+            kits.insert(
+                kit.serial.clone(),
+                BridgedKit {
+                    name: kit.name.unwrap_or(kit.serial),
+                    peripherals,
+                },
+            );
+        }
+
+        let quantity_types = models::QuantityType::all(conn)?
+            .into_iter()
+            .map(|quantity_type| (quantity_type.id, views::QuantityType::from(quantity_type)))
+            .collect();
+
+        Ok::<_, problem::Problem>((kits, quantity_types))
+    })
+    .await
+}
+
+/// Start the bridge. Returns a sender to which raw measurements are to be sent.
+///
+/// Must be called from within a Tokio runtime.
+pub fn run(pg_pool: PgPool, config: Config) -> mpsc::Sender<astroplant_mqtt::RawMeasurement> {
+    let (raw_measurement_sender, mut raw_measurement_receiver) = mpsc::channel(32);
+
+    let Config {
+        host,
+        port,
+        credentials,
+        topic_prefix,
+        discovery_prefix,
+    } = config;
+
+    let mut builder = astroplant_mqtt::ConnectionBuilder::new(host, port)
+        .with_client_id(crate::mqtt::client_id("astroplant-api-bridge"));
+    if let Some((username, password)) = credentials {
+        builder = builder.with_credentials(username, password);
+    }
+    let publisher = builder.create_publisher();
+
+    tokio::spawn(async move {
+        let mut bridge = Bridge::new(topic_prefix, discovery_prefix);
+
+        let mut reload_interval = tokio::time::interval(RELOAD_INTERVAL);
+        reload_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            let publications = tokio::select! {
+                measurement = raw_measurement_receiver.next() => {
+                    let measurement = match measurement {
+                        Some(measurement) => measurement,
+                        None => break,
+                    };
+                    bridge.measurement(&measurement)
+                }
+                _ = reload_interval.tick() => {
+                    match load_kits(pg_pool.clone()).await {
+                        Ok((kits, quantity_types)) => bridge.set_kits(kits, quantity_types),
+                        Err(_) => {
+                            tracing::warn!("encountered a problem when loading bridged kits");
+                            vec![]
+                        }
+                    }
+                }
+            };
+
+            for publication in publications {
+                match publisher.publish(
+                    publication.topic,
+                    publication.payload.clone(),
+                    publication.retain,
+                ) {
+                    Ok(()) => {
+                        if let Some(entity) = publication.announces {
+                            bridge.announced.insert(entity, publication.payload);
+                        }
+                    }
+                    Err(_) => tracing::trace!("MQTT bridge dropped a message"),
+                }
+            }
+        }
+    });
+
+    raw_measurement_sender
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn bridge(peripheral_name: &str) -> Bridge {
+        let mut bridge = Bridge::new("astroplant".to_owned(), "homeassistant".to_owned());
+        let removals = bridge.set_kits(kits(peripheral_name), quantity_types());
+        assert!(removals.is_empty());
+        bridge
+    }
+
+    fn kits(peripheral_name: &str) -> HashMap<String, BridgedKit> {
+        let peripheral = views::Peripheral {
+            id: 2,
+            kit_id: 1,
+            kit_configuration_id: 1,
+            peripheral_definition_id: 1,
+            name: peripheral_name.to_owned(),
+            configuration: serde_json::Value::Null,
+        };
+        let kit = BridgedKit {
+            name: "Greenhouse".to_owned(),
+            peripherals: vec![(2, peripheral)].into_iter().collect(),
+        };
+        vec![("k-1".to_owned(), kit)].into_iter().collect()
+    }
+
+    fn quantity_types() -> HashMap<i32, views::QuantityType> {
+        let quantity_type = views::QuantityType {
+            id: 3,
+            physical_quantity: "Temperature".to_owned(),
+            physical_unit: "Degrees Celsius".to_owned(),
+            physical_unit_symbol: Some("°C".to_owned()),
+        };
+        vec![(3, quantity_type)].into_iter().collect()
+    }
+
+    fn measurement(kit_serial: &str) -> astroplant_mqtt::RawMeasurement {
+        astroplant_mqtt::RawMeasurement {
+            id: uuid::Uuid::nil(),
+            kit_serial: kit_serial.to_owned(),
+            datetime: chrono::Utc::now(),
+            peripheral: 2,
+            quantity_type: 3,
+            value: 21.5,
+        }
+    }
+
+    /// Mark the publications as published.
+    fn publish(bridge: &mut Bridge, publications: Vec<Publication>) -> Vec<String> {
+        publications
+            .into_iter()
+            .map(|publication| {
+                if let Some(entity) = publication.announces {
+                    bridge.announced.insert(entity, publication.payload);
+                }
+                publication.topic
+            })
+            .collect()
+    }
+
+    #[test]
+    fn announces_once_and_publishes_states() {
+        let mut bridge = bridge("Thermometer");
+
+        let publications = bridge.measurement(&measurement("k-1"));
+        let discovery: serde_json::Value =
+            serde_json::from_slice(&publications[0].payload).unwrap();
+        assert_eq!(discovery["name"], "Thermometer Temperature");
+        assert_eq!(discovery["unit_of_measurement"], "°C");
+        assert_eq!(discovery["state_topic"], "astroplant/k-1/2/3");
+        let state: serde_json::Value = serde_json::from_slice(&publications[1].payload).unwrap();
+        assert_eq!(state["value"], 21.5);
+        assert_eq!(
+            publish(&mut bridge, publications),
+            vec!["homeassistant/sensor/k-1/2_3/config", "astroplant/k-1/2/3"]
+        );
+
+        let publications = bridge.measurement(&measurement("k-1"));
+        assert_eq!(
+            publish(&mut bridge, publications),
+            vec!["astroplant/k-1/2/3"]
+        );
+
+        // Renaming the peripheral changes the discovery configuration.
+        bridge.set_kits(kits("Probe"), quantity_types());
+        let publications = bridge.measurement(&measurement("k-1"));
+        assert_eq!(publications.len(), 2);
+    }
+
+    #[test]
+    fn only_bridges_opted_in_kits() {
+        let mut bridge = bridge("Thermometer");
+        assert!(bridge.measurement(&measurement("k-2")).is_empty());
+
+        let publications = bridge.measurement(&measurement("k-1"));
+        publish(&mut bridge, publications);
+
+        // The kit opts out: its sensor is removed.
+        let removals = bridge.set_kits(HashMap::new(), quantity_types());
+        assert_eq!(removals.len(), 1);
+        assert_eq!(removals[0].topic, "homeassistant/sensor/k-1/2_3/config");
+        assert!(removals[0].payload.is_empty() && removals[0].retain);
+        assert!(bridge.measurement(&measurement("k-1")).is_empty());
+    }
+}
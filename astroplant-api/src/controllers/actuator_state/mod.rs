@@ -0,0 +1,69 @@
+use axum::extract::Path;
+use axum::Extension;
+use serde::{Deserialize, Serialize};
+
+use crate::database::PgPool;
+use crate::problem::Problem;
+use crate::response::{Response, ResponseBuilder};
+use crate::{authorization, helpers, models, views};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Query {
+    cursor: Option<String>,
+    configuration: Option<i32>,
+    peripheral: Option<i32>,
+}
+
+/// Handles the `GET /kits/{kitSerial}/actuator-states` route.
+pub async fn kit_actuator_states(
+    Extension(pg): Extension<PgPool>,
+    user_id: Option<models::UserId>,
+    Path(kit_serial): Path<String>,
+    crate::extract::Query(query): crate::extract::Query<Query>,
+) -> Result<Response, Problem> {
+    use crate::cursors;
+
+    let mut out_query = query.clone();
+    let cursor = query.cursor.as_ref().map(|s| s.parse()).transpose()?;
+    let base_uri = format!("/kits/{}/actuator-states", kit_serial);
+
+    let (_user, _membership, kit) = helpers::fut_kit_permission_or_forbidden(
+        pg.clone(),
+        user_id,
+        kit_serial,
+        authorization::KitAction::View,
+    )
+    .await?;
+
+    let conn = pg.get().await?;
+    let mut response = ResponseBuilder::ok();
+    let actuator_states = conn
+        .interact_flatten_err(move |conn| {
+            models::ActuatorState::page(
+                conn,
+                kit.get_id(),
+                query.configuration,
+                query.peripheral,
+                cursor,
+            )
+        })
+        .await?;
+
+    if let Some(next_cursor) = cursors::ActuatorStates::next_from_page(&actuator_states) {
+        out_query.cursor = Some(next_cursor.into());
+        let next_page_uri = format!(
+            "{}?{}",
+            base_uri,
+            serde_urlencoded::to_string(&out_query).unwrap()
+        );
+        response = response.link(&next_page_uri, "next");
+    }
+
+    let body = actuator_states
+        .into_iter()
+        .map(views::ActuatorState::from)
+        .collect::<Vec<_>>();
+
+    Ok(response.body(body))
+}
@@ -41,7 +41,7 @@ async fn write_aggregates<W: tokio::io::AsyncWrite + Unpin>(
     // CSV file.
     let aggregate_keys: Vec<String> = sqlx::query!(
         "
-SELECT DISTINCT json_object_keys(values) as key
+SELECT DISTINCT jsonb_object_keys(values) as key
 FROM aggregate_measurements
 WHERE kit_id=$1
 AND ($2 OR kit_configuration_id=$3)
@@ -0,0 +1,171 @@
+//! Provisioning of pre-flashed kits.
+//!
+//! A device or factory tool registers an unclaimed kit using the provisioning key, and receives the
+//! kit's credentials and a one-time claim code (e.g. to print as a QR code). A user then claims the
+//! kit using the claim code, becoming the kit's super member.
+
+use axum::headers::{authorization::Bearer, Authorization};
+use axum::{Extension, TypedHeader};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::database::PgPool;
+use crate::problem::{self, Problem};
+use crate::response::{Response, ResponseBuilder};
+use crate::{models, views};
+
+/// The key authorizing devices and factory tools to register unclaimed kits. If no key is
+/// configured, unclaimed kits cannot be registered.
+#[derive(Clone, Debug, Default)]
+pub struct ProvisioningKey(Option<Arc<str>>);
+
+impl ProvisioningKey {
+    /// Read the provisioning key from the `KIT_PROVISIONING_KEY` environment variable.
+    pub fn from_env() -> Self {
+        Self(
+            std::env::var("KIT_PROVISIONING_KEY")
+                .ok()
+                .filter(|key| !key.is_empty())
+                .map(Into::into),
+        )
+    }
+
+    fn check(&self, key: &str) -> bool {
+        match &self.0 {
+            Some(expected) => crate::helpers::secret_eq(expected, key),
+            None => false,
+        }
+    }
+}
+
+/// The age after which claim codes expire.
+#[derive(Clone, Copy, Debug)]
+pub struct ClaimCodeMaxAge(chrono::Duration);
+
+impl ClaimCodeMaxAge {
+    const DEFAULT_DAYS: i64 = 90;
+
+    /// Read the maximum age from the `KIT_CLAIM_CODE_MAX_AGE_DAYS` environment variable.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let days = match std::env::var("KIT_CLAIM_CODE_MAX_AGE_DAYS") {
+            Ok(days) => match days.parse::<i64>() {
+                Ok(days) if days > 0 => days,
+                _ => anyhow::bail!("invalid KIT_CLAIM_CODE_MAX_AGE_DAYS: {:?}", days),
+            },
+            Err(_) => Self::DEFAULT_DAYS,
+        };
+        Ok(Self(chrono::Duration::days(days)))
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct RegisterUnclaimedKit {
+    name: Option<String>,
+    description: Option<String>,
+}
+
+/// Handles the `POST /unclaimed-kits` route.
+pub async fn register_unclaimed_kit(
+    Extension(pg): Extension<PgPool>,
+    Extension(provisioning_key): Extension<ProvisioningKey>,
+    authorization: Option<TypedHeader<Authorization<Bearer>>>,
+    crate::extract::Json(kit): crate::extract::Json<RegisterUnclaimedKit>,
+) -> Result<Response, Problem> {
+    use validator::Validate;
+
+    #[derive(Serialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    struct Registered {
+        kit_serial: String,
+        password: String,
+        claim_code: String,
+    }
+
+    match authorization {
+        Some(TypedHeader(Authorization(bearer))) if provisioning_key.check(bearer.token()) => {}
+        _ => return Err(problem::FORBIDDEN),
+    }
+
+    let (new_kit, password) = models::NewKit::new_with_generated_password(
+        kit.name,
+        kit.description,
+        None,
+        None,
+        false,
+        false,
+    );
+
+    if let Err(validation_errors) = new_kit.validate() {
+        let invalid_parameters = problem::InvalidParameters::from(validation_errors);
+        return Err(problem::Problem::InvalidParameters { invalid_parameters });
+    };
+
+    let conn = pg.get().await?;
+    conn.interact(move |conn| {
+        conn.transaction(|conn| {
+            let created_kit: models::Kit = new_kit.create(conn)?;
+            let kit_serial = created_kit.serial;
+            tracing::debug!("Registered unclaimed kit \"{}\"", kit_serial);
+
+            let (new_claim_code, claim_code) =
+                models::NewKitClaimCode::new_with_generated_code(models::KitId(created_kit.id));
+            new_claim_code.create(conn)?;
+
+            let response = ResponseBuilder::created().body(Registered {
+                kit_serial,
+                password,
+                claim_code,
+            });
+
+            Ok(response)
+        })
+    })
+    .await?
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ClaimKit {
+    claim_code: String,
+}
+
+/// Handles the `POST /kit-claims` route.
+pub async fn claim_kit(
+    Extension(pg): Extension<PgPool>,
+    Extension(ClaimCodeMaxAge(max_age)): Extension<ClaimCodeMaxAge>,
+    user_id: crate::extract::UserId,
+    crate::extract::Json(claim): crate::extract::Json<ClaimKit>,
+) -> Result<Response, Problem> {
+    let conn = pg.get().await?;
+    conn.interact(move |conn| {
+        // Expired claim codes are deleted, such that they cannot be taken.
+        let expired =
+            models::KitClaimCode::delete_created_before(conn, chrono::Utc::now() - max_age)?;
+        if expired > 0 {
+            tracing::debug!("Deleted {} expired claim code(s)", expired);
+        }
+
+        conn.transaction(|conn| {
+            let claim_code = match models::KitClaimCode::take(conn, &claim.claim_code)? {
+                Some(claim_code) => claim_code,
+                None => {
+                    return Err(problem::Problem::InvalidParameters {
+                        invalid_parameters: problem::InvalidParameterReason::NotFound
+                            .singleton("claimCode"),
+                    })
+                }
+            };
+
+            let kit_id = models::KitId(claim_code.kit_id);
+            let kit: models::Kit = models::Kit::by_id(kit_id).first(conn)?;
+            models::NewKitMembership::new(user_id, kit_id, true, true).create(conn)?;
+            tracing::debug!("User {} claimed kit \"{}\"", user_id.0, kit.serial);
+
+            let last_seen = kit.last_seen(conn)?;
+            Ok(ResponseBuilder::ok().body(views::Kit::from((kit, last_seen))))
+        })
+    })
+    .await?
+}
@@ -0,0 +1,169 @@
+use astroplant_mqtt::RawMeasurement;
+use astroplant_websocket::auth::Session;
+use astroplant_websocket::{LiveEvent, SocketHandler};
+use axum::extract::{Path, TypedHeader};
+use axum::headers::{authorization::Bearer, Authorization};
+use axum::http::HeaderMap;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::Extension;
+use futures::stream::{Stream, StreamExt};
+use serde::Deserialize;
+use sqlx::postgres::PgPool as SqlxPgPool;
+use std::time::Duration;
+
+use crate::authorization::KitAction;
+use crate::database::PgPool;
+use crate::helpers;
+use crate::problem::Problem;
+use crate::{models, websocket};
+
+/// Streams lost within this period can be resumed.
+const RESUMPTION_PERIOD: Duration = Duration::from_secs(30 * 60);
+
+/// The maximum number of missed raw measurements sent when resuming.
+const MAX_RESUMED: i64 = 1000;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LiveQuery {
+    /// For clients that cannot set headers, such as browsers' `EventSource`.
+    access_token: Option<String>,
+    /// For clients that cannot set headers when resuming a stream.
+    last_event_id: Option<uuid::Uuid>,
+}
+
+/// The raw measurements of the kit stored after the raw measurement with the given id, if that
+/// measurement was stored within the [RESUMPTION_PERIOD].
+async fn missed_raw_measurements(
+    sqlx_pg: &SqlxPgPool,
+    kit_id: i32,
+    kit_serial: &str,
+    last_event_id: uuid::Uuid,
+) -> Result<Option<Vec<RawMeasurement>>, sqlx::Error> {
+    let last: Option<chrono::DateTime<chrono::Utc>> = sqlx::query_scalar(
+        "SELECT datetime FROM raw_measurements
+        WHERE kit_id = $1 AND id = $2 AND datetime >= now() - make_interval(secs => $3)",
+    )
+    .bind(kit_id)
+    .bind(last_event_id)
+    .bind(RESUMPTION_PERIOD.as_secs_f64())
+    .fetch_optional(sqlx_pg)
+    .await?;
+    let last = match last {
+        Some(last) => last,
+        None => return Ok(None),
+    };
+
+    let missed: Vec<(uuid::Uuid, chrono::DateTime<chrono::Utc>, i32, i32, f64)> = sqlx::query_as(
+        "SELECT id, datetime, peripheral_id, quantity_type_id, value FROM raw_measurements
+        WHERE kit_id = $1 AND (datetime, id) > ($2, $3)
+        ORDER BY datetime, id
+        LIMIT $4",
+    )
+    .bind(kit_id)
+    .bind(last)
+    .bind(last_event_id)
+    .bind(MAX_RESUMED)
+    .fetch_all(sqlx_pg)
+    .await?;
+
+    Ok(Some(
+        missed
+            .into_iter()
+            .map(
+                |(id, datetime, peripheral, quantity_type, value)| RawMeasurement {
+                    id,
+                    kit_serial: kit_serial.to_owned(),
+                    datetime,
+                    peripheral,
+                    quantity_type,
+                    value,
+                },
+            )
+            .collect(),
+    ))
+}
+
+/// Authenticates with the bearer token, or with the access token given as query parameter by clients
+/// that cannot set headers. Invalid and expired tokens are treated as anonymous.
+pub(super) async fn authenticate(
+    auth: &websocket::Auth,
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+    access_token: Option<&str>,
+) -> Option<Session<models::UserId>> {
+    use astroplant_websocket::auth::Auth;
+
+    let access_token = match &bearer {
+        Some(TypedHeader(Authorization(bearer))) => Some(bearer.token()),
+        None => access_token,
+    };
+    match access_token {
+        Some(access_token) => auth.authenticate(access_token).await.ok(),
+        None => None,
+    }
+}
+
+fn sse_event(event: LiveEvent) -> Result<Event, serde_json::Error> {
+    match event {
+        LiveEvent::RawMeasurement(raw_measurement) => Event::default()
+            .event("rawMeasurement")
+            .id(raw_measurement.id.to_string())
+            .json_data(raw_measurement),
+        LiveEvent::AggregateMeasurement(aggregate_measurement) => Event::default()
+            .event("aggregateMeasurement")
+            .json_data(aggregate_measurement),
+        LiveEvent::Media(media) => Event::default().event("media").json_data(media),
+        LiveEvent::Closed(reason) => Event::default()
+            .event("closed")
+            .json_data(serde_json::json!({ "reason": reason })),
+    }
+}
+
+/// Handles the `GET /kits/{kitSerial}/live?accessToken=token&lastEventId=id` route.
+pub async fn live(
+    Extension(pg): Extension<PgPool>,
+    Extension(sqlx_pg): Extension<SqlxPgPool>,
+    Extension(ws_handler): Extension<SocketHandler>,
+    Path(kit_serial): Path<String>,
+    query: crate::extract::Query<LiveQuery>,
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<Event, serde_json::Error>>>, Problem> {
+    let auth = websocket::Auth::new(pg.clone());
+    let session = authenticate(&auth, bearer, query.access_token.as_deref()).await;
+
+    let (_, _, kit) = helpers::fut_kit_permission_or_forbidden(
+        pg,
+        session.map(|session| session.user),
+        kit_serial,
+        KitAction::SubscribeRealTimeMeasurements,
+    )
+    .await?;
+
+    // Clients resuming a stream send the ID of the last raw measurement they received.
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|id| id.to_str().ok())
+        .and_then(|id| id.parse().ok())
+        .or(query.last_event_id);
+
+    let missed = async {
+        let last_event_id = last_event_id?;
+        missed_raw_measurements(&sqlx_pg, kit.id, &kit.serial, last_event_id)
+            .await
+            .unwrap_or_else(|err| {
+                tracing::warn!(
+                    "failed to get the raw measurements of {} missed since {}: {:?}",
+                    kit.serial,
+                    last_event_id,
+                    err
+                );
+                None
+            })
+    };
+
+    let live = ws_handler
+        .live(auth, session, kit.serial.clone(), missed)
+        .await;
+    Ok(Sse::new(live.map(sse_event)).keep_alive(KeepAlive::default()))
+}
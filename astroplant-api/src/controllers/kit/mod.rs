@@ -17,6 +17,15 @@ use crate::{helpers, models, schema, views};
 mod archive;
 pub use archive::{archive, archive_authorize};
 
+mod claim;
+pub use claim::{claim_kit, register_unclaimed_kit, ClaimCodeMaxAge, ProvisioningKey};
+
+mod live;
+pub use live::live;
+
+mod preview;
+pub use preview::preview;
+
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct KitsQuery {
@@ -104,6 +113,63 @@ pub async fn kit_by_serial(
     Ok(ResponseBuilder::ok().body(views::Kit::from((kit, kit_last_seen))))
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DroppedMessagesQuery {
+    days: Option<u32>,
+}
+
+/// Handles the `GET /kits/{kitSerial}/dropped-messages?days=days` route.
+///
+/// Lists, per day, the messages published by the kit that were dropped for exceeding the kit's
+/// ingress quota. Lists the past 30 days by default.
+pub async fn dropped_messages(
+    Extension(pg): Extension<PgPool>,
+    Path(kit_serial): Path<String>,
+    user_id: Option<crate::extract::UserId>,
+    crate::extract::Query(DroppedMessagesQuery { days }): crate::extract::Query<
+        DroppedMessagesQuery,
+    >,
+) -> Result<Response, Problem> {
+    const DEFAULT_DAYS: u32 = 30;
+    const MAX_DAYS: u32 = 366;
+
+    let days = days.unwrap_or(DEFAULT_DAYS);
+    if days > MAX_DAYS {
+        return Err(Problem::InvalidParameters {
+            invalid_parameters: problem::InvalidParameterReason::MustBeInRange {
+                min: 0.0,
+                max: MAX_DAYS.into(),
+            }
+            .singleton("days"),
+        });
+    }
+
+    let (_, _, kit) = helpers::fut_kit_permission_or_forbidden(
+        pg.clone(),
+        user_id,
+        kit_serial,
+        crate::authorization::KitAction::ViewDroppedMessages,
+    )
+    .await?;
+
+    let since = Utc::now().date_naive() - chrono::Duration::days(days.into());
+
+    let conn = pg.get().await?;
+    let dropped_messages = conn
+        .interact_flatten_err(move |conn| {
+            models::KitDroppedMessages::of_kit_since(conn, kit.get_id(), since)
+        })
+        .await?;
+
+    Ok(ResponseBuilder::ok().body(
+        dropped_messages
+            .into_iter()
+            .map(views::KitDroppedMessages::from)
+            .collect::<Vec<_>>(),
+    ))
+}
+
 /// Handles the `POST /kits/{kitSerial}/password` route.
 pub async fn reset_password(
     Extension(pg): Extension<PgPool>,
@@ -129,6 +195,96 @@ pub async fn reset_password(
     Ok(ResponseBuilder::ok().body(password))
 }
 
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RotatePassword {
+    grace_days: Option<u32>,
+    #[serde(default)]
+    deliver: bool,
+}
+
+/// Handles the `POST /kits/{kitSerial}/password/rotate` route.
+///
+/// Generates a new password for the kit. The kit's current password remains valid until the kit
+/// first authenticates with the new password, or until the grace period (7 days by default) ends.
+/// If requested, the new password is delivered to the kit over kit RPC.
+pub async fn rotate_password(
+    Extension(pg): Extension<PgPool>,
+    Extension(kits_rpc): Extension<astroplant_mqtt::KitsRpc>,
+    Path(kit_serial): Path<String>,
+    user_id: Option<crate::extract::UserId>,
+    crate::extract::Json(RotatePassword {
+        grace_days,
+        deliver,
+    }): crate::extract::Json<RotatePassword>,
+) -> Result<Response, Problem> {
+    const DEFAULT_GRACE_DAYS: u32 = 7;
+    const MAX_GRACE_DAYS: u32 = 90;
+
+    #[derive(Serialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    struct Rotated {
+        password: String,
+        deadline: DateTime<Utc>,
+        delivered: bool,
+    }
+
+    let grace_days = grace_days.unwrap_or(DEFAULT_GRACE_DAYS);
+    if grace_days == 0 || grace_days > MAX_GRACE_DAYS {
+        return Err(Problem::InvalidParameters {
+            invalid_parameters: problem::InvalidParameterReason::MustBeInRange {
+                min: 1.0,
+                max: MAX_GRACE_DAYS.into(),
+            }
+            .singleton("graceDays"),
+        });
+    }
+
+    let (_, _, kit) = helpers::fut_kit_permission_or_forbidden(
+        pg.clone(),
+        user_id,
+        kit_serial,
+        crate::authorization::KitAction::ResetPassword,
+    )
+    .await?;
+    let deadline = Utc::now() + chrono::Duration::days(grace_days.into());
+
+    let conn = pg.get().await?;
+    let kit_id = kit.id;
+    let password = conn
+        .interact_flatten_err(move |conn| {
+            let (update_kit, password) =
+                models::UpdateKit::unchanged_for_id(kit_id).rotate_password(deadline);
+            update_kit.update(conn)?;
+            Ok::<_, Problem>(password)
+        })
+        .await?;
+
+    // The pending password is stored before delivering it, so it is valid as soon as the kit
+    // receives it.
+    let delivered = if deliver {
+        match kits_rpc.set_password(&kit.serial, password.clone()).await {
+            Ok(()) => true,
+            Err(err) => {
+                tracing::debug!(
+                    "Could not deliver rotated password to kit {}: {:?}",
+                    kit.serial,
+                    err
+                );
+                false
+            }
+        }
+    } else {
+        false
+    };
+
+    Ok(ResponseBuilder::ok().body(Rotated {
+        password,
+        deadline,
+        delivered,
+    }))
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct CreateKit {
@@ -204,6 +360,7 @@ pub struct KitPatch {
     longitude: Option<Option<f64>>,
     privacy_public_dashboard: Option<bool>,
     privacy_show_on_map: Option<bool>,
+    privacy_bridge_measurements: Option<bool>,
 }
 
 /// Handles the `PATCH /kits/{kitSerial}` route.
@@ -233,7 +390,10 @@ pub async fn patch_kit(
             .map(|l| l.and_then(BigDecimal::from_f64)),
         privacy_public_dashboard: kit_patch.privacy_public_dashboard,
         privacy_show_on_map: kit_patch.privacy_show_on_map,
+        privacy_bridge_measurements: kit_patch.privacy_bridge_measurements,
         password_hash: None,
+        pending_password_hash: None,
+        pending_password_deadline: None,
     };
 
     let conn = pg.get().await?;
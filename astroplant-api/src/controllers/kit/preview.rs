@@ -0,0 +1,112 @@
+use astroplant_websocket::{Preview, SocketHandler};
+use axum::body::StreamBody;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, TypedHeader};
+use axum::headers::{authorization::Bearer, Authorization};
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
+
+use crate::authorization::KitAction;
+use crate::database::PgPool;
+use crate::helpers;
+use crate::problem::{self, Problem};
+use crate::websocket;
+
+/// The boundary between the frames of an MJPEG stream.
+const BOUNDARY: &str = "frame";
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PreviewQuery {
+    peripheral: String,
+    /// For clients that cannot set headers, such as browsers' `<img>` elements.
+    access_token: Option<String>,
+}
+
+async fn send_frames(socket: WebSocket, mut preview: Preview) {
+    let (mut sink, mut stream) = socket.split();
+    loop {
+        tokio::select! {
+            frame = preview.next() => match frame {
+                Some(frame) => {
+                    if sink.send(Message::Binary(frame.to_vec())).await.is_err() {
+                        break;
+                    }
+                }
+                None => break,
+            },
+            // The client's messages are ignored, until it closes the socket.
+            message = stream.next() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+    let _ = sink.close().await;
+}
+
+fn mjpeg(preview: Preview) -> Response {
+    let parts = preview.map(|frame| {
+        let mut part = format!(
+            "--{}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+            BOUNDARY,
+            frame.len()
+        )
+        .into_bytes();
+        part.extend_from_slice(&frame);
+        part.extend_from_slice(b"\r\n");
+        Ok::<_, std::convert::Infallible>(Bytes::from(part))
+    });
+
+    (
+        [
+            (
+                header::CONTENT_TYPE,
+                format!("multipart/x-mixed-replace; boundary={}", BOUNDARY),
+            ),
+            (header::CACHE_CONTROL, "no-store".to_owned()),
+        ],
+        StreamBody::new(parts),
+    )
+        .into_response()
+}
+
+/// Handles the `GET /kits/{kitSerial}/preview?peripheral=name&accessToken=token` route.
+///
+/// WebSocket upgrade requests receive the frames as binary messages; other requests receive an
+/// MJPEG stream.
+pub async fn preview(
+    Extension(pg): Extension<PgPool>,
+    Extension(ws_handler): Extension<SocketHandler>,
+    Path(kit_serial): Path<String>,
+    query: crate::extract::Query<PreviewQuery>,
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+    ws: Option<WebSocketUpgrade>,
+) -> Result<Response, Problem> {
+    let auth = websocket::Auth::new(pg.clone());
+    let session = super::live::authenticate(&auth, bearer, query.access_token.as_deref()).await;
+
+    let (_, _, kit) = helpers::fut_kit_permission_or_forbidden(
+        pg,
+        session.map(|session| session.user),
+        kit_serial,
+        KitAction::ViewPreview,
+    )
+    .await?;
+
+    let preview = ws_handler
+        .preview(auth, session, kit.serial, query.peripheral.clone())
+        .await
+        .map_err(problem::KitRpcProblem::kit_rpc_response_error_into_problem)?;
+
+    Ok(match ws {
+        Some(ws) => ws
+            .on_upgrade(move |socket| send_frames(socket, preview))
+            .into_response(),
+        None => mjpeg(preview),
+    })
+}
@@ -0,0 +1,220 @@
+use axum::extract::Path;
+use axum::Extension;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::alerts::Condition;
+use crate::database::PgPool;
+use crate::problem::{self, Problem};
+use crate::response::{Response, ResponseBuilder};
+use crate::{authorization, helpers, models, views};
+
+/// Handles the `GET /kits/{kitSerial}/alert-rules` route.
+pub async fn kit_alert_rules(
+    Extension(pg): Extension<PgPool>,
+    user_id: Option<models::UserId>,
+    Path(kit_serial): Path<String>,
+) -> Result<Response, Problem> {
+    use std::convert::TryFrom;
+
+    let (_user, _membership, kit) = helpers::fut_kit_permission_or_forbidden(
+        pg.clone(),
+        user_id,
+        kit_serial,
+        authorization::KitAction::View,
+    )
+    .await?;
+
+    let conn = pg.get().await?;
+    let rules = conn
+        .interact_flatten_err(move |conn| models::KitAlertRule::rules_of_kit_id(conn, kit.get_id()))
+        .await?;
+
+    let body = rules
+        .into_iter()
+        .map(views::KitAlertRule::try_from)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(ResponseBuilder::ok().body(body))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AlertRule {
+    peripheral_id: i32,
+    quantity_type_id: i32,
+    name: String,
+    #[serde(flatten)]
+    condition: Condition,
+    #[serde(default)]
+    duration_seconds: i32,
+}
+
+/// Handles the `POST /kits/{kitSerial}/alert-rules` route.
+pub async fn create_alert_rule(
+    Extension(pg): Extension<PgPool>,
+    user_id: Option<models::UserId>,
+    Path(kit_serial): Path<String>,
+    crate::extract::Json(alert_rule): crate::extract::Json<AlertRule>,
+) -> Result<Response, Problem> {
+    use diesel::prelude::*;
+    use std::convert::TryFrom;
+
+    let (_user, _membership, kit) = helpers::fut_kit_permission_or_forbidden(
+        pg.clone(),
+        user_id,
+        kit_serial,
+        authorization::KitAction::EditAlertRules,
+    )
+    .await?;
+
+    let (kind, comparison, threshold) = alert_rule.condition.to_columns();
+    let new_alert_rule = models::NewKitAlertRule::new(
+        kit.get_id(),
+        models::PeripheralId(alert_rule.peripheral_id),
+        models::QuantityTypeId(alert_rule.quantity_type_id),
+        alert_rule.name,
+        kind,
+        comparison,
+        threshold,
+        alert_rule.duration_seconds,
+    );
+
+    let mut invalid_parameters = match new_alert_rule.validate() {
+        Ok(()) => problem::InvalidParameters::new(),
+        Err(validation_errors) => problem::InvalidParameters::from(validation_errors),
+    };
+    if alert_rule.condition == Condition::Absence && alert_rule.duration_seconds < 1 {
+        invalid_parameters.add(
+            "durationSeconds",
+            problem::InvalidParameterReason::MustBeInRange {
+                min: 1.0,
+                max: i32::MAX.into(),
+            },
+        );
+    }
+    if !invalid_parameters.is_empty() {
+        return Err(invalid_parameters.into_problem());
+    }
+
+    let conn = pg.get().await?;
+    let created_alert_rule = conn
+        .interact_flatten_err(move |conn| {
+            let mut invalid_parameters = problem::InvalidParameters::new();
+            match models::Peripheral::by_id(
+                conn,
+                models::PeripheralId(new_alert_rule.peripheral_id),
+            )? {
+                Some(peripheral) if peripheral.kit_id == new_alert_rule.kit_id => {}
+                _ => invalid_parameters
+                    .add("peripheralId", problem::InvalidParameterReason::NotFound),
+            }
+            if models::QuantityType::by_id(conn, new_alert_rule.quantity_type_id)
+                .optional()?
+                .is_none()
+            {
+                invalid_parameters.add("quantityTypeId", problem::InvalidParameterReason::NotFound);
+            }
+            if !invalid_parameters.is_empty() {
+                return Err(invalid_parameters.into_problem());
+            }
+
+            Ok::<_, Problem>(new_alert_rule.create(conn)?)
+        })
+        .await?;
+
+    Ok(ResponseBuilder::created().body(views::KitAlertRule::try_from(created_alert_rule)?))
+}
+
+/// Handles the `DELETE /alert-rules/{alertRuleId}` route.
+pub async fn delete_alert_rule(
+    Extension(pg): Extension<PgPool>,
+    user_id: Option<models::UserId>,
+    Path(alert_rule_id): Path<i32>,
+) -> Result<Response, Problem> {
+    use diesel::prelude::*;
+
+    let alert_rule_id = models::KitAlertRuleId(alert_rule_id);
+
+    let conn = pg.clone().get().await?;
+    let (alert_rule, kit) = conn
+        .interact(move |conn| {
+            let alert_rule =
+                models::KitAlertRule::by_id(conn, alert_rule_id)?.ok_or(problem::NOT_FOUND)?;
+            let kit = models::Kit::by_id(alert_rule.get_kit_id())
+                .first(conn)
+                .optional()?
+                .ok_or(problem::INTERNAL_SERVER_ERROR)?;
+            Ok::<_, Problem>((alert_rule, kit))
+        })
+        .await??;
+
+    helpers::fut_kit_permission_or_forbidden(
+        pg.clone(),
+        user_id,
+        kit.serial,
+        authorization::KitAction::EditAlertRules,
+    )
+    .await?;
+
+    let conn = pg.get().await?;
+    conn.interact(move |conn| {
+        alert_rule.delete(conn)?;
+        Ok(ResponseBuilder::ok().empty())
+    })
+    .await?
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Query {
+    cursor: Option<String>,
+    alert_rule: Option<i32>,
+}
+
+/// Handles the `GET /kits/{kitSerial}/alerts` route.
+pub async fn kit_alerts(
+    Extension(pg): Extension<PgPool>,
+    user_id: Option<models::UserId>,
+    Path(kit_serial): Path<String>,
+    crate::extract::Query(query): crate::extract::Query<Query>,
+) -> Result<Response, Problem> {
+    use crate::cursors;
+
+    let mut out_query = query.clone();
+    let cursor = query.cursor.as_ref().map(|s| s.parse()).transpose()?;
+    let base_uri = format!("/kits/{}/alerts", kit_serial);
+
+    let (_user, _membership, kit) = helpers::fut_kit_permission_or_forbidden(
+        pg.clone(),
+        user_id,
+        kit_serial,
+        authorization::KitAction::View,
+    )
+    .await?;
+
+    let conn = pg.get().await?;
+    let mut response = ResponseBuilder::ok();
+    let alerts = conn
+        .interact_flatten_err(move |conn| {
+            models::KitAlert::page(conn, kit.get_id(), query.alert_rule, cursor)
+        })
+        .await?;
+
+    if let Some(next_cursor) = cursors::KitAlerts::next_from_page(&alerts) {
+        out_query.cursor = Some(next_cursor.into());
+        let next_page_uri = format!(
+            "{}?{}",
+            base_uri,
+            serde_urlencoded::to_string(&out_query).unwrap()
+        );
+        response = response.link(&next_page_uri, "next");
+    }
+
+    let body = alerts
+        .into_iter()
+        .map(views::KitAlert::from)
+        .collect::<Vec<_>>();
+
+    Ok(response.body(body))
+}
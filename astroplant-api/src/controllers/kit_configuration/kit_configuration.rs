@@ -8,6 +8,7 @@ use crate::models::{Kit, KitConfigurationId, Peripheral};
 use crate::problem::{self, Problem};
 use crate::response::{Response, ResponseBuilder};
 use crate::utils::deserialize_some;
+use crate::webhooks::{EventType, Webhooks};
 use crate::{authorization, helpers, models, schema, views};
 
 use super::get_models_from_kit_configuration_id;
@@ -187,6 +188,7 @@ pub struct KitConfigurationPatch {
 /// If the configuration is set active, all other configurations of the kit are deactivated.
 pub async fn patch_configuration(
     Extension(pg): Extension<PgPool>,
+    Extension(webhooks): Extension<Webhooks>,
     user_id: Option<models::UserId>,
     Path(kit_configuration_id): Path<i32>,
     crate::extract::Json(kit_configuration_patch): crate::extract::Json<KitConfigurationPatch>,
@@ -223,6 +225,9 @@ pub async fn patch_configuration(
         active: kit_configuration_patch.active,
     };
 
+    let kit_serial = kit.serial.clone();
+    let activated = patch.active == Some(true) && !kit_configuration.active;
+
     let conn = pg.get().await?;
     let patched_configuration = conn
         .interact_flatten_err(move |conn| {
@@ -237,7 +242,16 @@ pub async fn patch_configuration(
         })
         .await?;
 
-    Ok(ResponseBuilder::ok().body(views::KitConfiguration::from(patched_configuration)))
+    let patched_configuration = views::KitConfiguration::from(patched_configuration);
+    if activated {
+        webhooks.dispatch(
+            kit_serial,
+            EventType::ConfigurationActivated,
+            &patched_configuration,
+        );
+    }
+
+    Ok(ResponseBuilder::ok().body(patched_configuration))
 }
 
 /// Handles the `DELETE /kit-configurations/{kitConfigurationId}` route.
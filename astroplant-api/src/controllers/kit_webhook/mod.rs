@@ -0,0 +1,205 @@
+use axum::extract::Path;
+use axum::Extension;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use validator::Validate;
+
+use crate::database::PgPool;
+use crate::problem::{self, Problem};
+use crate::response::{Response, ResponseBuilder};
+use crate::webhooks::{Destinations, EventType};
+use crate::{authorization, helpers, models, views};
+
+/// Handles the `GET /kits/{kitSerial}/webhooks` route.
+pub async fn kit_webhooks(
+    Extension(pg): Extension<PgPool>,
+    user_id: Option<models::UserId>,
+    Path(kit_serial): Path<String>,
+) -> Result<Response, Problem> {
+    let (_user, _membership, kit) = helpers::fut_kit_permission_or_forbidden(
+        pg.clone(),
+        user_id,
+        kit_serial,
+        authorization::KitAction::EditWebhooks,
+    )
+    .await?;
+
+    let conn = pg.get().await?;
+    let webhooks = conn
+        .interact_flatten_err(move |conn| {
+            models::KitWebhook::webhooks_of_kit_id(conn, kit.get_id())
+        })
+        .await?;
+
+    let body = webhooks
+        .into_iter()
+        .map(views::KitWebhook::try_from)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(ResponseBuilder::ok().body(body))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Webhook {
+    url: String,
+    event_types: Vec<EventType>,
+}
+
+/// Handles the `POST /kits/{kitSerial}/webhooks` route.
+///
+/// The webhook's secret is generated and returned only in the response of this route. The URL must
+/// be allowed by the [Destinations].
+pub async fn create_webhook(
+    Extension(pg): Extension<PgPool>,
+    Extension(destinations): Extension<Destinations>,
+    user_id: Option<models::UserId>,
+    Path(kit_serial): Path<String>,
+    crate::extract::Json(webhook): crate::extract::Json<Webhook>,
+) -> Result<Response, Problem> {
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Created {
+        #[serde(flatten)]
+        webhook: views::KitWebhook,
+        secret: String,
+    }
+
+    let (_user, _membership, kit) = helpers::fut_kit_permission_or_forbidden(
+        pg.clone(),
+        user_id,
+        kit_serial,
+        authorization::KitAction::EditWebhooks,
+    )
+    .await?;
+
+    let mut event_types: Vec<String> = webhook
+        .event_types
+        .iter()
+        .map(|event_type| event_type.as_str().to_owned())
+        .collect();
+    event_types.sort();
+    event_types.dedup();
+
+    let new_webhook =
+        models::NewKitWebhook::new_with_generated_secret(kit.get_id(), webhook.url, event_types);
+
+    let mut invalid_parameters = match new_webhook.validate() {
+        Ok(()) => problem::InvalidParameters::new(),
+        Err(validation_errors) => problem::InvalidParameters::from(validation_errors),
+    };
+    if destinations.check_url(&new_webhook.url).is_err() {
+        invalid_parameters.add("url", problem::InvalidParameterReason::MustBeUrl);
+    }
+    if !invalid_parameters.is_empty() {
+        return Err(invalid_parameters.into_problem());
+    }
+
+    let conn = pg.get().await?;
+    let created_webhook = conn
+        .interact_flatten_err(move |conn| new_webhook.create(conn))
+        .await?;
+
+    let secret = created_webhook.secret.clone();
+    Ok(ResponseBuilder::created().body(Created {
+        webhook: views::KitWebhook::try_from(created_webhook)?,
+        secret,
+    }))
+}
+
+async fn get_models_from_webhook_id(
+    pg: PgPool,
+    webhook_id: models::KitWebhookId,
+) -> Result<(models::Kit, models::KitWebhook), Problem> {
+    use diesel::prelude::*;
+
+    let conn = pg.get().await?;
+    conn.interact(move |conn| {
+        let webhook = models::KitWebhook::by_id(conn, webhook_id)?.ok_or(problem::NOT_FOUND)?;
+        let kit = models::Kit::by_id(webhook.get_kit_id())
+            .first(conn)
+            .optional()?
+            .ok_or(problem::INTERNAL_SERVER_ERROR)?;
+        Ok((kit, webhook))
+    })
+    .await?
+}
+
+/// Handles the `DELETE /webhooks/{webhookId}` route.
+pub async fn delete_webhook(
+    Extension(pg): Extension<PgPool>,
+    user_id: Option<models::UserId>,
+    Path(webhook_id): Path<i32>,
+) -> Result<Response, Problem> {
+    let (kit, webhook) =
+        get_models_from_webhook_id(pg.clone(), models::KitWebhookId(webhook_id)).await?;
+    helpers::fut_kit_permission_or_forbidden(
+        pg.clone(),
+        user_id,
+        kit.serial,
+        authorization::KitAction::EditWebhooks,
+    )
+    .await?;
+
+    let conn = pg.get().await?;
+    conn.interact(move |conn| {
+        webhook.delete(conn)?;
+        Ok(ResponseBuilder::ok().empty())
+    })
+    .await?
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Query {
+    cursor: Option<String>,
+}
+
+/// Handles the `GET /webhooks/{webhookId}/deliveries` route.
+pub async fn webhook_deliveries(
+    Extension(pg): Extension<PgPool>,
+    user_id: Option<models::UserId>,
+    Path(webhook_id): Path<i32>,
+    crate::extract::Query(query): crate::extract::Query<Query>,
+) -> Result<Response, Problem> {
+    use crate::cursors;
+
+    let mut out_query = query.clone();
+    let cursor = query.cursor.as_ref().map(|s| s.parse()).transpose()?;
+    let base_uri = format!("/webhooks/{}/deliveries", webhook_id);
+
+    let (kit, webhook) =
+        get_models_from_webhook_id(pg.clone(), models::KitWebhookId(webhook_id)).await?;
+    helpers::fut_kit_permission_or_forbidden(
+        pg.clone(),
+        user_id,
+        kit.serial,
+        authorization::KitAction::EditWebhooks,
+    )
+    .await?;
+
+    let conn = pg.get().await?;
+    let mut response = ResponseBuilder::ok();
+    let deliveries = conn
+        .interact_flatten_err(move |conn| {
+            models::KitWebhookDelivery::page(conn, webhook.get_id(), cursor)
+        })
+        .await?;
+
+    if let Some(next_cursor) = cursors::KitWebhookDeliveries::next_from_page(&deliveries) {
+        out_query.cursor = Some(next_cursor.into());
+        let next_page_uri = format!(
+            "{}?{}",
+            base_uri,
+            serde_urlencoded::to_string(&out_query).unwrap()
+        );
+        response = response.link(&next_page_uri, "next");
+    }
+
+    let body = deliveries
+        .into_iter()
+        .map(views::KitWebhookDelivery::try_from)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(response.body(body))
+}
@@ -72,3 +72,32 @@ pub async fn kit_aggregate_measurements(
 
     Ok(response.body(body))
 }
+
+/// Handles the `GET /kits/{kitSerial}/latest-measurements` route.
+pub async fn kit_latest_measurements(
+    Extension(pg): Extension<PgPool>,
+    user_id: Option<models::UserId>,
+    Path(kit_serial): Path<String>,
+) -> Result<Response, Problem> {
+    use std::convert::TryFrom;
+
+    let (_user, _membership, kit) = helpers::fut_kit_permission_or_forbidden(
+        pg.clone(),
+        user_id,
+        kit_serial,
+        authorization::KitAction::View,
+    )
+    .await?;
+
+    let conn = pg.get().await?;
+    let latest_measurements = conn
+        .interact_flatten_err(move |conn| models::LatestMeasurement::by_kit_id(conn, kit.get_id()))
+        .await?;
+
+    let body = latest_measurements
+        .into_iter()
+        .map(views::LatestMeasurement::try_from)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(ResponseBuilder::ok().body(body))
+}
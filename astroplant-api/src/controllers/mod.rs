@@ -1,9 +1,14 @@
+pub mod actuator_state;
 pub mod kit;
+pub mod kit_alert;
 pub mod kit_configuration;
+pub mod kit_event;
 pub mod kit_rpc;
+pub mod kit_webhook;
 pub mod me;
 pub mod measurement;
 pub mod media;
+pub mod mqtt_auth;
 pub mod peripheral_definition;
 pub mod permission;
 pub mod quantity_type;
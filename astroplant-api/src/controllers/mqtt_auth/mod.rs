@@ -0,0 +1,415 @@
+//! Authentication and authorization webhooks for the MQTT broker.
+//!
+//! Kits log in with their serial as username. They may only publish their messages to topics under
+//! `kit/{serial}/`, and subscribe to the RPC topics addressed to them. The server's own MQTT
+//! clients (such as this API and the ingest service) log in with separate [server
+//! accounts](ServerAccounts), and may access all topics.
+//!
+//! Two flavors of the webhooks are provided: one for mosquitto-go-auth's HTTP backend, and one for
+//! EMQX's HTTP authentication and authorization. The broker authenticates itself to the webhooks
+//! with the [MqttAuthKey].
+
+use async_trait::async_trait;
+use axum::extract::{FromRequest, RequestParts, TypedHeader};
+use axum::headers::{authorization::Bearer, Authorization};
+use axum::http::StatusCode;
+use axum::Extension;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::database::PgPool;
+use crate::models;
+use crate::problem::{self, Problem};
+use crate::response::{Response, ResponseBuilder};
+
+/// The key authorizing the MQTT broker to use the webhooks. If no key is configured, the webhooks
+/// deny all requests.
+#[derive(Clone, Debug, Default)]
+pub struct MqttAuthKey(Option<Arc<str>>);
+
+impl MqttAuthKey {
+    /// Read the key from the `MQTT_AUTH_KEY` environment variable.
+    pub fn from_env() -> Self {
+        let key = std::env::var("MQTT_AUTH_KEY")
+            .ok()
+            .filter(|key| !key.is_empty());
+        if key.is_none() {
+            tracing::warn!("MQTT_AUTH_KEY is not set: the MQTT broker webhooks deny all requests");
+        }
+        Self(key.map(Into::into))
+    }
+
+    fn check(&self, key: &str) -> bool {
+        match &self.0 {
+            Some(expected) => crate::helpers::secret_eq(expected, key),
+            None => false,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct BrokerKeyQuery {
+    key: Option<String>,
+}
+
+/// Guards the webhooks, rejecting requests that do not carry the [MqttAuthKey] as bearer token or,
+/// for brokers that cannot set headers, as the `key` query parameter.
+pub struct Broker;
+
+#[async_trait]
+impl<B: Send> FromRequest<B> for Broker {
+    type Rejection = Problem;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let Extension(mqtt_auth_key) = Extension::<MqttAuthKey>::from_request(req)
+            .await
+            .map_err(|_| problem::INTERNAL_SERVER_ERROR)?;
+
+        let authorized = match Option::<TypedHeader<Authorization<Bearer>>>::from_request(req)
+            .await
+            .unwrap_or(None)
+        {
+            Some(TypedHeader(Authorization(bearer))) => mqtt_auth_key.check(bearer.token()),
+            None => match axum::extract::Query::<BrokerKeyQuery>::from_request(req).await {
+                Ok(axum::extract::Query(BrokerKeyQuery { key: Some(key) })) => {
+                    mqtt_auth_key.check(&key)
+                }
+                _ => false,
+            },
+        };
+
+        if authorized {
+            Ok(Broker)
+        } else {
+            Err(problem::FORBIDDEN)
+        }
+    }
+}
+
+/// The access a client requests to a topic (or topic filter).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Access {
+    Publish,
+    Subscribe,
+}
+
+/// The topics under `kit/{serial}/` kits may publish to. These may be suffixed by `/json`.
+const KIT_PUBLISH_TOPICS: &[&str] = &[
+    "measurement/raw",
+    "measurement/aggregate",
+    "media",
+    "actuator",
+    "event",
+    "server-rpc/request",
+    "kit-rpc/response",
+];
+
+/// The topics under `kit/{serial}/` kits may subscribe to. These may be suffixed by `/json` or,
+/// as topic filter, by `/#`.
+const KIT_SUBSCRIBE_TOPICS: &[&str] = &["server-rpc/response", "kit-rpc/request"];
+
+/// The MQTT accounts of the server's own clients, mapping usernames to password hashes (as
+/// generated by [astroplant_auth::hash::hash_kit_password]).
+#[derive(Clone, Debug, Default)]
+pub struct ServerAccounts(Arc<HashMap<String, String>>);
+
+impl ServerAccounts {
+    /// Read the server accounts from the `MQTT_SERVER_ACCOUNTS` environment variable, a
+    /// comma-separated list of `username:passwordHash` pairs. Malformed pairs are ignored.
+    pub fn from_env() -> Self {
+        let accounts = std::env::var("MQTT_SERVER_ACCOUNTS").unwrap_or_default();
+        Self::parse(&accounts)
+    }
+
+    fn parse(accounts: &str) -> Self {
+        Self(Arc::new(
+            accounts
+                .split(',')
+                .filter_map(|account| {
+                    let (username, hash) = account.trim().split_once(':')?;
+                    Some((username.to_owned(), hash.to_owned()))
+                })
+                .collect(),
+        ))
+    }
+
+    fn is_server_account(&self, username: &str) -> bool {
+        self.0.contains_key(username)
+    }
+}
+
+/// Check the credentials of an MQTT client. Server accounts take precedence over kits.
+async fn authenticate(
+    pg: PgPool,
+    server_accounts: ServerAccounts,
+    username: String,
+    password: String,
+) -> Result<bool, Problem> {
+    let conn = pg.get().await?;
+    conn.interact_flatten_err(move |conn| {
+        if let Some(password_hash) = server_accounts.0.get(&username) {
+            return Ok::<_, Problem>(astroplant_auth::hash::check_kit_password(
+                &password,
+                password_hash,
+            ));
+        }
+
+        let kit: models::Kit = match models::Kit::by_serial(&username).first(conn).optional()? {
+            Some(kit) => kit,
+            None => {
+                // Probably unnecessary, but hash the provided password to help defeat timing
+                // attacks.
+                astroplant_auth::hash::hash_kit_password(&password);
+                return Ok(false);
+            }
+        };
+
+        match kit.check_password(&password, chrono::Utc::now()) {
+            models::KitPasswordCheck::Invalid => Ok(false),
+            models::KitPasswordCheck::Current => Ok(true),
+            models::KitPasswordCheck::Pending => {
+                let pending_password_hash = kit.pending_password_hash.clone().unwrap();
+                models::UpdateKit::unchanged_for_id(kit.id)
+                    .complete_password_rotation(pending_password_hash)
+                    .update(conn)?;
+                tracing::debug!("Kit {} completed its password rotation", kit.serial);
+                Ok(true)
+            }
+        }
+    })
+    .await
+}
+
+/// Whether the client may publish or subscribe to the topic (or topic filter). This does not
+/// check whether the client exists.
+fn authorize(
+    server_accounts: &ServerAccounts,
+    username: &str,
+    topic: &str,
+    access: Access,
+) -> bool {
+    if server_accounts.is_server_account(username) {
+        return true;
+    }
+
+    if username.contains(&['+', '#', '/'][..]) {
+        return false;
+    }
+    let rest = match topic
+        .strip_prefix("kit/")
+        .and_then(|topic| topic.strip_prefix(username))
+        .and_then(|topic| topic.strip_prefix('/'))
+    {
+        Some(rest) => rest,
+        None => return false,
+    };
+
+    match access {
+        Access::Publish => {
+            let rest = rest.strip_suffix("/json").unwrap_or(rest);
+            if rest.contains(&['+', '#'][..]) {
+                return false;
+            }
+            KIT_PUBLISH_TOPICS.contains(&rest)
+                || matches!(
+                    rest.strip_prefix("preview/"),
+                    Some(session) if !session.is_empty() && !session.contains('/')
+                )
+        }
+        Access::Subscribe => {
+            let rest = rest
+                .strip_suffix("/json")
+                .or_else(|| rest.strip_suffix("/#"))
+                .unwrap_or(rest);
+            KIT_SUBSCRIBE_TOPICS.contains(&rest)
+        }
+    }
+}
+
+/// The response body expected by mosquitto-go-auth in its `json` response mode. The `status`
+/// response mode only looks at the status code.
+#[derive(Serialize, Debug)]
+struct MosquittoResult {
+    ok: bool,
+    error: String,
+}
+
+fn mosquitto_response(ok: bool) -> Response {
+    if ok {
+        ResponseBuilder::ok().body(MosquittoResult {
+            ok,
+            error: String::new(),
+        })
+    } else {
+        ResponseBuilder::new(StatusCode::FORBIDDEN).body(MosquittoResult {
+            ok,
+            error: "access denied".to_owned(),
+        })
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MosquittoUser {
+    username: String,
+    password: String,
+}
+
+/// Handles the `POST /mqtt-auth/mosquitto/user` route.
+pub async fn mosquitto_user(
+    _: Broker,
+    Extension(pg): Extension<PgPool>,
+    Extension(server_accounts): Extension<ServerAccounts>,
+    crate::extract::Json(user): crate::extract::Json<MosquittoUser>,
+) -> Result<Response, Problem> {
+    let ok = authenticate(pg, server_accounts, user.username, user.password).await?;
+    Ok(mosquitto_response(ok))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MosquittoSuperuser {
+    username: String,
+}
+
+/// Handles the `POST /mqtt-auth/mosquitto/superuser` route.
+pub async fn mosquitto_superuser(
+    _: Broker,
+    Extension(server_accounts): Extension<ServerAccounts>,
+    crate::extract::Json(user): crate::extract::Json<MosquittoSuperuser>,
+) -> Result<Response, Problem> {
+    Ok(mosquitto_response(
+        server_accounts.is_server_account(&user.username),
+    ))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MosquittoAcl {
+    username: String,
+    topic: String,
+    /// The requested access: 1 to read, 2 to write, 3 to read and write, and 4 to subscribe.
+    acc: u8,
+}
+
+/// Handles the `POST /mqtt-auth/mosquitto/acl` route.
+pub async fn mosquitto_acl(
+    _: Broker,
+    Extension(server_accounts): Extension<ServerAccounts>,
+    crate::extract::Json(acl): crate::extract::Json<MosquittoAcl>,
+) -> Result<Response, Problem> {
+    let access: &[Access] = match acl.acc {
+        1 | 4 => &[Access::Subscribe],
+        2 => &[Access::Publish],
+        3 => &[Access::Subscribe, Access::Publish],
+        _ => return Err(problem::BAD_REQUEST),
+    };
+    Ok(mosquitto_response(access.iter().all(|&access| {
+        authorize(&server_accounts, &acl.username, &acl.topic, access)
+    })))
+}
+
+/// The response body expected by EMQX.
+#[derive(Serialize, Debug)]
+struct EmqxResult {
+    result: &'static str,
+    is_superuser: bool,
+}
+
+fn emqx_response(allow: bool, is_superuser: bool) -> Response {
+    ResponseBuilder::ok().body(EmqxResult {
+        result: if allow { "allow" } else { "deny" },
+        is_superuser,
+    })
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EmqxAuthentication {
+    username: String,
+    password: String,
+}
+
+/// Handles the `POST /mqtt-auth/emqx/authenticate` route.
+pub async fn emqx_authenticate(
+    _: Broker,
+    Extension(pg): Extension<PgPool>,
+    Extension(server_accounts): Extension<ServerAccounts>,
+    crate::extract::Json(authentication): crate::extract::Json<EmqxAuthentication>,
+) -> Result<Response, Problem> {
+    let is_superuser = server_accounts.is_server_account(&authentication.username);
+    let allow = authenticate(
+        pg,
+        server_accounts,
+        authentication.username,
+        authentication.password,
+    )
+    .await?;
+    Ok(emqx_response(allow, allow && is_superuser))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EmqxAuthorization {
+    username: String,
+    topic: String,
+    action: Access,
+}
+
+/// Handles the `POST /mqtt-auth/emqx/authorize` route.
+pub async fn emqx_authorize(
+    _: Broker,
+    Extension(server_accounts): Extension<ServerAccounts>,
+    crate::extract::Json(authorization): crate::extract::Json<EmqxAuthorization>,
+) -> Result<Response, Problem> {
+    let allow = authorize(
+        &server_accounts,
+        &authorization.username,
+        &authorization.topic,
+        authorization.action,
+    );
+    Ok(emqx_response(allow, false))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{authorize, Access, ServerAccounts};
+
+    #[test]
+    pub fn kit_topics() {
+        let server_accounts = ServerAccounts::parse("astroplant-api:PBKDF2$sha256$1$a$b");
+        let publish = |username: &str, topic: &str| {
+            authorize(&server_accounts, username, topic, Access::Publish)
+        };
+        let subscribe = |username: &str, topic: &str| {
+            authorize(&server_accounts, username, topic, Access::Subscribe)
+        };
+
+        assert!(publish("k-abcd", "kit/k-abcd/measurement/raw"));
+        assert!(publish("k-abcd", "kit/k-abcd/measurement/raw/json"));
+        assert!(publish("k-abcd", "kit/k-abcd/kit-rpc/response"));
+        assert!(publish(
+            "k-abcd",
+            "kit/k-abcd/preview/0f8fad5b-d9cb-469f-a165-70867728950e"
+        ));
+        assert!(!publish("k-abcd", "kit/k-abcd/kit-rpc/request"));
+        assert!(!publish("k-abcd", "kit/k-abcd/server-rpc/response"));
+        assert!(!publish("k-abcd", "kit/k-abcde/media"));
+        assert!(!publish("k-abcd", "kit/k-abcd/preview/a/b"));
+        assert!(!publish("k-abcd", "kit/k-abcd"));
+
+        assert!(subscribe("k-abcd", "kit/k-abcd/kit-rpc/request"));
+        assert!(subscribe("k-abcd", "kit/k-abcd/kit-rpc/request/json"));
+        assert!(subscribe("k-abcd", "kit/k-abcd/server-rpc/response/#"));
+        assert!(!subscribe("k-abcd", "kit/k-abcd/measurement/raw"));
+        assert!(!subscribe("k-abcd", "kit/k-abcd/#"));
+        assert!(!subscribe("k-abcd", "kit/+/kit-rpc/request"));
+        assert!(!subscribe("k-abcd", "kit/#"));
+        assert!(!subscribe("k+", "kit/k+/kit-rpc/request"));
+
+        assert!(authorize(
+            &server_accounts,
+            "astroplant-api",
+            "kit/#",
+            Access::Subscribe
+        ));
+    }
+}
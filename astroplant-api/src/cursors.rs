@@ -64,3 +64,123 @@ impl Media {
         }
     }
 }
+
+#[derive(Deserialize, Serialize)]
+pub struct KitEvents(pub DateTime<Utc>, pub Uuid);
+
+impl FromStr for KitEvents {
+    type Err = Problem;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s).map_err(|_| BAD_REQUEST)
+    }
+}
+
+impl From<KitEvents> for String {
+    fn from(cursor: KitEvents) -> Self {
+        serde_json::to_string(&cursor).unwrap()
+    }
+}
+
+impl KitEvents {
+    pub const PER_PAGE: usize = 50;
+
+    pub fn next_from_page(page: &[models::KitEvent]) -> Option<Self> {
+        if page.len() >= Self::PER_PAGE {
+            let event = page.last().unwrap();
+            Some(Self(event.datetime, event.id))
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct KitAlerts(pub DateTime<Utc>, pub Uuid);
+
+impl FromStr for KitAlerts {
+    type Err = Problem;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s).map_err(|_| BAD_REQUEST)
+    }
+}
+
+impl From<KitAlerts> for String {
+    fn from(cursor: KitAlerts) -> Self {
+        serde_json::to_string(&cursor).unwrap()
+    }
+}
+
+impl KitAlerts {
+    pub const PER_PAGE: usize = 50;
+
+    pub fn next_from_page(page: &[models::KitAlert]) -> Option<Self> {
+        if page.len() >= Self::PER_PAGE {
+            let alert = page.last().unwrap();
+            Some(Self(alert.datetime, alert.id))
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct KitWebhookDeliveries(pub DateTime<Utc>, pub Uuid);
+
+impl FromStr for KitWebhookDeliveries {
+    type Err = Problem;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s).map_err(|_| BAD_REQUEST)
+    }
+}
+
+impl From<KitWebhookDeliveries> for String {
+    fn from(cursor: KitWebhookDeliveries) -> Self {
+        serde_json::to_string(&cursor).unwrap()
+    }
+}
+
+impl KitWebhookDeliveries {
+    pub const PER_PAGE: usize = 50;
+
+    pub fn next_from_page(page: &[models::KitWebhookDelivery]) -> Option<Self> {
+        if page.len() >= Self::PER_PAGE {
+            let delivery = page.last().unwrap();
+            Some(Self(delivery.datetime, delivery.id))
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ActuatorStates(pub DateTime<Utc>, pub Uuid);
+
+impl FromStr for ActuatorStates {
+    type Err = Problem;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s).map_err(|_| BAD_REQUEST)
+    }
+}
+
+impl From<ActuatorStates> for String {
+    fn from(cursor: ActuatorStates) -> Self {
+        serde_json::to_string(&cursor).unwrap()
+    }
+}
+
+impl ActuatorStates {
+    pub const PER_PAGE: usize = 50;
+
+    pub fn next_from_page(page: &[models::ActuatorState]) -> Option<Self> {
+        if page.len() >= Self::PER_PAGE {
+            let actuator_state = page.last().unwrap();
+            Some(Self(actuator_state.datetime, actuator_state.id))
+        } else {
+            None
+        }
+    }
+}
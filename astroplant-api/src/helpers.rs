@@ -226,3 +226,14 @@ where
         None => Ok(val),
     }
 }
+
+/// Compare a given secret to the expected secret in constant time (with respect to the contents of
+/// the secrets).
+pub fn secret_eq(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
@@ -24,7 +24,11 @@ pub mod models;
 pub mod response;
 pub mod views;
 
+pub mod alerts;
+pub mod bridge;
 pub mod mqtt;
+pub mod webhooks;
+pub mod websocket;
 
 static TOKEN_SIGNER: OnceCell<astroplant_auth::token::TokenSigner> = OnceCell::new();
 
@@ -0,0 +1,72 @@
+use crate::cursors;
+use crate::schema::actuator_states;
+
+use chrono::{DateTime, Utc};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::{Identifiable, QueryResult, Queryable};
+use uuid::Uuid;
+
+#[rustfmt::skip]
+use super::{
+    Kit, KitId,
+    KitConfiguration, KitConfigurationId,
+    Peripheral, PeripheralId,
+};
+
+#[derive(Clone, Debug, PartialEq, Queryable, Identifiable, Associations)]
+#[diesel(
+    belongs_to(Kit, foreign_key = kit_id),
+    belongs_to(KitId, foreign_key = kit_id),
+    belongs_to(KitConfiguration, foreign_key = kit_configuration_id),
+    belongs_to(KitConfigurationId, foreign_key = kit_configuration_id),
+    belongs_to(Peripheral, foreign_key = peripheral_id),
+    belongs_to(PeripheralId, foreign_key = peripheral_id),
+)]
+pub struct ActuatorState {
+    pub id: Uuid,
+    pub peripheral_id: i32,
+    pub kit_id: i32,
+    pub kit_configuration_id: i32,
+    pub datetime: DateTime<Utc>,
+    pub state: serde_json::Value,
+}
+
+impl ActuatorState {
+    pub fn page(
+        conn: &mut PgConnection,
+        kit_id: KitId,
+        configuration_id: Option<i32>,
+        peripheral_id: Option<i32>,
+        cursor: Option<cursors::ActuatorStates>,
+    ) -> QueryResult<Vec<Self>> {
+        let mut query = actuator_states::table
+            .filter(actuator_states::columns::kit_id.eq(kit_id.0))
+            .into_boxed();
+
+        if let Some(configuration_id) = configuration_id {
+            query =
+                query.filter(actuator_states::columns::kit_configuration_id.eq(configuration_id));
+        }
+        if let Some(peripheral_id) = peripheral_id {
+            query = query.filter(actuator_states::columns::peripheral_id.eq(peripheral_id));
+        }
+
+        if let Some(cursors::ActuatorStates(datetime, id)) = cursor {
+            query = query.filter(
+                actuator_states::columns::datetime.lt(datetime).or(
+                    actuator_states::columns::datetime
+                        .eq(datetime)
+                        .and(actuator_states::columns::id.lt(id)),
+                ),
+            )
+        }
+        query
+            .order((
+                actuator_states::dsl::datetime.desc(),
+                actuator_states::dsl::id.desc(),
+            ))
+            .limit(cursors::ActuatorStates::PER_PAGE as i64)
+            .load(conn)
+    }
+}
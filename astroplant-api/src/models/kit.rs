@@ -25,6 +25,20 @@ pub struct Kit {
     pub privacy_show_on_map: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub pending_password_hash: Option<String>,
+    pub pending_password_deadline: Option<DateTime<Utc>>,
+    pub privacy_bridge_measurements: bool,
+}
+
+/// The outcome of checking a kit's password, see [Kit::check_password].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum KitPasswordCheck {
+    Invalid,
+    /// The password is the kit's current password.
+    Current,
+    /// The password is the kit's pending password. The password rotation should be completed, see
+    /// [UpdateKit::complete_password_rotation].
+    Pending,
 }
 
 #[derive(Clone, Debug, PartialEq, Queryable, Identifiable, Associations)]
@@ -46,6 +60,7 @@ pub type BySerial<'a> = diesel::dsl::Filter<All, diesel::dsl::Eq<kits::serial, &
 pub type ShowOnMap = diesel::dsl::Eq<kits::privacy_show_on_map, bool>;
 pub type PublicDashboard = diesel::dsl::Eq<kits::privacy_public_dashboard, bool>;
 pub type Public = diesel::dsl::And<ShowOnMap, PublicDashboard>;
+pub type BridgeMeasurements = diesel::dsl::Eq<kits::privacy_bridge_measurements, bool>;
 
 impl Kit {
     pub fn all() -> All {
@@ -75,6 +90,11 @@ impl Kit {
         Self::show_on_map().and(Self::public_dashboard())
     }
 
+    /// Kits whose measurements are republished by the MQTT bridge
+    pub fn bridge_measurements() -> BridgeMeasurements {
+        kits::privacy_bridge_measurements.eq(true)
+    }
+
     pub fn cursor_page(
         conn: &mut PgConnection,
         after: Option<i32>,
@@ -95,6 +115,30 @@ impl Kit {
         KitId(self.id)
     }
 
+    /// Check a password against the kit's password. During password rotation, both the current
+    /// and the pending password are valid, until the rotation deadline passes. After that, only
+    /// the pending password is valid.
+    pub fn check_password(&self, password: &str, now: DateTime<Utc>) -> KitPasswordCheck {
+        use astroplant_auth::hash::check_kit_password;
+
+        if let (Some(pending_password_hash), Some(deadline)) =
+            (&self.pending_password_hash, self.pending_password_deadline)
+        {
+            if check_kit_password(password, pending_password_hash) {
+                return KitPasswordCheck::Pending;
+            }
+            if now >= deadline {
+                return KitPasswordCheck::Invalid;
+            }
+        }
+
+        if check_kit_password(password, &self.password_hash) {
+            KitPasswordCheck::Current
+        } else {
+            KitPasswordCheck::Invalid
+        }
+    }
+
     pub fn last_seen(&self, conn: &mut PgConnection) -> QueryResult<Option<DateTime<Utc>>> {
         kit_last_seen::table
             .select(kit_last_seen::datetime_last_seen)
@@ -116,6 +160,9 @@ pub struct UpdateKit {
     pub longitude: Option<Option<BigDecimal>>,
     pub privacy_public_dashboard: Option<bool>,
     pub privacy_show_on_map: Option<bool>,
+    pub privacy_bridge_measurements: Option<bool>,
+    pub pending_password_hash: Option<Option<String>>,
+    pub pending_password_deadline: Option<Option<DateTime<Utc>>>,
 }
 
 impl UpdateKit {
@@ -129,15 +176,40 @@ impl UpdateKit {
             longitude: None,
             privacy_public_dashboard: None,
             privacy_show_on_map: None,
+            privacy_bridge_measurements: None,
+            pending_password_hash: None,
+            pending_password_deadline: None,
         }
     }
 
+    /// Replace the kit's password immediately. This cancels any pending password rotation.
     pub fn reset_password(mut self) -> (Self, String) {
         let password = random_string::password();
         self.password_hash = Some(astroplant_auth::hash::hash_kit_password(&password));
+        self.pending_password_hash = Some(None);
+        self.pending_password_deadline = Some(None);
+        (self, password)
+    }
+
+    /// Start a password rotation: the kit's current password remains valid alongside the new
+    /// password until the kit first authenticates with the new password, or until the deadline
+    /// passes.
+    pub fn rotate_password(mut self, deadline: DateTime<Utc>) -> (Self, String) {
+        let password = random_string::password();
+        self.pending_password_hash =
+            Some(Some(astroplant_auth::hash::hash_kit_password(&password)));
+        self.pending_password_deadline = Some(Some(deadline));
         (self, password)
     }
 
+    /// Replace the kit's current password by its pending password.
+    pub fn complete_password_rotation(mut self, pending_password_hash: String) -> Self {
+        self.password_hash = Some(pending_password_hash);
+        self.pending_password_hash = Some(None);
+        self.pending_password_deadline = Some(None);
+        self
+    }
+
     pub fn update(&self, conn: &mut PgConnection) -> QueryResult<Kit> {
         self.save_changes(conn)
     }
@@ -202,3 +274,50 @@ impl NewKit {
             .get_result::<Kit>(conn)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{Kit, KitPasswordCheck};
+    use astroplant_auth::hash::hash_kit_password;
+    use chrono::{Duration, Utc};
+
+    fn kit(password: &str, pending_password: Option<&str>) -> Kit {
+        let now = Utc::now();
+        Kit {
+            id: 1,
+            serial: "k-abcd".to_owned(),
+            password_hash: hash_kit_password(password),
+            name: None,
+            description: None,
+            latitude: None,
+            longitude: None,
+            privacy_public_dashboard: false,
+            privacy_show_on_map: false,
+            created_at: now,
+            updated_at: now,
+            pending_password_hash: pending_password.map(hash_kit_password),
+            pending_password_deadline: pending_password.map(|_| now + Duration::days(7)),
+            privacy_bridge_measurements: false,
+        }
+    }
+
+    #[test]
+    fn password_rotation() {
+        let now = Utc::now();
+        let kit = kit("old", Some("new"));
+
+        assert_eq!(kit.check_password("old", now), KitPasswordCheck::Current);
+        assert_eq!(kit.check_password("new", now), KitPasswordCheck::Pending);
+        assert_eq!(kit.check_password("other", now), KitPasswordCheck::Invalid);
+
+        let after_deadline = now + Duration::days(8);
+        assert_eq!(
+            kit.check_password("old", after_deadline),
+            KitPasswordCheck::Invalid
+        );
+        assert_eq!(
+            kit.check_password("new", after_deadline),
+            KitPasswordCheck::Pending
+        );
+    }
+}
@@ -0,0 +1,117 @@
+use crate::cursors;
+use crate::schema::kit_alerts;
+
+use chrono::{DateTime, Utc};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::{Identifiable, QueryResult, Queryable};
+use uuid::Uuid;
+
+use super::{Kit, KitAlertRule, KitAlertRuleId, KitId};
+
+/// A transition of an alert rule into (firing) or out of (resolved) the alerting state.
+#[derive(Clone, Debug, PartialEq, Queryable, Identifiable, Associations)]
+#[diesel(
+    table_name = kit_alerts,
+    belongs_to(Kit, foreign_key = kit_id),
+    belongs_to(KitId, foreign_key = kit_id),
+    belongs_to(KitAlertRule, foreign_key = kit_alert_rule_id),
+    belongs_to(KitAlertRuleId, foreign_key = kit_alert_rule_id),
+)]
+pub struct KitAlert {
+    pub id: Uuid,
+    pub kit_id: i32,
+    pub kit_alert_rule_id: i32,
+    pub datetime: DateTime<Utc>,
+    pub firing: bool,
+    /// The measured value, or for rate of change rules the change per minute, that caused the
+    /// transition. `None` for absence rules that started firing.
+    pub value: Option<f64>,
+}
+
+impl KitAlert {
+    /// A page of the kit's alert transitions, most recent first.
+    pub fn page(
+        conn: &mut PgConnection,
+        kit_id: KitId,
+        kit_alert_rule_id: Option<i32>,
+        cursor: Option<cursors::KitAlerts>,
+    ) -> QueryResult<Vec<Self>> {
+        let mut query = kit_alerts::table
+            .filter(kit_alerts::columns::kit_id.eq(kit_id.0))
+            .into_boxed();
+
+        if let Some(kit_alert_rule_id) = kit_alert_rule_id {
+            query = query.filter(kit_alerts::columns::kit_alert_rule_id.eq(kit_alert_rule_id));
+        }
+
+        if let Some(cursors::KitAlerts(datetime, id)) = cursor {
+            query = query.filter(
+                kit_alerts::columns::datetime
+                    .lt(datetime)
+                    .or(kit_alerts::columns::datetime
+                        .eq(datetime)
+                        .and(kit_alerts::columns::id.lt(id))),
+            )
+        }
+        query
+            .order((kit_alerts::dsl::datetime.desc(), kit_alerts::dsl::id.desc()))
+            .limit(cursors::KitAlerts::PER_PAGE as i64)
+            .load(conn)
+    }
+
+    /// The ids of the alert rules whose latest transition is into the alerting state.
+    pub fn firing_rule_ids(conn: &mut PgConnection) -> QueryResult<Vec<i32>> {
+        let latest: Vec<(i32, bool)> = kit_alerts::table
+            .select((kit_alerts::kit_alert_rule_id, kit_alerts::firing))
+            .distinct_on(kit_alerts::kit_alert_rule_id)
+            .order((
+                kit_alerts::kit_alert_rule_id,
+                kit_alerts::datetime.desc(),
+                kit_alerts::id.desc(),
+            ))
+            .load(conn)?;
+
+        Ok(latest
+            .into_iter()
+            .filter(|(_, firing)| *firing)
+            .map(|(kit_alert_rule_id, _)| kit_alert_rule_id)
+            .collect())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Insertable)]
+#[diesel(table_name = kit_alerts)]
+pub struct NewKitAlert {
+    pub id: Uuid,
+    pub kit_id: i32,
+    pub kit_alert_rule_id: i32,
+    pub datetime: DateTime<Utc>,
+    pub firing: bool,
+    pub value: Option<f64>,
+}
+
+impl NewKitAlert {
+    pub fn new(
+        kit_id: KitId,
+        kit_alert_rule_id: KitAlertRuleId,
+        datetime: DateTime<Utc>,
+        firing: bool,
+        value: Option<f64>,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            kit_id: kit_id.0,
+            kit_alert_rule_id: kit_alert_rule_id.0,
+            datetime,
+            firing,
+            value,
+        }
+    }
+
+    pub fn create(&self, conn: &mut PgConnection) -> QueryResult<KitAlert> {
+        diesel::insert_into(kit_alerts::table)
+            .values(self)
+            .get_result(conn)
+    }
+}
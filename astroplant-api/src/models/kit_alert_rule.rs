@@ -0,0 +1,125 @@
+use crate::schema::{kit_alert_rules, kits};
+
+use chrono::{DateTime, Utc};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::{Identifiable, QueryResult, Queryable};
+use validator::Validate;
+
+#[rustfmt::skip]
+use super::{
+    Kit, KitId,
+    Peripheral, PeripheralId,
+    QuantityTypeId,
+};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Identifiable)]
+#[diesel(table_name = kit_alert_rules)]
+pub struct KitAlertRuleId(#[diesel(column_name = id)] pub i32);
+
+/// An alert rule on a kit's raw measurements of a peripheral and quantity type. The condition is
+/// stored as its kind, comparison and threshold, see [crate::alerts::Condition].
+#[derive(Clone, Debug, PartialEq, Queryable, Identifiable, Associations)]
+#[diesel(
+    table_name = kit_alert_rules,
+    belongs_to(Kit, foreign_key = kit_id),
+    belongs_to(KitId, foreign_key = kit_id),
+    belongs_to(Peripheral, foreign_key = peripheral_id),
+    belongs_to(PeripheralId, foreign_key = peripheral_id),
+)]
+pub struct KitAlertRule {
+    pub id: i32,
+    pub kit_id: i32,
+    pub peripheral_id: i32,
+    pub quantity_type_id: i32,
+    pub name: String,
+    pub kind: String,
+    pub comparison: Option<String>,
+    pub threshold: Option<f64>,
+    pub duration_seconds: i32,
+    pub datetime_created: DateTime<Utc>,
+}
+
+impl KitAlertRule {
+    pub fn by_id(
+        conn: &mut PgConnection,
+        kit_alert_rule_id: KitAlertRuleId,
+    ) -> QueryResult<Option<Self>> {
+        kit_alert_rules::table
+            .find(kit_alert_rule_id.0)
+            .first(conn)
+            .optional()
+    }
+
+    pub fn get_id(&self) -> KitAlertRuleId {
+        KitAlertRuleId(self.id)
+    }
+
+    pub fn get_kit_id(&self) -> KitId {
+        KitId(self.kit_id)
+    }
+
+    pub fn rules_of_kit_id(conn: &mut PgConnection, kit_id: KitId) -> QueryResult<Vec<Self>> {
+        KitAlertRule::belonging_to(&kit_id)
+            .order(kit_alert_rules::id)
+            .load(conn)
+    }
+
+    /// All alert rules, with the serials of the kits they belong to.
+    pub fn all_with_kit_serials(conn: &mut PgConnection) -> QueryResult<Vec<(Self, String)>> {
+        kit_alert_rules::table
+            .inner_join(kits::table)
+            .select((kit_alert_rules::all_columns, kits::serial))
+            .load(conn)
+    }
+
+    pub fn delete(&self, conn: &mut PgConnection) -> QueryResult<bool> {
+        diesel::delete(self).execute(conn).map(|r| r > 0)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Insertable, Validate)]
+#[diesel(table_name = kit_alert_rules)]
+pub struct NewKitAlertRule {
+    pub kit_id: i32,
+    pub peripheral_id: i32,
+    pub quantity_type_id: i32,
+    #[validate(length(min = 1, max = 40))]
+    pub name: String,
+    pub kind: String,
+    pub comparison: Option<String>,
+    pub threshold: Option<f64>,
+    #[validate(range(min = 0))]
+    pub duration_seconds: i32,
+}
+
+impl NewKitAlertRule {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        kit_id: KitId,
+        peripheral_id: PeripheralId,
+        quantity_type_id: QuantityTypeId,
+        name: String,
+        kind: String,
+        comparison: Option<String>,
+        threshold: Option<f64>,
+        duration_seconds: i32,
+    ) -> Self {
+        Self {
+            kit_id: kit_id.0,
+            peripheral_id: peripheral_id.0,
+            quantity_type_id: quantity_type_id.0,
+            name,
+            kind,
+            comparison,
+            threshold,
+            duration_seconds,
+        }
+    }
+
+    pub fn create(&self, conn: &mut PgConnection) -> QueryResult<KitAlertRule> {
+        diesel::insert_into(kit_alert_rules::table)
+            .values(self)
+            .get_result(conn)
+    }
+}
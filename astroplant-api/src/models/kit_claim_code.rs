@@ -0,0 +1,112 @@
+use crate::schema::kit_claim_codes;
+
+use chrono::{DateTime, Utc};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::{QueryResult, Queryable};
+
+use super::{Kit, KitId};
+
+/// A one-time code with which a user can claim an unclaimed kit.
+#[derive(Clone, Debug, PartialEq, Eq, Queryable, Identifiable, Associations)]
+#[diesel(
+    table_name = kit_claim_codes,
+    primary_key(kit_id),
+    belongs_to(KitId, foreign_key = kit_id),
+    belongs_to(Kit, foreign_key = kit_id),
+)]
+pub struct KitClaimCode {
+    pub kit_id: i32,
+    pub claim_code_hash: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl KitClaimCode {
+    /// Normalize a claim code as entered by a user: separators and whitespace are ignored, and
+    /// the code is case-insensitive.
+    fn normalize(claim_code: &str) -> String {
+        claim_code
+            .chars()
+            .filter(|c| *c != '-' && !c.is_whitespace())
+            .flat_map(char::to_lowercase)
+            .collect()
+    }
+
+    /// Delete the claim code and return it, if it exists. A claim code can thus be used only once.
+    pub fn take(conn: &mut PgConnection, claim_code: &str) -> QueryResult<Option<Self>> {
+        let claim_code_hash = astroplant_auth::hash::hash_claim_code(&Self::normalize(claim_code));
+
+        diesel::delete(
+            kit_claim_codes::table.filter(kit_claim_codes::claim_code_hash.eq(claim_code_hash)),
+        )
+        .get_result(conn)
+        .optional()
+    }
+
+    /// Delete the claim codes created before the given time, returning how many were deleted.
+    pub fn delete_created_before(
+        conn: &mut PgConnection,
+        created_before: DateTime<Utc>,
+    ) -> QueryResult<usize> {
+        diesel::delete(
+            kit_claim_codes::table.filter(kit_claim_codes::created_at.lt(created_before)),
+        )
+        .execute(conn)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Insertable)]
+#[diesel(table_name = kit_claim_codes)]
+pub struct NewKitClaimCode {
+    pub kit_id: i32,
+    pub claim_code_hash: String,
+}
+
+impl NewKitClaimCode {
+    /// Generate a claim code for the kit. The claim code is returned formatted for printing, as
+    /// three groups of four characters, e.g. `k3xw-p7ra-mc4t`.
+    pub fn new_with_generated_code(kit_id: KitId) -> (Self, String) {
+        // Roughly 55 bits of entropy.
+        let claim_code = random_string::unambiguous_lowercase_string(12);
+        let claim_code_hash = astroplant_auth::hash::hash_claim_code(&claim_code);
+
+        let formatted = format!(
+            "{}-{}-{}",
+            &claim_code[0..4],
+            &claim_code[4..8],
+            &claim_code[8..12]
+        );
+
+        (
+            Self {
+                kit_id: kit_id.0,
+                claim_code_hash,
+            },
+            formatted,
+        )
+    }
+
+    pub fn create(&self, conn: &mut PgConnection) -> QueryResult<KitClaimCode> {
+        diesel::insert_into(kit_claim_codes::table)
+            .values(self)
+            .get_result(conn)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{KitClaimCode, NewKitClaimCode};
+    use crate::models::KitId;
+
+    #[test]
+    pub fn claim_code_normalization() {
+        let (new_claim_code, claim_code) = NewKitClaimCode::new_with_generated_code(KitId(1));
+        assert_eq!(claim_code.len(), 14);
+
+        let entered = format!(" {} ", claim_code.to_uppercase().replace('-', " "));
+        assert_eq!(
+            astroplant_auth::hash::hash_claim_code(&KitClaimCode::normalize(&entered)),
+            new_claim_code.claim_code_hash
+        );
+    }
+}
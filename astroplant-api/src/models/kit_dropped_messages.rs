@@ -0,0 +1,84 @@
+use crate::schema::kit_dropped_messages;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::{QueryResult, Queryable};
+
+use super::{Kit, KitId};
+
+/// The number of messages published by a kit on one day that were dropped for exceeding the kit's
+/// ingress quota.
+#[derive(Clone, Debug, PartialEq, Eq, Queryable, Identifiable, Associations)]
+#[diesel(
+    table_name = kit_dropped_messages,
+    primary_key(kit_id, date),
+    belongs_to(KitId, foreign_key = kit_id),
+    belongs_to(Kit, foreign_key = kit_id),
+)]
+pub struct KitDroppedMessages {
+    pub kit_id: i32,
+    pub date: NaiveDate,
+    pub messages: i64,
+    pub bytes: i64,
+    pub datetime_last_dropped: DateTime<Utc>,
+}
+
+impl KitDroppedMessages {
+    /// The dropped messages of the kit, per day, starting at `since`. Ordered by date, most recent
+    /// first.
+    pub fn of_kit_since(
+        conn: &mut PgConnection,
+        kit_id: KitId,
+        since: NaiveDate,
+    ) -> QueryResult<Vec<Self>> {
+        KitDroppedMessages::belonging_to(&kit_id)
+            .filter(kit_dropped_messages::date.ge(since))
+            .order(kit_dropped_messages::date.desc())
+            .load(conn)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Insertable)]
+#[diesel(table_name = kit_dropped_messages)]
+pub struct NewKitDroppedMessages {
+    pub kit_id: i32,
+    pub date: NaiveDate,
+    pub messages: i64,
+    pub bytes: i64,
+    pub datetime_last_dropped: DateTime<Utc>,
+}
+
+impl NewKitDroppedMessages {
+    pub fn new(
+        kit_id: KitId,
+        messages: i64,
+        bytes: i64,
+        datetime_last_dropped: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            kit_id: kit_id.0,
+            date: datetime_last_dropped.date_naive(),
+            messages,
+            bytes,
+            datetime_last_dropped,
+        }
+    }
+
+    /// Add these counts to the kit's counts of the day.
+    pub fn add(&self, conn: &mut PgConnection) -> QueryResult<KitDroppedMessages> {
+        use crate::schema::kit_dropped_messages::dsl::*;
+        use diesel::upsert::excluded;
+
+        diesel::insert_into(kit_dropped_messages)
+            .values(self)
+            .on_conflict((kit_id, date))
+            .do_update()
+            .set((
+                messages.eq(messages + excluded(messages)),
+                bytes.eq(bytes + excluded(bytes)),
+                datetime_last_dropped.eq(excluded(datetime_last_dropped)),
+            ))
+            .get_result(conn)
+    }
+}
@@ -0,0 +1,66 @@
+use crate::cursors;
+use crate::schema::kit_events;
+
+use chrono::{DateTime, Utc};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::{Identifiable, QueryResult, Queryable};
+use uuid::Uuid;
+
+use super::{Kit, KitId, Peripheral, PeripheralId};
+
+/// An event reported by a kit. The severity is stored as its numeric level, see
+/// [astroplant_mqtt::Severity].
+#[derive(Clone, Debug, PartialEq, Eq, Queryable, Identifiable, Associations)]
+#[diesel(
+    table_name = kit_events,
+    belongs_to(Kit, foreign_key = kit_id),
+    belongs_to(KitId, foreign_key = kit_id),
+    belongs_to(Peripheral, foreign_key = peripheral_id),
+    belongs_to(PeripheralId, foreign_key = peripheral_id),
+)]
+pub struct KitEvent {
+    pub id: Uuid,
+    pub kit_id: i32,
+    pub peripheral_id: Option<i32>,
+    pub datetime: DateTime<Utc>,
+    pub severity: i16,
+    pub message: String,
+}
+
+impl KitEvent {
+    /// A page of the kit's events, most recent first. Only events of at least `min_severity` are
+    /// included.
+    pub fn page(
+        conn: &mut PgConnection,
+        kit_id: KitId,
+        peripheral_id: Option<i32>,
+        min_severity: Option<astroplant_mqtt::Severity>,
+        cursor: Option<cursors::KitEvents>,
+    ) -> QueryResult<Vec<Self>> {
+        let mut query = kit_events::table
+            .filter(kit_events::columns::kit_id.eq(kit_id.0))
+            .into_boxed();
+
+        if let Some(peripheral_id) = peripheral_id {
+            query = query.filter(kit_events::columns::peripheral_id.eq(peripheral_id));
+        }
+        if let Some(min_severity) = min_severity {
+            query = query.filter(kit_events::columns::severity.ge(min_severity as i16));
+        }
+
+        if let Some(cursors::KitEvents(datetime, id)) = cursor {
+            query = query.filter(
+                kit_events::columns::datetime
+                    .lt(datetime)
+                    .or(kit_events::columns::datetime
+                        .eq(datetime)
+                        .and(kit_events::columns::id.lt(id))),
+            )
+        }
+        query
+            .order((kit_events::dsl::datetime.desc(), kit_events::dsl::id.desc()))
+            .limit(cursors::KitEvents::PER_PAGE as i64)
+            .load(conn)
+    }
+}
@@ -0,0 +1,45 @@
+use crate::schema::kit_retention_policies;
+
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::{QueryResult, Queryable};
+
+use super::{Kit, KitId};
+
+/// A kit's retention policy of raw measurements, overriding the instance-wide policy.
+#[derive(Clone, Debug, PartialEq, Eq, Queryable, Identifiable, Associations, Insertable)]
+#[diesel(
+    table_name = kit_retention_policies,
+    primary_key(kit_id),
+    belongs_to(KitId, foreign_key = kit_id),
+    belongs_to(Kit, foreign_key = kit_id),
+)]
+pub struct KitRetentionPolicy {
+    pub kit_id: i32,
+    /// The number of days raw measurements are retained. If `None`, raw measurements are retained
+    /// indefinitely.
+    pub raw_measurement_days: Option<i32>,
+    /// If set, raw measurements older than the retention period are downsampled to one per window
+    /// of this many minutes, rather than deleted.
+    pub downsample_minutes: Option<i32>,
+}
+
+impl KitRetentionPolicy {
+    /// Set the kit's retention policy, replacing its existing policy.
+    pub fn upsert(&self, conn: &mut PgConnection) -> QueryResult<Self> {
+        diesel::insert_into(kit_retention_policies::table)
+            .values(self)
+            .on_conflict(kit_retention_policies::kit_id)
+            .do_update()
+            .set((
+                kit_retention_policies::raw_measurement_days.eq(self.raw_measurement_days),
+                kit_retention_policies::downsample_minutes.eq(self.downsample_minutes),
+            ))
+            .get_result(conn)
+    }
+
+    /// Delete the kit's retention policy, such that the instance-wide policy applies.
+    pub fn delete_of_kit(conn: &mut PgConnection, kit_id: KitId) -> QueryResult<usize> {
+        diesel::delete(kit_retention_policies::table.find(kit_id.0)).execute(conn)
+    }
+}
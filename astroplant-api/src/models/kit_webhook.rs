@@ -0,0 +1,105 @@
+use crate::schema::{kit_webhooks, kits};
+
+use chrono::{DateTime, Utc};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::{Identifiable, QueryResult, Queryable};
+use validator::Validate;
+
+use super::{Kit, KitId};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Identifiable)]
+#[diesel(table_name = kit_webhooks)]
+pub struct KitWebhookId(#[diesel(column_name = id)] pub i32);
+
+/// A webhook receiving a kit's events of the given types, see [crate::webhooks::EventType].
+#[derive(Clone, Debug, PartialEq, Eq, Queryable, Identifiable, Associations)]
+#[diesel(
+    table_name = kit_webhooks,
+    belongs_to(Kit, foreign_key = kit_id),
+    belongs_to(KitId, foreign_key = kit_id),
+)]
+pub struct KitWebhook {
+    pub id: i32,
+    pub kit_id: i32,
+    pub url: String,
+    /// The secret signing the webhook's deliveries.
+    pub secret: String,
+    pub event_types: Vec<String>,
+    pub datetime_created: DateTime<Utc>,
+}
+
+impl KitWebhook {
+    pub fn by_id(
+        conn: &mut PgConnection,
+        kit_webhook_id: KitWebhookId,
+    ) -> QueryResult<Option<Self>> {
+        kit_webhooks::table
+            .find(kit_webhook_id.0)
+            .first(conn)
+            .optional()
+    }
+
+    pub fn get_id(&self) -> KitWebhookId {
+        KitWebhookId(self.id)
+    }
+
+    pub fn get_kit_id(&self) -> KitId {
+        KitId(self.kit_id)
+    }
+
+    pub fn webhooks_of_kit_id(conn: &mut PgConnection, kit_id: KitId) -> QueryResult<Vec<Self>> {
+        KitWebhook::belonging_to(&kit_id)
+            .order(kit_webhooks::id)
+            .load(conn)
+    }
+
+    /// The webhooks of the kit receiving events of the type.
+    pub fn subscribed_of_kit_serial(
+        conn: &mut PgConnection,
+        kit_serial: &str,
+        event_type: &str,
+    ) -> QueryResult<Vec<Self>> {
+        kit_webhooks::table
+            .inner_join(kits::table)
+            .filter(kits::serial.eq(kit_serial))
+            .filter(kit_webhooks::event_types.contains(vec![event_type]))
+            .select(kit_webhooks::all_columns)
+            .load(conn)
+    }
+
+    pub fn delete(&self, conn: &mut PgConnection) -> QueryResult<bool> {
+        diesel::delete(self).execute(conn).map(|r| r > 0)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Insertable, Validate)]
+#[diesel(table_name = kit_webhooks)]
+pub struct NewKitWebhook {
+    pub kit_id: i32,
+    #[validate(url, length(max = 2048))]
+    pub url: String,
+    pub secret: String,
+    #[validate(length(min = 1))]
+    pub event_types: Vec<String>,
+}
+
+impl NewKitWebhook {
+    /// Create a new webhook with a randomly generated secret.
+    pub fn new_with_generated_secret(kit_id: KitId, url: String, event_types: Vec<String>) -> Self {
+        const SECRET_LENGTH: usize = 40;
+
+        Self {
+            kit_id: kit_id.0,
+            url,
+            secret: random_string::string(SECRET_LENGTH),
+            event_types,
+        }
+    }
+
+    pub fn create(&self, conn: &mut PgConnection) -> QueryResult<KitWebhook> {
+        diesel::insert_into(kit_webhooks::table)
+            .values(self)
+            .get_result(conn)
+    }
+}
@@ -0,0 +1,82 @@
+use crate::cursors;
+use crate::schema::kit_webhook_deliveries;
+
+use chrono::{DateTime, Utc};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::{Identifiable, QueryResult, Queryable};
+use uuid::Uuid;
+
+use super::{KitWebhook, KitWebhookId};
+
+/// An attempt at delivering an event to a webhook.
+#[derive(Clone, Debug, PartialEq, Eq, Queryable, Identifiable, Associations)]
+#[diesel(
+    table_name = kit_webhook_deliveries,
+    belongs_to(KitWebhook, foreign_key = kit_webhook_id),
+    belongs_to(KitWebhookId, foreign_key = kit_webhook_id),
+)]
+pub struct KitWebhookDelivery {
+    pub id: Uuid,
+    pub kit_webhook_id: i32,
+    pub event_id: Uuid,
+    pub event_type: String,
+    /// The attempt number, starting at 1.
+    pub attempt: i32,
+    pub datetime: DateTime<Utc>,
+    /// The response's status code, if the webhook responded.
+    pub status_code: Option<i32>,
+    /// Why the attempt failed, if it did.
+    pub error: Option<String>,
+    pub succeeded: bool,
+}
+
+impl KitWebhookDelivery {
+    /// A page of the webhook's delivery attempts, most recent first.
+    pub fn page(
+        conn: &mut PgConnection,
+        kit_webhook_id: KitWebhookId,
+        cursor: Option<cursors::KitWebhookDeliveries>,
+    ) -> QueryResult<Vec<Self>> {
+        let mut query = KitWebhookDelivery::belonging_to(&kit_webhook_id).into_boxed();
+
+        if let Some(cursors::KitWebhookDeliveries(datetime, id)) = cursor {
+            query = query.filter(
+                kit_webhook_deliveries::columns::datetime.lt(datetime).or(
+                    kit_webhook_deliveries::columns::datetime
+                        .eq(datetime)
+                        .and(kit_webhook_deliveries::columns::id.lt(id)),
+                ),
+            )
+        }
+        query
+            .order((
+                kit_webhook_deliveries::dsl::datetime.desc(),
+                kit_webhook_deliveries::dsl::id.desc(),
+            ))
+            .limit(cursors::KitWebhookDeliveries::PER_PAGE as i64)
+            .load(conn)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Insertable)]
+#[diesel(table_name = kit_webhook_deliveries)]
+pub struct NewKitWebhookDelivery {
+    pub id: Uuid,
+    pub kit_webhook_id: i32,
+    pub event_id: Uuid,
+    pub event_type: String,
+    pub attempt: i32,
+    pub datetime: DateTime<Utc>,
+    pub status_code: Option<i32>,
+    pub error: Option<String>,
+    pub succeeded: bool,
+}
+
+impl NewKitWebhookDelivery {
+    pub fn create(&self, conn: &mut PgConnection) -> QueryResult<KitWebhookDelivery> {
+        diesel::insert_into(kit_webhook_deliveries::table)
+            .values(self)
+            .get_result(conn)
+    }
+}
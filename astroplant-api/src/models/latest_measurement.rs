@@ -0,0 +1,49 @@
+use crate::schema::latest_measurements;
+
+use chrono::{DateTime, Utc};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::{QueryResult, Queryable};
+use uuid::Uuid;
+
+#[rustfmt::skip]
+use super::{
+    Kit, KitId,
+    Peripheral, PeripheralId,
+    QuantityType, QuantityTypeId,
+};
+
+/// The newest raw and aggregate measurement of a peripheral and quantity type.
+#[derive(Clone, Debug, PartialEq, Queryable, Associations)]
+#[diesel(
+    belongs_to(Kit, foreign_key = kit_id),
+    belongs_to(KitId, foreign_key = kit_id),
+    belongs_to(Peripheral, foreign_key = peripheral_id),
+    belongs_to(PeripheralId, foreign_key = peripheral_id),
+    belongs_to(QuantityType, foreign_key = quantity_type_id),
+    belongs_to(QuantityTypeId, foreign_key = quantity_type_id),
+)]
+pub struct LatestMeasurement {
+    pub peripheral_id: i32,
+    pub quantity_type_id: i32,
+    pub kit_id: i32,
+    pub raw_measurement_id: Option<Uuid>,
+    pub raw_value: Option<f64>,
+    pub raw_datetime: Option<DateTime<Utc>>,
+    pub aggregate_measurement_id: Option<Uuid>,
+    pub aggregate_values: Option<serde_json::Value>,
+    pub aggregate_datetime_start: Option<DateTime<Utc>>,
+    pub aggregate_datetime_end: Option<DateTime<Utc>>,
+}
+
+impl LatestMeasurement {
+    pub fn by_kit_id(conn: &mut PgConnection, kit_id: KitId) -> QueryResult<Vec<Self>> {
+        latest_measurements::table
+            .filter(latest_measurements::columns::kit_id.eq(kit_id.0))
+            .order((
+                latest_measurements::columns::peripheral_id,
+                latest_measurements::columns::quantity_type_id,
+            ))
+            .load(conn)
+    }
+}
@@ -40,6 +40,9 @@ pub struct AggregateMeasurement {
     pub datetime_start: DateTime<Utc>,
     pub datetime_end: DateTime<Utc>,
     pub values: serde_json::Value,
+    /// Whether this aggregate was computed by the server from raw measurements, rather than
+    /// published by the kit.
+    pub server_computed: bool,
 }
 
 impl AggregateMeasurement {
@@ -48,7 +51,7 @@ impl AggregateMeasurement {
         aggregate_measurement_id: AggregateMeasurementId,
     ) -> QueryResult<Option<Self>> {
         aggregate_measurements::table
-            .find(&aggregate_measurement_id.0)
+            .filter(aggregate_measurements::columns::id.eq(&aggregate_measurement_id.0))
             .first(conn)
             .optional()
     }
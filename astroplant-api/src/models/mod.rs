@@ -1,9 +1,33 @@
 mod kit;
-pub use kit::{Kit, KitLastSeen, KitId, NewKit, UpdateKit};
+pub use kit::{Kit, KitLastSeen, KitId, KitPasswordCheck, NewKit, UpdateKit};
 
 mod user;
 pub use user::{NewUser, UpdateUser, User, UserId};
 
+mod kit_alert_rule;
+pub use kit_alert_rule::{KitAlertRule, KitAlertRuleId, NewKitAlertRule};
+
+mod kit_alert;
+pub use kit_alert::{KitAlert, NewKitAlert};
+
+mod kit_claim_code;
+pub use kit_claim_code::{KitClaimCode, NewKitClaimCode};
+
+mod kit_dropped_messages;
+pub use kit_dropped_messages::{KitDroppedMessages, NewKitDroppedMessages};
+
+mod kit_event;
+pub use kit_event::KitEvent;
+
+mod kit_retention_policy;
+pub use kit_retention_policy::KitRetentionPolicy;
+
+mod kit_webhook;
+pub use kit_webhook::{KitWebhook, KitWebhookId, NewKitWebhook};
+
+mod kit_webhook_delivery;
+pub use kit_webhook_delivery::{KitWebhookDelivery, NewKitWebhookDelivery};
+
 mod kit_membership;
 pub use kit_membership::{KitMembership, NewKitMembership};
 
@@ -29,5 +53,11 @@ pub use peripheral_definition_expected_quantity_type::PeripheralDefinitionExpect
 mod measurement;
 pub use measurement::{AggregateMeasurement, AggregateMeasurementId};
 
+mod latest_measurement;
+pub use latest_measurement::LatestMeasurement;
+
+mod actuator_state;
+pub use actuator_state::ActuatorState;
+
 mod media;
 pub use media::{Media, MediaId, NewMedia};
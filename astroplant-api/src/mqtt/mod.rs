@@ -1,15 +1,23 @@
 use crate::database::PgPool;
+use crate::webhooks::{EventType, Webhooks};
 use crate::{models, problem, views};
 
-use astroplant_mqtt::{ConnectionBuilder, Message, RpcError};
+use astroplant_mqtt::{
+    ConnectionBuilder, ConnectionStateRx, IngressQuota, Message, QuotaExceeded, RpcError,
+};
+use chrono::{DateTime, Utc};
 use futures::channel::mpsc;
 use futures::sink::SinkExt;
 use futures::stream::StreamExt;
+use std::collections::HashMap;
 use std::convert::TryFrom;
+use std::time::{Duration, Instant};
 
 async fn upload_media(
     pg_pool: PgPool,
     object_store: astroplant_object::ObjectStore,
+    webhooks: Webhooks,
+    ws_publisher: astroplant_websocket::Publisher,
     media: astroplant_mqtt::Media,
 ) {
     let implementation = move || async move {
@@ -60,7 +68,7 @@ async fn upload_media(
         };
 
         let conn = pg_pool.get().await?;
-        if let Err(_) = conn
+        match conn
             .interact(move |conn| {
                 let new = models::NewMedia::new(
                     id,
@@ -77,7 +85,17 @@ async fn upload_media(
             })
             .await
         {
-            // TODO: Failed to insert into database, remove object
+            Ok(Ok(media)) => {
+                if let Ok(media) = views::Media::try_from(media) {
+                    if let Ok(value) = serde_json::to_value(&media) {
+                        ws_publisher.publish_media(&kit_serial, value).await;
+                    }
+                    webhooks.dispatch(kit_serial, EventType::Media, media);
+                }
+            }
+            _ => {
+                // TODO: Failed to insert into database, remove object
+            }
         }
 
         Ok::<(), problem::Problem>(())
@@ -88,6 +106,88 @@ async fn upload_media(
     }
 }
 
+/// Messages of a kit that were dropped for exceeding the kit's ingress quota, and that are yet to be
+/// recorded in the database.
+struct DroppedMessages {
+    messages: i64,
+    bytes: i64,
+    datetime_last_dropped: DateTime<Utc>,
+}
+
+impl DroppedMessages {
+    fn add(&mut self, quota_exceeded: QuotaExceeded) {
+        self.messages += 1;
+        self.bytes += quota_exceeded.bytes as i64;
+        self.datetime_last_dropped = quota_exceeded.datetime;
+    }
+}
+
+impl From<QuotaExceeded> for DroppedMessages {
+    fn from(quota_exceeded: QuotaExceeded) -> Self {
+        Self {
+            messages: 1,
+            bytes: quota_exceeded.bytes as i64,
+            datetime_last_dropped: quota_exceeded.datetime,
+        }
+    }
+}
+
+async fn record_dropped_messages(pg_pool: PgPool, dropped: HashMap<String, DroppedMessages>) {
+    let implementation = move || async move {
+        let conn = pg_pool.get().await?;
+        conn.interact_flatten_err(move |conn| {
+            use diesel::prelude::*;
+
+            for (kit_serial, dropped) in dropped {
+                tracing::debug!(
+                    "Kit {} exceeded its ingress quota: dropped {} message(s), {} byte(s)",
+                    kit_serial,
+                    dropped.messages,
+                    dropped.bytes,
+                );
+
+                let kit = match models::Kit::by_serial(&kit_serial).first(conn).optional()? {
+                    Some(kit) => kit,
+                    None => continue,
+                };
+                models::NewKitDroppedMessages::new(
+                    kit.get_id(),
+                    dropped.messages,
+                    dropped.bytes,
+                    dropped.datetime_last_dropped,
+                )
+                .add(conn)?;
+            }
+
+            Ok::<_, problem::Problem>(())
+        })
+        .await
+    };
+
+    if implementation().await.is_err() {
+        tracing::warn!("encountered a problem when recording dropped messages");
+    }
+}
+
+/// The MQTT client id of this instance's connection named `name`. Client ids must be unique per
+/// connection, so they end in an id of this instance, allowing several API instances to connect.
+pub fn client_id(name: &str) -> String {
+    static INSTANCE: once_cell::sync::Lazy<uuid::Uuid> =
+        once_cell::sync::Lazy::new(uuid::Uuid::new_v4);
+    format!("{}-{}", name, INSTANCE.simple())
+}
+
+/// Read after how many minutes without messages a kit is considered offline from the environment.
+fn kit_offline_after() -> Duration {
+    const DEFAULT_KIT_OFFLINE_MINUTES: u64 = 5;
+
+    let minutes = std::env::var("KIT_OFFLINE_MINUTES")
+        .ok()
+        .and_then(|minutes| minutes.parse().ok())
+        .unwrap_or(DEFAULT_KIT_OFFLINE_MINUTES);
+    Duration::from_secs(minutes * 60)
+}
+
 struct Handler_ {
     pg_pool: PgPool,
 }
@@ -186,13 +286,18 @@ impl astroplant_mqtt::ServerRpcHandler for Handler_ {
 }
 
 /// Must be called from within a Tokio runtime.
+///
+/// Fails if the MQTT configuration in the environment is invalid.
 pub fn run(
     pg_pool: PgPool,
     object_store: astroplant_object::ObjectStore,
-) -> (
+    webhooks: Webhooks,
+    ws_publisher: astroplant_websocket::Publisher,
+) -> anyhow::Result<(
     mpsc::Receiver<astroplant_mqtt::RawMeasurement>,
     astroplant_mqtt::KitsRpc,
-) {
+    ConnectionStateRx,
+)> {
     let (mut raw_measurement_sender, raw_measurement_receiver) = mpsc::channel(32);
 
     let mut builder = ConnectionBuilder::new(
@@ -210,37 +315,131 @@ pub fn run(
         );
     }
 
-    let builder = builder.with_server_rpc_handler(Handler_ {
-        pg_pool: pg_pool.clone(),
-    });
+    let builder = builder
+        .with_client_id(client_id("astroplant-api"))
+        .with_shared_subscription("astroplant-api")
+        .with_ingress_quota(IngressQuota::from_env()?)
+        .with_server_rpc_handler(Handler_ {
+            pg_pool: pg_pool.clone(),
+        });
 
-    let (connection, kits_rpc) = builder.create();
+    let (connection, kits_rpc, connection_state) = builder.create();
 
     tokio::spawn(async move {
         let mut stream = connection.into_stream();
-        while let Some(msg) = stream.next().await {
-            match msg {
-                Ok(Message::RawMeasurement(measurement)) => {
-                    if (raw_measurement_sender.send(measurement).await).is_err() {
-                        break;
+
+        // Dropped messages are recorded in batches, as a kit exceeding its quota may be flooding.
+        let mut dropped: HashMap<String, DroppedMessages> = HashMap::new();
+        let mut record_dropped_interval = tokio::time::interval(Duration::from_secs(60));
+        record_dropped_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        // Kits are online from their first message, until they have not sent any message for a
+        // while. As this is not persisted, kits are online again from their first message after a
+        // restart.
+        let kit_offline_after = kit_offline_after();
+        let mut kits_last_message: HashMap<String, Instant> = HashMap::new();
+        let mut kits_offline_interval = tokio::time::interval(Duration::from_secs(30));
+        kits_offline_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                msg = stream.next() => {
+                    let msg = match msg {
+                        Some(msg) => msg,
+                        None => break,
+                    };
+
+                    if let Ok(msg) = &msg {
+                        let kit_serial = msg.kit_serial();
+                        match kits_last_message.get_mut(kit_serial) {
+                            Some(last_message) => *last_message = Instant::now(),
+                            None => {
+                                kits_last_message.insert(kit_serial.to_owned(), Instant::now());
+                                webhooks.dispatch(
+                                    kit_serial.to_owned(),
+                                    EventType::KitOnline,
+                                    (),
+                                );
+                            }
+                        }
+                    }
+
+                    match msg {
+                        Ok(Message::RawMeasurement(measurement)) => {
+                            if (raw_measurement_sender.send(measurement).await).is_err() {
+                                break;
+                            }
+                        }
+                        Ok(Message::AggregateMeasurement(measurement)) => {
+                            ws_publisher
+                                .publish_aggregate_measurement(measurement.clone())
+                                .await;
+                            webhooks.dispatch(
+                                measurement.kit_serial.clone(),
+                                EventType::AggregateMeasurement,
+                                measurement,
+                            );
+                        }
+                        Ok(Message::Media(media)) => {
+                            upload_media(
+                                pg_pool.clone(),
+                                object_store.clone(),
+                                webhooks.clone(),
+                                ws_publisher.clone(),
+                                media,
+                            )
+                            .await;
+                        }
+                        Ok(Message::PreviewFrame(preview_frame)) => {
+                            ws_publisher.publish_preview_frame(preview_frame);
+                        }
+                        Ok(Message::QuotaExceeded(quota_exceeded)) => {
+                            match dropped.get_mut(&quota_exceeded.kit_serial) {
+                                Some(kit_dropped) => kit_dropped.add(quota_exceeded),
+                                None => {
+                                    dropped.insert(
+                                        quota_exceeded.kit_serial.clone(),
+                                        quota_exceeded.into(),
+                                    );
+                                }
+                            }
+                        }
+                        Err(astroplant_mqtt::Error::Mqtt(err)) => {
+                            tracing::warn!("An MQTT connection error was encountered: {:?}", err)
+                        }
+                        Err(astroplant_mqtt::Error::MqttClientError(err)) => {
+                            tracing::warn!("An MQTT client error was encountered: {:?}", err)
+                        }
+                        Err(err) => {
+                            tracing::warn!("An MQTT error was encountered: {:?}", err)
+                        }
+                        _ => {}
                     }
                 }
-                Ok(Message::Media(media)) => {
-                    upload_media(pg_pool.clone(), object_store.clone(), media).await;
-                }
-                Err(astroplant_mqtt::Error::Mqtt(err)) => {
-                    tracing::warn!("An MQTT connection error was encountered: {:?}", err)
-                }
-                Err(astroplant_mqtt::Error::MqttClientError(err)) => {
-                    tracing::warn!("An MQTT client error was encountered: {:?}", err)
+                _ = kits_offline_interval.tick() => {
+                    kits_last_message.retain(|kit_serial, last_message| {
+                        let online = last_message.elapsed() < kit_offline_after;
+                        if !online {
+                            webhooks.dispatch(kit_serial.clone(), EventType::KitOffline, ());
+                        }
+                        online
+                    });
                 }
-                Err(err) => {
-                    tracing::warn!("An MQTT error was encountered: {:?}", err)
+                _ = record_dropped_interval.tick() => {
+                    if !dropped.is_empty() {
+                        tokio::spawn(record_dropped_messages(
+                            pg_pool.clone(),
+                            std::mem::take(&mut dropped),
+                        ));
+                    }
                 }
-                _ => {}
             }
         }
+
+        if !dropped.is_empty() {
+            record_dropped_messages(pg_pool, dropped).await;
+        }
     });
 
-    (raw_measurement_receiver, kits_rpc)
+    Ok((raw_measurement_receiver, kits_rpc, connection_state))
 }
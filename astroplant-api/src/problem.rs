@@ -54,6 +54,12 @@ pub enum Problem {
     #[serde(rename_all = "camelCase")]
     KitRpc(KitRpcProblem),
 
+    #[serde(rename = "/probs/kits-unreachable")]
+    #[serde(rename_all = "camelCase")]
+    KitsUnreachable {
+        last_error: Option<chrono::DateTime<chrono::Utc>>,
+    },
+
     #[serde(rename = "/probs/kits-require-one-super-member")]
     #[serde(rename_all = "camelCase")]
     KitsRequireOneSuperMember,
@@ -77,6 +83,7 @@ impl Problem {
             InvalidJson { .. } => StatusCode::BAD_REQUEST,
             InvalidParameters { .. } => StatusCode::BAD_REQUEST,
             KitRpc(_) => StatusCode::BAD_GATEWAY,
+            KitsUnreachable { .. } => StatusCode::SERVICE_UNAVAILABLE,
             KitsRequireOneSuperMember => StatusCode::BAD_REQUEST,
         }
     }
@@ -248,6 +255,13 @@ impl<'a> From<&'a Problem> for DescriptiveProblem<'a> {
                 )
             }
 
+            KitsUnreachable { .. } => {
+                (
+                    Some("The server is not connected to the kits' message broker".to_owned()),
+                    Some("Requests to kits cannot be made until the connection is re-established.".to_owned()),
+                )
+            }
+
             KitsRequireOneSuperMember => {
                 (
                     Some("Kits must have at least one member with super access".to_owned()),
@@ -462,6 +476,11 @@ impl KitRpcProblem {
     pub fn kit_rpc_response_error_into_problem(
         error: astroplant_mqtt::KitRpcResponseError,
     ) -> Problem {
-        Problem::KitRpc(KitRpcProblem::KitRpcResponseError(format!("{:?}", error)))
+        match error {
+            astroplant_mqtt::KitRpcResponseError::Disconnected { last_error } => {
+                Problem::KitsUnreachable { last_error }
+            }
+            error => Problem::KitRpc(KitRpcProblem::KitRpcResponseError(format!("{:?}", error))),
+        }
     }
 }
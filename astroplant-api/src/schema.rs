@@ -1,10 +1,54 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    /// Representation of the `actuator_states` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    actuator_states (id) {
+        /// The `id` column of the `actuator_states` table.
+        ///
+        /// Its SQL type is `Uuid`.
+        ///
+        /// (Automatically generated by Diesel.)
+        id -> Uuid,
+        /// The `peripheral_id` column of the `actuator_states` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        peripheral_id -> Int4,
+        /// The `kit_id` column of the `actuator_states` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        kit_id -> Int4,
+        /// The `kit_configuration_id` column of the `actuator_states` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        kit_configuration_id -> Int4,
+        /// The `datetime` column of the `actuator_states` table.
+        ///
+        /// Its SQL type is `Timestamptz`.
+        ///
+        /// (Automatically generated by Diesel.)
+        datetime -> Timestamptz,
+        /// The `state` column of the `actuator_states` table.
+        ///
+        /// Its SQL type is `Jsonb`.
+        ///
+        /// (Automatically generated by Diesel.)
+        state -> Jsonb,
+    }
+}
+
 diesel::table! {
     /// Representation of the `aggregate_measurements` table.
     ///
     /// (Automatically generated by Diesel.)
-    aggregate_measurements (id) {
+    aggregate_measurements (id, datetime_start) {
         /// The `id` column of the `aggregate_measurements` table.
         ///
         /// Its SQL type is `Uuid`.
@@ -49,10 +93,154 @@ diesel::table! {
         datetime_end -> Timestamptz,
         /// The `values` column of the `aggregate_measurements` table.
         ///
-        /// Its SQL type is `Json`.
+        /// Its SQL type is `Jsonb`.
+        ///
+        /// (Automatically generated by Diesel.)
+        values -> Jsonb,
+        /// The `server_computed` column of the `aggregate_measurements` table.
+        ///
+        /// Its SQL type is `Bool`.
+        ///
+        /// (Automatically generated by Diesel.)
+        server_computed -> Bool,
+    }
+}
+
+diesel::table! {
+    /// Representation of the `kit_alert_rules` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    kit_alert_rules (id) {
+        /// The `id` column of the `kit_alert_rules` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        id -> Int4,
+        /// The `kit_id` column of the `kit_alert_rules` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        kit_id -> Int4,
+        /// The `peripheral_id` column of the `kit_alert_rules` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        peripheral_id -> Int4,
+        /// The `quantity_type_id` column of the `kit_alert_rules` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        quantity_type_id -> Int4,
+        /// The `name` column of the `kit_alert_rules` table.
+        ///
+        /// Its SQL type is `Varchar`.
+        ///
+        /// (Automatically generated by Diesel.)
+        name -> Varchar,
+        /// The `kind` column of the `kit_alert_rules` table.
+        ///
+        /// Its SQL type is `Varchar`.
+        ///
+        /// (Automatically generated by Diesel.)
+        kind -> Varchar,
+        /// The `comparison` column of the `kit_alert_rules` table.
+        ///
+        /// Its SQL type is `Nullable<Varchar>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        comparison -> Nullable<Varchar>,
+        /// The `threshold` column of the `kit_alert_rules` table.
+        ///
+        /// Its SQL type is `Nullable<Float8>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        threshold -> Nullable<Float8>,
+        /// The `duration_seconds` column of the `kit_alert_rules` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        duration_seconds -> Int4,
+        /// The `datetime_created` column of the `kit_alert_rules` table.
+        ///
+        /// Its SQL type is `Timestamptz`.
+        ///
+        /// (Automatically generated by Diesel.)
+        datetime_created -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    /// Representation of the `kit_alerts` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    kit_alerts (id) {
+        /// The `id` column of the `kit_alerts` table.
+        ///
+        /// Its SQL type is `Uuid`.
         ///
         /// (Automatically generated by Diesel.)
-        values -> Json,
+        id -> Uuid,
+        /// The `kit_id` column of the `kit_alerts` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        kit_id -> Int4,
+        /// The `kit_alert_rule_id` column of the `kit_alerts` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        kit_alert_rule_id -> Int4,
+        /// The `datetime` column of the `kit_alerts` table.
+        ///
+        /// Its SQL type is `Timestamptz`.
+        ///
+        /// (Automatically generated by Diesel.)
+        datetime -> Timestamptz,
+        /// The `firing` column of the `kit_alerts` table.
+        ///
+        /// Its SQL type is `Bool`.
+        ///
+        /// (Automatically generated by Diesel.)
+        firing -> Bool,
+        /// The `value` column of the `kit_alerts` table.
+        ///
+        /// Its SQL type is `Nullable<Float8>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        value -> Nullable<Float8>,
+    }
+}
+
+diesel::table! {
+    /// Representation of the `kit_claim_codes` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    kit_claim_codes (kit_id) {
+        /// The `kit_id` column of the `kit_claim_codes` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        kit_id -> Int4,
+        /// The `claim_code_hash` column of the `kit_claim_codes` table.
+        ///
+        /// Its SQL type is `Varchar`.
+        ///
+        /// (Automatically generated by Diesel.)
+        claim_code_hash -> Varchar,
+        /// The `created_at` column of the `kit_claim_codes` table.
+        ///
+        /// Its SQL type is `Timestamptz`.
+        ///
+        /// (Automatically generated by Diesel.)
+        created_at -> Timestamptz,
     }
 }
 
@@ -130,6 +318,88 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    /// Representation of the `kit_dropped_messages` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    kit_dropped_messages (kit_id, date) {
+        /// The `kit_id` column of the `kit_dropped_messages` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        kit_id -> Int4,
+        /// The `date` column of the `kit_dropped_messages` table.
+        ///
+        /// Its SQL type is `Date`.
+        ///
+        /// (Automatically generated by Diesel.)
+        date -> Date,
+        /// The `messages` column of the `kit_dropped_messages` table.
+        ///
+        /// Its SQL type is `Int8`.
+        ///
+        /// (Automatically generated by Diesel.)
+        messages -> Int8,
+        /// The `bytes` column of the `kit_dropped_messages` table.
+        ///
+        /// Its SQL type is `Int8`.
+        ///
+        /// (Automatically generated by Diesel.)
+        bytes -> Int8,
+        /// The `datetime_last_dropped` column of the `kit_dropped_messages` table.
+        ///
+        /// Its SQL type is `Timestamptz`.
+        ///
+        /// (Automatically generated by Diesel.)
+        datetime_last_dropped -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    /// Representation of the `kit_events` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    kit_events (id) {
+        /// The `id` column of the `kit_events` table.
+        ///
+        /// Its SQL type is `Uuid`.
+        ///
+        /// (Automatically generated by Diesel.)
+        id -> Uuid,
+        /// The `kit_id` column of the `kit_events` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        kit_id -> Int4,
+        /// The `peripheral_id` column of the `kit_events` table.
+        ///
+        /// Its SQL type is `Nullable<Int4>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        peripheral_id -> Nullable<Int4>,
+        /// The `datetime` column of the `kit_events` table.
+        ///
+        /// Its SQL type is `Timestamptz`.
+        ///
+        /// (Automatically generated by Diesel.)
+        datetime -> Timestamptz,
+        /// The `severity` column of the `kit_events` table.
+        ///
+        /// Its SQL type is `Int2`.
+        ///
+        /// (Automatically generated by Diesel.)
+        severity -> Int2,
+        /// The `message` column of the `kit_events` table.
+        ///
+        /// Its SQL type is `Text`.
+        ///
+        /// (Automatically generated by Diesel.)
+        message -> Text,
+    }
+}
+
 diesel::table! {
     /// Representation of the `kit_last_seen` table.
     ///
@@ -194,6 +464,138 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    /// Representation of the `kit_retention_policies` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    kit_retention_policies (kit_id) {
+        /// The `kit_id` column of the `kit_retention_policies` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        kit_id -> Int4,
+        /// The `raw_measurement_days` column of the `kit_retention_policies` table.
+        ///
+        /// Its SQL type is `Nullable<Int4>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        raw_measurement_days -> Nullable<Int4>,
+        /// The `downsample_minutes` column of the `kit_retention_policies` table.
+        ///
+        /// Its SQL type is `Nullable<Int4>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        downsample_minutes -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    /// Representation of the `kit_webhook_deliveries` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    kit_webhook_deliveries (id) {
+        /// The `id` column of the `kit_webhook_deliveries` table.
+        ///
+        /// Its SQL type is `Uuid`.
+        ///
+        /// (Automatically generated by Diesel.)
+        id -> Uuid,
+        /// The `kit_webhook_id` column of the `kit_webhook_deliveries` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        kit_webhook_id -> Int4,
+        /// The `event_id` column of the `kit_webhook_deliveries` table.
+        ///
+        /// Its SQL type is `Uuid`.
+        ///
+        /// (Automatically generated by Diesel.)
+        event_id -> Uuid,
+        /// The `event_type` column of the `kit_webhook_deliveries` table.
+        ///
+        /// Its SQL type is `Text`.
+        ///
+        /// (Automatically generated by Diesel.)
+        event_type -> Text,
+        /// The `attempt` column of the `kit_webhook_deliveries` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        attempt -> Int4,
+        /// The `datetime` column of the `kit_webhook_deliveries` table.
+        ///
+        /// Its SQL type is `Timestamptz`.
+        ///
+        /// (Automatically generated by Diesel.)
+        datetime -> Timestamptz,
+        /// The `status_code` column of the `kit_webhook_deliveries` table.
+        ///
+        /// Its SQL type is `Nullable<Int4>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        status_code -> Nullable<Int4>,
+        /// The `error` column of the `kit_webhook_deliveries` table.
+        ///
+        /// Its SQL type is `Nullable<Text>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        error -> Nullable<Text>,
+        /// The `succeeded` column of the `kit_webhook_deliveries` table.
+        ///
+        /// Its SQL type is `Bool`.
+        ///
+        /// (Automatically generated by Diesel.)
+        succeeded -> Bool,
+    }
+}
+
+diesel::table! {
+    /// Representation of the `kit_webhooks` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    kit_webhooks (id) {
+        /// The `id` column of the `kit_webhooks` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        id -> Int4,
+        /// The `kit_id` column of the `kit_webhooks` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        kit_id -> Int4,
+        /// The `url` column of the `kit_webhooks` table.
+        ///
+        /// Its SQL type is `Text`.
+        ///
+        /// (Automatically generated by Diesel.)
+        url -> Text,
+        /// The `secret` column of the `kit_webhooks` table.
+        ///
+        /// Its SQL type is `Varchar`.
+        ///
+        /// (Automatically generated by Diesel.)
+        secret -> Varchar,
+        /// The `event_types` column of the `kit_webhooks` table.
+        ///
+        /// Its SQL type is `Array<Text>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        event_types -> Array<Text>,
+        /// The `datetime_created` column of the `kit_webhooks` table.
+        ///
+        /// Its SQL type is `Timestamptz`.
+        ///
+        /// (Automatically generated by Diesel.)
+        datetime_created -> Timestamptz,
+    }
+}
+
 diesel::table! {
     /// Representation of the `kits` table.
     ///
@@ -268,6 +670,93 @@ diesel::table! {
         ///
         /// (Automatically generated by Diesel.)
         updated_at -> Timestamptz,
+        /// The `pending_password_hash` column of the `kits` table.
+        ///
+        /// Its SQL type is `Nullable<Varchar>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        #[max_length = 255]
+        pending_password_hash -> Nullable<Varchar>,
+        /// The `pending_password_deadline` column of the `kits` table.
+        ///
+        /// Its SQL type is `Nullable<Timestamptz>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        pending_password_deadline -> Nullable<Timestamptz>,
+        /// The `privacy_bridge_measurements` column of the `kits` table.
+        ///
+        /// Its SQL type is `Bool`.
+        ///
+        /// (Automatically generated by Diesel.)
+        privacy_bridge_measurements -> Bool,
+    }
+}
+
+diesel::table! {
+    /// Representation of the `latest_measurements` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    latest_measurements (peripheral_id, quantity_type_id) {
+        /// The `peripheral_id` column of the `latest_measurements` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        peripheral_id -> Int4,
+        /// The `quantity_type_id` column of the `latest_measurements` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        quantity_type_id -> Int4,
+        /// The `kit_id` column of the `latest_measurements` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        kit_id -> Int4,
+        /// The `raw_measurement_id` column of the `latest_measurements` table.
+        ///
+        /// Its SQL type is `Nullable<Uuid>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        raw_measurement_id -> Nullable<Uuid>,
+        /// The `raw_value` column of the `latest_measurements` table.
+        ///
+        /// Its SQL type is `Nullable<Float8>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        raw_value -> Nullable<Float8>,
+        /// The `raw_datetime` column of the `latest_measurements` table.
+        ///
+        /// Its SQL type is `Nullable<Timestamptz>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        raw_datetime -> Nullable<Timestamptz>,
+        /// The `aggregate_measurement_id` column of the `latest_measurements` table.
+        ///
+        /// Its SQL type is `Nullable<Uuid>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        aggregate_measurement_id -> Nullable<Uuid>,
+        /// The `aggregate_values` column of the `latest_measurements` table.
+        ///
+        /// Its SQL type is `Nullable<Jsonb>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        aggregate_values -> Nullable<Jsonb>,
+        /// The `aggregate_datetime_start` column of the `latest_measurements` table.
+        ///
+        /// Its SQL type is `Nullable<Timestamptz>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        aggregate_datetime_start -> Nullable<Timestamptz>,
+        /// The `aggregate_datetime_end` column of the `latest_measurements` table.
+        ///
+        /// Its SQL type is `Nullable<Timestamptz>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        aggregate_datetime_end -> Nullable<Timestamptz>,
     }
 }
 
@@ -549,7 +1038,7 @@ diesel::table! {
     /// Representation of the `raw_measurements` table.
     ///
     /// (Automatically generated by Diesel.)
-    raw_measurements (id) {
+    raw_measurements (id, datetime) {
         /// The `id` column of the `raw_measurements` table.
         ///
         /// Its SQL type is `Uuid`.
@@ -662,14 +1151,97 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    /// Representation of the `websocket_published_events` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    websocket_published_events (id) {
+        /// The `id` column of the `websocket_published_events` table.
+        ///
+        /// Its SQL type is `Uuid`.
+        ///
+        /// (Automatically generated by Diesel.)
+        id -> Uuid,
+        /// The `datetime_published` column of the `websocket_published_events` table.
+        ///
+        /// Its SQL type is `Timestamptz`.
+        ///
+        /// (Automatically generated by Diesel.)
+        datetime_published -> Timestamptz,
+        /// The `event` column of the `websocket_published_events` table.
+        ///
+        /// Its SQL type is `Nullable<Jsonb>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        event -> Nullable<Jsonb>,
+    }
+}
+
+diesel::table! {
+    /// Representation of the `websocket_raw_measurement_cache` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    websocket_raw_measurement_cache (kit_serial, peripheral, quantity_type) {
+        /// The `kit_serial` column of the `websocket_raw_measurement_cache` table.
+        ///
+        /// Its SQL type is `Varchar`.
+        ///
+        /// (Automatically generated by Diesel.)
+        #[max_length = 255]
+        kit_serial -> Varchar,
+        /// The `peripheral` column of the `websocket_raw_measurement_cache` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        peripheral -> Int4,
+        /// The `quantity_type` column of the `websocket_raw_measurement_cache` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        quantity_type -> Int4,
+        /// The `raw_measurement` column of the `websocket_raw_measurement_cache` table.
+        ///
+        /// Its SQL type is `Jsonb`.
+        ///
+        /// (Automatically generated by Diesel.)
+        raw_measurement -> Jsonb,
+        /// The `datetime_received` column of the `websocket_raw_measurement_cache` table.
+        ///
+        /// Its SQL type is `Timestamptz`.
+        ///
+        /// (Automatically generated by Diesel.)
+        datetime_received -> Timestamptz,
+    }
+}
+
+diesel::joinable!(actuator_states -> kit_configurations (kit_configuration_id));
+diesel::joinable!(actuator_states -> kits (kit_id));
+diesel::joinable!(actuator_states -> peripherals (peripheral_id));
 diesel::joinable!(aggregate_measurements -> kit_configurations (kit_configuration_id));
 diesel::joinable!(aggregate_measurements -> kits (kit_id));
 diesel::joinable!(aggregate_measurements -> peripherals (peripheral_id));
 diesel::joinable!(aggregate_measurements -> quantity_types (quantity_type_id));
+diesel::joinable!(kit_alert_rules -> kits (kit_id));
+diesel::joinable!(kit_alert_rules -> peripherals (peripheral_id));
+diesel::joinable!(kit_alert_rules -> quantity_types (quantity_type_id));
+diesel::joinable!(kit_alerts -> kit_alert_rules (kit_alert_rule_id));
+diesel::joinable!(kit_alerts -> kits (kit_id));
+diesel::joinable!(kit_claim_codes -> kits (kit_id));
 diesel::joinable!(kit_configurations -> kits (kit_id));
+diesel::joinable!(kit_dropped_messages -> kits (kit_id));
+diesel::joinable!(kit_events -> kits (kit_id));
+diesel::joinable!(kit_events -> peripherals (peripheral_id));
 diesel::joinable!(kit_last_seen -> kits (kit_id));
 diesel::joinable!(kit_memberships -> kits (kit_id));
 diesel::joinable!(kit_memberships -> users (user_id));
+diesel::joinable!(kit_retention_policies -> kits (kit_id));
+diesel::joinable!(kit_webhook_deliveries -> kit_webhooks (kit_webhook_id));
+diesel::joinable!(kit_webhooks -> kits (kit_id));
+diesel::joinable!(latest_measurements -> kits (kit_id));
+diesel::joinable!(latest_measurements -> peripherals (peripheral_id));
+diesel::joinable!(latest_measurements -> quantity_types (quantity_type_id));
 diesel::joinable!(media -> kit_configurations (kit_configuration_id));
 diesel::joinable!(media -> kits (kit_id));
 diesel::joinable!(media -> peripherals (peripheral_id));
@@ -684,11 +1256,21 @@ diesel::joinable!(raw_measurements -> peripherals (peripheral_id));
 diesel::joinable!(raw_measurements -> quantity_types (quantity_type_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    actuator_states,
     aggregate_measurements,
+    kit_alert_rules,
+    kit_alerts,
+    kit_claim_codes,
     kit_configurations,
+    kit_dropped_messages,
+    kit_events,
     kit_last_seen,
     kit_memberships,
+    kit_retention_policies,
+    kit_webhook_deliveries,
+    kit_webhooks,
     kits,
+    latest_measurements,
     media,
     peripheral_definition_expected_quantity_types,
     peripheral_definitions,
@@ -697,4 +1279,6 @@ diesel::allow_tables_to_appear_in_same_query!(
     queue_media_pending_deletion,
     raw_measurements,
     users,
+    websocket_published_events,
+    websocket_raw_measurement_cache,
 );
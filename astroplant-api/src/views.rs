@@ -17,6 +17,7 @@ pub struct Kit {
     pub longitude: Option<f64>,
     pub privacy_public_dashboard: bool,
     pub privacy_show_on_map: bool,
+    pub privacy_bridge_measurements: bool,
     pub last_seen: Option<DateTime<Utc>>,
 }
 
@@ -33,6 +34,7 @@ impl From<(models::Kit, Option<DateTime<Utc>>)> for Kit {
             longitude,
             privacy_public_dashboard,
             privacy_show_on_map,
+            privacy_bridge_measurements,
             ..
         } = kit;
         Self {
@@ -44,6 +46,7 @@ impl From<(models::Kit, Option<DateTime<Utc>>)> for Kit {
             longitude: longitude.and_then(|l| l.to_f64()),
             privacy_public_dashboard,
             privacy_show_on_map,
+            privacy_bridge_measurements,
             last_seen,
         }
     }
@@ -401,6 +404,7 @@ pub struct AggregateMeasurement {
     pub datetime_start: DateTime<Utc>,
     pub datetime_end: DateTime<Utc>,
     pub values: HashMap<String, f64>,
+    pub server_computed: bool,
 }
 
 impl TryFrom<models::AggregateMeasurement> for AggregateMeasurement {
@@ -416,7 +420,7 @@ impl TryFrom<models::AggregateMeasurement> for AggregateMeasurement {
             datetime_start,
             datetime_end,
             values,
-            ..
+            server_computed,
         }: models::AggregateMeasurement,
     ) -> Result<Self, Self::Error> {
         Ok(Self {
@@ -428,6 +432,86 @@ impl TryFrom<models::AggregateMeasurement> for AggregateMeasurement {
             datetime_start,
             datetime_end,
             values: serde_json::from_value(values).map_err(|_| INTERNAL_SERVER_ERROR)?,
+            server_computed,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct LatestRawMeasurement {
+    pub id: uuid::Uuid,
+    pub value: f64,
+    pub datetime: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct LatestAggregateMeasurement {
+    pub id: uuid::Uuid,
+    pub values: HashMap<String, f64>,
+    pub datetime_start: DateTime<Utc>,
+    pub datetime_end: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct LatestMeasurement {
+    pub peripheral_id: i32,
+    pub quantity_type_id: i32,
+    pub kit_id: i32,
+    pub raw: Option<LatestRawMeasurement>,
+    pub aggregate: Option<LatestAggregateMeasurement>,
+}
+
+impl TryFrom<models::LatestMeasurement> for LatestMeasurement {
+    type Error = Problem;
+
+    fn try_from(
+        models::LatestMeasurement {
+            peripheral_id,
+            quantity_type_id,
+            kit_id,
+            raw_measurement_id,
+            raw_value,
+            raw_datetime,
+            aggregate_measurement_id,
+            aggregate_values,
+            aggregate_datetime_start,
+            aggregate_datetime_end,
+        }: models::LatestMeasurement,
+    ) -> Result<Self, Self::Error> {
+        let raw = match (raw_measurement_id, raw_value, raw_datetime) {
+            (Some(id), Some(value), Some(datetime)) => Some(LatestRawMeasurement {
+                id,
+                value,
+                datetime,
+            }),
+            _ => None,
+        };
+        let aggregate = match (
+            aggregate_measurement_id,
+            aggregate_values,
+            aggregate_datetime_start,
+            aggregate_datetime_end,
+        ) {
+            (Some(id), Some(values), Some(datetime_start), Some(datetime_end)) => {
+                Some(LatestAggregateMeasurement {
+                    id,
+                    values: serde_json::from_value(values).map_err(|_| INTERNAL_SERVER_ERROR)?,
+                    datetime_start,
+                    datetime_end,
+                })
+            }
+            _ => None,
+        };
+
+        Ok(Self {
+            peripheral_id,
+            quantity_type_id,
+            kit_id,
+            raw,
+            aggregate,
         })
     }
 }
@@ -476,3 +560,246 @@ impl TryFrom<models::Media> for Media {
         })
     }
 }
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ActuatorState {
+    pub id: uuid::Uuid,
+    pub peripheral_id: i32,
+    pub kit_id: i32,
+    pub kit_configuration_id: i32,
+    pub datetime: DateTime<Utc>,
+    pub state: serde_json::Value,
+}
+
+impl From<models::ActuatorState> for ActuatorState {
+    fn from(
+        models::ActuatorState {
+            id,
+            peripheral_id,
+            kit_id,
+            kit_configuration_id,
+            datetime,
+            state,
+        }: models::ActuatorState,
+    ) -> Self {
+        Self {
+            id,
+            peripheral_id,
+            kit_id,
+            kit_configuration_id,
+            datetime,
+            state,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct KitDroppedMessages {
+    pub kit_id: i32,
+    pub date: chrono::NaiveDate,
+    pub messages: i64,
+    pub bytes: i64,
+    pub datetime_last_dropped: DateTime<Utc>,
+}
+
+impl From<models::KitDroppedMessages> for KitDroppedMessages {
+    fn from(
+        models::KitDroppedMessages {
+            kit_id,
+            date,
+            messages,
+            bytes,
+            datetime_last_dropped,
+        }: models::KitDroppedMessages,
+    ) -> Self {
+        Self {
+            kit_id,
+            date,
+            messages,
+            bytes,
+            datetime_last_dropped,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct KitEvent {
+    pub id: uuid::Uuid,
+    pub kit_id: i32,
+    pub peripheral_id: Option<i32>,
+    pub datetime: DateTime<Utc>,
+    pub severity: astroplant_mqtt::Severity,
+    pub message: String,
+}
+
+impl TryFrom<models::KitEvent> for KitEvent {
+    type Error = Problem;
+
+    fn try_from(
+        models::KitEvent {
+            id,
+            kit_id,
+            peripheral_id,
+            datetime,
+            severity,
+            message,
+        }: models::KitEvent,
+    ) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id,
+            kit_id,
+            peripheral_id,
+            datetime,
+            severity: astroplant_mqtt::Severity::try_from(severity)
+                .map_err(|_| INTERNAL_SERVER_ERROR)?,
+            message,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct KitAlertRule {
+    pub id: i32,
+    pub kit_id: i32,
+    pub peripheral_id: i32,
+    pub quantity_type_id: i32,
+    pub name: String,
+    #[serde(flatten)]
+    pub condition: crate::alerts::Condition,
+    pub duration_seconds: i32,
+    pub datetime_created: DateTime<Utc>,
+}
+
+impl TryFrom<models::KitAlertRule> for KitAlertRule {
+    type Error = Problem;
+
+    fn try_from(rule: models::KitAlertRule) -> Result<Self, Self::Error> {
+        Ok(Self {
+            condition: crate::alerts::Condition::try_from(&rule)?,
+            id: rule.id,
+            kit_id: rule.kit_id,
+            peripheral_id: rule.peripheral_id,
+            quantity_type_id: rule.quantity_type_id,
+            name: rule.name,
+            duration_seconds: rule.duration_seconds,
+            datetime_created: rule.datetime_created,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct KitAlert {
+    pub id: uuid::Uuid,
+    pub kit_id: i32,
+    pub kit_alert_rule_id: i32,
+    pub datetime: DateTime<Utc>,
+    pub firing: bool,
+    pub value: Option<f64>,
+}
+
+impl From<models::KitAlert> for KitAlert {
+    fn from(
+        models::KitAlert {
+            id,
+            kit_id,
+            kit_alert_rule_id,
+            datetime,
+            firing,
+            value,
+        }: models::KitAlert,
+    ) -> Self {
+        Self {
+            id,
+            kit_id,
+            kit_alert_rule_id,
+            datetime,
+            firing,
+            value,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct KitWebhook {
+    pub id: i32,
+    pub kit_id: i32,
+    pub url: String,
+    pub event_types: Vec<crate::webhooks::EventType>,
+    pub datetime_created: DateTime<Utc>,
+}
+
+impl TryFrom<models::KitWebhook> for KitWebhook {
+    type Error = Problem;
+
+    fn try_from(
+        models::KitWebhook {
+            id,
+            kit_id,
+            url,
+            event_types,
+            datetime_created,
+            ..
+        }: models::KitWebhook,
+    ) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id,
+            kit_id,
+            url,
+            event_types: event_types
+                .iter()
+                .map(|event_type| crate::webhooks::EventType::try_from(event_type.as_str()))
+                .collect::<Result<_, _>>()?,
+            datetime_created,
+        })
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct KitWebhookDelivery {
+    pub id: uuid::Uuid,
+    pub kit_webhook_id: i32,
+    pub event_id: uuid::Uuid,
+    pub event_type: crate::webhooks::EventType,
+    pub attempt: i32,
+    pub datetime: DateTime<Utc>,
+    pub status_code: Option<i32>,
+    pub error: Option<String>,
+    pub succeeded: bool,
+}
+
+impl TryFrom<models::KitWebhookDelivery> for KitWebhookDelivery {
+    type Error = Problem;
+
+    fn try_from(
+        models::KitWebhookDelivery {
+            id,
+            kit_webhook_id,
+            event_id,
+            event_type,
+            attempt,
+            datetime,
+            status_code,
+            error,
+            succeeded,
+        }: models::KitWebhookDelivery,
+    ) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id,
+            kit_webhook_id,
+            event_id,
+            event_type: crate::webhooks::EventType::try_from(event_type.as_str())?,
+            attempt,
+            datetime,
+            status_code,
+            error,
+            succeeded,
+        })
+    }
+}
@@ -0,0 +1,747 @@
+//! Delivery of kit events to the kits' webhooks.
+//!
+//! An event is delivered as a `POST` request with the event as JSON body. The body is signed with
+//! the webhook's secret, see [astroplant_auth::hash::sign_webhook_payload]: the signature is in the
+//! `X-AstroPlant-Signature` header, and the timestamp it covers in the `X-AstroPlant-Timestamp`
+//! header. The event type is in the `X-AstroPlant-Event` header. Failed deliveries are retried
+//! with exponential backoff, and every attempt is logged as a
+//! [KitWebhookDelivery](crate::models::KitWebhookDelivery).
+//!
+//! Webhooks must be https URLs of public hosts, see [Destinations]. As their host is resolved for
+//! every connection, webhooks whose host resolves to a private address are rejected at delivery
+//! time. Redirects are not followed.
+
+use crate::database::PgPool;
+use crate::{models, problem};
+
+use chrono::{DateTime, Utc};
+use hyper::client::connect::dns::{GaiResolver, Name};
+use hyper::client::HttpConnector;
+use hyper::{header, StatusCode};
+use hyper_tls::HttpsConnector;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use uuid::Uuid;
+
+pub const SIGNATURE_HEADER: &str = "x-astroplant-signature";
+pub const TIMESTAMP_HEADER: &str = "x-astroplant-timestamp";
+pub const EVENT_HEADER: &str = "x-astroplant-event";
+pub const DELIVERY_HEADER: &str = "x-astroplant-delivery";
+
+/// The duration after which an attempt without response fails.
+const ATTEMPT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, EnumIter)]
+#[serde(rename_all = "camelCase")]
+pub enum EventType {
+    /// The kit published an aggregate measurement.
+    AggregateMeasurement,
+    /// The kit uploaded media.
+    Media,
+    /// The kit sent a message after not having sent any for a while.
+    KitOnline,
+    /// The kit has not sent any message for a while.
+    KitOffline,
+    /// A configuration of the kit was activated.
+    ConfigurationActivated,
+}
+
+impl EventType {
+    /// The event type as stored in the database and sent in the `X-AstroPlant-Event` header.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventType::AggregateMeasurement => "aggregate_measurement",
+            EventType::Media => "media",
+            EventType::KitOnline => "kit_online",
+            EventType::KitOffline => "kit_offline",
+            EventType::ConfigurationActivated => "configuration_activated",
+        }
+    }
+}
+
+impl TryFrom<&str> for EventType {
+    type Error = problem::Problem;
+
+    fn try_from(event_type: &str) -> Result<Self, Self::Error> {
+        use strum::IntoEnumIterator;
+
+        EventType::iter()
+            .find(|candidate| candidate.as_str() == event_type)
+            .ok_or(problem::INTERNAL_SERVER_ERROR)
+    }
+}
+
+/// An event of a kit, as delivered to webhooks.
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Event {
+    pub id: Uuid,
+    pub r#type: EventType,
+    pub kit_serial: String,
+    pub datetime: DateTime<Utc>,
+    /// The event's subject, e.g. the aggregate measurement or media. Null for kit online and
+    /// offline events.
+    pub data: serde_json::Value,
+}
+
+impl Event {
+    pub fn new(kit_serial: String, r#type: EventType, data: serde_json::Value) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            r#type,
+            kit_serial,
+            datetime: Utc::now(),
+            data,
+        }
+    }
+}
+
+/// How failed deliveries are retried.
+#[derive(Copy, Clone, Debug)]
+pub struct Retry {
+    /// The maximum number of attempts, including the first.
+    pub attempts: u32,
+    /// The delay before the second attempt. The delay doubles for every next attempt.
+    pub backoff: Duration,
+}
+
+impl Default for Retry {
+    /// Retry up to five times, over roughly five minutes.
+    fn default() -> Self {
+        Self {
+            attempts: 6,
+            backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+/// An attempt at delivering an event.
+#[derive(Clone, Debug)]
+pub struct Attempt {
+    /// The attempt number, starting at 1.
+    pub attempt: u32,
+    pub datetime: DateTime<Utc>,
+    pub status_code: Option<u16>,
+    pub error: Option<String>,
+    pub succeeded: bool,
+    /// Whether a next attempt could succeed. Requests that are rejected by the receiver are not
+    /// retried, except if the receiver times out or is rate limited.
+    retryable: bool,
+}
+
+/// The destinations webhooks may have.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Destinations {
+    /// Whether plain http URLs are allowed.
+    pub allow_http: bool,
+    /// Whether hosts with loopback, private, link-local and other non-public addresses are
+    /// allowed.
+    pub allow_private_addresses: bool,
+}
+
+impl Destinations {
+    /// Read the destinations from the `WEBHOOK_ALLOW_HTTP` and `WEBHOOK_ALLOW_PRIVATE_ADDRESSES`
+    /// environment variables. Both are disallowed by default.
+    pub fn from_env() -> anyhow::Result<Self> {
+        fn flag(name: &str) -> anyhow::Result<bool> {
+            match std::env::var(name) {
+                Ok(value) => match value.as_str() {
+                    "true" | "1" => Ok(true),
+                    "false" | "0" => Ok(false),
+                    _ => anyhow::bail!("invalid {}: {:?}", name, value),
+                },
+                Err(_) => Ok(false),
+            }
+        }
+
+        Ok(Self {
+            allow_http: flag("WEBHOOK_ALLOW_HTTP")?,
+            allow_private_addresses: flag("WEBHOOK_ALLOW_PRIVATE_ADDRESSES")?,
+        })
+    }
+
+    fn allows_address(self, address: IpAddr) -> bool {
+        self.allow_private_addresses || is_public(address)
+    }
+
+    /// Check the URL's scheme, and its host if it is an IP address. Hosts that are names are
+    /// checked when they are resolved.
+    pub fn check_url(self, url: &str) -> Result<(), String> {
+        let uri: hyper::Uri = url.parse().map_err(|_| "invalid URL".to_owned())?;
+
+        match uri.scheme_str() {
+            Some("https") => {}
+            Some("http") if self.allow_http => {}
+            _ => return Err("the URL must be https".to_owned()),
+        }
+
+        let host = uri.host().ok_or_else(|| "the URL has no host".to_owned())?;
+        if let Ok(address) = host.trim_start_matches('[').trim_end_matches(']').parse() {
+            if !self.allows_address(address) {
+                return Err(format!("{} is not a public address", address));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether the address is publicly routable.
+fn is_public(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => {
+            let [a, b, ..] = address.octets();
+            !(address.is_loopback()
+                || address.is_private()
+                || address.is_link_local()
+                || address.is_unspecified()
+                || address.is_broadcast()
+                || address.is_multicast()
+                || address.is_documentation()
+                // "This network".
+                || a == 0
+                // Shared address space, used for carrier-grade NAT.
+                || (a == 100 && b & 0xc0 == 64)
+                // Benchmarking.
+                || (a == 198 && b & 0xfe == 18)
+                // Reserved.
+                || a >= 240)
+        }
+        IpAddr::V6(address) => {
+            let bits = u128::from(address);
+            // Addresses embedding an IPv4 address reach that address: NAT64 (64:ff9b::/96), 6to4
+            // (2002::/16), and IPv4-mapped and IPv4-compatible addresses. The latter include `::`
+            // and `::1`, which embed addresses in 0.0.0.0/8.
+            let embedded = if bits >> 32 == 0x64_ff9b << 64 {
+                Some(Ipv4Addr::from(bits as u32))
+            } else if bits >> 112 == 0x2002 {
+                Some(Ipv4Addr::from((bits >> 80) as u32))
+            } else {
+                address.to_ipv4()
+            };
+
+            match embedded {
+                Some(address) => is_public(IpAddr::V4(address)),
+                None => {
+                    !(address.is_multicast()
+                        || address.is_unique_local()
+                        || address.is_unicast_link_local())
+                }
+            }
+        }
+    }
+}
+
+/// Resolves host names, rejecting the addresses not allowed by [Destinations].
+#[derive(Clone)]
+pub struct Resolver {
+    resolver: GaiResolver,
+    destinations: Destinations,
+}
+
+impl hyper::service::Service<Name> for Resolver {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = std::io::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.resolver.poll_ready(cx)
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let destinations = self.destinations;
+        let resolving = self.resolver.call(name);
+        Box::pin(async move {
+            let addresses: Vec<SocketAddr> = resolving
+                .await?
+                .filter(|address| destinations.allows_address(address.ip()))
+                .collect();
+            if addresses.is_empty() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::PermissionDenied,
+                    "the host has no public address",
+                ));
+            }
+            Ok(addresses.into_iter())
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct Client {
+    client: hyper::Client<HttpsConnector<HttpConnector<Resolver>>>,
+    destinations: Destinations,
+}
+
+pub fn client(destinations: Destinations) -> Client {
+    let mut http = HttpConnector::new_with_resolver(Resolver {
+        resolver: GaiResolver::new(),
+        destinations,
+    });
+    http.enforce_http(false);
+
+    Client {
+        client: hyper::Client::builder().build(HttpsConnector::new_with_connector(http)),
+        destinations,
+    }
+}
+
+async fn attempt(
+    client: &Client,
+    url: &str,
+    secret: &str,
+    event: &Event,
+    body: &bytes::Bytes,
+    attempt: u32,
+) -> Attempt {
+    let datetime = Utc::now();
+    let failed = |status_code: Option<StatusCode>, error: String, retryable: bool| Attempt {
+        attempt,
+        datetime,
+        status_code: status_code.map(|status_code| status_code.as_u16()),
+        error: Some(error),
+        succeeded: false,
+        retryable,
+    };
+
+    if let Err(err) = client.destinations.check_url(url) {
+        return failed(None, err, false);
+    }
+
+    let timestamp = datetime.timestamp();
+    let signature = astroplant_auth::hash::sign_webhook_payload(secret, timestamp, body);
+    let request = match hyper::Request::post(url)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(
+            header::USER_AGENT,
+            format!("AstroPlant-Webhooks/{}", crate::VERSION),
+        )
+        .header(EVENT_HEADER, event.r#type.as_str())
+        .header(DELIVERY_HEADER, event.id.hyphenated().to_string())
+        .header(TIMESTAMP_HEADER, timestamp)
+        .header(SIGNATURE_HEADER, format!("sha256={}", signature))
+        .body(hyper::Body::from(body.clone()))
+    {
+        Ok(request) => request,
+        Err(err) => return failed(None, format!("invalid request: {}", err), false),
+    };
+
+    match tokio::time::timeout(ATTEMPT_TIMEOUT, client.client.request(request)).await {
+        Err(_) => failed(None, "timed out".to_owned(), true),
+        Ok(Err(err)) => failed(None, err.to_string(), true),
+        Ok(Ok(response)) if response.status().is_success() => Attempt {
+            attempt,
+            datetime,
+            status_code: Some(response.status().as_u16()),
+            error: None,
+            succeeded: true,
+            retryable: false,
+        },
+        Ok(Ok(response)) => {
+            let status = response.status();
+            let retryable = status.is_server_error()
+                || status == StatusCode::REQUEST_TIMEOUT
+                || status == StatusCode::TOO_MANY_REQUESTS;
+            failed(
+                Some(status),
+                format!("unsuccessful status {}", status),
+                retryable,
+            )
+        }
+    }
+}
+
+/// Deliver the event to the webhook at `url`, retrying failed attempts. Every attempt is passed to
+/// `log`. Returns whether the event was delivered.
+pub async fn deliver<F, Fut>(
+    client: &Client,
+    url: &str,
+    secret: &str,
+    event: &Event,
+    retry: Retry,
+    mut log: F,
+) -> bool
+where
+    F: FnMut(Attempt) -> Fut,
+    Fut: std::future::Future<Output = ()>,
+{
+    let body = bytes::Bytes::from(serde_json::to_vec(event).expect("events serialize"));
+    let mut backoff = retry.backoff;
+
+    for number in 1..=retry.attempts {
+        let attempt = attempt(client, url, secret, event, &body, number).await;
+        let (succeeded, retryable) = (attempt.succeeded, attempt.retryable);
+        log(attempt).await;
+
+        if succeeded {
+            return true;
+        } else if !retryable {
+            return false;
+        }
+
+        if number < retry.attempts {
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+
+    false
+}
+
+async fn log_attempt(pg_pool: PgPool, webhook_id: i32, event: &Event, attempt: Attempt) {
+    let delivery = models::NewKitWebhookDelivery {
+        id: Uuid::new_v4(),
+        kit_webhook_id: webhook_id,
+        event_id: event.id,
+        event_type: event.r#type.as_str().to_owned(),
+        attempt: attempt.attempt as i32,
+        datetime: attempt.datetime,
+        status_code: attempt.status_code.map(i32::from),
+        error: attempt.error,
+        succeeded: attempt.succeeded,
+    };
+
+    let implementation = move || async move {
+        let conn = pg_pool.get().await?;
+        conn.interact_flatten_err(move |conn| {
+            delivery.create(conn)?;
+            Ok::<_, problem::Problem>(())
+        })
+        .await
+    };
+
+    if implementation().await.is_err() {
+        tracing::warn!("encountered a problem when logging a webhook delivery");
+    }
+}
+
+async fn dispatch_event(pg_pool: PgPool, client: Client, event: Event) {
+    let implementation = {
+        let pg_pool = pg_pool.clone();
+        let kit_serial = event.kit_serial.clone();
+        let event_type = event.r#type;
+        move || async move {
+            let conn = pg_pool.get().await?;
+            conn.interact_flatten_err(move |conn| {
+                Ok::<_, problem::Problem>(models::KitWebhook::subscribed_of_kit_serial(
+                    conn,
+                    &kit_serial,
+                    event_type.as_str(),
+                )?)
+            })
+            .await
+        }
+    };
+
+    let webhooks = match implementation().await {
+        Ok(webhooks) => webhooks,
+        Err(_) => {
+            tracing::warn!("encountered a problem when retrieving webhooks");
+            return;
+        }
+    };
+
+    for webhook in webhooks {
+        let pg_pool = pg_pool.clone();
+        let client = client.clone();
+        let event = event.clone();
+        tokio::spawn(async move {
+            let delivered = deliver(
+                &client,
+                &webhook.url,
+                &webhook.secret,
+                &event,
+                Retry::default(),
+                |attempt| log_attempt(pg_pool.clone(), webhook.id, &event, attempt),
+            )
+            .await;
+
+            if !delivered {
+                tracing::debug!(
+                    "Failed to deliver event {} to webhook {} of kit {}",
+                    event.id,
+                    webhook.id,
+                    event.kit_serial,
+                );
+            }
+        });
+    }
+}
+
+/// A handle for dispatching kit events to the kits' webhooks.
+#[derive(Clone)]
+pub struct Webhooks {
+    pg_pool: PgPool,
+    client: Client,
+}
+
+impl Webhooks {
+    pub fn new(pg_pool: PgPool, destinations: Destinations) -> Self {
+        Self {
+            pg_pool,
+            client: client(destinations),
+        }
+    }
+
+    /// Deliver the event to the kit's webhooks receiving events of its type, in the background.
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub fn dispatch<T: Serialize>(&self, kit_serial: String, r#type: EventType, data: T) {
+        let data = match serde_json::to_value(data) {
+            Ok(data) => data,
+            Err(_) => {
+                tracing::warn!("encountered a problem when serializing a webhook event");
+                return;
+            }
+        };
+
+        tokio::spawn(dispatch_event(
+            self.pg_pool.clone(),
+            self.client.clone(),
+            Event::new(kit_serial, r#type, data),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response};
+    use std::sync::{Arc, Mutex};
+
+    /// Allows delivering to stand-in receivers.
+    const LOCAL: Destinations = Destinations {
+        allow_http: true,
+        allow_private_addresses: true,
+    };
+
+    #[test]
+    fn only_public_https_destinations() {
+        let destinations = Destinations::default();
+        assert!(destinations.check_url("https://example.com/hook").is_ok());
+        assert!(destinations.check_url("https://93.184.216.34/hook").is_ok());
+        assert!(destinations.check_url("https://198.20.0.1/hook").is_ok());
+        assert!(destinations
+            .check_url("https://[2606:2800:220:1::1]/hook")
+            .is_ok());
+        assert!(destinations
+            .check_url("https://[64:ff9b::5db8:d822]/hook")
+            .is_ok());
+        assert!(destinations
+            .check_url("https://[2002:5db8:d822::1]/hook")
+            .is_ok());
+        assert!(destinations.check_url("http://example.com/hook").is_err());
+        assert!(destinations.check_url("ftp://example.com/hook").is_err());
+        for host in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "[::1]",
+            "[fd00::1]",
+            "[fe80::1]",
+            "[::ffff:127.0.0.1]",
+            "198.18.0.1",
+            "198.19.255.255",
+            "240.0.0.1",
+            "255.255.255.255",
+            "[::127.0.0.1]",
+            "[::]",
+            "[64:ff9b::a9fe:a9fe]",
+            "[64:ff9b::10.1.2.3]",
+            "[2002:7f00:1::1]",
+            "[2002:c0a8:101::]",
+        ] {
+            let url = format!("https://{}/hook", host);
+            assert!(destinations.check_url(&url).is_err(), "{}", url);
+            assert!(LOCAL.check_url(&url).is_ok(), "{}", url);
+        }
+        assert!(LOCAL.check_url("http://example.com/hook").is_ok());
+    }
+
+    #[tokio::test]
+    async fn rejects_hosts_resolving_to_private_addresses() {
+        let connections = Arc::new(Mutex::new(0));
+        let make_service = {
+            let connections = connections.clone();
+            make_service_fn(move |_| {
+                *connections.lock().unwrap() += 1;
+                async move {
+                    Ok::<_, hyper::Error>(service_fn(|_: Request<Body>| async {
+                        Ok::<_, hyper::Error>(Response::new(Body::empty()))
+                    }))
+                }
+            })
+        };
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let url = format!("http://localhost:{}/hook", server.local_addr().port());
+        tokio::spawn(server);
+
+        let event = Event::new(
+            "k-1".to_owned(),
+            EventType::KitOnline,
+            serde_json::Value::Null,
+        );
+        let retry = Retry {
+            attempts: 1,
+            backoff: Duration::from_millis(10),
+        };
+        let destinations = Destinations {
+            allow_http: true,
+            allow_private_addresses: false,
+        };
+        let delivered = deliver(
+            &client(destinations),
+            &url,
+            "secret",
+            &event,
+            retry,
+            |_| async {},
+        )
+        .await;
+        assert!(!delivered);
+        assert_eq!(*connections.lock().unwrap(), 0);
+
+        let delivered = deliver(&client(LOCAL), &url, "secret", &event, retry, |_| async {}).await;
+        assert!(delivered);
+        assert_eq!(*connections.lock().unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn does_not_follow_redirects() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let make_service = {
+            let requests = requests.clone();
+            make_service_fn(move |_| {
+                let requests = requests.clone();
+                async move {
+                    Ok::<_, hyper::Error>(service_fn(move |request: Request<Body>| {
+                        requests
+                            .lock()
+                            .unwrap()
+                            .push(request.uri().path().to_owned());
+                        async {
+                            Ok::<_, hyper::Error>(
+                                Response::builder()
+                                    .status(StatusCode::TEMPORARY_REDIRECT)
+                                    .header(header::LOCATION, "/elsewhere")
+                                    .body(Body::empty())
+                                    .unwrap(),
+                            )
+                        }
+                    }))
+                }
+            })
+        };
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let url = format!("http://{}/hook", server.local_addr());
+        tokio::spawn(server);
+
+        let event = Event::new(
+            "k-1".to_owned(),
+            EventType::KitOnline,
+            serde_json::Value::Null,
+        );
+        let attempts: Arc<Mutex<Vec<Attempt>>> = Default::default();
+        let delivered = deliver(
+            &client(LOCAL),
+            &url,
+            "secret",
+            &event,
+            Retry::default(),
+            |attempt| {
+                attempts.lock().unwrap().push(attempt);
+                async {}
+            },
+        )
+        .await;
+
+        assert!(!delivered);
+        assert_eq!(attempts.lock().unwrap().len(), 1);
+        assert_eq!(*requests.lock().unwrap(), vec!["/hook".to_owned()]);
+    }
+
+    #[tokio::test]
+    async fn retries_until_delivered_and_signs() {
+        // A stand-in receiver that fails the first request.
+        let received: Arc<Mutex<Vec<(hyper::HeaderMap, bytes::Bytes)>>> = Default::default();
+        let make_service = {
+            let received = received.clone();
+            make_service_fn(move |_| {
+                let received = received.clone();
+                async move {
+                    Ok::<_, hyper::Error>(service_fn(move |request: Request<Body>| {
+                        let received = received.clone();
+                        async move {
+                            let headers = request.headers().clone();
+                            let body = hyper::body::to_bytes(request.into_body()).await?;
+                            let mut received = received.lock().unwrap();
+                            received.push((headers, body));
+                            let status = if received.len() == 1 {
+                                StatusCode::SERVICE_UNAVAILABLE
+                            } else {
+                                StatusCode::NO_CONTENT
+                            };
+                            Ok::<_, hyper::Error>(
+                                Response::builder()
+                                    .status(status)
+                                    .body(Body::empty())
+                                    .unwrap(),
+                            )
+                        }
+                    }))
+                }
+            })
+        };
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let url = format!("http://{}/hook", server.local_addr());
+        tokio::spawn(server);
+
+        let event = Event::new(
+            "k-1".to_owned(),
+            EventType::KitOnline,
+            serde_json::Value::Null,
+        );
+        let retry = Retry {
+            attempts: 3,
+            backoff: Duration::from_millis(10),
+        };
+        let attempts: Arc<Mutex<Vec<Attempt>>> = Default::default();
+        let delivered = deliver(&client(LOCAL), &url, "secret", &event, retry, |attempt| {
+            attempts.lock().unwrap().push(attempt);
+            async {}
+        })
+        .await;
+
+        assert!(delivered);
+        let attempts = attempts.lock().unwrap();
+        assert_eq!(
+            attempts
+                .iter()
+                .map(|attempt| (attempt.attempt, attempt.status_code, attempt.succeeded))
+                .collect::<Vec<_>>(),
+            vec![(1, Some(503), false), (2, Some(204), true)]
+        );
+
+        let received = received.lock().unwrap();
+        let (headers, body) = received.last().unwrap();
+        let timestamp: i64 = headers[TIMESTAMP_HEADER].to_str().unwrap().parse().unwrap();
+        assert_eq!(
+            headers[SIGNATURE_HEADER].to_str().unwrap(),
+            format!(
+                "sha256={}",
+                astroplant_auth::hash::sign_webhook_payload("secret", timestamp, body)
+            )
+        );
+        assert_eq!(headers[EVENT_HEADER], "kit_online");
+    }
+}
@@ -0,0 +1,436 @@
+//! The WebSocket authentication and backends. Events are distributed to the subscribers of all API
+//! instances through Postgres `NOTIFY`, and the latest raw measurements are cached in a table
+//! shared by all instances. As the broker may redeliver a kit's MQTT message, possibly to another
+//! instance of the shared subscription, each event is published by the first instance claiming it.
+//! The in-memory backend serves a single instance.
+
+use astroplant_mqtt::RawMeasurement;
+use astroplant_websocket::auth::{Action, AuthenticationError, Session};
+use astroplant_websocket::backend::{Backend, Event, Memory, CACHE_RETENTION_PERIOD};
+use astroplant_websocket::{Publisher, SocketHandler};
+use futures::stream::BoxStream;
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::{PgListener, PgPool as SqlxPgPool};
+use sqlx::types::Json;
+use std::time::Duration;
+
+use crate::authorization::KitAction;
+use crate::database::PgPool;
+use crate::{helpers, models};
+
+/// The channel events are sent on.
+const CHANNEL: &str = "astroplant_websocket";
+
+/// The time to wait before reconnecting after losing the notification listener's connection.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Notification payloads must be shorter than this many bytes.
+const MAX_NOTIFICATION_PAYLOAD: usize = 8000;
+
+/// How long the ids of published events, and the events too large to notify, are remembered.
+/// Redelivered MQTT messages and notifications are received well within this period.
+const PUBLISHED_EVENT_RETENTION_PERIOD: Duration = Duration::from_secs(10 * 60);
+
+/// Create the WebSockets with the backend configured by `WEBSOCKET_BACKEND`: `memory` (the
+/// default) or `postgres`. The maximum number of subscriptions per connection is configured by
+/// `WEBSOCKET_MAX_SUBSCRIPTIONS`.
+pub fn create(sqlx_pg: SqlxPgPool) -> anyhow::Result<(Publisher, SocketHandler)> {
+    let (publisher, socket_handler) = match std::env::var("WEBSOCKET_BACKEND").as_deref() {
+        Err(_) | Ok("memory") => astroplant_websocket::create(Memory::new()),
+        Ok("postgres") => astroplant_websocket::create(Postgres::new(sqlx_pg)),
+        Ok(backend) => anyhow::bail!("unknown WebSocket backend '{}'", backend),
+    };
+
+    let max_subscriptions = match std::env::var("WEBSOCKET_MAX_SUBSCRIPTIONS") {
+        Ok(max_subscriptions) => max_subscriptions.parse().map_err(|_| {
+            anyhow::anyhow!(
+                "invalid maximum number of WebSocket subscriptions '{}'",
+                max_subscriptions
+            )
+        })?,
+        Err(_) => astroplant_websocket::DEFAULT_MAX_SUBSCRIPTIONS,
+    };
+
+    Ok((
+        publisher,
+        socket_handler.with_max_subscriptions(max_subscriptions),
+    ))
+}
+
+/// Authenticates WebSocket connections with access tokens, and authorizes subscriptions and kit
+/// RPC with the matching kit permissions.
+pub struct Auth {
+    pg: PgPool,
+}
+
+impl Auth {
+    pub fn new(pg: PgPool) -> Self {
+        Self { pg }
+    }
+}
+
+#[async_trait::async_trait]
+impl astroplant_websocket::auth::Auth for Auth {
+    type User = models::UserId;
+
+    async fn authenticate(
+        &self,
+        access_token: &str,
+    ) -> Result<Session<models::UserId>, AuthenticationError> {
+        let token_signer = crate::TOKEN_SIGNER.get().unwrap();
+        match token_signer.decode_access_token_with_expiry(access_token) {
+            Ok((authentication_state, expires)) => Ok(Session {
+                user: models::UserId(authentication_state.user_id),
+                expires,
+            }),
+            Err(astroplant_auth::token::Error::Expired) => Err(AuthenticationError::Expired),
+            Err(_) => Err(AuthenticationError::Invalid),
+        }
+    }
+
+    async fn authorize(
+        &self,
+        user: Option<models::UserId>,
+        kit_serial: &str,
+        action: Action,
+    ) -> bool {
+        let action = match action {
+            Action::Subscribe => KitAction::SubscribeRealTimeMeasurements,
+            Action::RpcVersion => KitAction::RpcVersion,
+            Action::RpcUptime => KitAction::RpcUptime,
+            Action::RpcPeripheralCommand => KitAction::RpcPeripheralCommand,
+            Action::RpcPeripheralCommandLock => KitAction::RpcPeripheralCommandLock,
+            Action::Preview => KitAction::ViewPreview,
+        };
+        helpers::fut_kit_permission_or_forbidden(
+            self.pg.clone(),
+            user,
+            kit_serial.to_owned(),
+            action,
+        )
+        .await
+        .is_ok()
+    }
+}
+
+/// The Postgres backend, for running several API instances.
+pub struct Postgres {
+    pool: SqlxPgPool,
+}
+
+impl Postgres {
+    /// Must be called from within a Tokio runtime.
+    pub fn new(pool: SqlxPgPool) -> Self {
+        tokio::spawn(keep_tables_clean(pool.clone()));
+        Self { pool }
+    }
+}
+
+async fn keep_tables_clean(pool: SqlxPgPool) {
+    const CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        if let Err(err) = sqlx::query(
+            "DELETE FROM websocket_raw_measurement_cache
+            WHERE datetime_received < now() - make_interval(secs => $1)",
+        )
+        .bind(CACHE_RETENTION_PERIOD.as_secs_f64())
+        .execute(&pool)
+        .await
+        {
+            tracing::warn!("failed to clean the raw measurement cache: {:?}", err);
+        }
+        if let Err(err) = sqlx::query(
+            "DELETE FROM websocket_published_events
+            WHERE datetime_published < now() - make_interval(secs => $1)",
+        )
+        .bind(PUBLISHED_EVENT_RETENTION_PERIOD.as_secs_f64())
+        .execute(&pool)
+        .await
+        {
+            tracing::warn!("failed to clean the published WebSocket events: {:?}", err);
+        }
+    }
+}
+
+/// The payload of a notification: the event, or the id of an event too large for a notification,
+/// which is stored in `websocket_published_events`.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Notification {
+    Event(Event),
+    Stored { stored: uuid::Uuid },
+}
+
+/// The id of the measurement or media of an event.
+fn event_id(event: &Event) -> Option<uuid::Uuid> {
+    match event {
+        Event::RawMeasurement(raw_measurement) => Some(raw_measurement.id),
+        Event::AggregateMeasurement(aggregate_measurement) => Some(aggregate_measurement.id),
+        Event::Media(media) => media.media.get("id")?.as_str()?.parse().ok(),
+    }
+}
+
+async fn listen(pool: &SqlxPgPool) -> Result<PgListener, sqlx::Error> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen(CHANNEL).await?;
+    Ok(listener)
+}
+
+/// Fetch an event that was too large for a notification.
+async fn stored_event(pool: &SqlxPgPool, id: uuid::Uuid) -> Result<Option<Event>, sqlx::Error> {
+    let event: Option<Option<Json<Event>>> =
+        sqlx::query_scalar("SELECT event FROM websocket_published_events WHERE id = $1")
+            .bind(id)
+            .fetch_optional(pool)
+            .await?;
+    Ok(event.flatten().map(|Json(event)| event))
+}
+
+#[async_trait::async_trait]
+impl Backend for Postgres {
+    async fn publish(&self, event: Event) -> anyhow::Result<()> {
+        let payload = serde_json::to_string(&event)?;
+        let fits = payload.len() < MAX_NOTIFICATION_PAYLOAD;
+        match event_id(&event) {
+            Some(id) => {
+                // Events too large for a notification, e.g. with large media metadata, are stored
+                // with their claim for the listeners to fetch.
+                let (payload, stored) = if fits {
+                    (payload, None)
+                } else {
+                    (
+                        serde_json::to_string(&Notification::Stored { stored: id })?,
+                        Some(Json(&event)),
+                    )
+                };
+                sqlx::query(
+                    "WITH claimed AS (
+                        INSERT INTO websocket_published_events (id, datetime_published, event)
+                        VALUES ($1, now(), $4)
+                        ON CONFLICT DO NOTHING
+                        RETURNING id
+                    )
+                    SELECT pg_notify($2, $3) FROM claimed",
+                )
+                .bind(id)
+                .bind(CHANNEL)
+                .bind(payload)
+                .bind(stored)
+                .execute(&self.pool)
+                .await?;
+            }
+            None if !fits => anyhow::bail!(
+                "the event of {} bytes is too large for a notification",
+                payload.len()
+            ),
+            None => {
+                sqlx::query("SELECT pg_notify($1, $2)")
+                    .bind(CHANNEL)
+                    .bind(payload)
+                    .execute(&self.pool)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn events(&self) -> BoxStream<'static, Event> {
+        let pool = self.pool.clone();
+        // Listen right away, such that events published after this returns are received.
+        let listener = match listen(&pool).await {
+            Ok(listener) => Some(listener),
+            Err(err) => {
+                tracing::warn!("failed to listen for WebSocket events: {:?}", err);
+                None
+            }
+        };
+
+        Box::pin(futures::stream::unfold(
+            (pool, listener),
+            |(pool, mut listener)| async move {
+                loop {
+                    let connected = match &mut listener {
+                        Some(connected) => connected,
+                        None => match listen(&pool).await {
+                            Ok(connected) => listener.insert(connected),
+                            Err(err) => {
+                                tracing::warn!("failed to listen for WebSocket events: {:?}", err);
+                                tokio::time::sleep(RECONNECT_DELAY).await;
+                                continue;
+                            }
+                        },
+                    };
+
+                    // The listener reconnects on the next receive if its connection was lost.
+                    // Events sent in the meantime are missed.
+                    match connected.recv().await {
+                        Ok(notification) => {
+                            match serde_json::from_str::<Notification>(notification.payload()) {
+                                Ok(Notification::Event(event)) => {
+                                    return Some((event, (pool, listener)))
+                                }
+                                Ok(Notification::Stored { stored }) => {
+                                    match stored_event(&pool, stored).await {
+                                        Ok(Some(event)) => return Some((event, (pool, listener))),
+                                        Ok(None) => tracing::warn!(
+                                            "the stored WebSocket event {} is gone",
+                                            stored
+                                        ),
+                                        Err(err) => tracing::warn!(
+                                            "failed to fetch the stored WebSocket event {}: {:?}",
+                                            stored,
+                                            err
+                                        ),
+                                    }
+                                }
+                                Err(err) => {
+                                    tracing::warn!("received an invalid WebSocket event: {:?}", err)
+                                }
+                            }
+                        }
+                        Err(err) => {
+                            tracing::warn!("failed to receive WebSocket events: {:?}", err);
+                            tokio::time::sleep(RECONNECT_DELAY).await;
+                        }
+                    }
+                }
+            },
+        ))
+    }
+
+    async fn cache_raw_measurement(&self, raw_measurement: &RawMeasurement) -> anyhow::Result<()> {
+        sqlx::query(
+            "INSERT INTO websocket_raw_measurement_cache
+                (kit_serial, peripheral, quantity_type, raw_measurement, datetime_received)
+            VALUES ($1, $2, $3, $4, now())
+            ON CONFLICT (kit_serial, peripheral, quantity_type) DO UPDATE
+            SET raw_measurement = EXCLUDED.raw_measurement,
+                datetime_received = EXCLUDED.datetime_received
+            -- Other instances cache the same measurement.
+            WHERE websocket_raw_measurement_cache.raw_measurement->'id'
+                IS DISTINCT FROM EXCLUDED.raw_measurement->'id'",
+        )
+        .bind(&raw_measurement.kit_serial)
+        .bind(raw_measurement.peripheral)
+        .bind(raw_measurement.quantity_type)
+        .bind(Json(raw_measurement))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn cached_raw_measurements(
+        &self,
+        kit_serial: &str,
+    ) -> anyhow::Result<Vec<RawMeasurement>> {
+        let raw_measurements: Vec<Json<RawMeasurement>> = sqlx::query_scalar(
+            "SELECT raw_measurement FROM websocket_raw_measurement_cache
+            WHERE kit_serial = $1 AND datetime_received >= now() - make_interval(secs => $2)",
+        )
+        .bind(kit_serial)
+        .bind(CACHE_RETENTION_PERIOD.as_secs_f64())
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(raw_measurements
+            .into_iter()
+            .map(|Json(raw_measurement)| raw_measurement)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use astroplant_websocket::backend::KitMedia;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    #[ignore = "requires a migrated database at DATABASE_URL"]
+    async fn instances_publish_an_event_once() {
+        const KIT_SERIAL: &str = "k-websocket-test";
+
+        let pool = crate::database::new_sqlx_pool().await.unwrap();
+        let instances = [Postgres::new(pool.clone()), Postgres::new(pool)];
+        let mut events = instances[0].events().await;
+
+        let raw_measurement = |value| RawMeasurement {
+            id: uuid::Uuid::new_v4(),
+            kit_serial: KIT_SERIAL.to_owned(),
+            datetime: chrono::Utc::now(),
+            peripheral: 1,
+            quantity_type: 1,
+            value,
+        };
+        let first = raw_measurement(1.0);
+        let second = raw_measurement(2.0);
+
+        // Both instances receive the first measurement from MQTT.
+        for instance in &instances {
+            instance
+                .publish(Event::RawMeasurement(first.clone()))
+                .await
+                .unwrap();
+        }
+        instances[1]
+            .publish(Event::RawMeasurement(second.clone()))
+            .await
+            .unwrap();
+
+        let mut received = vec![];
+        while received.len() < 2 {
+            let event = tokio::time::timeout(Duration::from_secs(5), events.next())
+                .await
+                .expect("timed out waiting for events")
+                .unwrap();
+            match event {
+                Event::RawMeasurement(raw_measurement)
+                    if raw_measurement.kit_serial == KIT_SERIAL =>
+                {
+                    received.push(raw_measurement.id)
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(received, vec![first.id, second.id]);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a migrated database at DATABASE_URL"]
+    async fn fetches_events_too_large_to_notify() {
+        const KIT_SERIAL: &str = "k-websocket-test-large";
+
+        let pool = crate::database::new_sqlx_pool().await.unwrap();
+        let instance = Postgres::new(pool);
+        let mut events = instance.events().await;
+
+        let id = uuid::Uuid::new_v4();
+        let media = serde_json::json!({
+            "id": id,
+            "metadata": "x".repeat(2 * MAX_NOTIFICATION_PAYLOAD),
+        });
+        instance
+            .publish(Event::Media(KitMedia {
+                kit_serial: KIT_SERIAL.to_owned(),
+                media: media.clone(),
+            }))
+            .await
+            .unwrap();
+
+        loop {
+            let event = tokio::time::timeout(Duration::from_secs(5), events.next())
+                .await
+                .expect("timed out waiting for events")
+                .unwrap();
+            if let Event::Media(received) = event {
+                if received.kit_serial == KIT_SERIAL {
+                    assert_eq!(received.media, media);
+                    break;
+                }
+            }
+        }
+    }
+}
@@ -106,6 +106,49 @@ pub fn hash_kit_password(password: &str) -> String {
     kit_hash_format(PBKDF2_ITERATIONS, &salt, &hash)
 }
 
+/// Check a password against a hash generated by [hash_kit_password].
+pub fn check_kit_password(password: &str, hash: &str) -> bool {
+    let parts: Vec<_> = hash.split('$').collect();
+    if parts.len() != 5 || parts[0] != "PBKDF2" || parts[1] != "sha256" {
+        return false;
+    }
+
+    let iterations: u32 = match parts[2].parse() {
+        Ok(iterations) => iterations,
+        Err(_) => return false,
+    };
+    let expected = match base64::decode(parts[4]) {
+        Ok(expected) => expected,
+        Err(_) => return false,
+    };
+
+    pbkdf2(password, parts[3].as_bytes(), iterations)[..] == expected[..]
+}
+
+/// Hash a kit claim code. Claim codes are random and single-use, so unlike passwords they are
+/// hashed without salt: this allows looking up a kit by the hash of its claim code.
+pub fn hash_claim_code(claim_code: &str) -> String {
+    use sha2::{Digest, Sha256};
+
+    base64::encode(&Sha256::digest(claim_code.as_bytes()))
+}
+
+/// Sign a webhook delivery's payload with the webhook's secret. The signature is the base64 of
+/// the HMAC-SHA256 of `"{timestamp}.{payload}"`, such that receivers can reject replayed
+/// deliveries by their timestamp.
+pub fn sign_webhook_payload(secret: &str, timestamp: i64, payload: &[u8]) -> String {
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC can be initialized with any key length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(payload);
+
+    base64::encode(&mac.finalize().into_bytes())
+}
+
 /// Perform pbkdf2.
 fn pbkdf2(password: &str, salt: &[u8], iterations: u32) -> [u8; 32] {
     use hmac::Hmac;
@@ -157,6 +200,14 @@ mod test {
         );
     }
 
+    #[test]
+    pub fn webhook_signature() {
+        assert_eq!(
+            super::sign_webhook_payload("whsec", 1_700_000_000, br#"{"type":"ping"}"#),
+            "Rkzg4ZRFioxhroIbtjVQJnzRZTEzRGWOoAKVdGaEN5Q="
+        );
+    }
+
     #[test]
     pub fn kit_hash_format() {
         assert_eq!(
@@ -169,6 +220,23 @@ mod test {
         )
     }
 
+    #[test]
+    pub fn check_kit_password() {
+        let hash =
+            "PBKDF2$sha256$2000$Z416JHE8vSmaiamV5TRz$z3y6FvWAZtyQe6TV+O/oyhC3oqnF8KJdlB5Lphi+Lwg=";
+        assert!(super::check_kit_password(
+            "It all adds up to normality.",
+            hash
+        ));
+        assert!(!super::check_kit_password("It all adds up.", hash));
+
+        let password = "It all adds up to normality.";
+        assert!(super::check_kit_password(
+            password,
+            &super::hash_kit_password(password)
+        ));
+    }
+
     #[test]
     pub fn check_v1_hash() {
         let v1_hash: super::V1Hash =
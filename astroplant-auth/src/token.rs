@@ -1,6 +1,6 @@
 //! TODO: add ability to revoke refresh tokens.
 
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 
 use jsonwebtoken::{DecodingKey, EncodingKey};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
@@ -110,9 +110,26 @@ impl TokenSigner {
     }
 
     pub fn decode_access_token(&self, token: &str) -> Result<AuthenticationState, Error> {
+        self.decode_access_token_with_expiry(token)
+            .map(|(state, _)| state)
+    }
+
+    /// Decode an access token, also returning when it expires.
+    pub fn decode_access_token_with_expiry(
+        &self,
+        token: &str,
+    ) -> Result<(AuthenticationState, chrono::DateTime<chrono::Utc>), Error> {
+        use chrono::TimeZone;
+
         let claims = self.decode_token(token)?;
         match claims.token_type {
-            TokenType::Access => Ok(claims.state),
+            TokenType::Access => {
+                let expiry = i64::try_from(claims.exp)
+                    .ok()
+                    .and_then(|exp| chrono::Utc.timestamp_opt(exp, 0).single())
+                    .ok_or(Error::Other)?;
+                Ok((claims.state, expiry))
+            }
             _ => Err(Error::Other),
         }
     }
@@ -175,6 +192,11 @@ mod test {
                 state,
                 token_signer.decode_access_token(&access_token).unwrap()
             );
+
+            let (_, expiry) = token_signer
+                .decode_access_token_with_expiry(&access_token)
+                .unwrap();
+            assert!(expiry > chrono::Utc::now() + chrono::Duration::minutes(14));
         }
     }
 }
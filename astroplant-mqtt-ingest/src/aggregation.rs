@@ -0,0 +1,108 @@
+//! Server-side aggregation of raw measurements.
+//!
+//! Kits with simple firmware publish only raw measurements. For those, the server can compute
+//! aggregate measurements (mean, minimum and maximum per peripheral and quantity type) over
+//! windows of configurable sizes. Windows are aligned to the Unix epoch, and are aggregated once
+//! they have ended, allowing some time for late measurements to arrive.
+
+use chrono::{DateTime, TimeZone, Utc};
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::database::Db;
+
+/// The time to wait after a window ends before aggregating it, allowing late measurements to
+/// arrive.
+const LATE_ARRIVAL_GRACE: Duration = Duration::from_secs(2 * 60);
+
+/// The interval at which to check for ended windows.
+const TICK: Duration = Duration::from_secs(60);
+
+/// Read the aggregation window sizes from the `AGGREGATE_WINDOW_MINUTES` environment variable, a
+/// comma-separated list of window sizes in minutes. Server-side aggregation is disabled if this is
+/// not set.
+pub(crate) fn windows_from_env() -> Vec<Duration> {
+    parse_windows(&std::env::var("AGGREGATE_WINDOW_MINUTES").unwrap_or_default())
+}
+
+fn parse_windows(windows: &str) -> Vec<Duration> {
+    windows
+        .split(',')
+        .filter_map(|minutes| match minutes.trim().parse::<u64>() {
+            Ok(minutes) if minutes > 0 => Some(Duration::from_secs(minutes * 60)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The start of the window of size `window` containing `datetime`.
+fn window_start(datetime: DateTime<Utc>, window: Duration) -> DateTime<Utc> {
+    let window = window.as_secs() as i64;
+    let timestamp = datetime.timestamp();
+    let start = timestamp - timestamp.rem_euclid(window);
+    Utc.timestamp_opt(start, 0).unwrap()
+}
+
+/// Periodically aggregate the raw measurements of ended windows. At startup, the most recently
+/// ended window of each size is (re)aggregated.
+///
+/// # Panics
+///
+/// This panics if it is run outside of a [LocalSet](tokio::task::LocalSet) context.
+pub(crate) async fn run(db: Rc<Db>, windows: Vec<Duration>) {
+    let grace = chrono::Duration::from_std(LATE_ARRIVAL_GRACE).expect("in range");
+
+    let now = Utc::now() - grace;
+    let mut aggregated_until: Vec<DateTime<Utc>> = windows
+        .iter()
+        .map(|&window| {
+            window_start(now, window) - chrono::Duration::from_std(window).expect("in range")
+        })
+        .collect();
+
+    let mut interval = tokio::time::interval(TICK);
+    loop {
+        interval.tick().await;
+
+        let now = Utc::now() - grace;
+        for (&window, aggregated_until) in windows.iter().zip(aggregated_until.iter_mut()) {
+            let end = window_start(now, window);
+            if end <= *aggregated_until {
+                continue;
+            }
+
+            match db.aggregate_raw(window, *aggregated_until, end).await {
+                Ok(_) => *aggregated_until = end,
+                Err(err) => {
+                    // The range is retried on the next tick.
+                    tracing::warn!("Failed to aggregate raw measurements: {:?}", err);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{TimeZone, Utc};
+    use std::time::Duration;
+
+    #[test]
+    pub fn windows() {
+        assert_eq!(
+            super::parse_windows("10, 60,x,0"),
+            vec![Duration::from_secs(600), Duration::from_secs(3600)]
+        );
+        assert!(super::parse_windows("").is_empty());
+
+        let datetime = Utc.with_ymd_and_hms(2026, 10, 18, 15, 27, 31).unwrap();
+        assert_eq!(
+            super::window_start(datetime, Duration::from_secs(600)),
+            Utc.with_ymd_and_hms(2026, 10, 18, 15, 20, 0).unwrap()
+        );
+        assert_eq!(
+            super::window_start(datetime, Duration::from_secs(3600)),
+            Utc.with_ymd_and_hms(2026, 10, 18, 15, 0, 0).unwrap()
+        );
+    }
+}
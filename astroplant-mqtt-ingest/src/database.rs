@@ -1,8 +1,9 @@
+use chrono::{DateTime, Utc};
 use std::{cell::RefCell, collections::HashMap};
 use tokio_postgres::types::Type;
 use tokio_postgres::{Client, Statement};
 
-use astroplant_mqtt::{AggregateMeasurement, RawMeasurement};
+use astroplant_mqtt::{ActuatorState, AggregateMeasurement, KitEvent, RawMeasurement};
 
 type PeripheralId = i32;
 type KitId = i32;
@@ -25,9 +26,16 @@ pub(crate) struct Db {
     config_cache: RefCell<HashMap<PeripheralId, KitAndConfigId>>,
     client: Client,
     get_config_and_kit: Statement,
+    get_kit_id: Statement,
     insert_raw_measurement: Statement,
     insert_aggregate_measurement: Statement,
+    insert_actuator_state: Statement,
+    insert_kit_event: Statement,
     upsert_kit_last_seen: Statement,
+    upsert_latest_raw_measurement: Statement,
+    upsert_latest_aggregate_measurement: Statement,
+    aggregate_raw_measurements: Statement,
+    upsert_latest_server_computed_aggregates: Statement,
 }
 
 const GET_CONFIG_AND_KIT: &str = "
@@ -37,6 +45,12 @@ const GET_CONFIG_AND_KIT: &str = "
     WHERE peripherals.id = $1
 ";
 
+const GET_KIT_ID: &str = "
+    SELECT kits.id AS kit_id
+    FROM kits
+    WHERE kits.serial = $1
+";
+
 const INSERT_RAW_MEASUREMENT: &str = "
     INSERT INTO raw_measurements (id, peripheral_id, kit_id, kit_configuration_id, quantity_type_id, value, datetime)
     VALUES ($1, $2, $3, $4, $5, $6, $7)
@@ -47,6 +61,18 @@ const INSERT_AGGREGATE_MEASUREMENT: &str = "
     VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
 ";
 
+const INSERT_ACTUATOR_STATE: &str = "
+    INSERT INTO actuator_states (id, peripheral_id, kit_id, kit_configuration_id, datetime, state)
+    VALUES ($1, $2, $3, $4, $5, $6)
+    ON CONFLICT (id) DO NOTHING
+";
+
+const INSERT_KIT_EVENT: &str = "
+    INSERT INTO kit_events (id, kit_id, peripheral_id, datetime, severity, message)
+    VALUES ($1, $2, $3, $4, $5, $6)
+    ON CONFLICT (id) DO NOTHING
+";
+
 const UPSERT_KIT_LAST_SEEN: &str = "
     INSERT INTO kit_last_seen (kit_id, datetime_last_seen)
     VALUES ($1, NOW())
@@ -54,12 +80,94 @@ const UPSERT_KIT_LAST_SEEN: &str = "
       SET datetime_last_seen = EXCLUDED.datetime_last_seen
 ";
 
+/// Measurements arriving out of order do not replace newer measurements.
+const UPSERT_LATEST_RAW_MEASUREMENT: &str = "
+    INSERT INTO latest_measurements AS latest (peripheral_id, quantity_type_id, kit_id, raw_measurement_id, raw_value, raw_datetime)
+    VALUES ($1, $2, $3, $4, $5, $6)
+    ON CONFLICT (peripheral_id, quantity_type_id) DO UPDATE
+      SET kit_id = EXCLUDED.kit_id,
+          raw_measurement_id = EXCLUDED.raw_measurement_id,
+          raw_value = EXCLUDED.raw_value,
+          raw_datetime = EXCLUDED.raw_datetime
+      WHERE latest.raw_datetime IS NULL OR latest.raw_datetime <= EXCLUDED.raw_datetime
+";
+
+/// Measurements arriving out of order do not replace newer measurements.
+const UPSERT_LATEST_AGGREGATE_MEASUREMENT: &str = "
+    INSERT INTO latest_measurements AS latest (peripheral_id, quantity_type_id, kit_id, aggregate_measurement_id, aggregate_values, aggregate_datetime_start, aggregate_datetime_end)
+    VALUES ($1, $2, $3, $4, $5, $6, $7)
+    ON CONFLICT (peripheral_id, quantity_type_id) DO UPDATE
+      SET kit_id = EXCLUDED.kit_id,
+          aggregate_measurement_id = EXCLUDED.aggregate_measurement_id,
+          aggregate_values = EXCLUDED.aggregate_values,
+          aggregate_datetime_start = EXCLUDED.aggregate_datetime_start,
+          aggregate_datetime_end = EXCLUDED.aggregate_datetime_end
+      WHERE latest.aggregate_datetime_end IS NULL OR latest.aggregate_datetime_end <= EXCLUDED.aggregate_datetime_end
+";
+
+/// Compute the mean, minimum and maximum of raw measurements per peripheral, quantity type and
+/// window of `$1` seconds, for windows starting in [`$2`, `$3`). Windows are aligned to the Unix
+/// epoch. Peripherals for which the kit publishes its own aggregates in this range are skipped.
+///
+/// The aggregate's id is derived from its window, so recomputing a window does not create a
+/// duplicate.
+const AGGREGATE_RAW_MEASUREMENTS: &str = "
+    INSERT INTO aggregate_measurements (id, peripheral_id, kit_id, kit_configuration_id, quantity_type_id, datetime_start, datetime_end, values, server_computed)
+    SELECT
+        md5(concat_ws('/', raw.peripheral_id, raw.quantity_type_id, raw.window_start, $1))::uuid,
+        raw.peripheral_id,
+        raw.kit_id,
+        raw.kit_configuration_id,
+        raw.quantity_type_id,
+        raw.window_start,
+        raw.window_start + make_interval(secs => $1),
+        jsonb_build_object('average', avg(raw.value), 'minimum', min(raw.value), 'maximum', max(raw.value)),
+        true
+    FROM (
+        SELECT *, to_timestamp(floor(extract(epoch FROM datetime) / $1) * $1) AS window_start
+        FROM raw_measurements
+        WHERE datetime >= $2 AND datetime < $3
+    ) raw
+    WHERE NOT EXISTS (
+        SELECT 1
+        FROM aggregate_measurements kit_aggregate
+        WHERE kit_aggregate.peripheral_id = raw.peripheral_id
+          AND kit_aggregate.quantity_type_id = raw.quantity_type_id
+          AND NOT kit_aggregate.server_computed
+          AND kit_aggregate.datetime_start < $3
+          AND kit_aggregate.datetime_end > $2
+    )
+    GROUP BY raw.peripheral_id, raw.kit_id, raw.kit_configuration_id, raw.quantity_type_id, raw.window_start
+    ON CONFLICT (peripheral_id, quantity_type_id, datetime_start, datetime_end) WHERE server_computed
+    DO NOTHING
+";
+
+/// Record the newest server-computed aggregates of windows starting in [`$1`, `$2`) as the latest
+/// aggregate measurements, unless newer aggregates are known.
+const UPSERT_LATEST_SERVER_COMPUTED_AGGREGATES: &str = "
+    INSERT INTO latest_measurements AS latest (peripheral_id, quantity_type_id, kit_id, aggregate_measurement_id, aggregate_values, aggregate_datetime_start, aggregate_datetime_end)
+    SELECT DISTINCT ON (peripheral_id, quantity_type_id)
+        peripheral_id, quantity_type_id, kit_id, id, values, datetime_start, datetime_end
+    FROM aggregate_measurements
+    WHERE server_computed AND datetime_start >= $1 AND datetime_start < $2
+    ORDER BY peripheral_id, quantity_type_id, datetime_end DESC
+    ON CONFLICT (peripheral_id, quantity_type_id) DO UPDATE
+      SET kit_id = EXCLUDED.kit_id,
+          aggregate_measurement_id = EXCLUDED.aggregate_measurement_id,
+          aggregate_values = EXCLUDED.aggregate_values,
+          aggregate_datetime_start = EXCLUDED.aggregate_datetime_start,
+          aggregate_datetime_end = EXCLUDED.aggregate_datetime_end
+      WHERE latest.aggregate_datetime_end IS NULL OR latest.aggregate_datetime_end <= EXCLUDED.aggregate_datetime_end
+";
+
 impl Db {
     pub(crate) async fn new(client: Client) -> anyhow::Result<Self> {
         let get_config_and_kit = client
             .prepare_typed(GET_CONFIG_AND_KIT, &[Type::INT4])
             .await?;
 
+        let get_kit_id = client.prepare_typed(GET_KIT_ID, &[Type::VARCHAR]).await?;
+
         let insert_raw_measurement = client
             .prepare_typed(
                 INSERT_RAW_MEASUREMENT,
@@ -86,7 +194,35 @@ impl Db {
                     Type::INT4,
                     Type::TIMESTAMPTZ,
                     Type::TIMESTAMPTZ,
-                    Type::JSON,
+                    Type::JSONB,
+                ],
+            )
+            .await?;
+
+        let insert_actuator_state = client
+            .prepare_typed(
+                INSERT_ACTUATOR_STATE,
+                &[
+                    Type::UUID,
+                    Type::INT4,
+                    Type::INT4,
+                    Type::INT4,
+                    Type::TIMESTAMPTZ,
+                    Type::JSONB,
+                ],
+            )
+            .await?;
+
+        let insert_kit_event = client
+            .prepare_typed(
+                INSERT_KIT_EVENT,
+                &[
+                    Type::UUID,
+                    Type::INT4,
+                    Type::INT4,
+                    Type::TIMESTAMPTZ,
+                    Type::INT2,
+                    Type::TEXT,
                 ],
             )
             .await?;
@@ -95,13 +231,63 @@ impl Db {
             .prepare_typed(UPSERT_KIT_LAST_SEEN, &[Type::INT4])
             .await?;
 
+        let upsert_latest_raw_measurement = client
+            .prepare_typed(
+                UPSERT_LATEST_RAW_MEASUREMENT,
+                &[
+                    Type::INT4,
+                    Type::INT4,
+                    Type::INT4,
+                    Type::UUID,
+                    Type::FLOAT8,
+                    Type::TIMESTAMPTZ,
+                ],
+            )
+            .await?;
+
+        let upsert_latest_aggregate_measurement = client
+            .prepare_typed(
+                UPSERT_LATEST_AGGREGATE_MEASUREMENT,
+                &[
+                    Type::INT4,
+                    Type::INT4,
+                    Type::INT4,
+                    Type::UUID,
+                    Type::JSONB,
+                    Type::TIMESTAMPTZ,
+                    Type::TIMESTAMPTZ,
+                ],
+            )
+            .await?;
+
+        let aggregate_raw_measurements = client
+            .prepare_typed(
+                AGGREGATE_RAW_MEASUREMENTS,
+                &[Type::FLOAT8, Type::TIMESTAMPTZ, Type::TIMESTAMPTZ],
+            )
+            .await?;
+
+        let upsert_latest_server_computed_aggregates = client
+            .prepare_typed(
+                UPSERT_LATEST_SERVER_COMPUTED_AGGREGATES,
+                &[Type::TIMESTAMPTZ, Type::TIMESTAMPTZ],
+            )
+            .await?;
+
         let db = Self {
             config_cache: RefCell::new(HashMap::new()),
             client,
             get_config_and_kit,
+            get_kit_id,
             insert_raw_measurement,
             insert_aggregate_measurement,
+            insert_actuator_state,
+            insert_kit_event,
             upsert_kit_last_seen,
+            upsert_latest_raw_measurement,
+            upsert_latest_aggregate_measurement,
+            aggregate_raw_measurements,
+            upsert_latest_server_computed_aggregates,
         };
         Ok(db)
     }
@@ -155,7 +341,9 @@ impl Db {
         Ok(config)
     }
 
-    pub(crate) async fn insert_raw(&self, raw: RawMeasurement) -> anyhow::Result<()> {
+    /// Store a raw measurement. Returns whether it was stored: measurements stating a peripheral
+    /// that does not belong to the kit are ignored. Fails if the measurement was already stored.
+    pub(crate) async fn insert_raw(&self, raw: &RawMeasurement) -> anyhow::Result<bool> {
         let config = match self.config_and_kit(raw.peripheral, &raw.kit_serial).await? {
             Some(config) => config,
             None => {
@@ -165,7 +353,7 @@ impl Db {
                     raw.kit_serial,
                     raw.peripheral,
                 );
-                return Ok(());
+                return Ok(false);
             }
         };
 
@@ -188,6 +376,20 @@ impl Db {
             .query(&self.upsert_kit_last_seen, &[&config.kit_id])
             .await?;
 
+        self.client
+            .query(
+                &self.upsert_latest_raw_measurement,
+                &[
+                    &raw.peripheral,
+                    &raw.quantity_type,
+                    &config.kit_id,
+                    &raw.id,
+                    &raw.value,
+                    &raw.datetime,
+                ],
+            )
+            .await?;
+
         tracing::trace!(
             "Inserted raw measurement {} of kit {} and peripheral {}",
             raw.id,
@@ -195,7 +397,7 @@ impl Db {
             raw.peripheral,
         );
 
-        Ok(())
+        Ok(true)
     }
 
     pub(crate) async fn insert_aggregate(&self, raw: AggregateMeasurement) -> anyhow::Result<()> {
@@ -212,6 +414,7 @@ impl Db {
             }
         };
 
+        let values = serde_json::to_value(raw.values)?;
         self.client
             .query(
                 &self.insert_aggregate_measurement,
@@ -223,7 +426,7 @@ impl Db {
                     &raw.quantity_type,
                     &raw.datetime_start,
                     &raw.datetime_end,
-                    &serde_json::to_value(raw.values)?,
+                    &values,
                 ],
             )
             .await?;
@@ -232,6 +435,21 @@ impl Db {
             .query(&self.upsert_kit_last_seen, &[&config.kit_id])
             .await?;
 
+        self.client
+            .query(
+                &self.upsert_latest_aggregate_measurement,
+                &[
+                    &raw.peripheral,
+                    &raw.quantity_type,
+                    &config.kit_id,
+                    &raw.id,
+                    &values,
+                    &raw.datetime_start,
+                    &raw.datetime_end,
+                ],
+            )
+            .await?;
+
         tracing::trace!(
             "Inserted aggregate measurement {} of kit {} and peripheral {}",
             raw.id,
@@ -241,4 +459,136 @@ impl Db {
 
         Ok(())
     }
+
+    pub(crate) async fn insert_actuator_state(&self, state: ActuatorState) -> anyhow::Result<()> {
+        let config = match self
+            .config_and_kit(state.peripheral, &state.kit_serial)
+            .await?
+        {
+            Some(config) => config,
+            None => {
+                tracing::trace!(
+                    "Ignoring actuator state {} of kit {} and peripheral {}: the stated peripheral does not belong to the kit",
+                    state.id,
+                    state.kit_serial,
+                    state.peripheral,
+                );
+                return Ok(());
+            }
+        };
+
+        self.client
+            .query(
+                &self.insert_actuator_state,
+                &[
+                    &state.id,
+                    &state.peripheral,
+                    &config.kit_id,
+                    &config.config_id,
+                    &state.datetime,
+                    &state.state,
+                ],
+            )
+            .await?;
+
+        self.client
+            .query(&self.upsert_kit_last_seen, &[&config.kit_id])
+            .await?;
+
+        tracing::trace!(
+            "Inserted actuator state {} of kit {} and peripheral {}",
+            state.id,
+            state.kit_serial,
+            state.peripheral,
+        );
+
+        Ok(())
+    }
+
+    pub(crate) async fn insert_event(&self, event: KitEvent) -> anyhow::Result<()> {
+        let kit_id = match event.peripheral {
+            Some(peripheral) => self
+                .config_and_kit(peripheral, &event.kit_serial)
+                .await?
+                .map(|config| config.kit_id),
+            None => self
+                .client
+                .query_opt(&self.get_kit_id, &[&event.kit_serial])
+                .await?
+                .map(|res| res.get("kit_id")),
+        };
+        let kit_id: KitId = match kit_id {
+            Some(kit_id) => kit_id,
+            None => {
+                tracing::trace!(
+                    "Ignoring event {} of kit {}: the kit or the stated peripheral {:?} does not exist",
+                    event.id,
+                    event.kit_serial,
+                    event.peripheral,
+                );
+                return Ok(());
+            }
+        };
+
+        self.client
+            .query(
+                &self.insert_kit_event,
+                &[
+                    &event.id,
+                    &kit_id,
+                    &event.peripheral,
+                    &event.datetime,
+                    &(event.severity as i16),
+                    &event.message,
+                ],
+            )
+            .await?;
+
+        self.client
+            .query(&self.upsert_kit_last_seen, &[&kit_id])
+            .await?;
+
+        tracing::trace!(
+            "Inserted event {} of kit {} and peripheral {:?}",
+            event.id,
+            event.kit_serial,
+            event.peripheral,
+        );
+
+        Ok(())
+    }
+
+    /// Compute server-side aggregates of the raw measurements in [`start`, `end`), using windows of
+    /// the given size. Returns the number of aggregates inserted.
+    pub(crate) async fn aggregate_raw(
+        &self,
+        window: std::time::Duration,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> anyhow::Result<u64> {
+        let inserted = self
+            .client
+            .execute(
+                &self.aggregate_raw_measurements,
+                &[&window.as_secs_f64(), &start, &end],
+            )
+            .await?;
+
+        self.client
+            .execute(
+                &self.upsert_latest_server_computed_aggregates,
+                &[&start, &end],
+            )
+            .await?;
+
+        tracing::trace!(
+            "Inserted {} server-computed aggregates of {:?} windows in [{}, {})",
+            inserted,
+            window,
+            start,
+            end,
+        );
+
+        Ok(inserted)
+    }
 }
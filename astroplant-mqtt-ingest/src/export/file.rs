@@ -0,0 +1,41 @@
+//! The file sink, appending newline-delimited JSON.
+
+use astroplant_mqtt::RawMeasurement;
+use tokio::io::AsyncWriteExt;
+
+use super::Sink;
+
+/// Appends measurements to a file, one JSON object per line.
+pub(crate) struct File {
+    file: tokio::fs::File,
+}
+
+impl File {
+    pub(crate) async fn open(path: String) -> anyhow::Result<Self> {
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        Ok(Self { file })
+    }
+}
+
+#[async_trait::async_trait]
+impl Sink for File {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    async fn write(&mut self, measurements: &[RawMeasurement]) -> anyhow::Result<()> {
+        let mut lines = Vec::new();
+        for measurement in measurements {
+            serde_json::to_writer(&mut lines, measurement)?;
+            lines.push(b'\n');
+        }
+
+        self.file.write_all(&lines).await?;
+        self.file.flush().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,99 @@
+//! The InfluxDB sink, writing line protocol over HTTP.
+
+use astroplant_mqtt::RawMeasurement;
+use hyper::header;
+
+use super::{http_post, HttpClient, Sink};
+
+/// The InfluxDB measurement name of raw measurements.
+const MEASUREMENT: &str = "astroplant_measurement";
+
+/// Writes to an InfluxDB write endpoint, e.g. `http://localhost:8086/api/v2/write?org=..&bucket=..`
+/// (InfluxDB 2) or `http://localhost:8086/write?db=..` (InfluxDB 1). Timestamps are in
+/// nanoseconds, the default precision of both.
+pub(crate) struct InfluxDb {
+    client: HttpClient,
+    url: hyper::Uri,
+    token: Option<String>,
+}
+
+impl InfluxDb {
+    pub(crate) fn new(client: HttpClient, url: hyper::Uri, token: Option<String>) -> Self {
+        Self { client, url, token }
+    }
+}
+
+/// Escape a tag value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+/// The line of a measurement. InfluxDB does not support non-finite floats, so those measurements
+/// have no line.
+fn line(measurement: &RawMeasurement) -> Option<String> {
+    if !measurement.value.is_finite() {
+        return None;
+    }
+
+    Some(format!(
+        "{},kit={},peripheral={},quantity_type={} value={:?} {}",
+        MEASUREMENT,
+        escape(&measurement.kit_serial),
+        measurement.peripheral,
+        measurement.quantity_type,
+        measurement.value,
+        measurement.datetime.timestamp_nanos(),
+    ))
+}
+
+#[async_trait::async_trait]
+impl Sink for InfluxDb {
+    fn name(&self) -> &'static str {
+        "influxdb"
+    }
+
+    async fn write(&mut self, measurements: &[RawMeasurement]) -> anyhow::Result<()> {
+        let body = measurements
+            .iter()
+            .filter_map(line)
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let mut request = hyper::Request::builder()
+            .uri(&self.url)
+            .header(header::CONTENT_TYPE, "text/plain; charset=utf-8");
+        if let Some(token) = &self.token {
+            request = request.header(header::AUTHORIZATION, format!("Token {}", token));
+        }
+
+        http_post(&self.client, request, body.into_bytes()).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn line_protocol() {
+        let mut measurement = astroplant_mqtt::RawMeasurement {
+            id: uuid::Uuid::nil(),
+            kit_serial: "k-1 a,b=c".to_owned(),
+            datetime: Utc.timestamp_millis_opt(1_700_000_000_123).unwrap(),
+            peripheral: 2,
+            quantity_type: 3,
+            value: 21.0,
+        };
+        assert_eq!(
+            super::line(&measurement).unwrap(),
+            "astroplant_measurement,kit=k-1\\ a\\,b\\=c,peripheral=2,quantity_type=3 value=21.0 1700000000123000000"
+        );
+
+        measurement.value = f64::NAN;
+        assert!(super::line(&measurement).is_none());
+    }
+}
@@ -0,0 +1,300 @@
+//! Export of raw measurements to external time-series databases.
+//!
+//! Sinks are configured per deployment through the environment, see [Exporter::from_env]. Each
+//! sink runs on its own task, fed by a bounded queue: measurements are exported in batches, and
+//! are dropped if a sink cannot keep up, so exporting never blocks ingestion into Postgres.
+
+use astroplant_mqtt::RawMeasurement;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+mod file;
+mod influxdb;
+mod prometheus;
+
+/// The number of measurements a sink's queue holds.
+const QUEUE_SIZE: usize = 10_000;
+
+/// The maximum number of measurements written at once.
+const BATCH_SIZE: usize = 1_000;
+
+/// The interval at which incomplete batches are written.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The number of attempts at writing a batch, after which the batch is dropped. Batches the sink
+/// [rejected](Rejected) are dropped right away.
+const WRITE_ATTEMPTS: u32 = 3;
+
+/// The time to wait before retrying to write a batch, multiplied by the attempt number.
+const RETRY_BACKOFF: Duration = Duration::from_secs(2);
+
+/// The timeout of HTTP requests made by sinks.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A destination of exported measurements.
+#[async_trait::async_trait]
+pub(crate) trait Sink: Send + 'static {
+    /// The name of the sink, for logging.
+    fn name(&self) -> &'static str;
+
+    /// Write a batch of measurements.
+    async fn write(&mut self, measurements: &[RawMeasurement]) -> anyhow::Result<()>;
+}
+
+type HttpClient = hyper::Client<hyper_tls::HttpsConnector<hyper::client::HttpConnector>>;
+
+fn http_client() -> HttpClient {
+    hyper::Client::builder().build(hyper_tls::HttpsConnector::new())
+}
+
+/// A sink rejected a batch with a client error, e.g. as Prometheus rejects duplicate or
+/// out-of-order samples. The batch is not retried, as it would be rejected again.
+#[derive(Debug)]
+struct Rejected {
+    status: hyper::StatusCode,
+    body: String,
+}
+
+impl std::fmt::Display for Rejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "rejected with status {}: {}", self.status, self.body)
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+/// POST a body, failing on non-2xx responses. Client errors other than timeouts and rate limiting
+/// fail with [Rejected].
+async fn http_post(
+    client: &HttpClient,
+    request: hyper::http::request::Builder,
+    body: Vec<u8>,
+) -> anyhow::Result<()> {
+    let request = request
+        .method(hyper::Method::POST)
+        .body(hyper::Body::from(body))?;
+    let response = tokio::time::timeout(HTTP_TIMEOUT, client.request(request)).await??;
+
+    let status = response.status();
+    if !status.is_success() {
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        let body = String::from_utf8_lossy(&body).into_owned();
+        if status.is_client_error()
+            && status != hyper::StatusCode::REQUEST_TIMEOUT
+            && status != hyper::StatusCode::TOO_MANY_REQUESTS
+        {
+            return Err(Rejected { status, body }.into());
+        }
+        anyhow::bail!("unsuccessful status {}: {}", status, body);
+    }
+
+    Ok(())
+}
+
+struct SinkHandle {
+    name: &'static str,
+    tx: mpsc::Sender<RawMeasurement>,
+    /// The number of measurements dropped as the queue was full, since this was last logged.
+    dropped: Arc<AtomicU64>,
+}
+
+/// Feeds raw measurements to the configured sinks.
+pub(crate) struct Exporter {
+    sinks: Vec<SinkHandle>,
+}
+
+impl Exporter {
+    /// Start the sinks configured in the environment:
+    ///
+    /// - `EXPORT_INFLUXDB_URL`: the InfluxDB write endpoint to POST line protocol to, with the
+    ///   optional `EXPORT_INFLUXDB_TOKEN`;
+    /// - `EXPORT_PROMETHEUS_URL`: the Prometheus remote-write endpoint, with the optional bearer
+    ///   token `EXPORT_PROMETHEUS_TOKEN`;
+    /// - `EXPORT_FILE_PATH`: a file to append measurements to as newline-delimited JSON.
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub(crate) async fn from_env() -> anyhow::Result<Self> {
+        let mut sinks: Vec<Box<dyn Sink>> = vec![];
+
+        if let Ok(url) = std::env::var("EXPORT_INFLUXDB_URL") {
+            sinks.push(Box::new(influxdb::InfluxDb::new(
+                http_client(),
+                url.parse()?,
+                std::env::var("EXPORT_INFLUXDB_TOKEN").ok(),
+            )));
+        }
+        if let Ok(url) = std::env::var("EXPORT_PROMETHEUS_URL") {
+            sinks.push(Box::new(prometheus::RemoteWrite::new(
+                http_client(),
+                url.parse()?,
+                std::env::var("EXPORT_PROMETHEUS_TOKEN").ok(),
+            )));
+        }
+        if let Ok(path) = std::env::var("EXPORT_FILE_PATH") {
+            sinks.push(Box::new(file::File::open(path).await?));
+        }
+
+        Ok(Self {
+            sinks: sinks.into_iter().map(start).collect(),
+        })
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+
+    pub(crate) fn sink_names(&self) -> Vec<&'static str> {
+        self.sinks.iter().map(|sink| sink.name).collect()
+    }
+
+    /// Queue a measurement for export. This never waits: if a sink's queue is full, the
+    /// measurement is dropped for that sink.
+    pub(crate) fn export(&self, measurement: &RawMeasurement) {
+        for sink in &self.sinks {
+            if sink.tx.try_send(measurement.clone()).is_err() {
+                sink.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+fn start(sink: Box<dyn Sink>) -> SinkHandle {
+    let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+    let dropped = Arc::new(AtomicU64::new(0));
+    let name = sink.name();
+
+    tokio::spawn(run(sink, rx, dropped.clone()));
+
+    SinkHandle { name, tx, dropped }
+}
+
+async fn run(
+    mut sink: Box<dyn Sink>,
+    mut rx: mpsc::Receiver<RawMeasurement>,
+    dropped: Arc<AtomicU64>,
+) {
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    let mut flush_interval = tokio::time::interval(FLUSH_INTERVAL);
+    flush_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    let mut open = true;
+    while open {
+        tokio::select! {
+            measurement = rx.recv() => match measurement {
+                Some(measurement) => {
+                    batch.push(measurement);
+                    if batch.len() < BATCH_SIZE {
+                        continue;
+                    }
+                }
+                None => open = false,
+            },
+            _ = flush_interval.tick() => {}
+        }
+
+        let dropped = dropped.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            tracing::warn!(
+                "Export sink {} dropped {} measurement(s): its queue was full",
+                sink.name(),
+                dropped
+            );
+        }
+
+        if batch.is_empty() {
+            continue;
+        }
+
+        for attempt in 1..=WRITE_ATTEMPTS {
+            match sink.write(&batch).await {
+                Ok(()) => break,
+                Err(err) if attempt < WRITE_ATTEMPTS && !err.is::<Rejected>() => {
+                    tracing::debug!(
+                        "Export sink {} failed to write {} measurement(s), retrying: {:?}",
+                        sink.name(),
+                        batch.len(),
+                        err
+                    );
+                    tokio::time::sleep(RETRY_BACKOFF * attempt).await;
+                }
+                Err(err) => {
+                    tracing::warn!(
+                        "Export sink {} failed to write {} measurement(s), dropping them: {:?}",
+                        sink.name(),
+                        batch.len(),
+                        err
+                    );
+                    break;
+                }
+            }
+        }
+        batch.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::AtomicU32;
+
+    /// Fails every write with the error made by `error`, counting the attempts.
+    struct Failing {
+        attempts: Arc<AtomicU32>,
+        error: fn() -> anyhow::Error,
+    }
+
+    #[async_trait::async_trait]
+    impl Sink for Failing {
+        fn name(&self) -> &'static str {
+            "failing"
+        }
+
+        async fn write(&mut self, _measurements: &[RawMeasurement]) -> anyhow::Result<()> {
+            self.attempts.fetch_add(1, Ordering::SeqCst);
+            Err((self.error)())
+        }
+    }
+
+    /// The number of attempts at writing a measurement to a sink failing with the error.
+    async fn attempts(error: fn() -> anyhow::Error) -> u32 {
+        let attempts = Arc::new(AtomicU32::new(0));
+        let sink = Failing {
+            attempts: attempts.clone(),
+            error,
+        };
+        let (tx, rx) = mpsc::channel(1);
+        tx.send(RawMeasurement {
+            id: uuid::Uuid::nil(),
+            kit_serial: "k-1".to_owned(),
+            datetime: chrono::Utc::now(),
+            peripheral: 2,
+            quantity_type: 3,
+            value: 21.0,
+        })
+        .await
+        .unwrap();
+        drop(tx);
+
+        run(Box::new(sink), rx, Default::default()).await;
+        attempts.load(Ordering::SeqCst)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_unless_rejected() {
+        assert_eq!(
+            attempts(|| anyhow::anyhow!("connection refused")).await,
+            WRITE_ATTEMPTS
+        );
+        assert_eq!(
+            attempts(|| Rejected {
+                status: hyper::StatusCode::BAD_REQUEST,
+                body: "out of order sample".to_owned(),
+            }
+            .into())
+            .await,
+            1
+        );
+    }
+}
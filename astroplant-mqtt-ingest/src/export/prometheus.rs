@@ -0,0 +1,180 @@
+//! The Prometheus remote-write sink.
+//!
+//! See the [remote-write specification](https://prometheus.io/docs/concepts/remote_write_spec/).
+
+use astroplant_mqtt::RawMeasurement;
+use hyper::header;
+use std::collections::BTreeMap;
+
+use super::{http_post, HttpClient, Sink};
+
+/// The metric name of raw measurements.
+const METRIC: &str = "astroplant_measurement";
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct WriteRequest {
+    #[prost(message, repeated, tag = "1")]
+    timeseries: Vec<TimeSeries>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct TimeSeries {
+    /// Sorted by name.
+    #[prost(message, repeated, tag = "1")]
+    labels: Vec<Label>,
+    /// Sorted by timestamp.
+    #[prost(message, repeated, tag = "2")]
+    samples: Vec<Sample>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct Label {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(string, tag = "2")]
+    value: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct Sample {
+    #[prost(double, tag = "1")]
+    value: f64,
+    /// In milliseconds.
+    #[prost(int64, tag = "2")]
+    timestamp: i64,
+}
+
+/// Writes to a Prometheus remote-write endpoint, e.g. `http://localhost:9090/api/v1/write`.
+pub(crate) struct RemoteWrite {
+    client: HttpClient,
+    url: hyper::Uri,
+    token: Option<String>,
+}
+
+impl RemoteWrite {
+    pub(crate) fn new(client: HttpClient, url: hyper::Uri, token: Option<String>) -> Self {
+        Self { client, url, token }
+    }
+}
+
+fn label(name: &str, value: String) -> Label {
+    Label {
+        name: name.to_owned(),
+        value,
+    }
+}
+
+/// The snappy-compressed protobuf write request of the measurements, with a time series per kit,
+/// peripheral and quantity type.
+fn encode(measurements: &[RawMeasurement]) -> anyhow::Result<Vec<u8>> {
+    use prost::Message;
+
+    let mut series: BTreeMap<(&str, i32, i32), Vec<Sample>> = BTreeMap::new();
+    for measurement in measurements {
+        series
+            .entry((
+                &measurement.kit_serial,
+                measurement.peripheral,
+                measurement.quantity_type,
+            ))
+            .or_default()
+            .push(Sample {
+                value: measurement.value,
+                timestamp: measurement.datetime.timestamp_millis(),
+            });
+    }
+
+    let timeseries = series
+        .into_iter()
+        .map(|((kit_serial, peripheral, quantity_type), mut samples)| {
+            samples.sort_by_key(|sample| sample.timestamp);
+            TimeSeries {
+                labels: vec![
+                    label("__name__", METRIC.to_owned()),
+                    label("kit", kit_serial.to_owned()),
+                    label("peripheral", peripheral.to_string()),
+                    label("quantity_type", quantity_type.to_string()),
+                ],
+                samples,
+            }
+        })
+        .collect();
+
+    let request = WriteRequest { timeseries }.encode_to_vec();
+    Ok(snap::raw::Encoder::new().compress_vec(&request)?)
+}
+
+#[async_trait::async_trait]
+impl Sink for RemoteWrite {
+    fn name(&self) -> &'static str {
+        "prometheus"
+    }
+
+    async fn write(&mut self, measurements: &[RawMeasurement]) -> anyhow::Result<()> {
+        let body = encode(measurements)?;
+
+        let mut request = hyper::Request::builder()
+            .uri(&self.url)
+            .header(header::CONTENT_ENCODING, "snappy")
+            .header(header::CONTENT_TYPE, "application/x-protobuf")
+            .header("X-Prometheus-Remote-Write-Version", "0.1.0");
+        if let Some(token) = &self.token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+
+        http_post(&self.client, request, body).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use prost::Message;
+
+    fn measurement(kit_serial: &str, millis: i64, value: f64) -> RawMeasurement {
+        RawMeasurement {
+            id: uuid::Uuid::nil(),
+            kit_serial: kit_serial.to_owned(),
+            datetime: Utc.timestamp_millis_opt(millis).unwrap(),
+            peripheral: 2,
+            quantity_type: 3,
+            value,
+        }
+    }
+
+    #[test]
+    fn write_request() {
+        let body = encode(&[
+            measurement("k-2", 2_000, 1.0),
+            measurement("k-1", 3_000, 2.0),
+            measurement("k-1", 1_000, 3.0),
+        ])
+        .unwrap();
+        let request = snap::raw::Decoder::new().decompress_vec(&body).unwrap();
+        let request = WriteRequest::decode(request.as_slice()).unwrap();
+
+        assert_eq!(request.timeseries.len(), 2);
+        let series = &request.timeseries[0];
+        let labels: Vec<_> = series
+            .labels
+            .iter()
+            .map(|label| (label.name.as_str(), label.value.as_str()))
+            .collect();
+        assert_eq!(
+            labels,
+            vec![
+                ("__name__", "astroplant_measurement"),
+                ("kit", "k-1"),
+                ("peripheral", "2"),
+                ("quantity_type", "3"),
+            ]
+        );
+        let samples: Vec<_> = series
+            .samples
+            .iter()
+            .map(|sample| (sample.timestamp, sample.value))
+            .collect();
+        assert_eq!(samples, vec![(1_000, 3.0), (3_000, 2.0)]);
+    }
+}
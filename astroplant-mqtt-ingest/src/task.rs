@@ -86,3 +86,12 @@ impl<E: std::fmt::Debug + 'static> LocalTaskPool<E> {
         }
     }
 }
+
+/// Aborts the task when dropped.
+pub(crate) struct AbortOnDrop<T>(pub tokio::task::JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
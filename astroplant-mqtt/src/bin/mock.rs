@@ -2,7 +2,7 @@ use futures::StreamExt;
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
-    let (connection, kits_rpc) = astroplant_mqtt::ConnectionBuilder::new("localhost", 1883)
+    let (connection, kits_rpc, _) = astroplant_mqtt::ConnectionBuilder::new("localhost", 1883)
         .with_credentials("server", "abcdef")
         .create();
 
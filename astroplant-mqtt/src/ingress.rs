@@ -0,0 +1,190 @@
+use anyhow::Context;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::num::{NonZeroU32, NonZeroU64};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Per-kit quotas on the messages kits publish (measurements, media, actuator states, events and
+/// preview frames). Messages exceeding a quota are dropped and reported as [QuotaExceeded](super::Message::QuotaExceeded).
+///
+/// By default, no quotas are applied.
+#[derive(Clone, Debug, Default)]
+pub struct IngressQuota {
+    /// The maximum number of messages a kit may publish per minute.
+    pub messages_per_minute: Option<NonZeroU32>,
+    /// The maximum number of payload bytes a kit may publish per day. This is tracked with a
+    /// granularity of kibibytes.
+    pub bytes_per_day: Option<NonZeroU64>,
+}
+
+fn env_var<T>(name: &str) -> anyhow::Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .with_context(|| format!("invalid {}: {:?}", name, value)),
+        Err(std::env::VarError::NotPresent) => Ok(None),
+        Err(err) => Err(err).with_context(|| format!("invalid {}", name)),
+    }
+}
+
+impl IngressQuota {
+    /// Read the quotas from the `MQTT_KIT_MESSAGES_PER_MINUTE` and `MQTT_KIT_BYTES_PER_DAY`
+    /// environment variables. Kits are unlimited if these are not set.
+    pub fn from_env() -> anyhow::Result<Self> {
+        Ok(IngressQuota {
+            messages_per_minute: env_var("MQTT_KIT_MESSAGES_PER_MINUTE")?,
+            bytes_per_day: env_var("MQTT_KIT_BYTES_PER_DAY")?,
+        })
+    }
+}
+
+/// A quota of `capacity` units per `period`, which may be used in bursts.
+struct Limit {
+    capacity: f64,
+    period: Duration,
+}
+
+impl Limit {
+    /// The units available after `elapsed`, given `available` units before.
+    fn refill(&self, available: f64, elapsed: Duration) -> f64 {
+        (available + self.capacity * elapsed.as_secs_f64() / self.period.as_secs_f64())
+            .min(self.capacity)
+    }
+}
+
+/// The units a kit has available.
+struct Available {
+    messages: f64,
+    kibibytes: f64,
+    at: Instant,
+}
+
+pub(crate) struct IngressLimiter {
+    messages: Option<Limit>,
+    kibibytes: Option<Limit>,
+    kits: HashMap<String, Available>,
+}
+
+fn to_kibibytes(bytes: u64) -> u32 {
+    u32::try_from(bytes.div_ceil(1024)).unwrap_or(u32::MAX)
+}
+
+impl IngressLimiter {
+    pub fn new(quota: IngressQuota) -> Self {
+        const MINUTE: Duration = Duration::from_secs(60);
+        const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+        Self {
+            messages: quota.messages_per_minute.map(|capacity| Limit {
+                capacity: capacity.get().into(),
+                period: MINUTE,
+            }),
+            kibibytes: quota.bytes_per_day.map(|bytes| Limit {
+                capacity: to_kibibytes(bytes.get()).into(),
+                period: DAY,
+            }),
+            kits: HashMap::new(),
+        }
+    }
+
+    /// Returns whether the kit may publish a message of the given size. A message counts towards
+    /// the quotas only if it is within all of them.
+    pub fn check(&mut self, kit_serial: &str, bytes: usize) -> bool {
+        self.check_at(kit_serial, bytes, Instant::now())
+    }
+
+    fn check_at(&mut self, kit_serial: &str, bytes: usize, now: Instant) -> bool {
+        if self.messages.is_none() && self.kibibytes.is_none() {
+            return true;
+        }
+
+        let messages = &self.messages;
+        let kibibytes = &self.kibibytes;
+        let available = self
+            .kits
+            .entry(kit_serial.to_owned())
+            .or_insert_with(|| Available {
+                messages: messages.as_ref().map_or(0.0, |limit| limit.capacity),
+                kibibytes: kibibytes.as_ref().map_or(0.0, |limit| limit.capacity),
+                at: now,
+            });
+
+        let elapsed = now.saturating_duration_since(available.at);
+        available.at = now;
+        if let Some(limit) = messages {
+            available.messages = limit.refill(available.messages, elapsed);
+        }
+        if let Some(limit) = kibibytes {
+            available.kibibytes = limit.refill(available.kibibytes, elapsed);
+        }
+
+        let n = f64::from(to_kibibytes(bytes as u64).max(1));
+        if (messages.is_some() && available.messages < 1.0)
+            || (kibibytes.is_some() && available.kibibytes < n)
+        {
+            return false;
+        }
+
+        available.messages -= 1.0;
+        available.kibibytes -= n;
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{IngressLimiter, IngressQuota};
+    use std::num::{NonZeroU32, NonZeroU64};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    pub fn messages_per_minute() {
+        let mut limiter = IngressLimiter::new(IngressQuota {
+            messages_per_minute: NonZeroU32::new(3),
+            bytes_per_day: None,
+        });
+
+        for _ in 0..3 {
+            assert!(limiter.check("k-abcd", 10));
+        }
+        assert!(!limiter.check("k-abcd", 10));
+        assert!(limiter.check("k-efgh", 10));
+    }
+
+    #[test]
+    pub fn bytes_per_day() {
+        let mut limiter = IngressLimiter::new(IngressQuota {
+            messages_per_minute: None,
+            bytes_per_day: NonZeroU64::new(10 * 1024),
+        });
+
+        assert!(!limiter.check("k-abcd", 11 * 1024));
+        assert!(limiter.check("k-abcd", 8 * 1024));
+        assert!(!limiter.check("k-abcd", 4 * 1024));
+        assert!(limiter.check("k-abcd", 1024));
+    }
+
+    #[test]
+    pub fn rejected_messages_are_not_counted() {
+        let mut limiter = IngressLimiter::new(IngressQuota {
+            messages_per_minute: NonZeroU32::new(2),
+            bytes_per_day: NonZeroU64::new(10 * 1024),
+        });
+        let now = Instant::now();
+
+        // Exceeds the byte quota, and does not use up a message.
+        assert!(!limiter.check_at("k-abcd", 11 * 1024, now));
+        assert!(limiter.check_at("k-abcd", 1024, now));
+        assert!(limiter.check_at("k-abcd", 1024, now));
+
+        // Exceeds the message quota, and does not use up bytes.
+        assert!(!limiter.check_at("k-abcd", 7 * 1024, now));
+        assert!(limiter.check_at("k-abcd", 8 * 1024, now + Duration::from_secs(30)));
+    }
+}
@@ -0,0 +1,352 @@
+//! The JSON encoding of kit messages.
+//!
+//! This is an alternative to the Cap'n Proto encoding, intended for prototyping and debugging. A
+//! kit chooses this encoding by suffixing a topic with `/json`. The JSON messages mirror the Cap'n
+//! Proto schema: field names are camelCase, datetimes are millisecond timestamps, unions are
+//! objects with a single key naming the variant, and `Void` is `null`. Binary data is
+//! base64-encoded, and fields that hold JSON-as-text in Cap'n Proto hold plain JSON values here.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::{RpcError, Severity};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RawMeasurement {
+    pub id: uuid::Uuid,
+    pub datetime: u64,
+    pub peripheral: i32,
+    pub quantity_type: i32,
+    pub value: f64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct AggregateMeasurement {
+    pub id: uuid::Uuid,
+    pub datetime_start: u64,
+    pub datetime_end: u64,
+    pub peripheral: i32,
+    pub quantity_type: i32,
+    pub values: HashMap<String, f64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Media {
+    pub id: uuid::Uuid,
+    pub datetime: u64,
+    pub peripheral: i32,
+    pub name: String,
+    pub r#type: String,
+    /// Base64-encoded.
+    pub data: String,
+    #[serde(default)]
+    pub metadata: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ActuatorState {
+    pub id: uuid::Uuid,
+    pub datetime: u64,
+    pub peripheral: i32,
+    pub state: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct KitEvent {
+    pub id: uuid::Uuid,
+    pub datetime: u64,
+    pub severity: Severity,
+    pub message: String,
+    /// The unnamed union of the event's subject. Events of the kit as a whole set `"kit": null` or
+    /// omit the subject.
+    #[serde(default)]
+    pub peripheral: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum RpcErrorBody {
+    Other(()),
+    MethodNotFound(()),
+    RateLimit(u64),
+}
+
+// Note: these are not `From` implementations, as those would be visible to (and could break type
+// inference in) dependent crates.
+impl RpcErrorBody {
+    pub fn from_rpc_error(error: RpcError) -> Self {
+        match error {
+            RpcError::Other => RpcErrorBody::Other(()),
+            RpcError::MethodNotFound => RpcErrorBody::MethodNotFound(()),
+            RpcError::RateLimit(duration) => RpcErrorBody::RateLimit(duration.as_millis() as u64),
+        }
+    }
+
+    pub fn into_rpc_error(self) -> RpcError {
+        match self {
+            RpcErrorBody::Other(()) => RpcError::Other,
+            RpcErrorBody::MethodNotFound(()) => RpcError::MethodNotFound,
+            RpcErrorBody::RateLimit(millis) => {
+                RpcError::RateLimit(std::time::Duration::from_millis(millis))
+            }
+        }
+    }
+}
+
+/// Used to recover the request id of a message whose body could not be decoded.
+#[derive(Deserialize)]
+pub(crate) struct RpcId {
+    pub id: u64,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ServerRpcRequest {
+    pub id: u64,
+    #[serde(flatten)]
+    pub body: ServerRpcRequestBody,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum ServerRpcRequestBody {
+    Version(()),
+    GetQuantityTypes(()),
+    GetActiveConfiguration(()),
+}
+
+#[derive(Serialize)]
+pub(crate) struct ServerRpcResponse {
+    pub id: u64,
+    #[serde(flatten)]
+    pub body: ServerRpcResponseBody,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum ServerRpcResponseBody {
+    Error(RpcErrorBody),
+    Version(String),
+    GetQuantityTypes(Vec<serde_json::Value>),
+    GetActiveConfiguration(ActiveConfiguration),
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum ActiveConfiguration {
+    Configuration(serde_json::Value),
+    None(()),
+}
+
+#[derive(Serialize)]
+pub(crate) struct KitRpcRequest {
+    pub id: u64,
+    #[serde(flatten)]
+    pub body: KitRpcRequestBody,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum KitRpcRequestBody {
+    Version(()),
+    Uptime(()),
+    PeripheralCommand(PeripheralCommand),
+    PeripheralCommandLock(PeripheralCommandLock),
+    SetPassword(String),
+    StartPreview(StartPreview),
+    StopPreview(String),
+}
+
+#[derive(Serialize)]
+pub(crate) struct PeripheralCommand {
+    pub peripheral: String,
+    pub command: serde_json::Value,
+}
+
+#[derive(Serialize)]
+pub(crate) struct PeripheralCommandLock {
+    pub peripheral: String,
+    #[serde(flatten)]
+    pub request: PeripheralCommandLockRequest,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct StartPreview {
+    pub session: String,
+    pub peripheral: String,
+    pub lease_seconds: u32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum PeripheralCommandLockRequest {
+    Status(()),
+    Acquire(()),
+    Release(()),
+}
+
+#[derive(Deserialize)]
+pub(crate) struct KitRpcResponse {
+    pub id: u64,
+    #[serde(flatten)]
+    pub body: KitRpcResponseBody,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum KitRpcResponseBody {
+    Error(RpcErrorBody),
+    Version(String),
+    Uptime(u64),
+    PeripheralCommand(PeripheralCommandResponse),
+    PeripheralCommandLock(bool),
+    SetPassword(()),
+    StartPreview(()),
+    StopPreview(()),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct PeripheralCommandResponse {
+    pub media_type: String,
+    /// Base64-encoded.
+    #[serde(default)]
+    pub data: String,
+    #[serde(default)]
+    pub metadata: serde_json::Value,
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::TryFrom;
+
+    use crate::{Encoding, Topic, TopicKind};
+
+    #[test]
+    pub fn topic_encoding() {
+        let topic = Topic::try_from("kit/k-abcd/measurement/raw/json").unwrap();
+        assert!(matches!(topic.kind, TopicKind::RawMeasurement));
+        assert_eq!(topic.encoding, Encoding::Json);
+
+        let topic = Topic::try_from("kit/k-abcd/media").unwrap();
+        assert!(matches!(topic.kind, TopicKind::Media));
+        assert_eq!(topic.encoding, Encoding::Capnp);
+
+        assert!(Topic::try_from("kit/k-abcd/json").is_err());
+    }
+
+    #[test]
+    pub fn preview_frame_topic() {
+        let topic =
+            Topic::try_from("kit/k-abcd/preview/0f8fad5b-d9cb-469f-a165-70867728950e").unwrap();
+        assert!(matches!(topic.kind, TopicKind::PreviewFrame(_)));
+
+        assert!(Topic::try_from("kit/k-abcd/preview/camera").is_err());
+    }
+
+    #[test]
+    pub fn raw_measurement() {
+        let measurement = crate::parse_raw_measurement_json(
+            "k-abcd".to_owned(),
+            br#"{
+                "id": "0f8fad5b-d9cb-469f-a165-70867728950e",
+                "datetime": 1600000000123,
+                "peripheral": 3,
+                "quantityType": 1,
+                "value": 21.5
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(measurement.kit_serial, "k-abcd");
+        assert_eq!(measurement.datetime.timestamp_millis(), 1600000000123);
+        assert_eq!(measurement.quantity_type, 1);
+        assert_eq!(measurement.value, 21.5);
+    }
+
+    #[test]
+    pub fn actuator_state() {
+        let actuator_state = crate::parse_actuator_state_json(
+            "k-abcd".to_owned(),
+            br#"{
+                "id": "0f8fad5b-d9cb-469f-a165-70867728950e",
+                "datetime": 1600000000123,
+                "peripheral": 3,
+                "state": {"on": true}
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(actuator_state.kit_serial, "k-abcd");
+        assert_eq!(actuator_state.datetime.timestamp_millis(), 1600000000123);
+        assert_eq!(actuator_state.peripheral, 3);
+        assert_eq!(actuator_state.state, serde_json::json!({"on": true}));
+    }
+
+    #[test]
+    pub fn kit_event() {
+        let event = crate::parse_kit_event_json(
+            "k-abcd".to_owned(),
+            br#"{
+                "id": "0f8fad5b-d9cb-469f-a165-70867728950e",
+                "datetime": 1600000000123,
+                "severity": "warning",
+                "message": "Sensor timed out",
+                "peripheral": 3
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(event.severity, crate::Severity::Warning);
+        assert_eq!(event.peripheral, Some(3));
+        assert_eq!(event.message, "Sensor timed out");
+
+        for subject in &[r#", "kit": null"#, ""] {
+            let event = crate::parse_kit_event_json(
+                "k-abcd".to_owned(),
+                format!(
+                    r#"{{
+                        "id": "0f8fad5b-d9cb-469f-a165-70867728950e",
+                        "datetime": 1600000000123,
+                        "severity": "info",
+                        "message": "Started"{}
+                    }}"#,
+                    subject
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+            assert_eq!(event.peripheral, None);
+        }
+    }
+
+    #[test]
+    pub fn server_rpc_round_trip() {
+        let request =
+            crate::server_rpc::decode_rpc_request(br#"{"id": 7, "version": null}"#, Encoding::Json)
+                .ok()
+                .unwrap();
+        assert_eq!(request.id, 7);
+        assert!(matches!(
+            request.body,
+            crate::server_rpc::ServerRpcRequestBody::Version
+        ));
+
+        let response = crate::server_rpc::ServerRpcResponseBuilder::new(
+            "k-abcd".to_owned(),
+            7,
+            Encoding::Json,
+        )
+        .set_error_rate_limit(1500)
+        .create();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&response.bytes).unwrap(),
+            serde_json::json!({ "id": 7, "error": { "rateLimit": 1500 } })
+        );
+    }
+}
@@ -1,10 +1,11 @@
 use capnp::serialize_packed;
 use rumqttc::{AsyncClient, QoS};
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::time::{Duration, Instant};
 use tokio::sync::{mpsc, oneshot};
 
-use super::{astroplant_capnp, RpcError};
+use super::{astroplant_capnp, json, ConnectionStateRx, Encoding, KitEncodings, RpcError};
 
 pub enum PeripheralCommandLockRequest {
     Status,
@@ -23,10 +24,33 @@ enum RequestBody {
         peripheral: String,
         request: PeripheralCommandLockRequest,
     },
+    SetPassword {
+        password: String,
+    },
+    StartPreview {
+        session: uuid::Uuid,
+        peripheral: String,
+        lease: Duration,
+    },
+    StopPreview {
+        session: uuid::Uuid,
+    },
+}
+
+/// The lease duration in whole seconds, as sent to kits.
+fn lease_seconds(lease: Duration) -> u32 {
+    u32::try_from(lease.as_secs()).unwrap_or(u32::MAX)
 }
 
 impl RequestBody {
-    fn build(self, request_id: u64) -> Vec<u8> {
+    fn build(self, request_id: u64, encoding: Encoding) -> Vec<u8> {
+        match encoding {
+            Encoding::Capnp => self.build_capnp(request_id),
+            Encoding::Json => self.build_json(request_id),
+        }
+    }
+
+    fn build_capnp(self, request_id: u64) -> Vec<u8> {
         let mut message_builder = capnp::message::Builder::new_default();
         let mut request_builder =
             message_builder.init_root::<astroplant_capnp::kit_rpc_request::Builder>();
@@ -60,12 +84,77 @@ impl RequestBody {
                     PeripheralCommandLockRequest::Release => builder.set_release(()),
                 };
             }
+            SetPassword { password } => {
+                request_builder.set_set_password(&password);
+            }
+            StartPreview {
+                session,
+                peripheral,
+                lease,
+            } => {
+                let mut builder = request_builder.init_start_preview();
+                builder.set_session(&session.to_string());
+                builder.set_peripheral(&peripheral);
+                builder.set_lease_seconds(lease_seconds(lease));
+            }
+            StopPreview { session } => {
+                request_builder.set_stop_preview(&session.to_string());
+            }
         }
 
         let mut bytes = Vec::new();
         capnp::serialize_packed::write_message(&mut bytes, &message_builder).unwrap();
         bytes
     }
+
+    fn build_json(self, request_id: u64) -> Vec<u8> {
+        use RequestBody::*;
+        let body = match self {
+            Version => json::KitRpcRequestBody::Version(()),
+            Uptime => json::KitRpcRequestBody::Uptime(()),
+            PeripheralCommand {
+                peripheral,
+                command,
+            } => json::KitRpcRequestBody::PeripheralCommand(json::PeripheralCommand {
+                peripheral,
+                command,
+            }),
+            PeripheralCommandLock {
+                peripheral,
+                request,
+            } => json::KitRpcRequestBody::PeripheralCommandLock(json::PeripheralCommandLock {
+                peripheral,
+                request: match request {
+                    PeripheralCommandLockRequest::Status => {
+                        json::PeripheralCommandLockRequest::Status(())
+                    }
+                    PeripheralCommandLockRequest::Acquire => {
+                        json::PeripheralCommandLockRequest::Acquire(())
+                    }
+                    PeripheralCommandLockRequest::Release => {
+                        json::PeripheralCommandLockRequest::Release(())
+                    }
+                },
+            }),
+            SetPassword { password } => json::KitRpcRequestBody::SetPassword(password),
+            StartPreview {
+                session,
+                peripheral,
+                lease,
+            } => json::KitRpcRequestBody::StartPreview(json::StartPreview {
+                session: session.to_string(),
+                peripheral,
+                lease_seconds: lease_seconds(lease),
+            }),
+            StopPreview { session } => json::KitRpcRequestBody::StopPreview(session.to_string()),
+        };
+
+        serde_json::to_vec(&json::KitRpcRequest {
+            id: request_id,
+            body,
+        })
+        .unwrap()
+    }
 }
 
 struct Request {
@@ -85,6 +174,9 @@ enum ResponseBody {
     Uptime(std::time::Duration),
     PeripheralCommand(PeripheralCommandResponse),
     PeripheralCommandLock(bool),
+    SetPassword,
+    StartPreview,
+    StopPreview,
     Error(RpcError),
 }
 
@@ -113,6 +205,12 @@ impl From<serde_json::Error> for DecodeErrorKind {
     }
 }
 
+impl From<base64::DecodeError> for DecodeErrorKind {
+    fn from(_error: base64::DecodeError) -> Self {
+        DecodeErrorKind::Malformed
+    }
+}
+
 /// Error that occurs when a kit RPC response message could not be decoded.
 #[derive(Debug, thiserror::Error)]
 #[error("A decoding error occurred")]
@@ -153,7 +251,17 @@ fn decode_rpc_error(
 }
 
 /// Returns the response's request id and the response body.
-fn decode_rpc_response(mut message: &[u8]) -> Result<(u64, ResponseBody), DecodeError> {
+fn decode_rpc_response(
+    message: &[u8],
+    encoding: Encoding,
+) -> Result<(u64, ResponseBody), DecodeError> {
+    match encoding {
+        Encoding::Capnp => decode_capnp_rpc_response(message),
+        Encoding::Json => decode_json_rpc_response(message),
+    }
+}
+
+fn decode_capnp_rpc_response(mut message: &[u8]) -> Result<(u64, ResponseBody), DecodeError> {
     let message_reader =
         serialize_packed::read_message(&mut message, capnp::message::ReaderOptions::default())
             .map_err(DecodeError::without_request_id)?;
@@ -195,6 +303,9 @@ fn decode_rpc_response(mut message: &[u8]) -> Result<(u64, ResponseBody), Decode
             ResponseBody::PeripheralCommand(peripheral_command_response)
         }
         Which::PeripheralCommandLock(v) => ResponseBody::PeripheralCommandLock(v),
+        Which::SetPassword(()) => ResponseBody::SetPassword,
+        Which::StartPreview(()) => ResponseBody::StartPreview,
+        Which::StopPreview(()) => ResponseBody::StopPreview,
         Which::Error(v) => {
             let v = v.map_err(|err| DecodeError::with_request_id(id, err))?;
 
@@ -206,11 +317,50 @@ fn decode_rpc_response(mut message: &[u8]) -> Result<(u64, ResponseBody), Decode
     Ok((id, body))
 }
 
+fn decode_json_rpc_response(message: &[u8]) -> Result<(u64, ResponseBody), DecodeError> {
+    let response: json::KitRpcResponse = serde_json::from_slice(message).map_err(|err| {
+        match serde_json::from_slice::<json::RpcId>(message) {
+            Ok(json::RpcId { id }) => DecodeError::with_request_id(id, err),
+            Err(_) => DecodeError::without_request_id(err),
+        }
+    })?;
+
+    let id = response.id;
+    let body = match response.body {
+        json::KitRpcResponseBody::Version(v) => ResponseBody::Version(v),
+        json::KitRpcResponseBody::Uptime(v) => {
+            ResponseBody::Uptime(std::time::Duration::from_secs(v))
+        }
+        json::KitRpcResponseBody::PeripheralCommand(v) => {
+            ResponseBody::PeripheralCommand(PeripheralCommandResponse {
+                media_type: v.media_type,
+                data: base64::decode(&v.data)
+                    .map_err(|err| DecodeError::with_request_id(id, err))?,
+                metadata: v.metadata,
+            })
+        }
+        json::KitRpcResponseBody::PeripheralCommandLock(v) => {
+            ResponseBody::PeripheralCommandLock(v)
+        }
+        json::KitRpcResponseBody::SetPassword(()) => ResponseBody::SetPassword,
+        json::KitRpcResponseBody::StartPreview(()) => ResponseBody::StartPreview,
+        json::KitRpcResponseBody::StopPreview(()) => ResponseBody::StopPreview,
+        json::KitRpcResponseBody::Error(v) => ResponseBody::Error(v.into_rpc_error()),
+    };
+
+    Ok((id, body))
+}
+
 pub(crate) struct ResponseTx(mpsc::Sender<Response>);
 
 impl ResponseTx {
-    pub async fn send(&self, kit_serial: String, payload: Vec<u8>) -> Result<(), DecodeError> {
-        let (request_id, body) = match decode_rpc_response(&payload) {
+    pub async fn send(
+        &self,
+        kit_serial: String,
+        payload: Vec<u8>,
+        encoding: Encoding,
+    ) -> Result<(), DecodeError> {
+        let (request_id, body) = match decode_rpc_response(&payload, encoding) {
             Err(DecodeError {
                 request_id: Some(id),
                 kind,
@@ -230,6 +380,10 @@ impl ResponseTx {
 }
 
 type SerialAndRequestId = (String, u64);
+
+/// Request ids are kept below 2^53, such that kits parsing JSON numbers as doubles respond with
+/// the exact id.
+const REQUEST_IDS: u64 = 1 << 53;
 type Waiter = (
     Instant, // Instant at which waiter was created.
     oneshot::Sender<Result<ResponseBody, DecodeErrorKind>>,
@@ -237,6 +391,7 @@ type Waiter = (
 
 pub(crate) struct Driver {
     mqtt: AsyncClient,
+    kit_encodings: KitEncodings,
     next_id: u64,
     waiters: HashMap<SerialAndRequestId, Waiter>,
     request_rx: mpsc::Receiver<Request>,
@@ -246,12 +401,16 @@ pub(crate) struct Driver {
 impl Driver {
     fn new(
         mqtt: AsyncClient,
+        kit_encodings: KitEncodings,
         request_rx: mpsc::Receiver<Request>,
         response_rx: mpsc::Receiver<Response>,
     ) -> Self {
         Self {
             mqtt,
-            next_id: 0,
+            kit_encodings,
+            // Kit RPC responses are received by every client, e.g. of every API instance. A
+            // random first id makes it unlikely that clients' requests to a kit share an id.
+            next_id: uuid::Uuid::new_v4().as_u64_pair().0 % REQUEST_IDS,
             waiters: HashMap::new(),
             request_rx,
             response_rx,
@@ -260,15 +419,20 @@ impl Driver {
 
     async fn handle_request(&mut self, request: Request) {
         let id = self.next_id;
-        self.next_id += 1;
+        self.next_id = (self.next_id + 1) % REQUEST_IDS;
 
+        let encoding = self.kit_encodings.get(&request.kit_serial);
         let _ = self
             .mqtt
             .publish(
-                format!("kit/{}/kit-rpc/request", request.kit_serial),
+                format!(
+                    "kit/{}/kit-rpc/request{}",
+                    request.kit_serial,
+                    encoding.topic_suffix()
+                ),
                 QoS::AtLeastOnce,
                 false,
-                request.body.build(id),
+                request.body.build(id, encoding),
             )
             .await;
 
@@ -324,6 +488,7 @@ impl Driver {
 #[derive(Clone)]
 pub struct KitsRpc {
     request_tx: mpsc::Sender<Request>,
+    connection_state: ConnectionStateRx,
 }
 
 /// Errors that can occur in response to a [kit RPC](KitsRpc) request.
@@ -333,6 +498,12 @@ pub struct KitsRpc {
 /// stream](super::Connection).
 #[derive(thiserror::Error, Debug)]
 pub enum KitRpcResponseError {
+    /// The MQTT connection is not established, so the request was not sent.
+    #[error("The MQTT connection is not established")]
+    Disconnected {
+        /// The date and time of the last connection error, if any.
+        last_error: Option<chrono::DateTime<chrono::Utc>>,
+    },
     /// The kit RPC request timed out: no response was received.
     #[error("The kit RPC request timed out: no response was received")]
     TimedOut,
@@ -354,10 +525,24 @@ pub struct PeripheralCommandResponse {
 }
 
 impl KitsRpc {
+    /// Fail fast if the connection is not established, rather than waiting for the request to time
+    /// out.
+    fn check_connected(&self) -> Result<(), KitRpcResponseError> {
+        let state = self.connection_state.borrow();
+        if state.is_connected() {
+            Ok(())
+        } else {
+            Err(KitRpcResponseError::Disconnected {
+                last_error: state.last_error,
+            })
+        }
+    }
+
     pub async fn version(
         &self,
         kit_serial: impl Into<String>,
     ) -> Result<String, KitRpcResponseError> {
+        self.check_connected()?;
         let (tx, rx) = oneshot::channel();
         let _ = self
             .request_tx
@@ -379,6 +564,7 @@ impl KitsRpc {
         &self,
         kit_serial: impl Into<String>,
     ) -> Result<std::time::Duration, KitRpcResponseError> {
+        self.check_connected()?;
         let (tx, rx) = oneshot::channel();
         let _ = self
             .request_tx
@@ -402,6 +588,7 @@ impl KitsRpc {
         peripheral: String,
         command: serde_json::Value,
     ) -> Result<PeripheralCommandResponse, KitRpcResponseError> {
+        self.check_connected()?;
         let (tx, rx) = oneshot::channel();
         let _ = self
             .request_tx
@@ -428,6 +615,7 @@ impl KitsRpc {
         peripheral: String,
         request: PeripheralCommandLockRequest,
     ) -> Result<bool, KitRpcResponseError> {
+        self.check_connected()?;
         let (tx, rx) = oneshot::channel();
         let _ = self
             .request_tx
@@ -447,14 +635,100 @@ impl KitsRpc {
             Err(_) => Err(KitRpcResponseError::MalformedResponse),
         }
     }
+
+    /// Deliver a new MQTT password to the kit.
+    pub async fn set_password(
+        &self,
+        kit_serial: impl Into<String>,
+        password: String,
+    ) -> Result<(), KitRpcResponseError> {
+        self.check_connected()?;
+        let (tx, rx) = oneshot::channel();
+        let _ = self
+            .request_tx
+            .send(Request {
+                kit_serial: kit_serial.into(),
+                body: RequestBody::SetPassword { password },
+                response_channel: tx,
+            })
+            .await;
+        match rx.await.map_err(|_| KitRpcResponseError::TimedOut)? {
+            Ok(ResponseBody::SetPassword) => Ok(()),
+            Ok(ResponseBody::Error(err)) => Err(err.into()),
+            Ok(_) => Err(KitRpcResponseError::InvalidResponse),
+            Err(_) => Err(KitRpcResponseError::MalformedResponse),
+        }
+    }
+
+    /// Have the kit publish a peripheral's preview frames as [PreviewFrame](super::PreviewFrame)
+    /// messages of the session, until the lease ends. Starting a started session extends its
+    /// lease.
+    pub async fn start_preview(
+        &self,
+        kit_serial: impl Into<String>,
+        session: uuid::Uuid,
+        peripheral: String,
+        lease: Duration,
+    ) -> Result<(), KitRpcResponseError> {
+        self.check_connected()?;
+        let (tx, rx) = oneshot::channel();
+        let _ = self
+            .request_tx
+            .send(Request {
+                kit_serial: kit_serial.into(),
+                body: RequestBody::StartPreview {
+                    session,
+                    peripheral,
+                    lease,
+                },
+                response_channel: tx,
+            })
+            .await;
+        match rx.await.map_err(|_| KitRpcResponseError::TimedOut)? {
+            Ok(ResponseBody::StartPreview) => Ok(()),
+            Ok(ResponseBody::Error(err)) => Err(err.into()),
+            Ok(_) => Err(KitRpcResponseError::InvalidResponse),
+            Err(_) => Err(KitRpcResponseError::MalformedResponse),
+        }
+    }
+
+    pub async fn stop_preview(
+        &self,
+        kit_serial: impl Into<String>,
+        session: uuid::Uuid,
+    ) -> Result<(), KitRpcResponseError> {
+        self.check_connected()?;
+        let (tx, rx) = oneshot::channel();
+        let _ = self
+            .request_tx
+            .send(Request {
+                kit_serial: kit_serial.into(),
+                body: RequestBody::StopPreview { session },
+                response_channel: tx,
+            })
+            .await;
+        match rx.await.map_err(|_| KitRpcResponseError::TimedOut)? {
+            Ok(ResponseBody::StopPreview) => Ok(()),
+            Ok(ResponseBody::Error(err)) => Err(err.into()),
+            Ok(_) => Err(KitRpcResponseError::InvalidResponse),
+            Err(_) => Err(KitRpcResponseError::MalformedResponse),
+        }
+    }
 }
 
-pub(crate) fn create(mqtt: AsyncClient) -> (KitsRpc, Driver, ResponseTx) {
+pub(crate) fn create(
+    mqtt: AsyncClient,
+    kit_encodings: KitEncodings,
+    connection_state: ConnectionStateRx,
+) -> (KitsRpc, Driver, ResponseTx) {
     let (request_tx, request_rx) = mpsc::channel(8);
     let (response_tx, response_rx) = mpsc::channel(8);
 
-    let kits_rpc = KitsRpc { request_tx };
-    let handler = Driver::new(mqtt, request_rx, response_rx);
+    let kits_rpc = KitsRpc {
+        request_tx,
+        connection_state,
+    };
+    let handler = Driver::new(mqtt, kit_encodings, request_rx, response_rx);
     let response_tx = ResponseTx(response_tx);
 
     (kits_rpc, handler, response_tx)
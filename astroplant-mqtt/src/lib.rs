@@ -4,24 +4,38 @@
 //! The client exposes a kit RPC handle to send RPC requests to kits. The client can be given a
 //! server RPC handler (to handle kits' requests to the server RPC). A Tokio task is spawned for
 //! each server RPC request.
+//!
+//! Kits' messages are encoded through Cap'n Proto by default. For prototyping and debugging, kits
+//! can instead use JSON by suffixing topics with `/json`. See the [json] module.
 
 use async_trait::async_trait;
 use capnp::serialize_packed;
 use chrono::{DateTime, Utc};
 use futures::Stream;
 use ratelimit_meter::{algorithms::NonConformance, KeyedRateLimiter};
-use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Packet, Publish};
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Packet, Publish, SubscribeFilter};
+use std::sync::{Arc, RwLock};
 use std::time::{Duration, Instant};
 use std::{collections::HashMap, convert::TryFrom};
+use tokio::sync::watch;
 
+mod ingress;
+mod json;
 mod kit_rpc;
+mod publisher;
 mod server_rpc;
+use ingress::IngressLimiter;
 use kit_rpc::{Driver as KitsRpcDriver, ResponseTx as KitsRpcResponseTx};
 use server_rpc::{
     ServerRpcRequest, ServerRpcRequestBody, ServerRpcResponse, ServerRpcResponseBuilder,
 };
 
-pub use kit_rpc::{DecodeError, KitRpcResponseError, KitsRpc};
+pub use ingress::IngressQuota;
+pub use kit_rpc::{
+    DecodeError, KitRpcResponseError, KitsRpc, PeripheralCommandLockRequest,
+    PeripheralCommandResponse,
+};
+pub use publisher::{PublishError, Publisher};
 
 #[allow(dead_code)]
 mod astroplant_capnp {
@@ -95,7 +109,7 @@ impl Error {
 }
 
 /// A raw measurement made by a kit.
-#[derive(serde::Serialize, Clone, Debug)]
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct RawMeasurement {
     pub id: uuid::Uuid,
@@ -107,7 +121,7 @@ pub struct RawMeasurement {
 }
 
 /// An aggregate of raw measurements made by a kit.
-#[derive(serde::Serialize, Clone, Debug)]
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct AggregateMeasurement {
     pub id: uuid::Uuid,
@@ -132,6 +146,140 @@ pub struct Media {
     pub metadata: serde_json::Value,
 }
 
+/// The state an actuator (such as a pump or LED panel) of a kit switched to.
+#[derive(serde::Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ActuatorState {
+    pub id: uuid::Uuid,
+    pub kit_serial: String,
+    pub datetime: DateTime<Utc>,
+    pub peripheral: i32,
+    /// The actuator-specific state, e.g. `{"on": true}`.
+    pub state: serde_json::Value,
+}
+
+/// The severity of a kit event.
+///
+/// Severities are ordered from least to most severe. Their numeric levels (`severity as i16`) are
+/// stable, and can be used for storage.
+#[derive(
+    serde::Serialize, serde::Deserialize, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(rename_all = "camelCase")]
+pub enum Severity {
+    Debug = 0,
+    Info = 1,
+    Warning = 2,
+    Error = 3,
+    Critical = 4,
+}
+
+impl From<astroplant_capnp::kit_event::Severity> for Severity {
+    fn from(severity: astroplant_capnp::kit_event::Severity) -> Self {
+        use astroplant_capnp::kit_event::Severity as S;
+        match severity {
+            S::Debug => Severity::Debug,
+            S::Info => Severity::Info,
+            S::Warning => Severity::Warning,
+            S::Error => Severity::Error,
+            S::Critical => Severity::Critical,
+        }
+    }
+}
+
+impl TryFrom<i16> for Severity {
+    type Error = ();
+
+    fn try_from(level: i16) -> Result<Self, ()> {
+        match level {
+            0 => Ok(Severity::Debug),
+            1 => Ok(Severity::Info),
+            2 => Ok(Severity::Warning),
+            3 => Ok(Severity::Error),
+            4 => Ok(Severity::Critical),
+            _ => Err(()),
+        }
+    }
+}
+
+/// An event reported by a kit, such as a peripheral failing or the kit restarting.
+#[derive(serde::Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct KitEvent {
+    pub id: uuid::Uuid,
+    pub kit_serial: String,
+    pub datetime: DateTime<Utc>,
+    pub severity: Severity,
+    /// The peripheral the event concerns. If this is `None`, the event concerns the kit as a
+    /// whole.
+    pub peripheral: Option<i32>,
+    pub message: String,
+}
+
+/// A frame of a preview session, published by a kit after a request to [start the
+/// preview](KitsRpc::start_preview). The data is in the peripheral's format, e.g. a JPEG image.
+#[derive(Debug)]
+pub struct PreviewFrame {
+    pub kit_serial: String,
+    pub session: uuid::Uuid,
+    pub data: Vec<u8>,
+}
+
+/// The encoding of the messages on a topic.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Encoding {
+    /// Packed Cap'n Proto, as defined by the schema in `proto/astroplant.capnp`.
+    Capnp,
+    /// JSON, as defined in the [json] module. Topics with this encoding end in `/json`.
+    Json,
+}
+
+impl Encoding {
+    fn topic_suffix(self) -> &'static str {
+        match self {
+            Encoding::Capnp => "",
+            Encoding::Json => "/json",
+        }
+    }
+}
+
+/// The encoding each kit last published with. Kit RPC requests are sent in this encoding.
+///
+/// With a [shared subscription](ConnectionBuilder::with_shared_subscription), the encodings are
+/// learned from the kit RPC responses and the share of the kits' messages this client receives.
+#[derive(Clone, Default)]
+struct KitEncodings(Arc<RwLock<HashMap<String, Encoding>>>);
+
+impl KitEncodings {
+    fn get(&self, kit_serial: &str) -> Encoding {
+        self.0
+            .read()
+            .unwrap()
+            .get(kit_serial)
+            .copied()
+            .unwrap_or(Encoding::Capnp)
+    }
+
+    fn set(&self, kit_serial: &str, encoding: Encoding) {
+        if self.get(kit_serial) != encoding {
+            self.0
+                .write()
+                .unwrap()
+                .insert(kit_serial.to_owned(), encoding);
+        }
+    }
+}
+
+/// A message published by a kit was dropped, as the kit exceeded its [ingress
+/// quota](IngressQuota).
+#[derive(Clone, Debug)]
+pub struct QuotaExceeded {
+    pub kit_serial: String,
+    pub datetime: DateTime<Utc>,
+    /// The size of the dropped message's payload.
+    pub bytes: usize,
+}
+
 /// Timestamp is in milliseconds. Returns None if the timestamp overflowed.
 fn timestamp_to_datetime(timestamp: u64) -> Option<DateTime<Utc>> {
     let naive = chrono::NaiveDateTime::from_timestamp(
@@ -264,6 +412,183 @@ fn parse_media(kit_serial: String, mut payload: &[u8]) -> Result<Media, Error> {
     Ok(media)
 }
 
+fn parse_actuator_state(kit_serial: String, mut payload: &[u8]) -> Result<ActuatorState, Error> {
+    let message_reader =
+        serialize_packed::read_message(&mut payload, capnp::message::ReaderOptions::default())
+            .map_err(|err| Error::from_kit_serial_and_capnp(kit_serial.clone(), err))?;
+    let actuator_state = message_reader
+        .get_root::<astroplant_capnp::actuator_state::Reader>()
+        .map_err(|err| Error::from_kit_serial_and_capnp(kit_serial.clone(), err))?;
+
+    let id = actuator_state
+        .get_id()
+        .map_err(|err| Error::from_kit_serial_and_capnp(kit_serial.clone(), err))?;
+    let state = actuator_state
+        .get_state()
+        .map_err(|err| Error::from_kit_serial_and_capnp(kit_serial.clone(), err))?;
+
+    let actuator_state = ActuatorState {
+        id: uuid::Uuid::from_slice(id).map_err(|_| Error::MalformedMessage {
+            kit_serial: kit_serial.clone(),
+        })?,
+        datetime: timestamp_to_datetime(actuator_state.get_datetime()).ok_or_else(|| {
+            Error::MalformedMessage {
+                kit_serial: kit_serial.clone(),
+            }
+        })?,
+        peripheral: actuator_state.get_peripheral(),
+        state: serde_json::from_str(state).map_err(|_| Error::MalformedMessage {
+            kit_serial: kit_serial.clone(),
+        })?,
+        kit_serial,
+    };
+
+    Ok(actuator_state)
+}
+
+fn parse_kit_event(kit_serial: String, mut payload: &[u8]) -> Result<KitEvent, Error> {
+    let message_reader =
+        serialize_packed::read_message(&mut payload, capnp::message::ReaderOptions::default())
+            .map_err(|err| Error::from_kit_serial_and_capnp(kit_serial.clone(), err))?;
+    let event = message_reader
+        .get_root::<astroplant_capnp::kit_event::Reader>()
+        .map_err(|err| Error::from_kit_serial_and_capnp(kit_serial.clone(), err))?;
+
+    let id = event
+        .get_id()
+        .map_err(|err| Error::from_kit_serial_and_capnp(kit_serial.clone(), err))?;
+
+    let event = KitEvent {
+        id: uuid::Uuid::from_slice(id).map_err(|_| Error::MalformedMessage {
+            kit_serial: kit_serial.clone(),
+        })?,
+        datetime: timestamp_to_datetime(event.get_datetime()).ok_or_else(|| {
+            Error::MalformedMessage {
+                kit_serial: kit_serial.clone(),
+            }
+        })?,
+        severity: event
+            .get_severity()
+            .map_err(|err| Error::from_kit_serial_and_capnp(kit_serial.clone(), err))?
+            .into(),
+        peripheral: match event
+            .which()
+            .map_err(|err| Error::from_kit_serial_and_capnp(kit_serial.clone(), err))?
+        {
+            astroplant_capnp::kit_event::Kit(()) => None,
+            astroplant_capnp::kit_event::Peripheral(peripheral) => Some(peripheral),
+        },
+        message: event
+            .get_message()
+            .map_err(|err| Error::from_kit_serial_and_capnp(kit_serial.clone(), err))?
+            .to_owned(),
+        kit_serial,
+    };
+
+    Ok(event)
+}
+
+fn parse_json<T: serde::de::DeserializeOwned>(
+    kit_serial: &str,
+    payload: &[u8],
+) -> Result<T, Error> {
+    serde_json::from_slice(payload).map_err(|_| Error::MalformedMessage {
+        kit_serial: kit_serial.to_owned(),
+    })
+}
+
+fn parse_raw_measurement_json(kit_serial: String, payload: &[u8]) -> Result<RawMeasurement, Error> {
+    let raw_measurement: json::RawMeasurement = parse_json(&kit_serial, payload)?;
+
+    Ok(RawMeasurement {
+        id: raw_measurement.id,
+        datetime: timestamp_to_datetime(raw_measurement.datetime).ok_or_else(|| {
+            Error::MalformedMessage {
+                kit_serial: kit_serial.clone(),
+            }
+        })?,
+        peripheral: raw_measurement.peripheral,
+        quantity_type: raw_measurement.quantity_type,
+        value: raw_measurement.value,
+        kit_serial,
+    })
+}
+
+fn parse_aggregate_measurement_json(
+    kit_serial: String,
+    payload: &[u8],
+) -> Result<AggregateMeasurement, Error> {
+    let aggregate_measurement: json::AggregateMeasurement = parse_json(&kit_serial, payload)?;
+
+    Ok(AggregateMeasurement {
+        id: aggregate_measurement.id,
+        datetime_start: timestamp_to_datetime(aggregate_measurement.datetime_start).ok_or_else(
+            || Error::MalformedMessage {
+                kit_serial: kit_serial.clone(),
+            },
+        )?,
+        datetime_end: timestamp_to_datetime(aggregate_measurement.datetime_end).ok_or_else(
+            || Error::MalformedMessage {
+                kit_serial: kit_serial.clone(),
+            },
+        )?,
+        peripheral: aggregate_measurement.peripheral,
+        quantity_type: aggregate_measurement.quantity_type,
+        values: aggregate_measurement.values,
+        kit_serial,
+    })
+}
+
+fn parse_media_json(kit_serial: String, payload: &[u8]) -> Result<Media, Error> {
+    let media: json::Media = parse_json(&kit_serial, payload)?;
+
+    Ok(Media {
+        id: media.id,
+        datetime: timestamp_to_datetime(media.datetime).ok_or_else(|| Error::MalformedMessage {
+            kit_serial: kit_serial.clone(),
+        })?,
+        peripheral: media.peripheral,
+        name: media.name,
+        r#type: media.r#type,
+        data: base64::decode(&media.data).map_err(|_| Error::MalformedMessage {
+            kit_serial: kit_serial.clone(),
+        })?,
+        metadata: media.metadata,
+        kit_serial,
+    })
+}
+
+fn parse_actuator_state_json(kit_serial: String, payload: &[u8]) -> Result<ActuatorState, Error> {
+    let actuator_state: json::ActuatorState = parse_json(&kit_serial, payload)?;
+
+    Ok(ActuatorState {
+        id: actuator_state.id,
+        datetime: timestamp_to_datetime(actuator_state.datetime).ok_or_else(|| {
+            Error::MalformedMessage {
+                kit_serial: kit_serial.clone(),
+            }
+        })?,
+        peripheral: actuator_state.peripheral,
+        state: actuator_state.state,
+        kit_serial,
+    })
+}
+
+fn parse_kit_event_json(kit_serial: String, payload: &[u8]) -> Result<KitEvent, Error> {
+    let event: json::KitEvent = parse_json(&kit_serial, payload)?;
+
+    Ok(KitEvent {
+        id: event.id,
+        datetime: timestamp_to_datetime(event.datetime).ok_or_else(|| Error::MalformedMessage {
+            kit_serial: kit_serial.clone(),
+        })?,
+        severity: event.severity,
+        peripheral: event.peripheral,
+        message: event.message,
+        kit_serial,
+    })
+}
+
 /// A message sent by a kit.
 ///
 /// These messages do not include requests to the server RPC, nor
@@ -273,6 +598,28 @@ pub enum Message {
     RawMeasurement(RawMeasurement),
     AggregateMeasurement(AggregateMeasurement),
     Media(Media),
+    ActuatorState(ActuatorState),
+    Event(KitEvent),
+    PreviewFrame(PreviewFrame),
+    /// A message sent by a kit was dropped.
+    QuotaExceeded(QuotaExceeded),
+}
+
+impl Message {
+    /// The serial of the kit that sent the message.
+    pub fn kit_serial(&self) -> &str {
+        match self {
+            Message::RawMeasurement(raw_measurement) => &raw_measurement.kit_serial,
+            Message::AggregateMeasurement(aggregate_measurement) => {
+                &aggregate_measurement.kit_serial
+            }
+            Message::Media(media) => &media.kit_serial,
+            Message::ActuatorState(actuator_state) => &actuator_state.kit_serial,
+            Message::Event(event) => &event.kit_serial,
+            Message::PreviewFrame(preview_frame) => &preview_frame.kit_serial,
+            Message::QuotaExceeded(quota_exceeded) => &quota_exceeded.kit_serial,
+        }
+    }
 }
 
 /// A server RPC request handler.
@@ -337,19 +684,55 @@ impl ServerRpcHandler for NullHandler {
     }
 }
 
+/// The topic filters of the kits' messages that are handled once by a [shared
+/// subscription](ConnectionBuilder::with_shared_subscription)'s group.
+const SHARED_TOPIC_FILTERS: &[&str] = &[
+    "kit/+/measurement/#",
+    "kit/+/media/#",
+    "kit/+/actuator/#",
+    "kit/+/event/#",
+    "kit/+/server-rpc/request/#",
+];
+
+/// The topic filters of the kits' messages every client of a shared subscription's group
+/// receives: kit RPC responses and preview frames are for the client that sent the request or
+/// started the session.
+const UNSHARED_TOPIC_FILTERS: &[&str] = &["kit/+/kit-rpc/response/#", "kit/+/preview/#"];
+
+/// The subscriptions to the kits' topics, shared with the given group, if any.
+fn subscriptions(shared_subscription: Option<&str>) -> Vec<SubscribeFilter> {
+    let filter = |path: String| SubscribeFilter::new(path, rumqttc::QoS::AtLeastOnce);
+    match shared_subscription {
+        None => vec![filter("kit/#".to_owned())],
+        Some(group) => SHARED_TOPIC_FILTERS
+            .iter()
+            .map(|topic_filter| filter(format!("$share/{}/{}", group, topic_filter)))
+            .chain(
+                UNSHARED_TOPIC_FILTERS
+                    .iter()
+                    .map(|topic_filter| filter((*topic_filter).to_owned())),
+            )
+            .collect(),
+    }
+}
+
 enum TopicKind {
     RawMeasurement,
     AggregateMeasurement,
     Media,
+    ActuatorState,
+    Event,
     ServerRpcRequest,
     ServerRpcResponse,
     KitRpcRequest,
     KitRpcResponse,
+    PreviewFrame(uuid::Uuid),
 }
 
 struct Topic {
     kit_serial: String,
     kind: TopicKind,
+    encoding: Encoding,
 }
 
 impl TryFrom<&str> for Topic {
@@ -366,18 +749,37 @@ impl TryFrom<&str> for Topic {
             None => return Err(Error::InvalidTopic(value.to_owned())),
         };
 
-        let kind = match (topic_parts.next(), topic_parts.next(), topic_parts.next()) {
-            (Some("measurement"), Some("raw"), None) => TopicKind::RawMeasurement,
-            (Some("measurement"), Some("aggregate"), None) => TopicKind::AggregateMeasurement,
-            (Some("media"), None, None) => TopicKind::Media,
-            (Some("server-rpc"), Some("request"), None) => TopicKind::ServerRpcRequest,
-            (Some("server-rpc"), Some("response"), None) => TopicKind::ServerRpcResponse,
-            (Some("kit-rpc"), Some("request"), None) => TopicKind::KitRpcRequest,
-            (Some("kit-rpc"), Some("response"), None) => TopicKind::KitRpcResponse,
+        let mut topic_parts: Vec<&str> = topic_parts.collect();
+        let encoding = if topic_parts.last() == Some(&"json") {
+            topic_parts.pop();
+            Encoding::Json
+        } else {
+            Encoding::Capnp
+        };
+
+        let kind = match topic_parts.as_slice() {
+            ["measurement", "raw"] => TopicKind::RawMeasurement,
+            ["measurement", "aggregate"] => TopicKind::AggregateMeasurement,
+            ["media"] => TopicKind::Media,
+            ["actuator"] => TopicKind::ActuatorState,
+            ["event"] => TopicKind::Event,
+            ["server-rpc", "request"] => TopicKind::ServerRpcRequest,
+            ["server-rpc", "response"] => TopicKind::ServerRpcResponse,
+            ["kit-rpc", "request"] => TopicKind::KitRpcRequest,
+            ["kit-rpc", "response"] => TopicKind::KitRpcResponse,
+            ["preview", session] => TopicKind::PreviewFrame(
+                session
+                    .parse()
+                    .map_err(|_| Error::InvalidTopic(value.to_owned()))?,
+            ),
             _ => return Err(Error::InvalidTopic(value.to_owned())),
         };
 
-        Ok(Topic { kit_serial, kind })
+        Ok(Topic {
+            kit_serial,
+            kind,
+            encoding,
+        })
     }
 }
 
@@ -389,6 +791,50 @@ impl TryFrom<String> for Topic {
     }
 }
 
+/// The status of the connection to the MQTT broker.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConnectionStatus {
+    /// The connection is being (re-)established.
+    Connecting,
+    /// The connection is established, and kits' topics are subscribed to.
+    Connected,
+    /// The connection was lost or could not be established. It is re-established when the
+    /// [connection stream](Connection::into_stream) is polled again.
+    Disconnected,
+}
+
+/// The state of the connection to the MQTT broker.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConnectionState {
+    pub status: ConnectionStatus,
+    /// The date and time of the last connection error, if any.
+    pub last_error: Option<DateTime<Utc>>,
+}
+
+impl ConnectionState {
+    pub fn is_connected(&self) -> bool {
+        self.status == ConnectionStatus::Connected
+    }
+}
+
+/// A handle to observe the [state](ConnectionState) of the connection to the MQTT broker.
+pub type ConnectionStateRx = watch::Receiver<ConnectionState>;
+
+fn set_connection_status(
+    connection_state: &watch::Sender<ConnectionState>,
+    status: ConnectionStatus,
+    error: bool,
+) {
+    connection_state.send_if_modified(|state| {
+        let modified = state.status != status || error;
+        state.status = status;
+        if error {
+            state.last_error = Some(Utc::now());
+        }
+        modified
+    });
+}
+
 /// An MQTT connection handle.
 ///
 /// It must be consumed into a stream, and the stream driven, in order for the underlying protocol
@@ -398,8 +844,12 @@ pub struct Connection<H> {
     event_loop: EventLoop,
     server_rpc_handler: Option<std::sync::Arc<H>>,
     server_rpc_rate_limiter: KeyedRateLimiter<String>,
+    ingress_limiter: IngressLimiter,
+    kit_encodings: KitEncodings,
     kits_rpc_driver: KitsRpcDriver,
     kits_rpc_response_tx: KitsRpcResponseTx,
+    connection_state: watch::Sender<ConnectionState>,
+    shared_subscription: Option<String>,
 }
 
 impl<H> Connection<H>
@@ -417,8 +867,12 @@ where
             event_loop,
             server_rpc_handler,
             server_rpc_rate_limiter,
+            ingress_limiter,
+            kit_encodings,
             kits_rpc_driver,
             kits_rpc_response_tx,
+            connection_state,
+            shared_subscription,
         } = self;
         tracing::debug!("MQTT client started");
         tokio::spawn(kits_rpc_driver.drive());
@@ -428,29 +882,58 @@ where
             event_loop: EventLoop,
             server_rpc_handler: Option<std::sync::Arc<H>>,
             server_rpc_rate_limiter: KeyedRateLimiter<String>,
+            ingress_limiter: IngressLimiter,
+            kit_encodings: KitEncodings,
             kits_rpc_response_tx: KitsRpcResponseTx,
+            connection_state: watch::Sender<ConnectionState>,
+            shared_subscription: Option<String>,
         }
 
         async fn step<H>(state: &mut InnerState<H>) -> Result<Option<Message>, Error>
         where
             H: ServerRpcHandler + Send + Sync + 'static,
         {
-            let event = state.event_loop.poll().await?;
+            if state.connection_state.borrow().status == ConnectionStatus::Disconnected {
+                set_connection_status(&state.connection_state, ConnectionStatus::Connecting, false);
+            }
+
+            let event = match state.event_loop.poll().await {
+                Ok(event) => event,
+                Err(err) => {
+                    set_connection_status(
+                        &state.connection_state,
+                        ConnectionStatus::Disconnected,
+                        true,
+                    );
+                    return Err(err.into());
+                }
+            };
 
             match event {
                 Event::Incoming(Packet::ConnAck(_)) => {
+                    // Subscriptions are (re-)made on every connection, as the broker may not have
+                    // persisted our session.
                     tracing::debug!("MQTT client connected");
                     state
                         .client
-                        .subscribe("kit/#", rumqttc::QoS::AtLeastOnce)
+                        .subscribe_many(subscriptions(state.shared_subscription.as_deref()))
                         .await?;
                 }
+                Event::Incoming(Packet::SubAck(_)) => {
+                    set_connection_status(
+                        &state.connection_state,
+                        ConnectionStatus::Connected,
+                        false,
+                    );
+                }
                 Event::Incoming(Packet::Publish(publish)) => {
                     tracing::trace!("Received Publish packet");
                     if let Some(message) = handle_publish(
                         &state.client,
                         &state.server_rpc_handler,
                         &mut state.server_rpc_rate_limiter,
+                        &mut state.ingress_limiter,
+                        &state.kit_encodings,
                         &state.kits_rpc_response_tx,
                         publish,
                     )
@@ -471,7 +954,11 @@ where
                 event_loop,
                 server_rpc_handler,
                 server_rpc_rate_limiter,
+                ingress_limiter,
+                kit_encodings,
                 kits_rpc_response_tx,
+                connection_state,
+                shared_subscription,
             },
             |mut state| async {
                 let value = loop {
@@ -494,6 +981,8 @@ async fn handle_publish<H>(
     client: &AsyncClient,
     server_rpc_handler: &Option<std::sync::Arc<H>>,
     server_rpc_rate_limiter: &mut KeyedRateLimiter<String>,
+    ingress_limiter: &mut IngressLimiter,
+    kit_encodings: &KitEncodings,
     kits_rpc_response_tx: &KitsRpcResponseTx,
     publish: Publish,
 ) -> Result<Option<Message>, Error>
@@ -502,9 +991,37 @@ where
 {
     let topic = Topic::try_from(publish.topic)?;
 
+    match topic.kind {
+        // Preview frames are not encoded.
+        TopicKind::ServerRpcResponse | TopicKind::KitRpcRequest | TopicKind::PreviewFrame(_) => {}
+        _ => kit_encodings.set(&topic.kit_serial, topic.encoding),
+    }
+
+    let limited = matches!(
+        topic.kind,
+        TopicKind::RawMeasurement
+            | TopicKind::AggregateMeasurement
+            | TopicKind::Media
+            | TopicKind::ActuatorState
+            | TopicKind::Event
+            | TopicKind::PreviewFrame(_)
+    );
+    if limited && !ingress_limiter.check(&topic.kit_serial, publish.payload.len()) {
+        tracing::trace!("Kit {} exceeded its ingress quota", topic.kit_serial);
+        return Ok(Some(Message::QuotaExceeded(QuotaExceeded {
+            kit_serial: topic.kit_serial,
+            datetime: Utc::now(),
+            bytes: publish.payload.len(),
+        })));
+    }
+
     match topic.kind {
         TopicKind::RawMeasurement => {
-            if let Ok(m) = parse_raw_measurement(topic.kit_serial, &publish.payload) {
+            let m = match topic.encoding {
+                Encoding::Capnp => parse_raw_measurement(topic.kit_serial, &publish.payload),
+                Encoding::Json => parse_raw_measurement_json(topic.kit_serial, &publish.payload),
+            };
+            if let Ok(m) = m {
                 Ok(Some(Message::RawMeasurement(m)))
             } else {
                 // Ignore decoding errors
@@ -512,7 +1029,13 @@ where
             }
         }
         TopicKind::AggregateMeasurement => {
-            if let Ok(m) = parse_aggregate_measurement(topic.kit_serial, &publish.payload) {
+            let m = match topic.encoding {
+                Encoding::Capnp => parse_aggregate_measurement(topic.kit_serial, &publish.payload),
+                Encoding::Json => {
+                    parse_aggregate_measurement_json(topic.kit_serial, &publish.payload)
+                }
+            };
+            if let Ok(m) = m {
                 Ok(Some(Message::AggregateMeasurement(m)))
             } else {
                 // Ignore decoding errors
@@ -520,13 +1043,46 @@ where
             }
         }
         TopicKind::Media => {
-            if let Ok(m) = parse_media(topic.kit_serial, &publish.payload) {
+            let m = match topic.encoding {
+                Encoding::Capnp => parse_media(topic.kit_serial, &publish.payload),
+                Encoding::Json => parse_media_json(topic.kit_serial, &publish.payload),
+            };
+            if let Ok(m) = m {
                 Ok(Some(Message::Media(m)))
             } else {
                 // Ignore decoding errors
                 Ok(None)
             }
         }
+        TopicKind::ActuatorState => {
+            let s = match topic.encoding {
+                Encoding::Capnp => parse_actuator_state(topic.kit_serial, &publish.payload),
+                Encoding::Json => parse_actuator_state_json(topic.kit_serial, &publish.payload),
+            };
+            if let Ok(s) = s {
+                Ok(Some(Message::ActuatorState(s)))
+            } else {
+                // Ignore decoding errors
+                Ok(None)
+            }
+        }
+        TopicKind::Event => {
+            let e = match topic.encoding {
+                Encoding::Capnp => parse_kit_event(topic.kit_serial, &publish.payload),
+                Encoding::Json => parse_kit_event_json(topic.kit_serial, &publish.payload),
+            };
+            if let Ok(e) = e {
+                Ok(Some(Message::Event(e)))
+            } else {
+                // Ignore decoding errors
+                Ok(None)
+            }
+        }
+        TopicKind::PreviewFrame(session) => Ok(Some(Message::PreviewFrame(PreviewFrame {
+            kit_serial: topic.kit_serial,
+            session,
+            data: publish.payload.to_vec(),
+        }))),
         TopicKind::ServerRpcRequest => {
             if let Some(server_rpc_handler) = server_rpc_handler {
                 handle_server_rpc_request(
@@ -535,6 +1091,7 @@ where
                     server_rpc_rate_limiter,
                     topic.kit_serial,
                     &publish.payload,
+                    topic.encoding,
                 )
                 .await?;
             }
@@ -542,8 +1099,13 @@ where
             Ok(None)
         }
         TopicKind::KitRpcResponse => {
-            handle_kit_rpc_response(kits_rpc_response_tx, topic.kit_serial, &publish.payload)
-                .await?;
+            handle_kit_rpc_response(
+                kits_rpc_response_tx,
+                topic.kit_serial,
+                &publish.payload,
+                topic.encoding,
+            )
+            .await?;
             Ok(None)
         }
         TopicKind::ServerRpcResponse | TopicKind::KitRpcRequest => {
@@ -561,7 +1123,7 @@ async fn call_server_rpc_handler<H>(
 where
     H: ServerRpcHandler + Send + Sync + 'static,
 {
-    let response = ServerRpcResponseBuilder::new(kit_serial.clone(), request.id);
+    let response = ServerRpcResponseBuilder::new(kit_serial.clone(), request.id, request.encoding);
 
     match request.body {
         ServerRpcRequestBody::Version => match server_rpc_handler.version().await {
@@ -588,9 +1150,10 @@ async fn handle_kit_rpc_response(
     kits_rpc_response_tx: &KitsRpcResponseTx,
     kit_serial: String,
     payload: &[u8],
+    encoding: Encoding,
 ) -> Result<(), Error> {
     kits_rpc_response_tx
-        .send(kit_serial.clone(), payload.to_owned())
+        .send(kit_serial.clone(), payload.to_owned(), encoding)
         .await
         .map_err(|error| Error::KitRpcResponse { kit_serial, error })?;
 
@@ -603,11 +1166,12 @@ async fn handle_server_rpc_request<H>(
     server_rpc_rate_limiter: &mut KeyedRateLimiter<String>,
     kit_serial: String,
     payload: &[u8],
+    encoding: Encoding,
 ) -> Result<(), Error>
 where
     H: ServerRpcHandler + Send + Sync + 'static,
 {
-    let request = crate::server_rpc::decode_rpc_request(payload);
+    let request = crate::server_rpc::decode_rpc_request(payload, encoding);
     let client = client.clone();
     let handler = server_rpc_handler.clone();
 
@@ -624,7 +1188,7 @@ where
     tokio::spawn(async move {
         let response = match request {
             Err(crate::server_rpc::DecodeError::WithRequestId { id, .. }) => {
-                let response = ServerRpcResponseBuilder::new(kit_serial.clone(), id)
+                let response = ServerRpcResponseBuilder::new(kit_serial.clone(), id, encoding)
                     .set_error_method_not_found()
                     .create();
                 Some(response)
@@ -632,9 +1196,10 @@ where
             Err(crate::server_rpc::DecodeError::WithoutRequestId(_)) => None,
             Ok(request) => {
                 if let Some(wait_time) = rate_limit_wait_time {
-                    let response = ServerRpcResponseBuilder::new(kit_serial.clone(), request.id)
-                        .set_error_rate_limit(wait_time)
-                        .create();
+                    let response =
+                        ServerRpcResponseBuilder::new(kit_serial.clone(), request.id, encoding)
+                            .set_error_rate_limit(wait_time)
+                            .create();
                     Some(response)
                 } else {
                     Some(call_server_rpc_handler(handler, kit_serial, request).await)
@@ -645,7 +1210,11 @@ where
         if let Some(response) = response {
             let _ = client
                 .publish(
-                    format!("kit/{}/server-rpc/response", response.kit_serial),
+                    format!(
+                        "kit/{}/server-rpc/response{}",
+                        response.kit_serial,
+                        response.encoding.topic_suffix()
+                    ),
                     rumqttc::QoS::AtLeastOnce,
                     false,
                     response.bytes,
@@ -659,12 +1228,13 @@ where
 
 /// An MQTT connection builder.
 pub struct ConnectionBuilder<H> {
-    // TODO: allow specifying subscriptions.
     host: String,
     port: u16,
     client_id: String,
     username: Option<String>,
     password: Option<String>,
+    ingress_quota: IngressQuota,
+    shared_subscription: Option<String>,
     server_rpc_handler: Option<H>,
 }
 
@@ -677,6 +1247,8 @@ impl ConnectionBuilder<NullHandler> {
             client_id: "astroplant-mqtt".to_owned(),
             username: None,
             password: None,
+            ingress_quota: IngressQuota::default(),
+            shared_subscription: None,
             server_rpc_handler: None,
         }
     }
@@ -704,6 +1276,27 @@ impl<H> ConnectionBuilder<H> {
         }
     }
 
+    /// Limit the messages each kit may publish. Messages exceeding the quota are dropped.
+    pub fn with_ingress_quota(self, ingress_quota: IngressQuota) -> Self {
+        Self {
+            ingress_quota,
+            ..self
+        }
+    }
+
+    /// Share the subscription to the kits' messages with the other clients of the group, such
+    /// that each message is handled by one of them, e.g. to run several instances of a service.
+    /// Kit RPC responses and preview frames are still received by every client.
+    ///
+    /// Each client enforces the [ingress quota](Self::with_ingress_quota) on the messages it
+    /// receives.
+    pub fn with_shared_subscription<S: Into<String>>(self, group: S) -> Self {
+        Self {
+            shared_subscription: Some(group.into()),
+            ..self
+        }
+    }
+
     /// Add a server RPC handler to respond to requests made by kits to the server. The handler
     /// should implement the [ServerRpcHandler] trait. If no handler is added, this MQTT client
     /// ignores RPC requests. This allows a different MQTT client to handle requests.
@@ -714,13 +1307,29 @@ impl<H> ConnectionBuilder<H> {
             client_id: self.client_id,
             username: self.username,
             password: self.password,
+            ingress_quota: self.ingress_quota,
+            shared_subscription: self.shared_subscription,
             server_rpc_handler: Some(server_rpc_handler),
         }
     }
 
-    /// Create the MQTT client. Returns a connection and a kits RPC handle. The connection must be
-    /// driven for the underlying protocol to make progress.
-    pub fn create(self) -> (Connection<H>, KitsRpc) {
+    /// Create a plain MQTT client that only publishes, without subscribing to kits' topics. The
+    /// connection is driven on a spawned task, so this must be called from within a Tokio runtime.
+    pub fn create_publisher(self) -> Publisher {
+        let mut options = MqttOptions::new(self.client_id, self.host, self.port);
+        if self.username.is_some() {
+            options.set_credentials(self.username.unwrap(), self.password.unwrap());
+        }
+        options.set_keep_alive(Duration::from_secs(10));
+        let (client, event_loop) = AsyncClient::new(options, 256);
+
+        Publisher::create(client, event_loop)
+    }
+
+    /// Create the MQTT client. Returns a connection, a kits RPC handle, and a handle to observe the
+    /// connection state. The connection must be driven for the underlying protocol to make
+    /// progress.
+    pub fn create(self) -> (Connection<H>, KitsRpc, ConnectionStateRx) {
         let mut options = MqttOptions::new(self.client_id, self.host, self.port);
         options.set_max_packet_size(
             // Note: capnproto traversal is limited to 64 MiB as well
@@ -733,8 +1342,17 @@ impl<H> ConnectionBuilder<H> {
         options.set_keep_alive(Duration::from_secs(10));
         let (client, event_loop) = AsyncClient::new(options, 32);
 
-        let (kits_rpc, kits_rpc_driver, kits_rpc_response_tx) =
-            crate::kit_rpc::create(client.clone());
+        let (connection_state, connection_state_rx) = watch::channel(ConnectionState {
+            status: ConnectionStatus::Connecting,
+            last_error: None,
+        });
+
+        let kit_encodings = KitEncodings::default();
+        let (kits_rpc, kits_rpc_driver, kits_rpc_response_tx) = crate::kit_rpc::create(
+            client.clone(),
+            kit_encodings.clone(),
+            connection_state_rx.clone(),
+        );
 
         let server_rpc_rate_limiter = {
             const NUM_REQUESTS: u32 = 30u32;
@@ -748,10 +1366,77 @@ impl<H> ConnectionBuilder<H> {
             event_loop,
             server_rpc_handler: self.server_rpc_handler.map(std::sync::Arc::new),
             server_rpc_rate_limiter,
+            ingress_limiter: IngressLimiter::new(self.ingress_quota),
+            kit_encodings,
             kits_rpc_driver,
             kits_rpc_response_tx,
+            connection_state,
+            shared_subscription: self.shared_subscription,
+        };
+
+        (connection, kits_rpc, connection_state_rx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    pub fn actuator_state() {
+        let id = uuid::Uuid::new_v4();
+
+        let mut message_builder = capnp::message::Builder::new_default();
+        let mut builder =
+            message_builder.init_root::<crate::astroplant_capnp::actuator_state::Builder>();
+        builder.set_id(id.as_bytes());
+        builder.set_datetime(1600000000123);
+        builder.set_peripheral(3);
+        builder.set_state(r#"{"on": true}"#);
+        let mut payload = Vec::new();
+        capnp::serialize_packed::write_message(&mut payload, &message_builder).unwrap();
+
+        let actuator_state = crate::parse_actuator_state("k-abcd".to_owned(), &payload).unwrap();
+        assert_eq!(actuator_state.id, id);
+        assert_eq!(actuator_state.datetime.timestamp_millis(), 1600000000123);
+        assert_eq!(actuator_state.peripheral, 3);
+        assert_eq!(actuator_state.state, serde_json::json!({"on": true}));
+
+        assert!(crate::parse_actuator_state("k-abcd".to_owned(), b"garbage").is_err());
+    }
+
+    #[test]
+    pub fn shared_subscriptions() {
+        let paths = |shared_subscription| {
+            crate::subscriptions(shared_subscription)
+                .into_iter()
+                .map(|filter| filter.path)
+                .collect::<Vec<_>>()
         };
 
-        (connection, kits_rpc)
+        assert_eq!(paths(None), vec!["kit/#"]);
+
+        let shared = paths(Some("astroplant-api"));
+        assert!(shared.contains(&"$share/astroplant-api/kit/+/measurement/#".to_owned()));
+        assert!(shared.contains(&"$share/astroplant-api/kit/+/server-rpc/request/#".to_owned()));
+        assert!(shared.contains(&"kit/+/kit-rpc/response/#".to_owned()));
+        assert!(shared.contains(&"kit/+/preview/#".to_owned()));
+
+        // Every topic kind a kit publishes is subscribed to exactly once.
+        for topic in [
+            "kit/k-1/measurement/raw",
+            "kit/k-1/measurement/aggregate/json",
+            "kit/k-1/media",
+            "kit/k-1/actuator/json",
+            "kit/k-1/event",
+            "kit/k-1/server-rpc/request",
+            "kit/k-1/kit-rpc/response/json",
+            "kit/k-1/preview/0f8fad5b-d9cb-469f-a165-70867728950e",
+        ] {
+            let matching = shared
+                .iter()
+                .map(|path| path.trim_start_matches("$share/astroplant-api/"))
+                .filter(|path| rumqttc::matches(topic, path))
+                .count();
+            assert_eq!(matching, 1, "{}", topic);
+        }
     }
 }
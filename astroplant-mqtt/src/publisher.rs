@@ -0,0 +1,59 @@
+//! A plain MQTT client that publishes to topics outside of the kit protocol, e.g. to bridge kits'
+//! measurements to other systems.
+
+use rumqttc::{AsyncClient, Event, EventLoop, Packet, QoS};
+use std::time::Duration;
+
+/// The time to wait before reconnecting after a connection issue.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// A message could not be queued for publishing.
+#[derive(thiserror::Error, Debug)]
+#[error("The message could not be queued for publishing")]
+pub struct PublishError;
+
+/// A handle to publish messages. Publishing never waits for the connection: messages are dropped
+/// while the client's queue is full, e.g. when the broker is unreachable.
+#[derive(Clone)]
+pub struct Publisher {
+    client: AsyncClient,
+}
+
+impl Publisher {
+    pub(crate) fn create(client: AsyncClient, event_loop: EventLoop) -> Self {
+        tokio::spawn(drive(event_loop));
+        Self { client }
+    }
+
+    /// Publish a message. Retained messages are kept by the broker and sent to new subscribers.
+    pub fn publish<S: Into<String>, V: Into<Vec<u8>>>(
+        &self,
+        topic: S,
+        payload: V,
+        retain: bool,
+    ) -> Result<(), PublishError> {
+        self.client
+            .try_publish(topic, QoS::AtLeastOnce, retain, payload)
+            .map_err(|_| PublishError)
+    }
+}
+
+async fn drive(mut event_loop: EventLoop) {
+    tracing::debug!("MQTT publisher started");
+
+    loop {
+        match event_loop.poll().await {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                tracing::debug!("MQTT publisher connected");
+            }
+            Ok(_) => {}
+            Err(err) => {
+                tracing::warn!(
+                    "An MQTT publisher connection error was encountered: {:?}",
+                    err
+                );
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        }
+    }
+}
@@ -1,9 +1,11 @@
 use capnp::serialize_packed;
 
-use super::{astroplant_capnp, RpcError};
+use super::{astroplant_capnp, json, Encoding, RpcError};
+use crate::kit_rpc::DecodeErrorKind;
 
 pub struct ServerRpcRequest {
     pub id: u64,
+    pub encoding: Encoding,
     pub body: ServerRpcRequestBody,
 }
 
@@ -14,23 +16,33 @@ pub enum ServerRpcRequestBody {
 }
 
 pub enum DecodeError {
-    WithRequestId { id: u64, error: capnp::Error },
-    WithoutRequestId(capnp::Error),
+    WithRequestId { id: u64, error: DecodeErrorKind },
+    WithoutRequestId(DecodeErrorKind),
 }
 
-pub fn decode_rpc_request(mut message: &[u8]) -> Result<ServerRpcRequest, DecodeError> {
+pub fn decode_rpc_request(
+    message: &[u8],
+    encoding: Encoding,
+) -> Result<ServerRpcRequest, DecodeError> {
+    match encoding {
+        Encoding::Capnp => decode_capnp_rpc_request(message),
+        Encoding::Json => decode_json_rpc_request(message),
+    }
+}
+
+fn decode_capnp_rpc_request(mut message: &[u8]) -> Result<ServerRpcRequest, DecodeError> {
     let message_reader =
         serialize_packed::read_message(&mut message, capnp::message::ReaderOptions::default())
-            .map_err(DecodeError::WithoutRequestId)?;
+            .map_err(|err| DecodeError::WithoutRequestId(err.into()))?;
     let request = message_reader
         .get_root::<astroplant_capnp::server_rpc_request::Reader>()
-        .map_err(DecodeError::WithoutRequestId)?;
+        .map_err(|err| DecodeError::WithoutRequestId(err.into()))?;
 
     let id = request.get_id();
 
     let body = match request.which().map_err(|err| DecodeError::WithRequestId {
         id,
-        error: err.into(),
+        error: capnp::Error::from(err).into(),
     })? {
         astroplant_capnp::server_rpc_request::Which::Version(_) => ServerRpcRequestBody::Version,
         astroplant_capnp::server_rpc_request::Which::GetActiveConfiguration(_) => {
@@ -41,111 +53,179 @@ pub fn decode_rpc_request(mut message: &[u8]) -> Result<ServerRpcRequest, Decode
         }
     };
 
-    Ok(ServerRpcRequest { id, body })
+    Ok(ServerRpcRequest {
+        id,
+        encoding: Encoding::Capnp,
+        body,
+    })
+}
+
+fn decode_json_rpc_request(message: &[u8]) -> Result<ServerRpcRequest, DecodeError> {
+    let request: json::ServerRpcRequest = serde_json::from_slice(message).map_err(|err| {
+        match serde_json::from_slice::<json::RpcId>(message) {
+            Ok(json::RpcId { id }) => DecodeError::WithRequestId {
+                id,
+                error: err.into(),
+            },
+            Err(_) => DecodeError::WithoutRequestId(err.into()),
+        }
+    })?;
+
+    let body = match request.body {
+        json::ServerRpcRequestBody::Version(()) => ServerRpcRequestBody::Version,
+        json::ServerRpcRequestBody::GetActiveConfiguration(()) => {
+            ServerRpcRequestBody::GetActiveConfiguration
+        }
+        json::ServerRpcRequestBody::GetQuantityTypes(()) => ServerRpcRequestBody::GetQuantityTypes,
+    };
+
+    Ok(ServerRpcRequest {
+        id: request.id,
+        encoding: Encoding::Json,
+        body,
+    })
 }
 
 #[derive(Debug)]
 pub struct ServerRpcResponse {
     pub kit_serial: String,
+    pub encoding: Encoding,
     pub bytes: Vec<u8>,
 }
 
+enum ResponseBody {
+    Error(RpcError),
+    Version(String),
+    ActiveConfiguration(Option<serde_json::Value>),
+    QuantityTypes(Vec<serde_json::Value>),
+}
+
 pub struct ServerRpcResponseBuilder {
     kit_serial: String,
-    message_builder: capnp::message::Builder<capnp::message::HeapAllocator>,
+    id: u64,
+    encoding: Encoding,
+    body: ResponseBody,
 }
 
 impl ServerRpcResponseBuilder {
-    pub fn new(kit_serial: String, id: u64) -> Self {
-        let mut message_builder = capnp::message::Builder::new_default();
-        let mut response_builder =
-            message_builder.init_root::<astroplant_capnp::server_rpc_response::Builder>();
-        response_builder.set_id(id);
+    pub fn new(kit_serial: String, id: u64, encoding: Encoding) -> Self {
         Self {
             kit_serial,
-            message_builder,
+            id,
+            encoding,
+            body: ResponseBody::Error(RpcError::Other),
         }
     }
 
     pub fn set_from_rpc_error(self, rpc_error: RpcError) -> Self {
-        match rpc_error {
-            RpcError::Other => self.set_error_other(),
-            RpcError::MethodNotFound => self.set_error_method_not_found(),
-            RpcError::RateLimit(duration) => self.set_error_rate_limit(duration.as_millis() as u64),
+        Self {
+            body: ResponseBody::Error(rpc_error),
+            ..self
         }
     }
 
-    pub fn set_error_other(mut self) -> Self {
-        let response_builder = self
-            .message_builder
-            .get_root::<astroplant_capnp::server_rpc_response::Builder>()
-            .expect("could not get root");
-        response_builder.init_error().set_other(());
-        self
+    pub fn set_error_method_not_found(self) -> Self {
+        self.set_from_rpc_error(RpcError::MethodNotFound)
+    }
+
+    pub fn set_error_rate_limit(self, millis: u64) -> Self {
+        self.set_from_rpc_error(RpcError::RateLimit(std::time::Duration::from_millis(
+            millis,
+        )))
     }
 
-    pub fn set_error_method_not_found(mut self) -> Self {
-        let response_builder = self
-            .message_builder
-            .get_root::<astroplant_capnp::server_rpc_response::Builder>()
-            .expect("could not get root");
-        response_builder.init_error().set_method_not_found(());
-        self
+    pub fn set_version(self, version: String) -> Self {
+        Self {
+            body: ResponseBody::Version(version),
+            ..self
+        }
     }
 
-    pub fn set_error_rate_limit(mut self, millis: u64) -> Self {
-        let response_builder = self
-            .message_builder
-            .get_root::<astroplant_capnp::server_rpc_response::Builder>()
-            .expect("could not get root");
-        response_builder.init_error().set_rate_limit(millis);
-        self
+    pub fn set_active_configuration(self, configuration: Option<serde_json::Value>) -> Self {
+        Self {
+            body: ResponseBody::ActiveConfiguration(configuration),
+            ..self
+        }
     }
 
-    pub fn set_version(mut self, version: String) -> Self {
-        let mut response_builder = self
-            .message_builder
-            .get_root::<astroplant_capnp::server_rpc_response::Builder>()
-            .expect("could not get root");
-        response_builder.set_version(&version);
-        self
+    pub fn set_quantity_types(self, quantity_types: Vec<serde_json::Value>) -> Self {
+        Self {
+            body: ResponseBody::QuantityTypes(quantity_types),
+            ..self
+        }
     }
 
-    pub fn set_active_configuration(mut self, configuration: Option<serde_json::Value>) -> Self {
-        let response_builder = self
-            .message_builder
-            .get_root::<astroplant_capnp::server_rpc_response::Builder>()
-            .expect("could not get root");
-        match configuration {
-            Some(configuration) => {
+    fn build_capnp(id: u64, body: ResponseBody) -> Vec<u8> {
+        let mut message_builder = capnp::message::Builder::new_default();
+        let mut response_builder =
+            message_builder.init_root::<astroplant_capnp::server_rpc_response::Builder>();
+        response_builder.set_id(id);
+
+        match body {
+            ResponseBody::Error(RpcError::Other) => {
+                response_builder.init_error().set_other(());
+            }
+            ResponseBody::Error(RpcError::MethodNotFound) => {
+                response_builder.init_error().set_method_not_found(());
+            }
+            ResponseBody::Error(RpcError::RateLimit(duration)) => {
+                response_builder
+                    .init_error()
+                    .set_rate_limit(duration.as_millis() as u64);
+            }
+            ResponseBody::Version(version) => {
+                response_builder.set_version(&version);
+            }
+            ResponseBody::ActiveConfiguration(Some(configuration)) => {
                 response_builder
                     .init_get_active_configuration()
                     .set_configuration(&configuration.to_string());
             }
-            None => {
+            ResponseBody::ActiveConfiguration(None) => {
                 response_builder
                     .init_get_active_configuration()
                     .set_none(());
             }
+            ResponseBody::QuantityTypes(quantity_types) => {
+                response_builder
+                    .set_get_quantity_types(&serde_json::to_string(&quantity_types).unwrap());
+            }
         }
-        self
+
+        let mut bytes = Vec::new();
+        serialize_packed::write_message(&mut bytes, &message_builder).unwrap();
+        bytes
     }
 
-    pub fn set_quantity_types(mut self, quantity_types: Vec<serde_json::Value>) -> Self {
-        let mut response_builder = self
-            .message_builder
-            .get_root::<astroplant_capnp::server_rpc_response::Builder>()
-            .expect("could not get root");
-        response_builder.set_get_quantity_types(&serde_json::to_string(&quantity_types).unwrap());
-        self
+    fn build_json(id: u64, body: ResponseBody) -> Vec<u8> {
+        let body = match body {
+            ResponseBody::Error(error) => {
+                json::ServerRpcResponseBody::Error(json::RpcErrorBody::from_rpc_error(error))
+            }
+            ResponseBody::Version(version) => json::ServerRpcResponseBody::Version(version),
+            ResponseBody::ActiveConfiguration(configuration) => {
+                json::ServerRpcResponseBody::GetActiveConfiguration(match configuration {
+                    Some(configuration) => json::ActiveConfiguration::Configuration(configuration),
+                    None => json::ActiveConfiguration::None(()),
+                })
+            }
+            ResponseBody::QuantityTypes(quantity_types) => {
+                json::ServerRpcResponseBody::GetQuantityTypes(quantity_types)
+            }
+        };
+
+        serde_json::to_vec(&json::ServerRpcResponse { id, body }).unwrap()
     }
 
     pub fn create(self) -> ServerRpcResponse {
-        let mut bytes = Vec::new();
-        serialize_packed::write_message(&mut bytes, &self.message_builder).unwrap();
+        let bytes = match self.encoding {
+            Encoding::Capnp => Self::build_capnp(self.id, self.body),
+            Encoding::Json => Self::build_json(self.id, self.body),
+        };
 
         ServerRpcResponse {
             kit_serial: self.kit_serial,
+            encoding: self.encoding,
             bytes,
         }
     }
@@ -0,0 +1,151 @@
+//! Authentication of WebSocket connections, and authorization of their subscriptions and kit RPC.
+//!
+//! A connection is anonymous until it authenticates, either when upgrading to a WebSocket or later
+//! through the `authenticate` method. Authentication expires with the access token it was made
+//! with, after which the connection is anonymous again unless it authenticates anew.
+
+use chrono::{DateTime, Utc};
+use tokio::sync::watch;
+
+/// An authenticated user of a connection, until the session expires.
+#[derive(Clone, Copy, Debug)]
+pub struct Session<U> {
+    pub user: U,
+    pub expires: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub enum AuthenticationError {
+    Expired,
+    Invalid,
+}
+
+/// What a connection does with a kit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Subscribe,
+    RpcVersion,
+    RpcUptime,
+    RpcPeripheralCommand,
+    RpcPeripheralCommandLock,
+    /// View a live preview of a peripheral, such as a camera.
+    Preview,
+}
+
+#[async_trait::async_trait]
+pub trait Auth: Send + Sync + 'static {
+    type User: Copy + Send + Sync + 'static;
+
+    /// Authenticate with an access token.
+    async fn authenticate(
+        &self,
+        access_token: &str,
+    ) -> Result<Session<Self::User>, AuthenticationError>;
+
+    /// Whether the user (or an anonymous connection, if `None`) may perform the action on the kit.
+    async fn authorize(&self, user: Option<Self::User>, kit_serial: &str, action: Action) -> bool;
+}
+
+/// The user of a session that has not expired.
+pub(crate) fn current_user<U: Copy>(session: &Option<Session<U>>) -> Option<U> {
+    session
+        .as_ref()
+        .filter(|session| session.expires > Utc::now())
+        .map(|session| session.user)
+}
+
+/// Checks whether a subscription is still authorized as its connection's session changes.
+pub(crate) struct Authorization<A: Auth> {
+    pub(crate) auth: std::sync::Arc<A>,
+    pub(crate) session: watch::Receiver<Option<Session<A::User>>>,
+    pub(crate) kit_serial: String,
+    pub(crate) action: Action,
+}
+
+impl<A: Auth> Authorization<A> {
+    pub(crate) async fn check(&self) -> bool {
+        let user = current_user(&self.session.borrow());
+        self.auth
+            .authorize(user, &self.kit_serial, self.action)
+            .await
+    }
+
+    /// Checks authorization once a session expired at `expired`. The session's user is only
+    /// considered if the connection has since renewed its session.
+    pub(crate) async fn check_expired(&self, expired: DateTime<Utc>) -> bool {
+        let session = self
+            .session
+            .borrow()
+            .filter(|session| session.expires > expired);
+        self.auth
+            .authorize(current_user(&session), &self.kit_serial, self.action)
+            .await
+    }
+
+    /// Resolves with the current session's expiry when it expires. Pending if there is no
+    /// session, or if it already expired.
+    pub(crate) fn expiry(&self) -> impl std::future::Future<Output = DateTime<Utc>> {
+        let expires = self
+            .session
+            .borrow()
+            .as_ref()
+            .map(|session| session.expires);
+        let until =
+            expires.and_then(|expires| Some((expires, (expires - Utc::now()).to_std().ok()?)));
+        async move {
+            match until {
+                Some((expires, until)) => {
+                    tokio::time::sleep(until).await;
+                    expires
+                }
+                None => futures::future::pending().await,
+            }
+        }
+    }
+}
+
+/// An [Auth] for tests, authenticating every access token as user 1, and authorizing users while
+/// `authorized` is set. Anonymous connections are not authorized.
+#[cfg(test)]
+#[derive(Clone)]
+pub(crate) struct StubAuth {
+    pub(crate) authorized: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    /// How long sessions last.
+    pub(crate) session_duration: chrono::Duration,
+}
+
+#[cfg(test)]
+impl StubAuth {
+    pub(crate) fn new(session_duration: chrono::Duration) -> Self {
+        Self {
+            authorized: std::sync::Arc::new(true.into()),
+            session_duration,
+        }
+    }
+
+    pub(crate) fn set_authorized(&self, authorized: bool) {
+        self.authorized
+            .store(authorized, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    pub(crate) fn session(&self) -> Session<i32> {
+        Session {
+            user: 1,
+            expires: Utc::now() + self.session_duration,
+        }
+    }
+}
+
+#[cfg(test)]
+#[async_trait::async_trait]
+impl Auth for StubAuth {
+    type User = i32;
+
+    async fn authenticate(&self, _access_token: &str) -> Result<Session<i32>, AuthenticationError> {
+        Ok(self.session())
+    }
+
+    async fn authorize(&self, user: Option<i32>, _kit_serial: &str, _action: Action) -> bool {
+        user.is_some() && self.authorized.load(std::sync::atomic::Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,151 @@
+//! Backends distribute published events to the WebSocket subscribers, and cache the latest raw
+//! measurements sent to new subscribers. The in-memory backend serves a single API instance.
+//! Running several instances requires a backend shared by all instances, such that subscribers
+//! receive events published by any instance.
+
+use astroplant_mqtt::{AggregateMeasurement, RawMeasurement};
+use futures::sink::SinkExt;
+use futures::stream::BoxStream;
+use futures_channel::mpsc;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long raw measurements are cached after being received.
+pub const CACHE_RETENTION_PERIOD: Duration = Duration::from_secs(30 * 60);
+
+/// An event sent to the subscribers of a kit.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", content = "event", rename_all = "camelCase")]
+pub enum Event {
+    RawMeasurement(RawMeasurement),
+    AggregateMeasurement(AggregateMeasurement),
+    Media(KitMedia),
+}
+
+/// Stored media of a kit, in the JSON representation the API serves media in.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct KitMedia {
+    pub kit_serial: String,
+    pub media: serde_json::Value,
+}
+
+#[async_trait::async_trait]
+pub trait Backend: Send + Sync + 'static {
+    /// Publish an event to the subscribers of all instances, including this one.
+    async fn publish(&self, event: Event) -> anyhow::Result<()>;
+
+    /// The events published by all instances. This is called once, when the WebSockets are
+    /// created.
+    async fn events(&self) -> BoxStream<'static, Event>;
+
+    /// Cache a raw measurement as the newest (in terms of arrival time) of its kit, peripheral and
+    /// quantity type.
+    async fn cache_raw_measurement(&self, raw_measurement: &RawMeasurement) -> anyhow::Result<()>;
+
+    /// The cached raw measurements of a kit received within the [CACHE_RETENTION_PERIOD].
+    async fn cached_raw_measurements(
+        &self,
+        kit_serial: &str,
+    ) -> anyhow::Result<Vec<RawMeasurement>>;
+}
+
+type RawMeasurementCache = HashMap<String, HashMap<(i32, i32), (Instant, RawMeasurement)>>;
+
+/// The in-memory backend, for running a single API instance.
+pub struct Memory {
+    tx: mpsc::Sender<Event>,
+    rx: Mutex<Option<mpsc::Receiver<Event>>>,
+    /// Holds the newest raw measurement for each kit serial and (peripheral, quantity type) tuple.
+    raw_measurement_cache: Arc<Mutex<RawMeasurementCache>>,
+}
+
+impl Memory {
+    /// Must be called from within a Tokio runtime.
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::channel(1024);
+        let raw_measurement_cache: Arc<Mutex<RawMeasurementCache>> = Default::default();
+
+        // Spawn a task to flush the raw measurement cache every so often.
+        tokio::spawn(keep_raw_measurement_cache_clean(
+            raw_measurement_cache.clone(),
+        ));
+
+        Self {
+            tx,
+            rx: Mutex::new(Some(rx)),
+            raw_measurement_cache,
+        }
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+async fn keep_raw_measurement_cache_clean(raw_measurement_cache: Arc<Mutex<RawMeasurementCache>>) {
+    const CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let now = Instant::now();
+        let mut raw_measurement_cache = raw_measurement_cache.lock().unwrap();
+        raw_measurement_cache.retain(|_, for_kit| {
+            for_kit.retain(|_, (added, _)| now.duration_since(*added) < CACHE_RETENTION_PERIOD);
+            !for_kit.is_empty()
+        });
+    }
+}
+
+#[async_trait::async_trait]
+impl Backend for Memory {
+    async fn publish(&self, event: Event) -> anyhow::Result<()> {
+        self.tx.clone().send(event).await?;
+        Ok(())
+    }
+
+    async fn events(&self) -> BoxStream<'static, Event> {
+        match self.rx.lock().unwrap().take() {
+            Some(rx) => Box::pin(rx),
+            None => Box::pin(futures::stream::empty()),
+        }
+    }
+
+    async fn cache_raw_measurement(&self, raw_measurement: &RawMeasurement) -> anyhow::Result<()> {
+        self.raw_measurement_cache
+            .lock()
+            .unwrap()
+            .entry(raw_measurement.kit_serial.clone())
+            .or_default()
+            .insert(
+                (raw_measurement.peripheral, raw_measurement.quantity_type),
+                (Instant::now(), raw_measurement.clone()),
+            );
+        Ok(())
+    }
+
+    async fn cached_raw_measurements(
+        &self,
+        kit_serial: &str,
+    ) -> anyhow::Result<Vec<RawMeasurement>> {
+        let now = Instant::now();
+        Ok(self
+            .raw_measurement_cache
+            .lock()
+            .unwrap()
+            .get(kit_serial)
+            .map(|measurements| {
+                measurements
+                    .values()
+                    .filter(|(added, _)| now.duration_since(*added) < CACHE_RETENTION_PERIOD)
+                    .map(|(_, measurement)| measurement.clone())
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+}
@@ -0,0 +1,129 @@
+use futures::stream::BoxStream;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use tokio::sync::broadcast;
+
+/// The number of items a lagging subscriber may fall behind before it misses items.
+const CAPACITY: usize = 8;
+
+/// Broadcasts of items per kit serial. A kit's broadcast exists only while it has subscribers.
+pub(crate) struct KitBroadcasts<T> {
+    senders: RwLock<HashMap<String, broadcast::Sender<T>>>,
+}
+
+impl<T> Default for KitBroadcasts<T> {
+    fn default() -> Self {
+        Self {
+            senders: Default::default(),
+        }
+    }
+}
+
+impl<T: Clone> KitBroadcasts<T> {
+    /// Subscribe to the items of a kit. Call [KitBroadcasts::unsubscribed] after dropping the
+    /// receiver.
+    pub(crate) fn subscribe(&self, kit_serial: &str) -> broadcast::Receiver<T> {
+        if let Some(sender) = self.senders.read().unwrap().get(kit_serial) {
+            return sender.subscribe();
+        }
+
+        // The broadcast may have been created since the read lock was released.
+        self.senders
+            .write()
+            .unwrap()
+            .entry(kit_serial.to_owned())
+            .or_insert_with(|| broadcast::channel(CAPACITY).0)
+            .subscribe()
+    }
+
+    /// Remove the kit's broadcast if its last receiver was dropped.
+    pub(crate) fn unsubscribed(&self, kit_serial: &str) {
+        let mut senders = self.senders.write().unwrap();
+        let receivers = senders
+            .get(kit_serial)
+            .map(|sender| sender.receiver_count())
+            .unwrap_or(0);
+        tracing::trace!(
+            "subscription for {} was dropped -- there are {} subscribers left",
+            kit_serial,
+            receivers
+        );
+
+        if receivers == 0 && senders.remove(kit_serial).is_some() {
+            tracing::debug!("broadcast for {} was dropped", kit_serial);
+        }
+    }
+
+    /// Send an item to the kit's subscribers, if any.
+    pub(crate) fn send(&self, kit_serial: &str, item: T) {
+        if let Some(sender) = self.senders.read().unwrap().get(kit_serial) {
+            // Returns an error if all receivers are dropped, the last-dropped receiver will handle
+            // deregistering the sender. If we were to deregister here, a memory leak could occur
+            // if a kit never sends items after the last receiver is dropped.
+            let _ = sender.send(item);
+        }
+    }
+}
+
+#[cfg(test)]
+impl<T> KitBroadcasts<T> {
+    /// Whether the kit currently has a broadcast.
+    pub(crate) fn contains(&self, kit_serial: &str) -> bool {
+        self.senders.read().unwrap().contains_key(kit_serial)
+    }
+}
+
+/// The items received by a subscriber. Items a lagging subscriber missed are skipped.
+pub(crate) fn receive<T: Clone + Send + 'static>(
+    receiver: broadcast::Receiver<T>,
+) -> BoxStream<'static, T> {
+    Box::pin(futures::stream::unfold(
+        receiver,
+        |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(item) => return Some((item, receiver)),
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        },
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn drops_the_broadcast_with_its_last_receiver() {
+        let broadcasts = KitBroadcasts::<u32>::default();
+        let first = broadcasts.subscribe("k-1");
+        let second = broadcasts.subscribe("k-1");
+        let other = broadcasts.subscribe("k-2");
+
+        drop(first);
+        broadcasts.unsubscribed("k-1");
+        assert!(broadcasts.contains("k-1"));
+
+        let mut second = receive(second);
+        broadcasts.send("k-1", 1);
+        assert_eq!(second.next().await, Some(1));
+
+        // Dropped as soon as the last receiver is, without waiting for the kit to send an item.
+        drop(second);
+        broadcasts.unsubscribed("k-1");
+        assert!(!broadcasts.contains("k-1"));
+        assert!(broadcasts.contains("k-2"));
+
+        // Items sent without subscribers are dropped, and do not recreate the broadcast.
+        broadcasts.send("k-1", 2);
+        assert!(!broadcasts.contains("k-1"));
+
+        let mut third = receive(broadcasts.subscribe("k-1"));
+        broadcasts.send("k-1", 3);
+        assert_eq!(third.next().await, Some(3));
+        drop(other);
+    }
+}
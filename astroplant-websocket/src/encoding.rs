@@ -0,0 +1,115 @@
+//! The encodings of a connection's JSON-RPC messages, negotiated as WebSocket subprotocols.
+//!
+//! The RPC module produces JSON; with a binary encoding, messages are transcoded when they are
+//! sent and received. Text messages are always JSON, such that clients may send requests as JSON
+//! regardless of the encoding.
+
+use axum::extract::ws::Message as WsMessage;
+
+/// The subprotocol of the JSON encoding.
+pub const JSON_PROTOCOL: &str = "astroplant.json";
+/// The subprotocol of the CBOR encoding.
+pub const CBOR_PROTOCOL: &str = "astroplant.cbor";
+
+/// The subprotocols, in decreasing order of preference.
+pub const PROTOCOLS: [&str; 2] = [CBOR_PROTOCOL, JSON_PROTOCOL];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    /// JSON text messages.
+    Json,
+    /// [CBOR](https://cbor.io) binary messages.
+    Cbor,
+}
+
+impl Encoding {
+    /// The encoding of the negotiated subprotocol. Connections that negotiated no subprotocol use
+    /// JSON.
+    pub fn from_protocol(protocol: Option<&str>) -> Self {
+        match protocol {
+            Some(CBOR_PROTOCOL) => Encoding::Cbor,
+            _ => Encoding::Json,
+        }
+    }
+
+    /// Encode a JSON message produced by the RPC module.
+    pub(crate) fn encode(self, message: String) -> anyhow::Result<WsMessage> {
+        match self {
+            Encoding::Json => Ok(WsMessage::Text(message)),
+            Encoding::Cbor => {
+                let value: serde_json::Value = serde_json::from_str(&message)?;
+                let mut buf = Vec::with_capacity(message.len());
+                ciborium::ser::into_writer(&value, &mut buf)?;
+                Ok(WsMessage::Binary(buf))
+            }
+        }
+    }
+
+    /// Decode a received message into JSON. Returns `None` for messages that carry no JSON-RPC
+    /// message, such as pings, and for binary messages that fail to decode.
+    pub(crate) fn decode(self, message: WsMessage) -> Option<String> {
+        match (self, message) {
+            (_, WsMessage::Text(message)) => Some(message),
+            (Encoding::Cbor, WsMessage::Binary(message)) => {
+                ciborium::de::from_reader::<serde_json::Value, _>(&message[..])
+                    .ok()
+                    .map(|value| value.to_string())
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cbor_round_trip() {
+        let notification = serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "subscribe_raw_measurements",
+            "params": {
+                "subscription": 4242,
+                "result": {
+                    "id": "0f8fad5b-d9cb-469f-a165-70867728950e",
+                    "kitSerial": "k-1",
+                    "datetime": 1_650_000_000_000u64,
+                    "peripheral": 1,
+                    "quantityType": 2,
+                    "value": 21.5,
+                },
+            },
+        });
+
+        let encoded = Encoding::Cbor.encode(notification.to_string()).unwrap();
+        let decoded = match encoded {
+            WsMessage::Binary(ref buf) => {
+                assert!(buf.len() < notification.to_string().len());
+                Encoding::Cbor.decode(encoded).unwrap()
+            }
+            _ => panic!("expected a binary message"),
+        };
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&decoded).unwrap(),
+            notification
+        );
+    }
+
+    #[test]
+    fn json_fallback() {
+        assert_eq!(Encoding::from_protocol(None), Encoding::Json);
+        assert_eq!(Encoding::from_protocol(Some("graphql-ws")), Encoding::Json);
+        assert_eq!(Encoding::from_protocol(Some(CBOR_PROTOCOL)), Encoding::Cbor);
+
+        assert!(matches!(
+            Encoding::Json.encode("{}".to_owned()).unwrap(),
+            WsMessage::Text(_)
+        ));
+        assert_eq!(Encoding::Json.decode(WsMessage::Binary(vec![0xa0])), None);
+        assert_eq!(
+            Encoding::Cbor.decode(WsMessage::Text("{}".to_owned())),
+            Some("{}".to_owned())
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use astroplant_mqtt::{AggregateMeasurement, RawMeasurement};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Restricts the measurements sent on a subscription. All fields are optional.
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct SubscriptionFilter {
+    /// Only send measurements of this peripheral.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    peripheral: Option<i32>,
+    /// Only send measurements of this quantity type.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quantity_type: Option<i32>,
+    /// Send at most one measurement per this many seconds for each peripheral and quantity type.
+    /// Measurements arriving sooner are skipped.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min_interval: Option<u64>,
+}
+
+/// A measurement of a peripheral and quantity type.
+pub(crate) trait Series {
+    fn series(&self) -> (i32, i32);
+}
+
+impl Series for RawMeasurement {
+    fn series(&self) -> (i32, i32) {
+        (self.peripheral, self.quantity_type)
+    }
+}
+
+impl Series for AggregateMeasurement {
+    fn series(&self) -> (i32, i32) {
+        (self.peripheral, self.quantity_type)
+    }
+}
+
+/// Applies a [SubscriptionFilter] to the measurements of a subscription.
+pub(crate) struct Filter {
+    filter: SubscriptionFilter,
+    last_sent: HashMap<(i32, i32), Instant>,
+}
+
+impl Filter {
+    pub(crate) fn new(filter: SubscriptionFilter) -> Self {
+        Self {
+            filter,
+            last_sent: HashMap::new(),
+        }
+    }
+
+    /// Whether the measurement should be sent at `now`. If so, it is counted towards the rate
+    /// limit.
+    pub(crate) fn keep(&mut self, measurement: &impl Series, now: Instant) -> bool {
+        let (peripheral, quantity_type) = measurement.series();
+        if self.filter.peripheral.is_some_and(|p| p != peripheral)
+            || self
+                .filter
+                .quantity_type
+                .is_some_and(|q| q != quantity_type)
+        {
+            return false;
+        }
+
+        if let Some(min_interval) = self.filter.min_interval {
+            let min_interval = Duration::from_secs(min_interval);
+            match self.last_sent.get_mut(&(peripheral, quantity_type)) {
+                Some(last_sent) if now.duration_since(*last_sent) < min_interval => return false,
+                Some(last_sent) => *last_sent = now,
+                None => {
+                    self.last_sent.insert((peripheral, quantity_type), now);
+                }
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn filter() {
+        let mut filter = Filter::new(SubscriptionFilter {
+            peripheral: Some(1),
+            quantity_type: None,
+            min_interval: Some(10),
+        });
+        let start = Instant::now();
+
+        assert!(filter.keep(&(1, 1), start));
+        assert!(!filter.keep(&(2, 1), start));
+        // Rate limits apply per peripheral and quantity type.
+        assert!(filter.keep(&(1, 2), start));
+        assert!(!filter.keep(&(1, 1), start + Duration::from_secs(9)));
+        assert!(filter.keep(&(1, 1), start + Duration::from_secs(10)));
+        assert!(!filter.keep(&(1, 1), start + Duration::from_secs(19)));
+    }
+
+    impl Series for (i32, i32) {
+        fn series(&self) -> (i32, i32) {
+            *self
+        }
+    }
+}
@@ -0,0 +1,218 @@
+//! Kit RPC over the WebSocket. Peripheral command locks are held on behalf of a connection, and
+//! released when the connection is closed.
+
+use astroplant_mqtt::{
+    KitRpcResponseError, KitsRpc, PeripheralCommandLockRequest, PeripheralCommandResponse,
+};
+use jsonrpsee::types::error::{CallError, ErrorObject};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::error_code;
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum LockRequest {
+    /// Whether the peripheral's command lock is held.
+    Status,
+    Acquire,
+    Release,
+}
+
+/// A peripheral command's response. The data is base64-encoded.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PeripheralCommandOutput {
+    media_type: String,
+    data: String,
+    metadata: serde_json::Value,
+}
+
+impl From<PeripheralCommandResponse> for PeripheralCommandOutput {
+    fn from(response: PeripheralCommandResponse) -> Self {
+        Self {
+            media_type: response.media_type,
+            data: base64::encode(&response.data),
+            metadata: response.metadata,
+        }
+    }
+}
+
+pub(crate) fn peripheral_command_locked_error() -> jsonrpsee::core::Error {
+    CallError::Custom(ErrorObject::owned(
+        error_code::PERIPHERAL_COMMAND_LOCKED,
+        "another connection holds the peripheral's command lock",
+        None::<()>,
+    ))
+    .into()
+}
+
+pub(crate) fn kit_rpc_error(error: KitRpcResponseError) -> jsonrpsee::core::Error {
+    let error = match error {
+        KitRpcResponseError::Disconnected { .. } => ErrorObject::owned(
+            error_code::KITS_UNREACHABLE,
+            "the kits are unreachable",
+            None::<()>,
+        ),
+        error => ErrorObject::owned(error_code::KIT_RPC, error.to_string(), None::<()>),
+    };
+    CallError::Custom(error).into()
+}
+
+/// The peripheral command locks held by connections of this instance, by kit serial and
+/// peripheral. A lock is held by at most one connection.
+#[derive(Default)]
+pub(crate) struct PeripheralCommandLocks {
+    holders: Mutex<HashMap<(String, String), usize>>,
+}
+
+impl PeripheralCommandLocks {
+    /// Claim the lock for the connection. Returns false if another connection holds it.
+    fn claim(&self, kit_serial: &str, peripheral: &str, connection_id: usize) -> bool {
+        let mut holders = self.holders.lock().unwrap();
+        let holder = holders
+            .entry((kit_serial.to_owned(), peripheral.to_owned()))
+            .or_insert(connection_id);
+        *holder == connection_id
+    }
+
+    fn holds(&self, kit_serial: &str, peripheral: &str, connection_id: usize) -> bool {
+        self.holders
+            .lock()
+            .unwrap()
+            .get(&(kit_serial.to_owned(), peripheral.to_owned()))
+            == Some(&connection_id)
+    }
+
+    /// Whether a connection other than the given one holds the lock.
+    pub(crate) fn held_by_other(
+        &self,
+        kit_serial: &str,
+        peripheral: &str,
+        connection_id: usize,
+    ) -> bool {
+        matches!(
+            self.holders
+                .lock()
+                .unwrap()
+                .get(&(kit_serial.to_owned(), peripheral.to_owned())),
+            Some(holder) if *holder != connection_id
+        )
+    }
+
+    fn unclaim(&self, kit_serial: &str, peripheral: &str, connection_id: usize) {
+        let mut holders = self.holders.lock().unwrap();
+        let key = (kit_serial.to_owned(), peripheral.to_owned());
+        if holders.get(&key) == Some(&connection_id) {
+            holders.remove(&key);
+        }
+    }
+
+    /// Handle a connection's lock request.
+    pub(crate) async fn request(
+        &self,
+        kits_rpc: &KitsRpc,
+        kit_serial: &str,
+        peripheral: String,
+        request: LockRequest,
+        connection_id: usize,
+    ) -> Result<bool, KitRpcResponseError> {
+        match request {
+            LockRequest::Status => {
+                kits_rpc
+                    .peripheral_command_lock(
+                        kit_serial,
+                        peripheral,
+                        PeripheralCommandLockRequest::Status,
+                    )
+                    .await
+            }
+            LockRequest::Acquire => {
+                if !self.claim(kit_serial, &peripheral, connection_id) {
+                    return Ok(false);
+                }
+                let acquired = kits_rpc
+                    .peripheral_command_lock(
+                        kit_serial,
+                        peripheral.clone(),
+                        PeripheralCommandLockRequest::Acquire,
+                    )
+                    .await;
+                if !matches!(acquired, Ok(true)) {
+                    self.unclaim(kit_serial, &peripheral, connection_id);
+                }
+                acquired
+            }
+            LockRequest::Release => {
+                if !self.holds(kit_serial, &peripheral, connection_id) {
+                    return Ok(false);
+                }
+                let released = kits_rpc
+                    .peripheral_command_lock(
+                        kit_serial,
+                        peripheral.clone(),
+                        PeripheralCommandLockRequest::Release,
+                    )
+                    .await?;
+                self.unclaim(kit_serial, &peripheral, connection_id);
+                Ok(released)
+            }
+        }
+    }
+
+    /// Release the locks held by a closed connection.
+    pub(crate) async fn release_all(&self, kits_rpc: &KitsRpc, connection_id: usize) {
+        let held: Vec<(String, String)> = {
+            let mut holders = self.holders.lock().unwrap();
+            let held = holders
+                .iter()
+                .filter(|(_, holder)| **holder == connection_id)
+                .map(|(key, _)| key.clone())
+                .collect();
+            holders.retain(|_, holder| *holder != connection_id);
+            held
+        };
+
+        for (kit_serial, peripheral) in held {
+            if let Err(err) = kits_rpc
+                .peripheral_command_lock(
+                    &kit_serial,
+                    peripheral.clone(),
+                    PeripheralCommandLockRequest::Release,
+                )
+                .await
+            {
+                tracing::warn!(
+                    "failed to release the command lock of peripheral {} of {}: {:?}",
+                    peripheral,
+                    kit_serial,
+                    err
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn claims() {
+        let locks = PeripheralCommandLocks::default();
+
+        assert!(locks.claim("k-1", "led", 1));
+        assert!(locks.claim("k-1", "led", 1));
+        assert!(!locks.claim("k-1", "led", 2));
+        assert!(locks.claim("k-1", "fan", 2));
+        assert!(!locks.held_by_other("k-1", "led", 1));
+        assert!(locks.held_by_other("k-1", "led", 2));
+        assert!(!locks.held_by_other("k-1", "pump", 2));
+
+        locks.unclaim("k-1", "led", 2);
+        assert!(locks.holds("k-1", "led", 1));
+        locks.unclaim("k-1", "led", 1);
+        assert!(locks.claim("k-1", "led", 2));
+    }
+}
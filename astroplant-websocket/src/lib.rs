@@ -1,280 +1,278 @@
-use astroplant_mqtt::RawMeasurement;
+use astroplant_mqtt::{AggregateMeasurement, KitsRpc, PreviewFrame, RawMeasurement};
 use axum::extract::ws::{Message as WsMessage, WebSocket};
 use futures::sink::SinkExt;
 use futures::stream::StreamExt;
 use futures_channel::mpsc;
 use jsonrpsee::core::server::helpers::{BoundedSubscriptions, MethodSink};
 use jsonrpsee::core::server::rpc_module::{ConnState, MethodKind};
-use jsonrpsee::types::error::ErrorCode;
-use jsonrpsee::types::params::Params;
+use jsonrpsee::core::traits::IdProvider;
+use jsonrpsee::types::error::{ErrorCode, ErrorObject};
+use jsonrpsee::types::params::{Params, SubscriptionId};
 use jsonrpsee::types::request::Request;
 use jsonrpsee::ws_server::RandomIntegerIdProvider;
 use jsonrpsee::RpcModule;
-use std::collections::HashMap;
-use std::sync::Arc;
-use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
-use tokio::sync::{broadcast, oneshot};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::oneshot;
+
+pub mod auth;
+pub mod backend;
+mod broadcast;
+mod encoding;
+mod filter;
+mod kit_rpc;
+mod live;
+mod preview;
+mod rpc_impl;
+use auth::{Auth, Session};
+use backend::{Backend, Event, KitMedia};
+use broadcast::KitBroadcasts;
+pub use encoding::{Encoding, CBOR_PROTOCOL, JSON_PROTOCOL, PROTOCOLS};
+pub use filter::SubscriptionFilter;
+use kit_rpc::PeripheralCommandLocks;
+pub use live::{CloseReason, Live, LiveEvent};
+use preview::Previews;
+pub use preview::{Preview, MAX_PREVIEW_DURATION};
 
 // Note this implementation uses std::sync Mutex and RwLock. These are more performant than Tokio's
 // async counterparts, but should not be held across await points.
 
-mod rpc_impl {
-    use jsonrpsee::core::server::rpc_module::PendingSubscription;
-    use jsonrpsee::proc_macros::rpc;
-    use jsonrpsee::types::error::ErrorObject;
-    use tokio::sync::broadcast;
-    #[rpc(server)]
-    pub trait Rpc {
-        #[subscription(name = "subscribe_raw_measurements", item = String)]
-        fn sub(&self, kit_serial: String);
-    }
+/// The application-defined JSON-RPC error codes.
+pub mod error_code {
+    /// The connection is not authorized to subscribe to the kit, or to perform the kit RPC.
+    pub const UNAUTHORIZED: i32 = 1;
+    /// The access token given to `authenticate` is invalid.
+    pub const INVALID_ACCESS_TOKEN: i32 = 2;
+    /// The access token given to `authenticate` has expired.
+    pub const ACCESS_TOKEN_EXPIRED: i32 = 3;
+    /// The connection has reached its maximum number of subscriptions.
+    pub const TOO_MANY_SUBSCRIPTIONS: i32 = 4;
+    /// The kits cannot be reached for kit RPC.
+    pub const KITS_UNREACHABLE: i32 = 5;
+    /// The kit RPC failed, e.g. because the kit did not respond in time or returned an error.
+    pub const KIT_RPC: i32 = 6;
+    /// Another connection holds the command lock of the peripheral.
+    pub const PERIPHERAL_COMMAND_LOCKED: i32 = 7;
+}
 
-    pub(crate) struct RpcServerImpl<F> {
-        pub(crate) raw_measurement_listeners:
-            std::sync::Arc<std::sync::RwLock<crate::RawMeasurementListeners>>,
-        pub(crate) raw_measurement_cache:
-            std::sync::Arc<std::sync::Mutex<crate::RawMeasurementCache>>,
-        pub(crate) auth_check: F,
-    }
+/// The methods that are handled in order with the connection's other messages, as they change the
+/// connection's state. Other methods may take a while, e.g. kit RPC, and are handled concurrently.
+const IN_ORDER_METHODS: &[&str] = &["authenticate"];
 
-    #[async_trait::async_trait]
-    impl<F, Fut> RpcServer for RpcServerImpl<F>
-    where
-        F: Fn(String) -> Fut + Send + Sync + 'static,
-        Fut: std::future::Future<Output = bool> + Send + 'static,
-    {
-        fn sub(&self, pending: PendingSubscription, kit_serial: String) {
-            let auth_check_fut = (self.auth_check)(kit_serial.clone());
-            let raw_measurement_listeners = self.raw_measurement_listeners.clone();
-            let raw_measurement_cache = self.raw_measurement_cache.clone();
-
-            tokio::spawn(async move {
-                if !auth_check_fut.await {
-                    pending.reject(ErrorObject::borrowed(
-                        1,
-                        &"you are not authorized to subscribe to this kit",
-                        None,
-                    ));
-                    return;
-                }
+/// The interval of the WebSocket heartbeat, see [send_all].
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(4 * 60);
 
-                let mut sink = pending.accept().unwrap();
+/// The default maximum number of subscriptions per connection.
+pub const DEFAULT_MAX_SUBSCRIPTIONS: u32 = 8;
 
-                // Dump all cached measurements on the sink.
-                match raw_measurement_cache.lock().unwrap().get(&kit_serial) {
-                    Some(measurements) => measurements.values().for_each(|(_, measurement)| {
-                        let _ = sink.send(measurement);
-                    }),
-                    None => {}
-                }
+/// Create the WebSocket publisher and handler, distributing events through the given backend.
+/// Must be called from within a Tokio runtime.
+pub fn create(backend: impl Backend) -> (Publisher, SocketHandler) {
+    let backend: Arc<dyn Backend> = Arc::new(backend);
+    let previews: Arc<Previews> = Default::default();
+    let shared = Arc::new(Shared {
+        backend: backend.clone(),
+        raw_measurements: Default::default(),
+        aggregate_measurements: Default::default(),
+        media: Default::default(),
+        peripheral_command_locks: Default::default(),
+        previews: previews.clone(),
+    });
 
-                let receiver = raw_measurement_listeners
-                    .read()
-                    .unwrap()
-                    .get(&kit_serial)
-                    .map(|sender| sender.subscribe());
-
-                let mut receiver = match receiver {
-                    Some(receiver) => receiver,
-                    None => {
-                        let (tx, rx) = broadcast::channel(8);
-                        raw_measurement_listeners
-                            .write()
-                            .unwrap()
-                            .insert(kit_serial.clone(), tx);
-                        rx
-                    }
-                };
-
-                // We periodically check if the subscription was closed. We are not notified
-                // automatically of subscription closure, and may not notice if the kit is not
-                // sending measurements.
-                let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
-
-                loop {
-                    tokio::select! {
-                        Ok(raw_measurement) = receiver.recv() => {
-                            if let Err(_) = sink.send(&raw_measurement) {
-                                // Channel closed
-                                break;
-                            }
-                            interval.reset();
-                        }
-                        _ = interval.tick() => {
-                            if sink.is_closed() {
-                                break;
-                            }
-                        }
-                    }
-                }
-
-                // The subscription was closed. Check if we are the last receiver of measurements
-                // for this kit.
-                drop(receiver);
-                let cnt = raw_measurement_listeners
-                    .read()
-                    .unwrap()
-                    .get(&kit_serial)
-                    .expect("invariant")
-                    .receiver_count();
-                tracing::trace!(
-                    "raw measurement subscription for {} was dropped -- there are {} subscribers left",
-                    kit_serial,
-                    cnt
-                );
-                if cnt == 0 {
-                    raw_measurement_listeners
-                        .write()
-                        .unwrap()
-                        .remove(&kit_serial);
-                    tracing::debug!("raw measurement broadcast for {} was dropped", kit_serial);
-                }
-            });
-        }
-    }
-}
-
-pub fn create() -> (Publisher, SocketHandler) {
-    let publisher = Publisher {
-        raw_measurement_listeners: Default::default(),
-        raw_measurement_cache: Default::default(),
-    };
+    let publisher = Publisher { backend, previews };
 
     let socket_handler = SocketHandler {
-        id_provider: RandomIntegerIdProvider,
-        raw_measurement_listeners: publisher.raw_measurement_listeners.clone(),
-        raw_measurement_cache: publisher.raw_measurement_cache.clone(),
+        max_subscriptions: DEFAULT_MAX_SUBSCRIPTIONS,
+        kits_rpc: None,
+        shared: shared.clone(),
         next_connection_id: Default::default(),
     };
 
-    let raw_measurement_cache = publisher.raw_measurement_cache.clone();
-
-    // Spawn a task to flush the raw measurement cache every so often.
-    tokio::spawn(keep_raw_measurement_cache_clean(raw_measurement_cache));
+    // Spawn a task delivering the events published by all instances to this instance's
+    // subscribers.
+    tokio::spawn(async move {
+        let mut events = shared.backend.events().await;
+        while let Some(event) = events.next().await {
+            shared.deliver(event);
+        }
+        tracing::warn!("the WebSocket event stream ended");
+    });
 
     (publisher, socket_handler)
 }
 
-async fn keep_raw_measurement_cache_clean(
-    raw_measurement_cache: Arc<std::sync::Mutex<RawMeasurementCache>>,
-) {
-    const CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
-    const RETENTION_PERIOD: Duration = Duration::from_secs(30 * 60);
+/// The state shared by the WebSocket subscriptions. Items are broadcast per kit serial.
+struct Shared {
+    backend: Arc<dyn Backend>,
+    raw_measurements: KitBroadcasts<RawMeasurement>,
+    aggregate_measurements: KitBroadcasts<AggregateMeasurement>,
+    /// Media are broadcast in the JSON representation the API serves them in.
+    media: KitBroadcasts<serde_json::Value>,
+    peripheral_command_locks: PeripheralCommandLocks,
+    previews: Arc<Previews>,
+}
 
-    let mut interval = tokio::time::interval(CHECK_INTERVAL);
-    loop {
-        interval.tick().await;
-        let now = Instant::now();
-        let mut raw_measurement_cache = raw_measurement_cache.lock().unwrap();
-        raw_measurement_cache.retain(|_, for_kit| {
-            for_kit.retain(|_, (added, _)| now.duration_since(*added) < RETENTION_PERIOD);
-            for_kit.len() > 0
-        });
+impl Shared {
+    fn deliver(&self, event: Event) {
+        match event {
+            Event::RawMeasurement(raw_measurement) => self
+                .raw_measurements
+                .send(&raw_measurement.kit_serial.clone(), raw_measurement),
+            Event::AggregateMeasurement(aggregate_measurement) => self.aggregate_measurements.send(
+                &aggregate_measurement.kit_serial.clone(),
+                aggregate_measurement,
+            ),
+            Event::Media(KitMedia { kit_serial, media }) => self.media.send(&kit_serial, media),
+        }
     }
 }
 
-// Raw measurements are broadcast per kit serial.
-type RawMeasurementListeners = HashMap<String, broadcast::Sender<RawMeasurement>>;
-type RawMeasurementCache = HashMap<String, HashMap<(i32, i32), (Instant, RawMeasurement)>>;
-
 #[derive(Clone)]
 pub struct Publisher {
-    /// Holds a raw measurement broadcast handle for each kit serial.
-    raw_measurement_listeners: Arc<std::sync::RwLock<RawMeasurementListeners>>,
-    /// Holds the newest raw measurement (in terms of arrival time) for each kit serial and
-    /// (peripheral, quantity type) tuple.
-    raw_measurement_cache: Arc<std::sync::Mutex<RawMeasurementCache>>,
+    backend: Arc<dyn Backend>,
+    previews: Arc<Previews>,
 }
 
 impl Publisher {
     pub async fn publish_raw_measurement(&self, raw_measurement: RawMeasurement) {
-        self.raw_measurement_cache
+        if let Err(err) = self.backend.cache_raw_measurement(&raw_measurement).await {
+            tracing::warn!("failed to cache raw measurement: {:?}", err);
+        }
+        self.publish(Event::RawMeasurement(raw_measurement)).await;
+    }
+
+    pub async fn publish_aggregate_measurement(&self, aggregate_measurement: AggregateMeasurement) {
+        self.publish(Event::AggregateMeasurement(aggregate_measurement))
+            .await;
+    }
+
+    /// Publish stored media of a kit, in the JSON representation the API serves media in.
+    pub async fn publish_media(&self, kit_serial: &str, media: serde_json::Value) {
+        self.publish(Event::Media(KitMedia {
+            kit_serial: kit_serial.to_owned(),
+            media,
+        }))
+        .await;
+    }
+
+    /// Deliver a preview frame to the viewers of its session on this instance.
+    pub fn publish_preview_frame(&self, preview_frame: PreviewFrame) {
+        self.previews.deliver(preview_frame);
+    }
+
+    async fn publish(&self, event: Event) {
+        if let Err(err) = self.backend.publish(event).await {
+            tracing::warn!("failed to publish WebSocket event: {:?}", err);
+        }
+    }
+}
+
+/// Generates the subscription IDs of a connection, remembering the last one such that the
+/// subscription it was generated for can learn its ID. A connection's subscriptions are made one at
+/// a time.
+#[derive(Debug, Default)]
+pub(crate) struct SubscriptionIds {
+    last: Mutex<Option<SubscriptionId<'static>>>,
+}
+
+impl SubscriptionIds {
+    /// The ID of the subscription currently being made.
+    pub(crate) fn last(&self) -> SubscriptionId<'static> {
+        self.last
             .lock()
             .unwrap()
-            .entry(raw_measurement.kit_serial.clone())
-            .or_default()
-            .insert(
-                (raw_measurement.peripheral, raw_measurement.quantity_type),
-                (Instant::now(), raw_measurement.clone()),
-            );
+            .clone()
+            .expect("subscription IDs are generated before subscribing")
+    }
+}
 
-        let listeners = self.raw_measurement_listeners.read().unwrap();
-        if let Some(sender) = listeners.get(&raw_measurement.kit_serial) {
-            // Returns an error if all receivers are dropped, the last-dropped receiver will handle
-            // deregistering the sender. If we were to deregister here, a memory leak could occur
-            // if a kit never sends measurements after the last receiver is dropped.
-            let _ = sender.send(raw_measurement);
-        }
+impl IdProvider for SubscriptionIds {
+    fn next_id(&self) -> SubscriptionId<'static> {
+        let id = RandomIntegerIdProvider.next_id();
+        *self.last.lock().unwrap() = Some(id.clone());
+        id
     }
 }
 
-struct SocketState<'a, F> {
+struct SocketState<A: Auth> {
     connection_id: usize,
-    id_provider: &'a RandomIntegerIdProvider,
+    subscription_ids: Arc<SubscriptionIds>,
     bounded_subscriptions: BoundedSubscriptions,
-    rpc_module: RpcModule<rpc_impl::RpcServerImpl<F>>,
+    rpc_module: RpcModule<rpc_impl::RpcServerImpl<A>>,
     method_sink: MethodSink,
 }
 
 #[derive(Clone)]
 pub struct SocketHandler {
-    id_provider: RandomIntegerIdProvider,
-    raw_measurement_listeners: Arc<std::sync::RwLock<RawMeasurementListeners>>,
-    raw_measurement_cache: Arc<std::sync::Mutex<RawMeasurementCache>>,
+    max_subscriptions: u32,
+    kits_rpc: Option<KitsRpc>,
+    shared: Arc<Shared>,
     next_connection_id: Arc<std::sync::atomic::AtomicUsize>,
 }
 
 impl SocketHandler {
-    /// Hands off a websocket to the socket handler, including a closure that can be called to
-    /// check whether the websocket is allowed to subscribe to raw measurements of a specific kit.
-    pub async fn handle<F, Fut>(&self, socket: WebSocket, auth_check: F)
-    where
-        F: Fn(String) -> Fut + Send + Sync + 'static,
-        Fut: std::future::Future<Output = bool> + Send + 'static,
-    {
-        use crate::rpc_impl::RpcServer;
+    /// Set the maximum number of subscriptions per connection. Defaults to
+    /// [DEFAULT_MAX_SUBSCRIPTIONS].
+    pub fn with_max_subscriptions(mut self, max_subscriptions: u32) -> Self {
+        self.max_subscriptions = max_subscriptions;
+        self
+    }
 
-        let connection_id = self
-            .next_connection_id
-            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    /// Enable kit RPC over the WebSockets.
+    pub fn with_kits_rpc(mut self, kits_rpc: KitsRpc) -> Self {
+        self.kits_rpc = Some(kits_rpc);
+        self
+    }
 
-        let server = rpc_impl::RpcServerImpl {
-            raw_measurement_listeners: self.raw_measurement_listeners.clone(),
-            raw_measurement_cache: self.raw_measurement_cache.clone(),
-            auth_check,
-        };
+    /// Hands off a websocket to the socket handler, including the authentication and
+    /// authorization of the websocket's subscriptions. The websocket may already be authenticated,
+    /// e.g. when upgrading.
+    ///
+    /// Messages are encoded according to the websocket's subprotocol, see [PROTOCOLS].
+    pub async fn handle<A: Auth>(
+        &self,
+        socket: WebSocket,
+        auth: A,
+        session: Option<Session<A::User>>,
+    ) {
+        let encoding = Encoding::from_protocol(
+            socket
+                .protocol()
+                .and_then(|protocol| protocol.to_str().ok()),
+        );
 
         let (mut sink, stream) = socket.split();
         let (tx, rx) = mpsc::unbounded();
         let (close_tx, close_rx) = oneshot::channel();
 
+        let state = self.connect(auth, session, tx);
+        let connection_id = state.connection_id;
+
         // Spawn a task proxying RPC responses to the WebSocket sink.
         tokio::spawn(async move {
-            send_all(&mut sink, rx, close_rx).await;
+            send_all(&mut sink, rx, close_rx, encoding).await;
             tracing::debug!(
                 "The sink was closed for WebSocket connection {}",
                 connection_id
             );
         });
 
-        let state = SocketState {
-            connection_id,
-            id_provider: &self.id_provider,
-            bounded_subscriptions: BoundedSubscriptions::new(8),
-            rpc_module: server.into_rpc(),
-            method_sink: MethodSink::new(tx),
-        };
-
         let mut stream = Box::pin(stream);
 
         while let Some(Ok(ws_msg)) = stream.next().await {
-            match ws_msg {
-                WsMessage::Text(msg) => self.handle_ws_message(&state, &msg).await,
-                _ => {}
+            if let Some(msg) = encoding.decode(ws_msg) {
+                self.handle_ws_message(&state, &msg).await;
             }
         }
 
+        // Close the connection's subscriptions, and release its peripheral command locks.
         state.bounded_subscriptions.close();
+        if let Some(kits_rpc) = &self.kits_rpc {
+            self.shared
+                .peripheral_command_locks
+                .release_all(kits_rpc, connection_id)
+                .await;
+        }
         tracing::debug!(
             "We stopped listening to WebSocket connection {}",
             connection_id
@@ -284,11 +282,41 @@ impl SocketHandler {
         let _ = close_tx.send(());
     }
 
-    async fn handle_ws_message<F, Fut>(&self, state: &SocketState<'_, F>, message: &str)
-    where
-        F: Fn(String) -> Fut + Send + Sync + 'static,
-        Fut: std::future::Future<Output = bool> + Send + 'static,
-    {
+    /// Set up the state of a new connection, sending the RPC module's messages to `tx`.
+    fn connect<A: Auth>(
+        &self,
+        auth: A,
+        session: Option<Session<A::User>>,
+        tx: mpsc::UnboundedSender<String>,
+    ) -> SocketState<A> {
+        use crate::rpc_impl::RpcServer;
+
+        let connection_id = self
+            .next_connection_id
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+
+        let subscription_ids = Arc::new(SubscriptionIds::default());
+
+        let server = rpc_impl::RpcServerImpl {
+            shared: self.shared.clone(),
+            auth: Arc::new(auth),
+            session: tokio::sync::watch::channel(session).0,
+            subscription_ids: subscription_ids.clone(),
+            subscriptions: Default::default(),
+            kits_rpc: self.kits_rpc.clone(),
+            connection_id,
+        };
+
+        SocketState {
+            connection_id,
+            subscription_ids,
+            bounded_subscriptions: BoundedSubscriptions::new(self.max_subscriptions),
+            rpc_module: server.into_rpc(),
+            method_sink: MethodSink::new(tx),
+        }
+    }
+
+    async fn handle_ws_message<A: Auth>(&self, state: &SocketState<A>, message: &str) {
         if let Ok(req) = serde_json::from_str::<Request>(message) {
             tracing::event!(
                 tracing::Level::DEBUG,
@@ -306,16 +334,50 @@ impl SocketHandler {
                         .send_error(req.id, ErrorCode::MethodNotFound.into());
                 }
                 Some(method) => match method.inner() {
+                    // Some methods are handled in order, such that e.g. subscriptions made after
+                    // authenticating are authorized as such.
+                    MethodKind::Async(callback) => {
+                        let response = callback(
+                            id.into_owned(),
+                            params.into_owned(),
+                            state.method_sink.clone(),
+                            state.connection_id,
+                            None,
+                        );
+                        if IN_ORDER_METHODS.contains(&req.method.as_ref()) {
+                            response.await;
+                        } else {
+                            tokio::spawn(response);
+                        }
+                    }
                     MethodKind::Subscription(callback) => {
-                        if let Some(cn) = state.bounded_subscriptions.acquire() {
-                            let conn_state = ConnState {
-                                conn_id: state.connection_id,
-                                close_notify: cn,
-                                id_provider: state.id_provider,
-                            };
-                            callback(id, params, state.method_sink.clone(), conn_state);
+                        match state.bounded_subscriptions.acquire() {
+                            Some(cn) => {
+                                let conn_state = ConnState {
+                                    conn_id: state.connection_id,
+                                    close_notify: cn,
+                                    id_provider: &*state.subscription_ids,
+                                };
+                                callback(id, params, state.method_sink.clone(), conn_state);
+                            }
+                            None => {
+                                state.method_sink.send_error(
+                                    id,
+                                    ErrorObject::owned(
+                                        error_code::TOO_MANY_SUBSCRIPTIONS,
+                                        format!(
+                                            "the connection has reached its maximum of {} subscriptions",
+                                            state.bounded_subscriptions.max()
+                                        ),
+                                        None::<()>,
+                                    ),
+                                );
+                            }
                         }
                     }
+                    MethodKind::Unsubscription(callback) => {
+                        callback(id, params, &state.method_sink, state.connection_id);
+                    }
                     _ => {}
                 },
             }
@@ -323,8 +385,8 @@ impl SocketHandler {
     }
 }
 
-/// Sink all messages produced by the RPC module to the websocket. Stop when the websocket is
-/// closed, or when `close_rx` is signalled.
+/// Sink all messages produced by the RPC module to the websocket, in the given encoding. Stop when
+/// the websocket is closed, or when `close_rx` is signalled.
 ///
 /// This adds a heartbeat to the websocket, sending a ping whenever we haven't sent any other
 /// message for a few minutes. We do this as we'd like to keep connections open even when they're
@@ -334,18 +396,24 @@ async fn send_all<S>(
     ws_sink: &mut S,
     mut rpc_rx: mpsc::UnboundedReceiver<String>,
     mut close_rx: oneshot::Receiver<()>,
+    encoding: Encoding,
 ) where
     S: futures::Sink<WsMessage> + Unpin,
 {
-    const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(4 * 60);
-
     let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
     heartbeat.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
 
     loop {
         tokio::select! {
             Some(msg) = rpc_rx.next() => {
-                if ws_sink.send(WsMessage::Text(msg)).await.is_err() {
+                let msg = match encoding.encode(msg) {
+                    Ok(msg) => msg,
+                    Err(err) => {
+                        tracing::warn!("failed to encode WebSocket message: {:?}", err);
+                        continue;
+                    }
+                };
+                if ws_sink.send(msg).await.is_err() {
                     break;
                 }
                 heartbeat.reset();
@@ -372,3 +440,186 @@ async fn send_all<S>(
 
     let _ = ws_sink.close().await;
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::auth::StubAuth;
+    use crate::backend::Memory;
+    use serde_json::{json, Value};
+    use tokio::time::Instant;
+
+    /// An authenticated connection, and the messages it sends.
+    struct Connection {
+        handler: SocketHandler,
+        state: SocketState<StubAuth>,
+        rx: mpsc::UnboundedReceiver<String>,
+        next_id: u64,
+    }
+
+    impl Connection {
+        fn new(handler: SocketHandler) -> Self {
+            let auth = StubAuth::new(chrono::Duration::hours(1));
+            let session = auth.session();
+            let (tx, rx) = mpsc::unbounded();
+            let state = handler.connect(auth, Some(session), tx);
+            Self {
+                handler,
+                state,
+                rx,
+                next_id: 0,
+            }
+        }
+
+        /// Call the method, returning its response.
+        async fn call(&mut self, method: &str, params: Value) -> Value {
+            self.next_id += 1;
+            let request = json!({
+                "jsonrpc": "2.0",
+                "id": self.next_id,
+                "method": method,
+                "params": params,
+            });
+            self.handler
+                .handle_ws_message(&self.state, &request.to_string())
+                .await;
+
+            loop {
+                let message: Value = serde_json::from_str(&self.rx.next().await.unwrap()).unwrap();
+                if message["id"] == self.next_id {
+                    return message;
+                }
+            }
+        }
+
+        async fn subscribe(&mut self, method: &str, kit_serial: &str) -> Value {
+            let response = self.call(method, json!([kit_serial])).await;
+            response["result"].clone()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn lists_and_unsubscribes() {
+        let (_publisher, handler) = create(Memory::new());
+        let mut connection = Connection::new(handler.clone());
+
+        let raw_measurements = connection
+            .subscribe("subscribe_raw_measurements", "k-1")
+            .await;
+        let media = connection.subscribe("subscribe_media", "k-2").await;
+        assert!(handler.shared.raw_measurements.contains("k-1"));
+
+        let subscriptions = connection.call("list_subscriptions", json!([])).await;
+        assert_eq!(
+            subscriptions["result"],
+            json!([
+                {
+                    "subscription": raw_measurements,
+                    "method": "subscribe_raw_measurements",
+                    "kitSerial": "k-1",
+                },
+                {
+                    "subscription": media,
+                    "method": "subscribe_media",
+                    "kitSerial": "k-2",
+                },
+            ])
+        );
+
+        let unsubscribed = connection
+            .call("unsubscribe_raw_measurements", json!([raw_measurements]))
+            .await;
+        assert_eq!(unsubscribed["result"], true);
+
+        // The kit's broadcast is dropped once the subscription's task ends, without waiting for
+        // the kit to send another measurement.
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        assert!(!handler.shared.raw_measurements.contains("k-1"));
+        assert!(handler.shared.media.contains("k-2"));
+
+        let subscriptions = connection.call("list_subscriptions", json!([])).await;
+        assert_eq!(subscriptions["result"][0]["subscription"], media);
+        assert_eq!(subscriptions["result"].as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn limits_subscriptions() {
+        let (_publisher, handler) = create(Memory::new());
+        let mut connection = Connection::new(handler.with_max_subscriptions(2));
+
+        let first = connection.subscribe("subscribe_media", "k-1").await;
+        connection.subscribe("subscribe_media", "k-2").await;
+
+        let response = connection.call("subscribe_media", json!(["k-3"])).await;
+        assert_eq!(
+            response["error"]["code"],
+            error_code::TOO_MANY_SUBSCRIPTIONS
+        );
+
+        // Unsubscribing makes room for another subscription.
+        connection.call("unsubscribe_media", json!([first])).await;
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        let response = connection.call("subscribe_media", json!(["k-3"])).await;
+        assert!(response["result"].is_u64(), "{}", response);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn heartbeat_after_idle() {
+        let (rpc_tx, rpc_rx) = mpsc::unbounded();
+        let (_close_tx, close_rx) = oneshot::channel();
+        let (mut ws_tx, mut ws_rx) = mpsc::unbounded();
+        tokio::spawn(async move { send_all(&mut ws_tx, rpc_rx, close_rx, Encoding::Json).await });
+
+        // A ping is sent on connecting, and after every idle interval.
+        let start = Instant::now();
+        assert!(matches!(ws_rx.next().await, Some(WsMessage::Ping(_))));
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        assert!(matches!(ws_rx.next().await, Some(WsMessage::Ping(_))));
+        assert_eq!(start.elapsed(), HEARTBEAT_INTERVAL);
+
+        // Sending a message postpones the heartbeat.
+        tokio::time::sleep(HEARTBEAT_INTERVAL / 2).await;
+        rpc_tx.unbounded_send("{}".to_owned()).unwrap();
+        assert_eq!(ws_rx.next().await, Some(WsMessage::Text("{}".to_owned())));
+        let sent = Instant::now();
+        assert!(matches!(ws_rx.next().await, Some(WsMessage::Ping(_))));
+        assert_eq!(sent.elapsed(), HEARTBEAT_INTERVAL);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn closes_sink() {
+        let (rpc_tx, rpc_rx) = mpsc::unbounded();
+        let (close_tx, close_rx) = oneshot::channel();
+        let (mut ws_tx, mut ws_rx) = mpsc::unbounded();
+        let send =
+            tokio::spawn(
+                async move { send_all(&mut ws_tx, rpc_rx, close_rx, Encoding::Cbor).await },
+            );
+
+        assert!(matches!(ws_rx.next().await, Some(WsMessage::Ping(_))));
+        rpc_tx.unbounded_send(r#"{"a":1}"#.to_owned()).unwrap();
+        assert_eq!(
+            ws_rx.next().await,
+            Some(WsMessage::Binary(vec![0xa1, 0x61, b'a', 0x01]))
+        );
+
+        close_tx.send(()).unwrap();
+        assert_eq!(ws_rx.next().await, None);
+        send.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stops_when_websocket_is_closed() {
+        let (rpc_tx, rpc_rx) = mpsc::unbounded();
+        let (_close_tx, close_rx) = oneshot::channel();
+        let (mut ws_tx, ws_rx) = mpsc::unbounded();
+        let send =
+            tokio::spawn(
+                async move { send_all(&mut ws_tx, rpc_rx, close_rx, Encoding::Json).await },
+            );
+
+        drop(ws_rx);
+        rpc_tx.unbounded_send("{}".to_owned()).unwrap();
+        send.await.unwrap();
+    }
+}
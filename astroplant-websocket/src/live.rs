@@ -0,0 +1,244 @@
+//! The live events of a kit as a single stream, for transports other than the WebSocket's
+//! JSON-RPC subscriptions, e.g. Server-Sent Events.
+
+use astroplant_mqtt::{AggregateMeasurement, RawMeasurement};
+use chrono::Utc;
+use futures::stream::{BoxStream, Stream, StreamExt};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use crate::auth::{Action, Auth, Authorization, Session};
+use crate::broadcast::receive;
+use crate::rpc_impl::forward;
+use crate::{Shared, SocketHandler};
+
+/// An event of a kit.
+#[derive(Clone, Debug)]
+pub enum LiveEvent {
+    RawMeasurement(RawMeasurement),
+    AggregateMeasurement(AggregateMeasurement),
+    /// Stored media, in the JSON representation the API serves media in.
+    Media(serde_json::Value),
+    /// The last event, sent when the session is no longer authorized to subscribe to the kit.
+    Closed(CloseReason),
+}
+
+/// Why the live events ended.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum CloseReason {
+    /// The session expired. The client may continue with a new session.
+    Expired,
+    /// The permission to subscribe to the kit was revoked.
+    Unauthorized,
+}
+
+/// A kit's live events. Deregisters the kit's broadcasts when dropped, if it was their last
+/// subscriber.
+pub struct Live {
+    // Dropped before `_unsubscribe`, such that the broadcasts' receivers are gone by then.
+    events: BoxStream<'static, LiveEvent>,
+    _unsubscribe: Unsubscribe,
+}
+
+struct Unsubscribe {
+    shared: Arc<Shared>,
+    kit_serial: String,
+}
+
+impl Drop for Unsubscribe {
+    fn drop(&mut self) {
+        self.shared.raw_measurements.unsubscribed(&self.kit_serial);
+        self.shared
+            .aggregate_measurements
+            .unsubscribed(&self.kit_serial);
+        self.shared.media.unsubscribed(&self.kit_serial);
+    }
+}
+
+impl Stream for Live {
+    type Item = LiveEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<LiveEvent>> {
+        self.events.poll_next_unpin(cx)
+    }
+}
+
+impl SocketHandler {
+    /// The live events of a kit, ending with [LiveEvent::Closed] when the session is no longer
+    /// authorized to subscribe to the kit. The caller should check the session is authorized to
+    /// begin with.
+    ///
+    /// The events start with the raw measurements `missed` resolves to, e.g. those a reconnecting
+    /// client missed, or with the cached raw measurements if it resolves to `None`. Live raw
+    /// measurements that were among those are skipped.
+    pub async fn live<A: Auth>(
+        &self,
+        auth: A,
+        session: Option<Session<A::User>>,
+        kit_serial: String,
+        missed: impl Future<Output = Option<Vec<RawMeasurement>>>,
+    ) -> Live {
+        let shared = self.shared.clone();
+        let _unsubscribe = Unsubscribe {
+            shared: shared.clone(),
+            kit_serial: kit_serial.clone(),
+        };
+
+        // Subscribe before resolving the initial raw measurements, such that no events are missed
+        // in between.
+        let events = futures::stream::select_all([
+            receive(shared.raw_measurements.subscribe(&kit_serial))
+                .map(LiveEvent::RawMeasurement)
+                .boxed(),
+            receive(shared.aggregate_measurements.subscribe(&kit_serial))
+                .map(LiveEvent::AggregateMeasurement)
+                .boxed(),
+            receive(shared.media.subscribe(&kit_serial))
+                .map(LiveEvent::Media)
+                .boxed(),
+        ]);
+
+        let initial = match missed.await {
+            Some(missed) => missed,
+            None => shared
+                .backend
+                .cached_raw_measurements(&kit_serial)
+                .await
+                .unwrap_or_else(|err| {
+                    tracing::warn!(
+                        "failed to get cached raw measurements of {}: {:?}",
+                        kit_serial,
+                        err
+                    );
+                    vec![]
+                }),
+        };
+
+        let mut sent: HashSet<uuid::Uuid> =
+            initial.iter().map(|measurement| measurement.id).collect();
+        let not_sent = move |event: &LiveEvent| match event {
+            LiveEvent::RawMeasurement(raw_measurement) => !sent.remove(&raw_measurement.id),
+            _ => true,
+        };
+
+        // The session cannot change, as there is no way to authenticate anew.
+        let expires = session.as_ref().map(|session| session.expires);
+        let authorization = Authorization {
+            auth: Arc::new(auth),
+            session: tokio::sync::watch::channel(session).1,
+            kit_serial,
+            action: Action::Subscribe,
+        };
+        let closed = futures::stream::once(async move {
+            match expires {
+                Some(expires) if expires <= Utc::now() => LiveEvent::Closed(CloseReason::Expired),
+                _ => LiveEvent::Closed(CloseReason::Unauthorized),
+            }
+        });
+
+        Live {
+            events: futures::stream::iter(initial.into_iter().map(LiveEvent::RawMeasurement))
+                .chain(forward(events.boxed(), not_sent, authorization))
+                .chain(closed)
+                .boxed(),
+            _unsubscribe,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::auth::StubAuth;
+    use crate::backend::Memory;
+
+    fn raw_measurement(value: f64) -> RawMeasurement {
+        RawMeasurement {
+            id: uuid::Uuid::new_v4(),
+            kit_serial: "k-1".to_owned(),
+            datetime: Utc::now(),
+            peripheral: 1,
+            quantity_type: 1,
+            value,
+        }
+    }
+
+    fn values(events: &[LiveEvent]) -> Vec<f64> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                LiveEvent::RawMeasurement(raw_measurement) => Some(raw_measurement.value),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn skips_missed_raw_measurements() {
+        let (publisher, handler) = crate::create(Memory::new());
+        let auth = StubAuth::new(chrono::Duration::hours(1));
+        let session = auth.session();
+
+        let missed = raw_measurement(1.0);
+        let mut live = handler
+            .live(auth, Some(session), "k-1".to_owned(), {
+                let missed = missed.clone();
+                async { Some(vec![missed]) }
+            })
+            .await;
+
+        // The missed measurement also arrives live, e.g. as it was stored while the client
+        // reconnected.
+        publisher.publish_raw_measurement(missed).await;
+        publisher
+            .publish_raw_measurement(raw_measurement(2.0))
+            .await;
+
+        let events = vec![live.next().await.unwrap(), live.next().await.unwrap()];
+        assert_eq!(values(&events), vec![1.0, 2.0]);
+    }
+
+    #[tokio::test]
+    async fn closes_when_the_session_expires() {
+        let (_publisher, handler) = crate::create(Memory::new());
+        let auth = StubAuth::new(chrono::Duration::milliseconds(50));
+        let session = auth.session();
+
+        let mut live = handler
+            .live(auth, Some(session), "k-1".to_owned(), async {
+                Some(vec![])
+            })
+            .await;
+
+        assert!(matches!(
+            live.next().await,
+            Some(LiveEvent::Closed(CloseReason::Expired))
+        ));
+        assert!(live.next().await.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn closes_when_unauthorized() {
+        let (_publisher, handler) = crate::create(Memory::new());
+        let auth = StubAuth::new(chrono::Duration::hours(1));
+        let session = auth.session();
+
+        let mut live = handler
+            .live(auth.clone(), Some(session), "k-1".to_owned(), async {
+                Some(vec![])
+            })
+            .await;
+
+        auth.set_authorized(false);
+        assert!(matches!(
+            live.next().await,
+            Some(LiveEvent::Closed(CloseReason::Unauthorized))
+        ));
+        assert!(live.next().await.is_none());
+    }
+}
@@ -0,0 +1,306 @@
+//! Live previews of peripherals, such as cameras. While a preview session has viewers, the kit is
+//! leased to publish the session's frames; the session ends when its last viewer leaves.
+//!
+//! Sessions are per API instance, and frames are delivered to the viewers of this instance only:
+//! they are too large to distribute through the [backend](crate::backend).
+
+use astroplant_mqtt::{KitRpcResponseError, KitsRpc, PreviewFrame};
+use bytes::Bytes;
+use futures::stream::{BoxStream, Stream, StreamExt};
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::watch;
+
+use crate::auth::{Action, Auth, Authorization, Session};
+use crate::rpc_impl::forward;
+use crate::SocketHandler;
+
+/// The duration kits publish a session's frames for, unless the lease is extended. Leases are
+/// extended halfway through while the session has viewers.
+const LEASE: Duration = Duration::from_secs(30);
+
+/// The maximum duration of a viewer's preview. The viewer may start a new preview afterwards.
+pub const MAX_PREVIEW_DURATION: Duration = Duration::from_secs(10 * 60);
+
+struct PreviewSession {
+    id: uuid::Uuid,
+    /// The latest frame.
+    frames: watch::Sender<Option<Bytes>>,
+    viewers: usize,
+}
+
+/// The preview sessions of this instance, by kit serial and peripheral.
+#[derive(Default)]
+pub(crate) struct Previews {
+    sessions: Mutex<HashMap<(String, String), PreviewSession>>,
+}
+
+impl Previews {
+    /// Send a frame to the viewers of its session. Frames of unknown sessions, e.g. those of
+    /// other instances, are ignored.
+    pub(crate) fn deliver(&self, frame: PreviewFrame) {
+        let sessions = self.sessions.lock().unwrap();
+        if let Some(session) = sessions
+            .iter()
+            .find(|((kit_serial, _), session)| {
+                session.id == frame.session && *kit_serial == frame.kit_serial
+            })
+            .map(|(_, session)| session)
+        {
+            let _ = session.frames.send(Some(Bytes::from(frame.data)));
+        }
+    }
+
+    /// Join the peripheral's session, creating it if it does not exist. Returns the session's ID,
+    /// the receiver of its frames, and whether the session was created.
+    fn join(
+        &self,
+        kit_serial: &str,
+        peripheral: &str,
+    ) -> (uuid::Uuid, watch::Receiver<Option<Bytes>>, bool) {
+        let mut sessions = self.sessions.lock().unwrap();
+        let key = (kit_serial.to_owned(), peripheral.to_owned());
+        match sessions.get_mut(&key) {
+            Some(session) => {
+                session.viewers += 1;
+                (session.id, session.frames.subscribe(), false)
+            }
+            None => {
+                let id = uuid::Uuid::new_v4();
+                let (frames, frames_rx) = watch::channel(None);
+                sessions.insert(
+                    key,
+                    PreviewSession {
+                        id,
+                        frames,
+                        viewers: 1,
+                    },
+                );
+                (id, frames_rx, true)
+            }
+        }
+    }
+
+    /// Leave the session. Returns true if this was its last viewer, ending the session.
+    fn leave(&self, kit_serial: &str, peripheral: &str, id: uuid::Uuid) -> bool {
+        let mut sessions = self.sessions.lock().unwrap();
+        let key = (kit_serial.to_owned(), peripheral.to_owned());
+        match sessions.get_mut(&key) {
+            Some(session) if session.id == id => {
+                session.viewers -= 1;
+                if session.viewers == 0 {
+                    sessions.remove(&key);
+                    true
+                } else {
+                    false
+                }
+            }
+            _ => false,
+        }
+    }
+
+    /// End the session, ending the previews of its viewers.
+    fn end(&self, kit_serial: &str, peripheral: &str, id: uuid::Uuid) {
+        let mut sessions = self.sessions.lock().unwrap();
+        let key = (kit_serial.to_owned(), peripheral.to_owned());
+        if matches!(sessions.get(&key), Some(session) if session.id == id) {
+            sessions.remove(&key);
+        }
+    }
+
+    fn is_active(&self, kit_serial: &str, peripheral: &str, id: uuid::Uuid) -> bool {
+        matches!(
+            self.sessions
+                .lock()
+                .unwrap()
+                .get(&(kit_serial.to_owned(), peripheral.to_owned())),
+            Some(session) if session.id == id
+        )
+    }
+}
+
+/// Leaves the session when dropped, and stops the kit's preview if it was the last viewer.
+struct Viewer {
+    previews: Arc<Previews>,
+    kits_rpc: KitsRpc,
+    kit_serial: String,
+    peripheral: String,
+    session: uuid::Uuid,
+}
+
+impl Drop for Viewer {
+    fn drop(&mut self) {
+        if !self
+            .previews
+            .leave(&self.kit_serial, &self.peripheral, self.session)
+        {
+            return;
+        }
+
+        let kits_rpc = self.kits_rpc.clone();
+        let kit_serial = self.kit_serial.clone();
+        let session = self.session;
+        tokio::spawn(async move {
+            if let Err(err) = kits_rpc.stop_preview(&kit_serial, session).await {
+                tracing::warn!(
+                    "failed to stop preview session {} of {}: {:?}",
+                    session,
+                    kit_serial,
+                    err
+                );
+            }
+        });
+    }
+}
+
+async fn extend_lease(
+    previews: Arc<Previews>,
+    kits_rpc: KitsRpc,
+    kit_serial: String,
+    peripheral: String,
+    session: uuid::Uuid,
+) {
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + LEASE / 2, LEASE / 2);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        if !previews.is_active(&kit_serial, &peripheral, session) {
+            break;
+        }
+        if let Err(err) = kits_rpc
+            .start_preview(&kit_serial, session, peripheral.clone(), LEASE)
+            .await
+        {
+            tracing::warn!(
+                "failed to extend the lease of preview session {} of {}: {:?}",
+                session,
+                kit_serial,
+                err
+            );
+        }
+    }
+}
+
+/// The frames of a session, starting with the latest frame if there is one. Slow viewers skip
+/// frames. Ends when the session ends.
+fn frames(frames: watch::Receiver<Option<Bytes>>) -> BoxStream<'static, Bytes> {
+    let latest = frames.borrow().clone();
+    futures::stream::iter(latest)
+        .chain(futures::stream::unfold(frames, |mut frames| async move {
+            loop {
+                frames.changed().await.ok()?;
+                let frame = frames.borrow().clone();
+                if let Some(frame) = frame {
+                    return Some((frame, frames));
+                }
+            }
+        }))
+        .boxed()
+}
+
+/// A viewer's preview frames. Leaves the preview session when dropped.
+pub struct Preview {
+    // Dropped before `_viewer`, such that the session's frames are no longer received by then.
+    frames: BoxStream<'static, Bytes>,
+    _viewer: Viewer,
+}
+
+impl Stream for Preview {
+    type Item = Bytes;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Bytes>> {
+        self.frames.poll_next_unpin(cx)
+    }
+}
+
+impl SocketHandler {
+    /// Preview a peripheral of a kit, joining the peripheral's preview session or starting one.
+    /// The preview ends when the session is no longer authorized to preview the kit, after
+    /// [MAX_PREVIEW_DURATION], or when the kit fails to start the session. The caller should check
+    /// the session is authorized to begin with.
+    ///
+    /// Requires [kit RPC](SocketHandler::with_kits_rpc).
+    pub async fn preview<A: Auth>(
+        &self,
+        auth: A,
+        session: Option<Session<A::User>>,
+        kit_serial: String,
+        peripheral: String,
+    ) -> Result<Preview, KitRpcResponseError> {
+        let kits_rpc = match &self.kits_rpc {
+            Some(kits_rpc) => kits_rpc.clone(),
+            None => return Err(KitRpcResponseError::Disconnected { last_error: None }),
+        };
+        let previews = self.shared.previews.clone();
+
+        let (id, frames_rx, created) = previews.join(&kit_serial, &peripheral);
+        let viewer = Viewer {
+            previews: previews.clone(),
+            kits_rpc: kits_rpc.clone(),
+            kit_serial: kit_serial.clone(),
+            peripheral: peripheral.clone(),
+            session: id,
+        };
+
+        if created {
+            if let Err(err) = kits_rpc
+                .start_preview(&kit_serial, id, peripheral.clone(), LEASE)
+                .await
+            {
+                // Viewers that joined in the meantime see their previews end.
+                previews.end(&kit_serial, &peripheral, id);
+                return Err(err);
+            }
+            tokio::spawn(extend_lease(
+                previews,
+                kits_rpc,
+                kit_serial.clone(),
+                peripheral,
+                id,
+            ));
+        }
+
+        let authorization = Authorization {
+            auth: Arc::new(auth),
+            session: watch::channel(session).1,
+            kit_serial,
+            action: Action::Preview,
+        };
+
+        Ok(Preview {
+            frames: forward(frames(frames_rx), |_| true, authorization)
+                .take_until(tokio::time::sleep(MAX_PREVIEW_DURATION))
+                .boxed(),
+            _viewer: viewer,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sessions() {
+        let previews = Previews::default();
+
+        let (id, _, created) = previews.join("k-1", "camera");
+        assert!(created);
+        let (joined, _, created) = previews.join("k-1", "camera");
+        assert!(!created);
+        assert_eq!(joined, id);
+
+        assert!(!previews.leave("k-1", "camera", id));
+        assert!(previews.is_active("k-1", "camera", id));
+        assert!(previews.leave("k-1", "camera", id));
+        assert!(!previews.is_active("k-1", "camera", id));
+
+        let (new, _, created) = previews.join("k-1", "camera");
+        assert!(created);
+        assert_ne!(new, id);
+        assert!(!previews.leave("k-1", "camera", id));
+    }
+}
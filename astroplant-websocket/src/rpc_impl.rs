@@ -0,0 +1,503 @@
+use futures::future::{BoxFuture, FutureExt};
+use futures::stream::{BoxStream, StreamExt};
+use jsonrpsee::core::error::SubscriptionClosed;
+use jsonrpsee::core::server::rpc_module::PendingSubscription;
+use jsonrpsee::core::RpcResult;
+use jsonrpsee::proc_macros::rpc;
+use jsonrpsee::types::error::{CallError, ErrorObject};
+use jsonrpsee::types::params::SubscriptionId;
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+use crate::auth::{current_user, Action, Auth, AuthenticationError, Authorization, Session};
+use crate::broadcast::receive;
+use crate::filter::Filter;
+use crate::kit_rpc::{
+    kit_rpc_error, peripheral_command_locked_error, LockRequest, PeripheralCommandOutput,
+};
+use crate::{error_code, KitBroadcasts, Shared, SubscriptionFilter, SubscriptionIds};
+
+/// The interval at which subscriptions are checked to still be authorized.
+const AUTHORIZATION_RECHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Authenticated {
+    expires: chrono::DateTime<chrono::Utc>,
+}
+
+/// A subscription of the connection.
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ActiveSubscription {
+    subscription: SubscriptionId<'static>,
+    method: &'static str,
+    kit_serial: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    filter: Option<SubscriptionFilter>,
+}
+
+#[rpc(server)]
+pub trait Rpc {
+    /// Authenticate the connection, until the access token expires.
+    #[method(name = "authenticate")]
+    async fn authenticate(&self, access_token: String) -> RpcResult<Authenticated>;
+
+    /// The connection's subscriptions, in the order they were made.
+    #[method(name = "list_subscriptions")]
+    async fn list_subscriptions(&self) -> RpcResult<Vec<ActiveSubscription>>;
+
+    /// The kit's software version.
+    #[method(name = "kit_rpc_version")]
+    async fn kit_rpc_version(&self, kit_serial: String) -> RpcResult<String>;
+
+    /// The kit's uptime in seconds.
+    #[method(name = "kit_rpc_uptime")]
+    async fn kit_rpc_uptime(&self, kit_serial: String) -> RpcResult<u64>;
+
+    /// Send a command to a peripheral of the kit.
+    #[method(name = "kit_rpc_peripheral_command")]
+    async fn kit_rpc_peripheral_command(
+        &self,
+        kit_serial: String,
+        peripheral: String,
+        command: serde_json::Value,
+    ) -> RpcResult<PeripheralCommandOutput>;
+
+    /// Get the status of, acquire or release a peripheral's command lock. Locks are released when
+    /// the connection is closed.
+    #[method(name = "kit_rpc_peripheral_command_lock")]
+    async fn kit_rpc_peripheral_command_lock(
+        &self,
+        kit_serial: String,
+        peripheral: String,
+        request: LockRequest,
+    ) -> RpcResult<bool>;
+
+    #[subscription(name = "subscribe_raw_measurements", item = astroplant_mqtt::RawMeasurement)]
+    fn sub_raw_measurements(&self, kit_serial: String, filter: Option<SubscriptionFilter>);
+
+    #[subscription(
+        name = "subscribe_aggregate_measurements",
+        item = astroplant_mqtt::AggregateMeasurement
+    )]
+    fn sub_aggregate_measurements(&self, kit_serial: String, filter: Option<SubscriptionFilter>);
+
+    #[subscription(name = "subscribe_media", item = serde_json::Value)]
+    fn sub_media(&self, kit_serial: String);
+}
+
+pub(crate) struct RpcServerImpl<A: Auth> {
+    pub(crate) shared: Arc<Shared>,
+    pub(crate) auth: Arc<A>,
+    /// The connection's session, watched by its subscriptions.
+    pub(crate) session: watch::Sender<Option<Session<A::User>>>,
+    pub(crate) subscription_ids: Arc<SubscriptionIds>,
+    pub(crate) subscriptions: Arc<Mutex<Vec<ActiveSubscription>>>,
+    pub(crate) kits_rpc: Option<astroplant_mqtt::KitsRpc>,
+    pub(crate) connection_id: usize,
+}
+
+impl<A: Auth> RpcServerImpl<A> {
+    /// Check whether the connection may perform the kit RPC, and get the kits' RPC handle if so.
+    async fn kits_rpc(
+        &self,
+        kit_serial: &str,
+        action: Action,
+    ) -> RpcResult<&astroplant_mqtt::KitsRpc> {
+        let user = current_user(&self.session.borrow());
+        if !self.auth.authorize(user, kit_serial, action).await {
+            return Err(CallError::Custom(ErrorObject::owned(
+                error_code::UNAUTHORIZED,
+                "you are not authorized to perform this kit RPC",
+                None::<()>,
+            ))
+            .into());
+        }
+
+        self.kits_rpc.as_ref().ok_or_else(|| {
+            CallError::Custom(ErrorObject::owned(
+                error_code::KITS_UNREACHABLE,
+                "kit RPC is not available",
+                None::<()>,
+            ))
+            .into()
+        })
+    }
+
+    /// Describe the subscription being made.
+    fn active_subscription(
+        &self,
+        method: &'static str,
+        kit_serial: String,
+        filter: Option<SubscriptionFilter>,
+    ) -> ActiveSubscription {
+        ActiveSubscription {
+            subscription: self.subscription_ids.last(),
+            method,
+            kit_serial,
+            filter,
+        }
+    }
+
+    /// Forward the items of a kit's broadcast to the subscription, while the connection is
+    /// authorized. The items resolved by `initial` are sent first, e.g. cached items. Only items
+    /// for which `keep` returns true are sent.
+    fn subscribe<T, I, K>(
+        &self,
+        pending: PendingSubscription,
+        subscription: ActiveSubscription,
+        broadcasts: fn(&Shared) -> &KitBroadcasts<T>,
+        initial: I,
+        mut keep: K,
+    ) where
+        T: Clone + serde::Serialize + Send + 'static,
+        I: FnOnce(&Shared, &str) -> BoxFuture<'static, Vec<T>> + Send + 'static,
+        K: FnMut(&T) -> bool + Send + 'static,
+    {
+        let kit_serial = subscription.kit_serial.clone();
+        let authorization = Authorization {
+            auth: self.auth.clone(),
+            session: self.session.subscribe(),
+            kit_serial: kit_serial.clone(),
+            action: Action::Subscribe,
+        };
+        let shared = self.shared.clone();
+        let subscriptions = self.subscriptions.clone();
+
+        tokio::spawn(async move {
+            if !authorization.check().await {
+                pending.reject(ErrorObject::borrowed(
+                    error_code::UNAUTHORIZED,
+                    &"you are not authorized to subscribe to this kit",
+                    None,
+                ));
+                return;
+            }
+
+            let mut sink = match pending.accept() {
+                Some(sink) => sink,
+                None => return,
+            };
+
+            let id = subscription.subscription.clone();
+            subscriptions.lock().unwrap().push(subscription);
+
+            // Subscribe before resolving the initial items, such that no items are missed in
+            // between.
+            let receiver = broadcasts(&shared).subscribe(&kit_serial);
+
+            for item in initial(&shared, &kit_serial).await {
+                if keep(&item) {
+                    let _ = sink.send(&item);
+                }
+            }
+
+            // The subscription is closed as soon as the client unsubscribes or disconnects.
+            match sink
+                .pipe_from_stream(forward(receive(receiver), keep, authorization))
+                .await
+            {
+                // The items only end when the connection is no longer authorized.
+                SubscriptionClosed::Success => {
+                    tracing::debug!("closing unauthorized subscription for {}", kit_serial);
+                    sink.close(ErrorObject::borrowed(
+                        error_code::UNAUTHORIZED,
+                        &"you are no longer authorized to subscribe to this kit",
+                        None,
+                    ));
+                }
+                SubscriptionClosed::RemotePeerAborted | SubscriptionClosed::Failed(_) => {}
+            }
+
+            subscriptions
+                .lock()
+                .unwrap()
+                .retain(|subscription| subscription.subscription != id);
+            // Deregister the broadcast if we were its last receiver.
+            broadcasts(&shared).unsubscribed(&kit_serial);
+        });
+    }
+}
+
+/// The items to forward to a subscription, ending when the connection is no longer authorized.
+/// Authorization is checked periodically, as permissions may be revoked, and whenever the
+/// connection's session changes or expires.
+pub(crate) fn forward<T, A>(
+    items: BoxStream<'static, T>,
+    keep: impl FnMut(&T) -> bool + Send + 'static,
+    authorization: Authorization<A>,
+) -> BoxStream<'static, T>
+where
+    T: Send + 'static,
+    A: Auth,
+{
+    let mut recheck = tokio::time::interval_at(
+        tokio::time::Instant::now() + AUTHORIZATION_RECHECK_INTERVAL,
+        AUTHORIZATION_RECHECK_INTERVAL,
+    );
+    recheck.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    // The expiry is only reset when the session changes, and ends once the session expired.
+    let expiry = Box::pin(authorization.expiry().fuse());
+
+    Box::pin(futures::stream::unfold(
+        (items.fuse(), keep, authorization, recheck, expiry),
+        |(mut items, mut keep, mut authorization, mut recheck, mut expiry)| async move {
+            loop {
+                let authorized = tokio::select! {
+                    Some(item) = items.next() => {
+                        if keep(&item) {
+                            return Some((item, (items, keep, authorization, recheck, expiry)));
+                        }
+                        continue;
+                    }
+                    _ = recheck.tick() => authorization.check().await,
+                    Ok(()) = authorization.session.changed() => {
+                        expiry.set(authorization.expiry().fuse());
+                        authorization.check().await
+                    }
+                    expired = &mut expiry => authorization.check_expired(expired).await,
+                };
+                if !authorized {
+                    return None;
+                }
+            }
+        },
+    ))
+}
+
+#[async_trait::async_trait]
+impl<A: Auth> RpcServer for RpcServerImpl<A> {
+    async fn authenticate(&self, access_token: String) -> RpcResult<Authenticated> {
+        match self.auth.authenticate(&access_token).await {
+            Ok(session) => {
+                let expires = session.expires;
+                self.session.send_replace(Some(session));
+                Ok(Authenticated { expires })
+            }
+            Err(AuthenticationError::Expired) => Err(CallError::Custom(ErrorObject::owned(
+                error_code::ACCESS_TOKEN_EXPIRED,
+                "the access token has expired",
+                None::<()>,
+            ))
+            .into()),
+            Err(AuthenticationError::Invalid) => Err(CallError::Custom(ErrorObject::owned(
+                error_code::INVALID_ACCESS_TOKEN,
+                "the access token is invalid",
+                None::<()>,
+            ))
+            .into()),
+        }
+    }
+
+    async fn list_subscriptions(&self) -> RpcResult<Vec<ActiveSubscription>> {
+        Ok(self.subscriptions.lock().unwrap().clone())
+    }
+
+    async fn kit_rpc_version(&self, kit_serial: String) -> RpcResult<String> {
+        self.kits_rpc(&kit_serial, Action::RpcVersion)
+            .await?
+            .version(kit_serial)
+            .await
+            .map_err(kit_rpc_error)
+    }
+
+    async fn kit_rpc_uptime(&self, kit_serial: String) -> RpcResult<u64> {
+        let uptime = self
+            .kits_rpc(&kit_serial, Action::RpcUptime)
+            .await?
+            .uptime(kit_serial)
+            .await
+            .map_err(kit_rpc_error)?;
+        Ok(uptime.as_secs())
+    }
+
+    async fn kit_rpc_peripheral_command(
+        &self,
+        kit_serial: String,
+        peripheral: String,
+        command: serde_json::Value,
+    ) -> RpcResult<PeripheralCommandOutput> {
+        let kits_rpc = self
+            .kits_rpc(&kit_serial, Action::RpcPeripheralCommand)
+            .await?;
+        if self.shared.peripheral_command_locks.held_by_other(
+            &kit_serial,
+            &peripheral,
+            self.connection_id,
+        ) {
+            return Err(peripheral_command_locked_error());
+        }
+        let response = kits_rpc
+            .peripheral_command(kit_serial, peripheral, command)
+            .await
+            .map_err(kit_rpc_error)?;
+        Ok(response.into())
+    }
+
+    async fn kit_rpc_peripheral_command_lock(
+        &self,
+        kit_serial: String,
+        peripheral: String,
+        request: LockRequest,
+    ) -> RpcResult<bool> {
+        let kits_rpc = self
+            .kits_rpc(&kit_serial, Action::RpcPeripheralCommandLock)
+            .await?;
+        self.shared
+            .peripheral_command_locks
+            .request(
+                kits_rpc,
+                &kit_serial,
+                peripheral,
+                request,
+                self.connection_id,
+            )
+            .await
+            .map_err(kit_rpc_error)
+    }
+
+    fn sub_raw_measurements(
+        &self,
+        pending: PendingSubscription,
+        kit_serial: String,
+        filter: Option<SubscriptionFilter>,
+    ) {
+        let subscription =
+            self.active_subscription("subscribe_raw_measurements", kit_serial, filter.clone());
+        let mut filter = Filter::new(filter.unwrap_or_default());
+        self.subscribe(
+            pending,
+            subscription,
+            |shared| &shared.raw_measurements,
+            |shared, kit_serial| {
+                // Start with all cached measurements.
+                let backend = shared.backend.clone();
+                let kit_serial = kit_serial.to_owned();
+                Box::pin(async move {
+                    backend
+                        .cached_raw_measurements(&kit_serial)
+                        .await
+                        .unwrap_or_else(|err| {
+                            tracing::warn!(
+                                "failed to get cached raw measurements of {}: {:?}",
+                                kit_serial,
+                                err
+                            );
+                            vec![]
+                        })
+                })
+            },
+            move |measurement| filter.keep(measurement, Instant::now()),
+        );
+    }
+
+    fn sub_aggregate_measurements(
+        &self,
+        pending: PendingSubscription,
+        kit_serial: String,
+        filter: Option<SubscriptionFilter>,
+    ) {
+        let subscription = self.active_subscription(
+            "subscribe_aggregate_measurements",
+            kit_serial,
+            filter.clone(),
+        );
+        let mut filter = Filter::new(filter.unwrap_or_default());
+        self.subscribe(
+            pending,
+            subscription,
+            |shared| &shared.aggregate_measurements,
+            |_, _| Box::pin(async { vec![] }),
+            move |measurement| filter.keep(measurement, Instant::now()),
+        );
+    }
+
+    fn sub_media(&self, pending: PendingSubscription, kit_serial: String) {
+        let subscription = self.active_subscription("subscribe_media", kit_serial, None);
+        self.subscribe(
+            pending,
+            subscription,
+            |shared| &shared.media,
+            |_, _| Box::pin(async { vec![] }),
+            |_| true,
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::auth::StubAuth;
+    use futures::channel::mpsc;
+    use tokio::time::Instant;
+
+    /// Forward the items sent on the returned sender to a subscription of a connection with the
+    /// given session.
+    fn subscription(
+        auth: &StubAuth,
+        session: &watch::Sender<Option<Session<i32>>>,
+    ) -> (mpsc::UnboundedSender<u32>, BoxStream<'static, u32>) {
+        let (sender, receiver) = mpsc::unbounded();
+        let authorization = Authorization {
+            auth: Arc::new(auth.clone()),
+            session: session.subscribe(),
+            kit_serial: "k-1".to_owned(),
+            action: Action::Subscribe,
+        };
+        (sender, forward(receiver.boxed(), |_| true, authorization))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ends_when_revoked() {
+        let auth = StubAuth::new(chrono::Duration::hours(1));
+        let (session, _) = watch::channel(Some(auth.session()));
+        let (sender, mut items) = subscription(&auth, &session);
+        let start = Instant::now();
+
+        sender.unbounded_send(1).unwrap();
+        assert_eq!(items.next().await, Some(1));
+
+        // Revocation is noticed at the next recheck.
+        auth.set_authorized(false);
+        assert_eq!(items.next().await, None);
+        assert_eq!(start.elapsed(), AUTHORIZATION_RECHECK_INTERVAL);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ends_when_the_session_expires() {
+        let auth = StubAuth::new(chrono::Duration::minutes(10));
+        let (session, _) = watch::channel(Some(auth.session()));
+        let (_sender, mut items) = subscription(&auth, &session);
+        let start = Instant::now();
+
+        assert_eq!(items.next().await, None);
+        let elapsed = start.elapsed();
+        assert!(elapsed > Duration::from_secs(9 * 60) && elapsed <= Duration::from_secs(10 * 60));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reauthenticating_keeps_it_open() {
+        let auth = StubAuth::new(chrono::Duration::minutes(10));
+        let (session, _) = watch::channel(Some(auth.session()));
+        let (sender, mut items) = subscription(&auth, &session);
+
+        tokio::time::sleep(Duration::from_secs(5 * 60)).await;
+        let renewed = Session {
+            expires: session.borrow().unwrap().expires + chrono::Duration::minutes(10),
+            ..auth.session()
+        };
+        session.send_replace(Some(renewed));
+
+        // Past the original expiry.
+        tokio::time::sleep(Duration::from_secs(10 * 60)).await;
+        sender.unbounded_send(1).unwrap();
+        assert_eq!(items.next().await, Some(1));
+
+        // The renewed session expires in turn.
+        assert_eq!(items.next().await, None);
+    }
+}
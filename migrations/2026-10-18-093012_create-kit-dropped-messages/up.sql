@@ -0,0 +1,16 @@
+-- Messages kits published that were dropped for exceeding the kits' ingress
+-- quotas, counted per day.
+CREATE TABLE kit_dropped_messages (
+    kit_id int4 NOT NULL,
+    date date NOT NULL,
+    messages int8 NOT NULL,
+    bytes int8 NOT NULL,
+    datetime_last_dropped timestamptz NOT NULL,
+    CONSTRAINT kit_dropped_messages_pkey PRIMARY KEY (kit_id, date),
+    CONSTRAINT messages_positive CHECK ((messages >= 0)),
+    CONSTRAINT bytes_positive CHECK ((bytes >= 0))
+);
+
+-- foreign keys
+ALTER TABLE public.kit_dropped_messages
+    ADD CONSTRAINT kit_dropped_messages_kit_id_fkey FOREIGN KEY (kit_id) REFERENCES kits (id) ON DELETE CASCADE ON UPDATE CASCADE
@@ -0,0 +1,19 @@
+-- Events reported by kits, such as peripheral failures. Severity is one of
+-- debug (0), info (1), warning (2), error (3) or critical (4).
+CREATE TABLE kit_events (
+    id uuid NOT NULL,
+    kit_id int4 NOT NULL,
+    peripheral_id int4,
+    datetime timestamptz NOT NULL,
+    severity int2 NOT NULL,
+    message text NOT NULL,
+    CONSTRAINT kit_events_pkey PRIMARY KEY (id),
+    CONSTRAINT severity_valid CHECK ((severity >= 0 AND severity <= 4))
+);
+CREATE INDEX ix_kit_events_kit_id_datetime ON public.kit_events USING btree (kit_id, datetime);
+CREATE INDEX ix_kit_events_peripheral_id ON public.kit_events USING btree (peripheral_id);
+
+-- foreign keys
+ALTER TABLE public.kit_events
+    ADD CONSTRAINT kit_events_kit_id_fkey FOREIGN KEY (kit_id) REFERENCES kits (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    ADD CONSTRAINT kit_events_peripheral_id_fkey FOREIGN KEY (peripheral_id) REFERENCES peripherals (id) ON DELETE CASCADE ON UPDATE CASCADE
@@ -0,0 +1 @@
+DROP TABLE actuator_states
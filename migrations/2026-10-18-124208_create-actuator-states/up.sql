@@ -0,0 +1,19 @@
+-- The states kits' actuators switched to, such as a pump switching on.
+CREATE TABLE actuator_states (
+    id uuid NOT NULL,
+    peripheral_id int4 NOT NULL,
+    kit_id int4 NOT NULL,
+    kit_configuration_id int4 NOT NULL,
+    datetime timestamptz NOT NULL,
+    state jsonb NOT NULL,
+    CONSTRAINT actuator_states_pkey PRIMARY KEY (id)
+);
+CREATE INDEX ix_actuator_states_kit_id_datetime ON public.actuator_states USING btree (kit_id, datetime);
+CREATE INDEX ix_actuator_states_kit_configuration_id ON public.actuator_states USING btree (kit_configuration_id);
+CREATE INDEX ix_actuator_states_peripheral_id ON public.actuator_states USING btree (peripheral_id);
+
+-- foreign keys
+ALTER TABLE public.actuator_states
+    ADD CONSTRAINT actuator_states_peripheral_id_fkey FOREIGN KEY (peripheral_id) REFERENCES peripherals (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    ADD CONSTRAINT actuator_states_kit_id_fkey FOREIGN KEY (kit_id) REFERENCES kits (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    ADD CONSTRAINT actuator_states_kit_configuration_id_fkey FOREIGN KEY (kit_configuration_id) REFERENCES kit_configurations (id) ON DELETE CASCADE ON UPDATE CASCADE
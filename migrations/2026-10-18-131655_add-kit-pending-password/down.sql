@@ -0,0 +1,3 @@
+ALTER TABLE kits
+    DROP pending_password_hash,
+    DROP pending_password_deadline;
@@ -0,0 +1,8 @@
+-- A kit's pending password during password rotation. Both the current and the
+-- pending password are valid until the kit first authenticates with the pending
+-- password, or until the deadline passes. Then, the pending password replaces
+-- the current password.
+ALTER TABLE kits
+    ADD pending_password_hash varchar(255) NULL,
+    ADD pending_password_deadline timestamptz NULL,
+    ADD CONSTRAINT pending_password_has_deadline CHECK ((pending_password_hash IS NULL) = (pending_password_deadline IS NULL));
@@ -0,0 +1 @@
+DROP TABLE kit_claim_codes;
@@ -0,0 +1,8 @@
+-- One-time codes with which a user can claim an unclaimed kit, becoming its
+-- super member. Only a hash of the code is stored.
+CREATE TABLE kit_claim_codes (
+    kit_id integer PRIMARY KEY,
+    claim_code_hash varchar(255) NOT NULL UNIQUE,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    FOREIGN KEY (kit_id) REFERENCES kits (id) ON UPDATE CASCADE ON DELETE CASCADE
+);
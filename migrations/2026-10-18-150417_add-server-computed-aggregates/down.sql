@@ -0,0 +1,3 @@
+DROP INDEX ix_aggregate_measurements_server_computed_window;
+ALTER TABLE aggregate_measurements
+    DROP server_computed;
@@ -0,0 +1,7 @@
+-- Aggregate measurements computed by the server from raw measurements, rather
+-- than published by the kit. Each server-computed window is stored only once.
+ALTER TABLE aggregate_measurements
+    ADD server_computed boolean NOT NULL DEFAULT false;
+CREATE UNIQUE INDEX ix_aggregate_measurements_server_computed_window
+    ON aggregate_measurements (peripheral_id, quantity_type_id, datetime_start, datetime_end)
+    WHERE server_computed;
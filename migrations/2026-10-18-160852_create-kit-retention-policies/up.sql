@@ -0,0 +1,12 @@
+-- Per-kit retention policies of raw measurements, overriding the instance-wide
+-- policy. Raw measurements older than `raw_measurement_days` days that are
+-- covered by aggregate measurements are deleted or, if `downsample_minutes` is
+-- set, downsampled to one raw measurement per peripheral, quantity type and
+-- window of that many minutes. If `raw_measurement_days` is NULL, the kit's raw
+-- measurements are kept indefinitely.
+CREATE TABLE kit_retention_policies (
+    kit_id integer PRIMARY KEY,
+    raw_measurement_days integer NULL CHECK (raw_measurement_days > 0),
+    downsample_minutes integer NULL CHECK (downsample_minutes > 0),
+    FOREIGN KEY (kit_id) REFERENCES kits (id) ON UPDATE CASCADE ON DELETE CASCADE
+);
@@ -0,0 +1,80 @@
+-- Move the partitioned measurements back into unpartitioned tables. If the
+-- unpartitioned tables were dropped after backfilling, they are recreated.
+CREATE TABLE IF NOT EXISTS raw_measurements_unpartitioned (
+	id uuid NOT NULL,
+	peripheral_id int4 NOT NULL,
+	kit_id int4 NOT NULL,
+	kit_configuration_id int4 NOT NULL,
+	quantity_type_id int4 NOT NULL,
+	value float8 NOT NULL,
+	datetime timestamptz NOT NULL,
+	CONSTRAINT raw_measurements_unpartitioned_pkey PRIMARY KEY (id),
+	CONSTRAINT raw_measurements_kit_configuration_id_fkey FOREIGN KEY (kit_configuration_id) REFERENCES kit_configurations (id) ON DELETE CASCADE ON UPDATE CASCADE,
+	CONSTRAINT raw_measurements_kit_id_fkey FOREIGN KEY (kit_id) REFERENCES kits (id) ON DELETE CASCADE ON UPDATE CASCADE,
+	CONSTRAINT raw_measurements_peripheral_id_fkey FOREIGN KEY (peripheral_id) REFERENCES peripherals (id) ON DELETE CASCADE ON UPDATE CASCADE,
+	CONSTRAINT raw_measurements_quantity_type_id_fkey FOREIGN KEY (quantity_type_id) REFERENCES quantity_types (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+CREATE INDEX IF NOT EXISTS ix_raw_measurements_unpartitioned_datetime ON raw_measurements_unpartitioned USING btree (datetime);
+CREATE INDEX IF NOT EXISTS ix_raw_measurements_unpartitioned_kit_configuration_id ON raw_measurements_unpartitioned USING btree (kit_configuration_id);
+CREATE INDEX IF NOT EXISTS ix_raw_measurements_unpartitioned_kit_id ON raw_measurements_unpartitioned USING btree (kit_id);
+CREATE INDEX IF NOT EXISTS ix_raw_measurements_unpartitioned_peripheral_id ON raw_measurements_unpartitioned USING btree (peripheral_id);
+CREATE INDEX IF NOT EXISTS ix_raw_measurements_unpartitioned_quantity_type_id ON raw_measurements_unpartitioned USING btree (quantity_type_id);
+
+CREATE TABLE IF NOT EXISTS aggregate_measurements_unpartitioned (
+	id uuid NOT NULL,
+	peripheral_id int4 NOT NULL,
+	kit_id int4 NOT NULL,
+	kit_configuration_id int4 NOT NULL,
+	quantity_type_id int4 NOT NULL,
+	datetime_start timestamptz NOT NULL,
+	datetime_end timestamptz NOT NULL,
+	"values" json NOT NULL,
+	server_computed boolean NOT NULL DEFAULT false,
+	CONSTRAINT aggregate_measurements_unpartitioned_pkey PRIMARY KEY (id),
+	CONSTRAINT aggregate_measurements_kit_configuration_id_fkey FOREIGN KEY (kit_configuration_id) REFERENCES kit_configurations (id) ON DELETE CASCADE ON UPDATE CASCADE,
+	CONSTRAINT aggregate_measurements_kit_id_fkey FOREIGN KEY (kit_id) REFERENCES kits (id) ON DELETE CASCADE ON UPDATE CASCADE,
+	CONSTRAINT aggregate_measurements_peripheral_id_fkey FOREIGN KEY (peripheral_id) REFERENCES peripherals (id) ON DELETE CASCADE ON UPDATE CASCADE,
+	CONSTRAINT aggregate_measurements_quantity_type_id_fkey FOREIGN KEY (quantity_type_id) REFERENCES quantity_types (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+CREATE INDEX IF NOT EXISTS ix_aggregate_measurements_unpartitioned_datetime_start ON aggregate_measurements_unpartitioned USING btree (datetime_start);
+CREATE INDEX IF NOT EXISTS ix_aggregate_measurements_unpartitioned_datetime_start_id ON aggregate_measurements_unpartitioned USING btree (datetime_start, id);
+CREATE INDEX IF NOT EXISTS ix_aggregate_measurements_unpartitioned_kit_configuration_id ON aggregate_measurements_unpartitioned USING btree (kit_configuration_id);
+CREATE INDEX IF NOT EXISTS ix_aggregate_measurements_unpartitioned_kit_id ON aggregate_measurements_unpartitioned USING btree (kit_id);
+CREATE INDEX IF NOT EXISTS ix_aggregate_measurements_unpartitioned_peripheral_id ON aggregate_measurements_unpartitioned USING btree (peripheral_id);
+CREATE INDEX IF NOT EXISTS ix_aggregate_measurements_unpartitioned_quantity_type_id ON aggregate_measurements_unpartitioned USING btree (quantity_type_id);
+CREATE UNIQUE INDEX IF NOT EXISTS ix_aggregate_measurements_unpartitioned_server_computed_window
+    ON aggregate_measurements_unpartitioned (peripheral_id, quantity_type_id, datetime_start, datetime_end)
+    WHERE server_computed;
+
+INSERT INTO raw_measurements_unpartitioned
+SELECT id, peripheral_id, kit_id, kit_configuration_id, quantity_type_id, value, datetime
+FROM raw_measurements
+ON CONFLICT DO NOTHING;
+
+INSERT INTO aggregate_measurements_unpartitioned
+SELECT id, peripheral_id, kit_id, kit_configuration_id, quantity_type_id, datetime_start, datetime_end, "values"::json, server_computed
+FROM aggregate_measurements
+ON CONFLICT DO NOTHING;
+
+DROP FUNCTION create_measurement_partitions(timestamptz);
+DROP FUNCTION create_measurement_partition(text, text, timestamptz);
+DROP TABLE raw_measurements;
+DROP TABLE aggregate_measurements;
+
+ALTER TABLE raw_measurements_unpartitioned RENAME TO raw_measurements;
+ALTER INDEX raw_measurements_unpartitioned_pkey RENAME TO raw_measurements_pkey;
+ALTER INDEX ix_raw_measurements_unpartitioned_datetime RENAME TO ix_raw_measurements_datetime;
+ALTER INDEX ix_raw_measurements_unpartitioned_kit_configuration_id RENAME TO ix_raw_measurements_kit_configuration_id;
+ALTER INDEX ix_raw_measurements_unpartitioned_kit_id RENAME TO ix_raw_measurements_kit_id;
+ALTER INDEX ix_raw_measurements_unpartitioned_peripheral_id RENAME TO ix_raw_measurements_peripheral_id;
+ALTER INDEX ix_raw_measurements_unpartitioned_quantity_type_id RENAME TO ix_raw_measurements_quantity_type_id;
+
+ALTER TABLE aggregate_measurements_unpartitioned RENAME TO aggregate_measurements;
+ALTER INDEX aggregate_measurements_unpartitioned_pkey RENAME TO aggregate_measurements_pkey;
+ALTER INDEX ix_aggregate_measurements_unpartitioned_datetime_start RENAME TO ix_aggregate_measurements_datetime_start;
+ALTER INDEX ix_aggregate_measurements_unpartitioned_datetime_start_id RENAME TO ix_aggregate_measurements_datetime_start_id;
+ALTER INDEX ix_aggregate_measurements_unpartitioned_kit_configuration_id RENAME TO ix_aggregate_measurements_kit_configuration_id;
+ALTER INDEX ix_aggregate_measurements_unpartitioned_kit_id RENAME TO ix_aggregate_measurements_kit_id;
+ALTER INDEX ix_aggregate_measurements_unpartitioned_peripheral_id RENAME TO ix_aggregate_measurements_peripheral_id;
+ALTER INDEX ix_aggregate_measurements_unpartitioned_quantity_type_id RENAME TO ix_aggregate_measurements_quantity_type_id;
+ALTER INDEX ix_aggregate_measurements_unpartitioned_server_computed_window RENAME TO ix_aggregate_measurements_server_computed_window;
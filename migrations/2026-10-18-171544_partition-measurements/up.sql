@@ -0,0 +1,123 @@
+-- Partition the measurement tables by month.
+--
+-- The existing tables are kept as `raw_measurements_unpartitioned` and
+-- `aggregate_measurements_unpartitioned`, and the partitioned tables start out
+-- empty: copying every measurement here would hold the tables' locks for the
+-- whole copy and need the disk space twice over. Their rows are copied in
+-- batches, newest first, by `astroplant-admin backfill-measurements`, which
+-- should be run right after this migration and drops the unpartitioned tables
+-- when requested. Until it is done, the API serves the measurements ingested
+-- after this migration and those backfilled so far.
+ALTER TABLE raw_measurements RENAME TO raw_measurements_unpartitioned;
+ALTER INDEX raw_measurements_pkey RENAME TO raw_measurements_unpartitioned_pkey;
+ALTER INDEX ix_raw_measurements_datetime RENAME TO ix_raw_measurements_unpartitioned_datetime;
+ALTER INDEX ix_raw_measurements_kit_configuration_id RENAME TO ix_raw_measurements_unpartitioned_kit_configuration_id;
+ALTER INDEX ix_raw_measurements_kit_id RENAME TO ix_raw_measurements_unpartitioned_kit_id;
+ALTER INDEX ix_raw_measurements_peripheral_id RENAME TO ix_raw_measurements_unpartitioned_peripheral_id;
+ALTER INDEX ix_raw_measurements_quantity_type_id RENAME TO ix_raw_measurements_unpartitioned_quantity_type_id;
+
+ALTER TABLE aggregate_measurements RENAME TO aggregate_measurements_unpartitioned;
+ALTER INDEX aggregate_measurements_pkey RENAME TO aggregate_measurements_unpartitioned_pkey;
+ALTER INDEX ix_aggregate_measurements_datetime_start RENAME TO ix_aggregate_measurements_unpartitioned_datetime_start;
+ALTER INDEX ix_aggregate_measurements_datetime_start_id RENAME TO ix_aggregate_measurements_unpartitioned_datetime_start_id;
+ALTER INDEX ix_aggregate_measurements_kit_configuration_id RENAME TO ix_aggregate_measurements_unpartitioned_kit_configuration_id;
+ALTER INDEX ix_aggregate_measurements_kit_id RENAME TO ix_aggregate_measurements_unpartitioned_kit_id;
+ALTER INDEX ix_aggregate_measurements_peripheral_id RENAME TO ix_aggregate_measurements_unpartitioned_peripheral_id;
+ALTER INDEX ix_aggregate_measurements_quantity_type_id RENAME TO ix_aggregate_measurements_unpartitioned_quantity_type_id;
+ALTER INDEX ix_aggregate_measurements_server_computed_window RENAME TO ix_aggregate_measurements_unpartitioned_server_computed_window;
+
+-- The primary key of a partitioned table must include the partition key.
+CREATE TABLE raw_measurements (
+    id uuid NOT NULL,
+    peripheral_id int4 NOT NULL,
+    kit_id int4 NOT NULL,
+    kit_configuration_id int4 NOT NULL,
+    quantity_type_id int4 NOT NULL,
+    value float8 NOT NULL,
+    datetime timestamptz NOT NULL,
+    CONSTRAINT raw_measurements_pkey PRIMARY KEY (id, datetime),
+    CONSTRAINT raw_measurements_peripheral_id_fkey FOREIGN KEY (peripheral_id) REFERENCES peripherals (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    CONSTRAINT raw_measurements_kit_id_fkey FOREIGN KEY (kit_id) REFERENCES kits (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    CONSTRAINT raw_measurements_kit_configuration_id_fkey FOREIGN KEY (kit_configuration_id) REFERENCES kit_configurations (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    CONSTRAINT raw_measurements_quantity_type_id_fkey FOREIGN KEY (quantity_type_id) REFERENCES quantity_types (id) ON DELETE CASCADE ON UPDATE CASCADE
+) PARTITION BY RANGE (datetime);
+
+-- Serves listing a kit's measurements, the peripheral foreign key and retention.
+CREATE INDEX ix_raw_measurements_kit_id_datetime ON raw_measurements (kit_id, datetime);
+CREATE INDEX ix_raw_measurements_peripheral_id_quantity_type_id_datetime ON raw_measurements (peripheral_id, quantity_type_id, datetime);
+CREATE INDEX ix_raw_measurements_kit_configuration_id ON raw_measurements (kit_configuration_id);
+
+CREATE TABLE aggregate_measurements (
+    id uuid NOT NULL,
+    peripheral_id int4 NOT NULL,
+    kit_id int4 NOT NULL,
+    kit_configuration_id int4 NOT NULL,
+    quantity_type_id int4 NOT NULL,
+    datetime_start timestamptz NOT NULL,
+    datetime_end timestamptz NOT NULL,
+    "values" jsonb NOT NULL,
+    server_computed boolean NOT NULL DEFAULT false,
+    CONSTRAINT aggregate_measurements_pkey PRIMARY KEY (id, datetime_start),
+    CONSTRAINT aggregate_measurements_peripheral_id_fkey FOREIGN KEY (peripheral_id) REFERENCES peripherals (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    CONSTRAINT aggregate_measurements_kit_id_fkey FOREIGN KEY (kit_id) REFERENCES kits (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    CONSTRAINT aggregate_measurements_kit_configuration_id_fkey FOREIGN KEY (kit_configuration_id) REFERENCES kit_configurations (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    CONSTRAINT aggregate_measurements_quantity_type_id_fkey FOREIGN KEY (quantity_type_id) REFERENCES quantity_types (id) ON DELETE CASCADE ON UPDATE CASCADE
+) PARTITION BY RANGE (datetime_start);
+
+-- Serves paging through and archiving a kit's aggregates, the peripheral
+-- foreign key and retention.
+CREATE INDEX ix_aggregate_measurements_kit_id_datetime_start_id ON aggregate_measurements (kit_id, datetime_start, id);
+CREATE INDEX ix_aggregate_measurements_peripheral_id_quantity_type_id_datetime_start ON aggregate_measurements (peripheral_id, quantity_type_id, datetime_start);
+CREATE INDEX ix_aggregate_measurements_kit_configuration_id ON aggregate_measurements (kit_configuration_id);
+CREATE UNIQUE INDEX ix_aggregate_measurements_server_computed_window
+    ON aggregate_measurements (peripheral_id, quantity_type_id, datetime_start, datetime_end)
+    WHERE server_computed;
+
+-- Measurements outside of the monthly partitions end up in the default
+-- partitions. Partitions should be created ahead of time (see
+-- `astroplant-admin create-measurement-partitions`).
+CREATE TABLE raw_measurements_default PARTITION OF raw_measurements DEFAULT;
+CREATE TABLE aggregate_measurements_default PARTITION OF aggregate_measurements DEFAULT;
+
+-- Create the partition of the measurement table `parent`, partitioned by
+-- `key`, for the (UTC) month containing `month`, if it does not exist yet. A
+-- partition cannot be created while the default partition holds rows in its
+-- range, so those rows are moved into the new partition.
+CREATE FUNCTION create_measurement_partition(parent text, key text, month timestamptz) RETURNS void AS $$
+DECLARE
+    month_start timestamptz := date_trunc('month', month AT TIME ZONE 'UTC') AT TIME ZONE 'UTC';
+    month_end timestamptz := (date_trunc('month', month AT TIME ZONE 'UTC') + interval '1 month') AT TIME ZONE 'UTC';
+    partition_name text := parent || '_' || to_char(month AT TIME ZONE 'UTC', '"y"YYYY"m"MM');
+BEGIN
+    IF to_regclass(partition_name) IS NOT NULL THEN
+        RETURN;
+    END IF;
+
+    EXECUTE format('CREATE TEMPORARY TABLE measurement_partition_rows (LIKE %I)', parent);
+    EXECUTE format(
+        'WITH moved AS (DELETE FROM %I WHERE %I >= %L AND %I < %L RETURNING *)
+         INSERT INTO measurement_partition_rows SELECT * FROM moved',
+        parent || '_default', key, month_start, key, month_end
+    );
+    EXECUTE format(
+        'CREATE TABLE %I PARTITION OF %I FOR VALUES FROM (%L) TO (%L)',
+        partition_name, parent, month_start, month_end
+    );
+    EXECUTE format('INSERT INTO %I SELECT * FROM measurement_partition_rows', parent);
+    DROP TABLE measurement_partition_rows;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Create the partitions of the measurement tables for the (UTC) month
+-- containing `month`, if they do not exist yet.
+CREATE FUNCTION create_measurement_partitions(month timestamptz) RETURNS void AS $$
+BEGIN
+    PERFORM create_measurement_partition('raw_measurements', 'datetime', month);
+    PERFORM create_measurement_partition('aggregate_measurements', 'datetime_start', month);
+END;
+$$ LANGUAGE plpgsql;
+
+-- Create the partitions of this month and the two months ahead. The
+-- partitions of earlier months are created by the backfill.
+SELECT create_measurement_partitions(month)
+FROM generate_series(now(), now() + interval '2 months', interval '1 month') AS month;
@@ -0,0 +1,2 @@
+DROP TABLE kit_alerts;
+DROP TABLE kit_alert_rules;
@@ -0,0 +1,51 @@
+-- Alert rules on a kit's raw measurements of a peripheral and quantity type.
+--
+-- - threshold: the measured value compares to the threshold for at least the duration;
+-- - rate_of_change: the change per minute between consecutive measurements compares to the
+--   threshold for at least the duration;
+-- - absence: no measurement was received for the duration.
+CREATE TABLE kit_alert_rules (
+    id serial4 NOT NULL,
+    kit_id int4 NOT NULL,
+    peripheral_id int4 NOT NULL,
+    quantity_type_id int4 NOT NULL,
+    name varchar(255) NOT NULL,
+    kind varchar(16) NOT NULL,
+    comparison varchar(8),
+    threshold float8,
+    duration_seconds int4 NOT NULL DEFAULT 0,
+    datetime_created timestamptz NOT NULL DEFAULT now(),
+    CONSTRAINT kit_alert_rules_pkey PRIMARY KEY (id),
+    CONSTRAINT kind_valid CHECK (kind IN ('threshold', 'rate_of_change', 'absence')),
+    CONSTRAINT comparison_valid CHECK (comparison IN ('above', 'below')),
+    CONSTRAINT condition_valid CHECK (
+        CASE kind
+            WHEN 'absence' THEN comparison IS NULL AND threshold IS NULL AND duration_seconds > 0
+            ELSE comparison IS NOT NULL AND threshold IS NOT NULL AND duration_seconds >= 0
+        END
+    )
+);
+CREATE INDEX ix_kit_alert_rules_kit_id ON public.kit_alert_rules USING btree (kit_id);
+CREATE INDEX ix_kit_alert_rules_peripheral_id ON public.kit_alert_rules USING btree (peripheral_id);
+
+-- Transitions of alert rules into (firing) and out of (resolved) the alerting state.
+CREATE TABLE kit_alerts (
+    id uuid NOT NULL,
+    kit_id int4 NOT NULL,
+    kit_alert_rule_id int4 NOT NULL,
+    datetime timestamptz NOT NULL,
+    firing bool NOT NULL,
+    value float8,
+    CONSTRAINT kit_alerts_pkey PRIMARY KEY (id)
+);
+CREATE INDEX ix_kit_alerts_kit_id_datetime ON public.kit_alerts USING btree (kit_id, datetime);
+CREATE INDEX ix_kit_alerts_kit_alert_rule_id_datetime ON public.kit_alerts USING btree (kit_alert_rule_id, datetime);
+
+-- foreign keys
+ALTER TABLE public.kit_alert_rules
+    ADD CONSTRAINT kit_alert_rules_kit_id_fkey FOREIGN KEY (kit_id) REFERENCES kits (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    ADD CONSTRAINT kit_alert_rules_peripheral_id_fkey FOREIGN KEY (peripheral_id) REFERENCES peripherals (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    ADD CONSTRAINT kit_alert_rules_quantity_type_id_fkey FOREIGN KEY (quantity_type_id) REFERENCES quantity_types (id) ON DELETE CASCADE ON UPDATE CASCADE;
+ALTER TABLE public.kit_alerts
+    ADD CONSTRAINT kit_alerts_kit_id_fkey FOREIGN KEY (kit_id) REFERENCES kits (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    ADD CONSTRAINT kit_alerts_kit_alert_rule_id_fkey FOREIGN KEY (kit_alert_rule_id) REFERENCES kit_alert_rules (id) ON DELETE CASCADE ON UPDATE CASCADE
@@ -0,0 +1,2 @@
+DROP TABLE kit_webhook_deliveries;
+DROP TABLE kit_webhooks;
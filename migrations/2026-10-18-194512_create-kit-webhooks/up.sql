@@ -0,0 +1,36 @@
+-- Webhooks of kits, receiving the kit's events of the given types. The secret signs deliveries.
+CREATE TABLE kit_webhooks (
+    id serial4 NOT NULL,
+    kit_id int4 NOT NULL,
+    url text NOT NULL,
+    secret varchar(255) NOT NULL,
+    event_types text[] NOT NULL,
+    datetime_created timestamptz NOT NULL DEFAULT now(),
+    CONSTRAINT kit_webhooks_pkey PRIMARY KEY (id),
+    CONSTRAINT event_types_valid CHECK (
+        event_types <@ ARRAY['aggregate_measurement', 'media', 'kit_online', 'kit_offline', 'configuration_activated']
+    )
+);
+CREATE INDEX ix_kit_webhooks_kit_id ON public.kit_webhooks USING btree (kit_id);
+
+-- Attempts at delivering events to webhooks. The status code is set if the webhook responded,
+-- the error otherwise or if the response was not successful.
+CREATE TABLE kit_webhook_deliveries (
+    id uuid NOT NULL,
+    kit_webhook_id int4 NOT NULL,
+    event_id uuid NOT NULL,
+    event_type text NOT NULL,
+    attempt int4 NOT NULL,
+    datetime timestamptz NOT NULL,
+    status_code int4,
+    error text,
+    succeeded bool NOT NULL,
+    CONSTRAINT kit_webhook_deliveries_pkey PRIMARY KEY (id)
+);
+CREATE INDEX ix_kit_webhook_deliveries_kit_webhook_id_datetime ON public.kit_webhook_deliveries USING btree (kit_webhook_id, datetime);
+
+-- foreign keys
+ALTER TABLE public.kit_webhooks
+    ADD CONSTRAINT kit_webhooks_kit_id_fkey FOREIGN KEY (kit_id) REFERENCES kits (id) ON DELETE CASCADE ON UPDATE CASCADE;
+ALTER TABLE public.kit_webhook_deliveries
+    ADD CONSTRAINT kit_webhook_deliveries_kit_webhook_id_fkey FOREIGN KEY (kit_webhook_id) REFERENCES kit_webhooks (id) ON DELETE CASCADE ON UPDATE CASCADE
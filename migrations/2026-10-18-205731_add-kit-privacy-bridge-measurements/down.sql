@@ -0,0 +1,2 @@
+ALTER TABLE kits
+    DROP privacy_bridge_measurements;
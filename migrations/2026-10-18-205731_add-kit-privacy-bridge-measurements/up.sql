@@ -0,0 +1,4 @@
+-- Whether the kit's measurements are republished on the public topics of the
+-- MQTT bridge (e.g. for Home Assistant). Kits must opt in.
+ALTER TABLE kits
+    ADD privacy_bridge_measurements bool NOT NULL DEFAULT false;
@@ -0,0 +1 @@
+DROP TABLE websocket_raw_measurement_cache;
@@ -0,0 +1,11 @@
+-- The newest raw measurement of each kit, peripheral and quantity type, as received by any API
+-- instance. Sent to new WebSocket subscribers. This is a cache, so it is not crash-safe.
+CREATE UNLOGGED TABLE websocket_raw_measurement_cache (
+    kit_serial varchar(255) NOT NULL,
+    peripheral int4 NOT NULL,
+    quantity_type int4 NOT NULL,
+    raw_measurement jsonb NOT NULL,
+    datetime_received timestamptz NOT NULL,
+    CONSTRAINT websocket_raw_measurement_cache_pkey PRIMARY KEY (kit_serial, peripheral, quantity_type)
+);
+CREATE INDEX ix_websocket_raw_measurement_cache_datetime_received ON public.websocket_raw_measurement_cache USING btree (datetime_received);
@@ -0,0 +1,22 @@
+-- The newest raw and aggregate measurement of each peripheral and quantity type, maintained by
+-- measurement ingestion. Either may be missing, e.g. if a kit publishes only raw measurements.
+CREATE TABLE latest_measurements (
+    peripheral_id int4 NOT NULL,
+    quantity_type_id int4 NOT NULL,
+    kit_id int4 NOT NULL,
+    raw_measurement_id uuid NULL,
+    raw_value float8 NULL,
+    raw_datetime timestamptz NULL,
+    aggregate_measurement_id uuid NULL,
+    aggregate_values jsonb NULL,
+    aggregate_datetime_start timestamptz NULL,
+    aggregate_datetime_end timestamptz NULL,
+    CONSTRAINT latest_measurements_pkey PRIMARY KEY (peripheral_id, quantity_type_id)
+);
+CREATE INDEX ix_latest_measurements_kit_id ON public.latest_measurements USING btree (kit_id);
+
+-- foreign keys
+ALTER TABLE public.latest_measurements
+    ADD CONSTRAINT latest_measurements_peripheral_id_fkey FOREIGN KEY (peripheral_id) REFERENCES peripherals (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    ADD CONSTRAINT latest_measurements_quantity_type_id_fkey FOREIGN KEY (quantity_type_id) REFERENCES quantity_types (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    ADD CONSTRAINT latest_measurements_kit_id_fkey FOREIGN KEY (kit_id) REFERENCES kits (id) ON DELETE CASCADE ON UPDATE CASCADE
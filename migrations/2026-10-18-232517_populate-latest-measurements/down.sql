@@ -0,0 +1,3 @@
+-- no-op
+SELECT
+    1
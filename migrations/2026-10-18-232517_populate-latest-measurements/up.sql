@@ -0,0 +1,49 @@
+-- Populate the latest measurements from the measurements stored so far: those ingested since the
+-- measurement tables were partitioned, and those kept in the unpartitioned tables by the
+-- partitioning migration.
+INSERT INTO latest_measurements (peripheral_id, quantity_type_id, kit_id, raw_measurement_id, raw_value, raw_datetime)
+SELECT DISTINCT ON (peripheral_id, quantity_type_id)
+    peripheral_id,
+    quantity_type_id,
+    kit_id,
+    id,
+    value,
+    datetime
+FROM (
+    SELECT peripheral_id, quantity_type_id, kit_id, id, value, datetime
+    FROM raw_measurements
+    UNION ALL
+    SELECT peripheral_id, quantity_type_id, kit_id, id, value, datetime
+    FROM raw_measurements_unpartitioned
+) AS measurements
+ORDER BY
+    peripheral_id,
+    quantity_type_id,
+    datetime DESC;
+
+INSERT INTO latest_measurements (peripheral_id, quantity_type_id, kit_id, aggregate_measurement_id, aggregate_values, aggregate_datetime_start, aggregate_datetime_end)
+SELECT DISTINCT ON (peripheral_id, quantity_type_id)
+    peripheral_id,
+    quantity_type_id,
+    kit_id,
+    id,
+    values,
+    datetime_start,
+    datetime_end
+FROM (
+    SELECT peripheral_id, quantity_type_id, kit_id, id, values, datetime_start, datetime_end
+    FROM aggregate_measurements
+    UNION ALL
+    SELECT peripheral_id, quantity_type_id, kit_id, id, values::jsonb, datetime_start, datetime_end
+    FROM aggregate_measurements_unpartitioned
+) AS measurements
+ORDER BY
+    peripheral_id,
+    quantity_type_id,
+    datetime_end DESC
+ON CONFLICT (peripheral_id, quantity_type_id)
+    DO UPDATE SET
+        aggregate_measurement_id = EXCLUDED.aggregate_measurement_id,
+        aggregate_values = EXCLUDED.aggregate_values,
+        aggregate_datetime_start = EXCLUDED.aggregate_datetime_start,
+        aggregate_datetime_end = EXCLUDED.aggregate_datetime_end
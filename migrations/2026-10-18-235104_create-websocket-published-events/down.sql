@@ -0,0 +1 @@
+DROP TABLE websocket_published_events;
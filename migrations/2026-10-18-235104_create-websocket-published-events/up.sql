@@ -0,0 +1,10 @@
+-- The events published to WebSocket subscribers by any API instance. The broker may redeliver a
+-- kit's MQTT message, so an event is published by the instance that first claims its id here.
+-- Events too large for a notification are stored here, and notified by id.
+CREATE UNLOGGED TABLE websocket_published_events (
+    id uuid NOT NULL,
+    datetime_published timestamptz NOT NULL,
+    event jsonb,
+    CONSTRAINT websocket_published_events_pkey PRIMARY KEY (id)
+);
+CREATE INDEX ix_websocket_published_events_datetime_published ON public.websocket_published_events USING btree (datetime_published);